@@ -0,0 +1,62 @@
+/* The C interface of the rolling-shutter engine, built with the `ffi` feature. */
+
+#ifndef ROLLING_SHUTTER_H
+#define ROLLING_SHUTTER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Returned by rs_render on success. Failures return the exit code the command line tool would exit
+ * with for the same class of failure, or one of the negative codes below. */
+#define RS_OK 0
+/* A null pointer, a frame with no data, or an output buffer too small for the composite. */
+#define RS_INVALID_ARGUMENT (-1)
+/* Rendering panicked. */
+#define RS_PANICKED (-2)
+
+/* A frame of 8-bit RGBA pixels, row by row with no padding: width * height * 4 bytes. */
+typedef struct rs_frame {
+    const uint8_t *data;
+    uint32_t width;
+    uint32_t height;
+} rs_frame;
+
+/* The options of a render. Fill it in with rs_config_init before changing any of it. */
+typedef struct rs_config {
+    /* Where the shutter starts from: 0 for the top, 1 for the right, 2 for the bottom and 3 for
+     * the left. */
+    int direction;
+    /* How many consecutive frames are averaged into each frame the shutter sees. */
+    uint32_t preblend;
+    /* Whether to stretch the bands over the whole image when there are too few frames for a line
+     * each, if nonzero. */
+    int auto_stretch;
+} rs_config;
+
+/* Fills in config with the defaults of the command line tool. */
+void rs_config_init(rs_config *config);
+
+/* Renders the composite of n frames into out_buf, as 8-bit RGBA pixels the size of the first
+ * frame, and stores its dimensions in out_width and out_height. config may be NULL for the
+ * defaults. On failure, rs_last_error describes what went wrong. */
+int rs_render(const rs_frame *frames,
+              size_t n,
+              const rs_config *config,
+              uint8_t *out_buf,
+              size_t out_len,
+              uint32_t *out_width,
+              uint32_t *out_height);
+
+/* A description of the last error on this thread, or NULL if there hasn't been one. It stays
+ * valid until the next call to rs_render on the same thread. */
+const char *rs_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
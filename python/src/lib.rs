@@ -0,0 +1,103 @@
+//! Python bindings for rendering frames held in memory, as NumPy arrays, without going through
+//! files or the command line tool.
+
+use engine::{Direction, FrameSource, NoProgress, ShutterConfig};
+use image::{DynamicImage, ImageBuffer};
+use numpy::ndarray::Array3;
+use numpy::{IntoPyArray, PyArray3, PyReadonlyArray3};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+create_exception!(rolling_shutter, RenderError, PyException);
+
+/// Reads the render options from the keyword arguments of `render`.
+fn config(kwargs: Option<&PyDict>) -> PyResult<ShutterConfig> {
+    let mut config = ShutterConfig::new();
+    let kwargs = match kwargs {
+        Some(kwargs) => kwargs,
+        None => return Ok(config),
+    };
+    for (key, value) in kwargs.iter() {
+        let key: &str = key.extract()?;
+        config = match key {
+            "direction" => {
+                let direction: Direction = value.extract::<&str>()?
+                    .parse()
+                    .map_err(|e: engine::Error| PyValueError::new_err(e.to_string()))?;
+                config.direction(direction)
+            }
+            "preblend" => config.preblend(value.extract()?),
+            "stabilize" => config.stabilize(value.extract()?),
+            "autocrop" => config.autocrop(value.extract()?),
+            "auto_stretch" => config.auto_stretch(value.extract()?),
+            "linear_blend" => config.linear_blend(value.extract()?),
+            "deflicker" => config.deflicker(value.extract()?),
+            _ => {
+                return Err(PyTypeError::new_err(format!("render() got an unexpected keyword \
+                                                         argument '{}'",
+                                                        key)))
+            }
+        };
+    }
+    Ok(config)
+}
+
+/// Converts a `height x width x channels` array of 8-bit RGB or RGBA pixels into a frame.
+fn frame(index: usize, array: PyReadonlyArray3<u8>) -> PyResult<DynamicImage> {
+    let array = array.as_array();
+    let (height, width, channels) = array.dim();
+    // Iterating follows the logical order, so arrays that aren't contiguous work too.
+    let data: Vec<u8> = array.iter().copied().collect();
+    let (width, height) = (width as u32, height as u32);
+    let image = match channels {
+        3 => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8),
+        4 => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8),
+        _ => None,
+    };
+    image.ok_or_else(|| {
+        PyValueError::new_err(format!("frame {} has {} channels; frames need 3 (RGB) or 4 (RGBA)",
+                                      index,
+                                      channels))
+    })
+}
+
+/// Renders the composite of a list of frames, each a `height x width x 3` or `height x width x 4`
+/// uint8 array of RGB or RGBA pixels, and returns it as a `height x width x 4` RGBA array.
+///
+/// The options are given as keyword arguments: `direction` ("N", "E", "S" or "W", or "north" and
+/// so on), `preblend`, `stabilize`, `autocrop`, `auto_stretch`, `linear_blend` and `deflicker`.
+#[pyfunction]
+#[pyo3(signature = (frames, **config))]
+fn render<'py>(py: Python<'py>,
+               frames: Vec<PyReadonlyArray3<'py, u8>>,
+               config: Option<&PyDict>)
+               -> PyResult<&'py PyArray3<u8>> {
+    let options = self::config(config)?.build();
+    let frames = frames.into_iter()
+        .enumerate()
+        .map(|(index, array)| frame(index, array))
+        .collect::<PyResult<Vec<_>>>()?;
+
+    let composite = py.allow_threads(|| {
+            engine::render(&options, FrameSource::Frames(frames), &mut NoProgress)
+        })
+        .map_err(|e| {
+            let causes: Vec<String> = e.iter().map(|e| e.to_string()).collect();
+            RenderError::new_err(causes.join(" "))
+        })?;
+
+    let (width, height) = composite.dimensions();
+    let array = Array3::from_shape_vec((height as usize, width as usize, 4), composite.into_raw())
+        .expect("the composite has 4 channels");
+    Ok(array.into_pyarray(py))
+}
+
+/// Creates rolling shutter images from sequences of frames.
+#[pymodule]
+fn rolling_shutter(py: Python, module: &PyModule) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(render, module)?)?;
+    module.add("RenderError", py.get_type::<RenderError>())?;
+    Ok(())
+}
@@ -0,0 +1,542 @@
+use deflate;
+use gif::{self, SetParameter};
+use image::RgbaImage;
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+
+use ::errors::{Error, ErrorKind, Result, ResultExt};
+use ::image_processing::FinishedComposite;
+use ::observer::{Flow, RenderObserver};
+use ::png_writer::{self, PNG_SIGNATURE};
+use ::output_processing::{self, EncoderSettings, OutputFormat};
+use ::output_sink::OutputSink;
+use ::report::ReportOutput;
+
+/// How long each frame of the animation is shown, in hundredths of a second.
+const FRAME_DELAY: u16 = 4;
+
+/// How long the finished image is held at the end of the animation, in hundredths of a second.
+const FINAL_FRAME_DELAY: u16 = 200;
+
+/// Where the `acTL` chunk of an animated PNG starts: right after the signature and `IHDR` chunk.
+const ACTL_OFFSET: u64 = 8 + 12 + 13;
+
+/// The codec used when an animation is saved as a video.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum VideoCodec {
+    /// H.264, which almost every player supports.
+    H264,
+    /// H.265, which compresses better but is less widely supported.
+    H265,
+}
+
+impl FromStr for VideoCodec {
+    type Err = Error;
+
+    /// Parses a video codec from its name: `h264` or `h265`.
+    fn from_str(s: &str) -> Result<VideoCodec> {
+        match s {
+            "h264" => Ok(VideoCodec::H264),
+            "h265" => Ok(VideoCodec::H265),
+            _ => bail!(ErrorKind::CouldNotParseOption("video codec", s.into(), "h264 or h265")),
+        }
+    }
+}
+
+impl VideoCodec {
+    /// The name of the ffmpeg encoder for this codec.
+    fn encoder(&self) -> &'static str {
+        match *self {
+            VideoCodec::H264 => "libx264",
+            VideoCodec::H265 => "libx265",
+        }
+    }
+}
+
+/// The kind of file an animation is saved as.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AnimationFormat {
+    /// An animated GIF, which is limited to 256 colors per frame.
+    Gif,
+    /// An animated PNG, which keeps full color.
+    Apng,
+    /// An animated WebP, encoded by an ffmpeg child process.
+    WebP,
+    /// A video, encoded by an ffmpeg child process.
+    Video(VideoCodec),
+}
+
+impl AnimationFormat {
+    /// Picks the animation format from the extension of the path it is saved to.
+    ///
+    /// # Errors
+    /// This fails if the extension isn't that of a supported animation format.
+    pub fn from_path(path: &Path) -> Result<AnimationFormat> {
+        let extension = path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        match extension.as_deref() {
+            Some("gif") => Ok(AnimationFormat::Gif),
+            Some("png") | Some("apng") => Ok(AnimationFormat::Apng),
+            Some("webp") => Ok(AnimationFormat::WebP),
+            _ => bail!(ErrorKind::UnsupportedAnimationFormat(path.to_path_buf())),
+        }
+    }
+}
+
+/// Where and how often to record the accumulating composite as an animation.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AnimationSettings {
+    /// The path the animation is saved to.
+    pub path: PathBuf,
+    /// The kind of file the animation is saved as.
+    pub format: AnimationFormat,
+    /// The quality of animated WebPs, from 0 to 100.
+    pub webp_quality: u8,
+    /// How many bands are copied between recorded frames.
+    pub every: u32,
+}
+
+/// Writes an animated PNG one frame at a time.
+struct ApngEncoder<W: Write + Seek> {
+    writer: W,
+    dimensions: (u32, u32),
+    frames: u32,
+    /// The sequence number of the next `fcTL` or `fdAT` chunk.
+    sequence: u32,
+}
+
+/// Builds the `acTL` chunk for an animation with the given number of frames that loops forever.
+fn actl_chunk(frames: u32) -> Vec<u8> {
+    let mut data = vec![];
+    png_writer::write_u32(&mut data, frames);
+    png_writer::write_u32(&mut data, 0);
+    png_writer::chunk(b"acTL", &data)
+}
+
+impl<W: Write + Seek> ApngEncoder<W> {
+    fn new(mut writer: W, (width, height): (u32, u32)) -> io::Result<ApngEncoder<W>> {
+        writer.write_all(&PNG_SIGNATURE)?;
+        writer.write_all(&png_writer::ihdr_chunk(width, height, 8))?;
+        // The number of frames isn't known yet, so this is rewritten once they have been written.
+        writer.write_all(&actl_chunk(0))?;
+        Ok(ApngEncoder {
+            writer,
+            dimensions: (width, height),
+            frames: 0,
+            sequence: 0,
+        })
+    }
+
+    fn write_frame(&mut self, buf: &RgbaImage, delay: u16) -> io::Result<()> {
+        let (width, height) = self.dimensions;
+        let mut fctl = vec![];
+        png_writer::write_u32(&mut fctl, self.sequence);
+        png_writer::write_u32(&mut fctl, width);
+        png_writer::write_u32(&mut fctl, height);
+        png_writer::write_u32(&mut fctl, 0);
+        png_writer::write_u32(&mut fctl, 0);
+        // The delay as a fraction of a second, then no disposal, and replacing the previous frame
+        // rather than blending over it.
+        fctl.extend_from_slice(&[(delay >> 8) as u8, delay as u8, 0, 100, 0, 0]);
+        self.writer.write_all(&png_writer::chunk(b"fcTL", &fctl))?;
+        self.sequence += 1;
+
+        let filtered = png_writer::filter_scanlines(buf, width as usize * 4, 4);
+        let compressed = deflate::deflate_bytes_zlib(&filtered);
+
+        // The first frame doubles as the still image that viewers without APNG support show.
+        if self.frames == 0 {
+            self.writer.write_all(&png_writer::chunk(b"IDAT", &compressed))?;
+        } else {
+            let mut data = Vec::with_capacity(compressed.len() + 4);
+            png_writer::write_u32(&mut data, self.sequence);
+            data.extend_from_slice(&compressed);
+            self.writer.write_all(&png_writer::chunk(b"fdAT", &data))?;
+            self.sequence += 1;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.write_all(&png_writer::chunk(b"IEND", &[]))?;
+        self.writer.seek(SeekFrom::Start(ACTL_OFFSET))?;
+        self.writer.write_all(&actl_chunk(self.frames))?;
+        self.writer.flush()
+    }
+}
+
+/// Where recorded frames are sent.
+enum Encoder {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Apng(ApngEncoder<BufWriter<File>>),
+    /// An ffmpeg process reading raw RGBA frames from its standard input, for formats that are
+    /// impractical to encode here.
+    Ffmpeg(Child),
+}
+
+/// Records the composite as it is built up into an animation, writing each frame as it arrives.
+pub struct Animation {
+    path: PathBuf,
+    every: u32,
+    encoder: Encoder,
+    /// The number of bands copied since the last recorded frame.
+    pending: u32,
+    /// Whether the animation was completely written.
+    finished: bool,
+}
+
+/// Builds the arguments for an ffmpeg process that encodes raw RGBA frames of the given dimensions
+/// from its standard input into a video or animated WebP.
+fn ffmpeg_args(path: &Path,
+               (width, height): (u32, u32),
+               format: AnimationFormat,
+               webp_quality: u8)
+               -> Vec<String> {
+    let frame_rate = 100 / FRAME_DELAY;
+    let mut args: Vec<String> = vec!["-y".into(),
+         "-loglevel".into(),
+         "error".into(),
+         "-f".into(),
+         "rawvideo".into(),
+         "-pix_fmt".into(),
+         "rgba".into(),
+         "-s".into(),
+         format!("{}x{}", width, height),
+         "-r".into(),
+         frame_rate.to_string(),
+         "-i".into(),
+         "-".into()];
+    match format {
+        AnimationFormat::Video(codec) => {
+            args.extend(vec!["-c:v".into(),
+                             codec.encoder().into(),
+                             // Most players only handle 4:2:0 chroma subsampling, which needs
+                             // even dimensions.
+                             "-vf".into(),
+                             "pad=ceil(iw/2)*2:ceil(ih/2)*2".into(),
+                             "-pix_fmt".into(),
+                             "yuv420p".into()]);
+        }
+        AnimationFormat::WebP => {
+            args.extend(vec!["-c:v".into(),
+                             "libwebp_anim".into(),
+                             "-quality".into(),
+                             webp_quality.to_string(),
+                             "-loop".into(),
+                             "0".into()]);
+        }
+        AnimationFormat::Gif | AnimationFormat::Apng => unreachable!(),
+    }
+    args.push(path.to_string_lossy().into_owned());
+    args
+}
+
+/// Converts the composite into a GIF frame, quantizing it to a 256-color palette.
+fn gif_frame(buf: &RgbaImage, delay: u16) -> gif::Frame<'static> {
+    let (width, height) = buf.dimensions();
+    let mut pixels = buf.clone().into_raw();
+    let mut frame = gif::Frame::from_rgba(width as u16, height as u16, &mut pixels);
+    frame.delay = delay;
+    // The parts of the composite not yet filled in are transparent, so each frame has to replace
+    // the one before it rather than being drawn over it.
+    frame.dispose = gif::DisposalMethod::Background;
+    frame
+}
+
+impl Animation {
+    /// Creates the animation file for a composite of the given dimensions.
+    ///
+    /// # Errors
+    /// This fails if the file can't be created, or if the composite is too large for a GIF.
+    pub fn create(settings: &AnimationSettings,
+                  (width, height): (u32, u32))
+                  -> Result<Animation> {
+        let path = settings.path.clone();
+        let encoder = match settings.format {
+            AnimationFormat::Gif => {
+                if width > u16::MAX as u32 || height > u16::MAX as u32 {
+                    bail!(ErrorKind::AnimationTooLarge(path, (width, height)));
+                }
+                let encoder = File::create(&path)
+                    .and_then(|file| {
+                        gif::Encoder::new(BufWriter::new(file), width as u16, height as u16, &[])
+                    })
+                    .and_then(|mut encoder| encoder.set(gif::Repeat::Infinite).map(|_| encoder))
+                    .chain_err(|| ErrorKind::CouldNotSaveOutput(path.clone()))?;
+                Encoder::Gif(encoder)
+            }
+            AnimationFormat::Apng => {
+                let encoder = File::create(&path)
+                    .and_then(|file| ApngEncoder::new(BufWriter::new(file), (width, height)))
+                    .chain_err(|| ErrorKind::CouldNotSaveOutput(path.clone()))?;
+                Encoder::Apng(encoder)
+            }
+            AnimationFormat::WebP |
+            AnimationFormat::Video(_) => {
+                let args =
+                    ffmpeg_args(&path, (width, height), settings.format, settings.webp_quality);
+                let child = Command::new("ffmpeg")
+                    .args(&args)
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .chain_err(|| ErrorKind::CouldNotRunFfmpeg)?;
+                Encoder::Ffmpeg(child)
+            }
+        };
+        Ok(Animation {
+            path,
+            every: ::std::cmp::max(settings.every, 1),
+            encoder,
+            pending: 0,
+            finished: false,
+        })
+    }
+
+    fn write(&mut self, buf: &RgbaImage, delay: u16) -> Result<()> {
+        let result = match self.encoder {
+            Encoder::Gif(ref mut encoder) => encoder.write_frame(&gif_frame(buf, delay)),
+            Encoder::Apng(ref mut encoder) => encoder.write_frame(buf, delay),
+            Encoder::Ffmpeg(ref mut child) => {
+                // The input has a fixed frame rate, so longer frames are repeated.
+                let stdin = child.stdin.as_mut().unwrap();
+                let mut result = Ok(());
+                for _ in 0..::std::cmp::max(delay / FRAME_DELAY, 1) {
+                    result = stdin.write_all(buf);
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                result
+            }
+        };
+        result.chain_err(|| ErrorKind::CouldNotSaveOutput(self.path.clone()))
+    }
+
+}
+
+impl RenderObserver for Animation {
+    /// Notes that another band has been copied into the composite, recording a frame if enough
+    /// bands have been copied since the last one.
+    fn snapshot_ready(&mut self, buf: &RgbaImage) -> Result<Flow> {
+        self.pending += 1;
+        if self.pending < self.every {
+            return Ok(Flow::Continue);
+        }
+        self.pending = 0;
+        self.write(buf, FRAME_DELAY)?;
+        Ok(Flow::Continue)
+    }
+}
+
+impl OutputSink for Animation {
+    /// Records the finished composite, which is held for a while before the animation loops, and
+    /// waits for the animation to be completely written.
+    fn finish(&mut self, composite: &FinishedComposite) -> Result<Option<ReportOutput>> {
+        self.write(composite.composite(), FINAL_FRAME_DELAY)?;
+        match self.encoder {
+            Encoder::Gif(_) => (),
+            Encoder::Apng(ref mut encoder) => {
+                encoder.finish().chain_err(|| ErrorKind::CouldNotSaveOutput(self.path.clone()))?
+            }
+            Encoder::Ffmpeg(ref mut child) => {
+                // Closing standard input tells ffmpeg there are no more frames.
+                drop(child.stdin.take());
+                let status = child.wait().chain_err(|| ErrorKind::CouldNotRunFfmpeg)?;
+                self.finished = true;
+                if !status.success() {
+                    bail!(ErrorKind::FfmpegFailed(status.code()));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Drop for Animation {
+    /// Stops an ffmpeg process left without the rest of its frames, such as when the render failed,
+    /// and removes what it wrote, which would otherwise pass for a complete video.
+    fn drop(&mut self) {
+        if let Encoder::Ffmpeg(ref mut child) = self.encoder {
+            if !self.finished {
+                let _ = child.kill();
+                let _ = child.wait();
+                let _ = fs::remove_file(&self.path);
+            }
+        }
+    }
+}
+
+/// Where and how often to save snapshots of the accumulating composite.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SnapshotSettings {
+    /// The directory snapshots are saved in.
+    pub dir: PathBuf,
+    /// How many bands are copied between snapshots.
+    pub every: u32,
+}
+
+/// Saves the composite as it is built up as a series of numbered PNGs.
+pub struct Snapshots {
+    dir: PathBuf,
+    every: u32,
+    /// The number of bands copied since the last snapshot.
+    pending: u32,
+    /// The number of snapshots saved so far.
+    count: u32,
+}
+
+/// Builds the path of a snapshot from its number, padded so that snapshots sort in order.
+fn snapshot_path(dir: &Path, number: u32) -> PathBuf {
+    dir.join(format!("{:05}.png", number))
+}
+
+impl Snapshots {
+    /// Creates the snapshot directory, if it doesn't exist yet.
+    ///
+    /// # Errors
+    /// This fails if the directory can't be created.
+    pub fn create(settings: &SnapshotSettings) -> Result<Snapshots> {
+        fs::create_dir_all(&settings.dir)
+            .chain_err(|| ErrorKind::CouldNotSaveOutput(settings.dir.clone()))?;
+        Ok(Snapshots {
+            dir: settings.dir.clone(),
+            every: ::std::cmp::max(settings.every, 1),
+            pending: 0,
+            count: 0,
+        })
+    }
+}
+
+impl RenderObserver for Snapshots {
+    /// Notes that another band has been copied into the composite, saving a snapshot if enough
+    /// bands have been copied since the last one.
+    fn snapshot_ready(&mut self, buf: &RgbaImage) -> Result<Flow> {
+        self.pending += 1;
+        if self.pending < self.every {
+            return Ok(Flow::Continue);
+        }
+        self.pending = 0;
+        let path = snapshot_path(&self.dir, self.count);
+        output_processing::save(buf,
+                                &path,
+                                Some(OutputFormat::Png),
+                                None,
+                                None,
+                                &EncoderSettings::default())
+            .chain_err(|| ErrorKind::CouldNotSaveOutput(path.clone()))?;
+        self.count += 1;
+        Ok(Flow::Continue)
+    }
+}
+
+impl OutputSink for Snapshots {
+    fn finish(&mut self, _composite: &FinishedComposite) -> Result<Option<ReportOutput>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{ImageBuffer, Rgba};
+
+    #[test]
+    fn test_parse_video_codec() {
+        assert_eq!("h265".parse::<VideoCodec>().unwrap(), VideoCodec::H265);
+        match "vp9".parse::<VideoCodec>() {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "vp9"),
+            result => assert!(false, "expected a parse error, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn test_ffmpeg_args() {
+        let format = AnimationFormat::Video(VideoCodec::H265);
+        let args = ffmpeg_args(Path::new("out.mp4"), (641, 480), format, 80);
+        let joined = args.join(" ");
+        assert!(joined.contains("-f rawvideo -pix_fmt rgba -s 641x480 -r 25 -i -"));
+        assert!(joined.contains("-c:v libx265"));
+        assert_eq!(args.last().unwrap(), "out.mp4");
+
+        let args = ffmpeg_args(Path::new("out.webp"), (640, 480), AnimationFormat::WebP, 80);
+        assert!(args.join(" ").contains("-c:v libwebp_anim -quality 80"));
+    }
+
+    #[test]
+    fn test_snapshot_path() {
+        assert_eq!(snapshot_path(Path::new("snaps"), 42), PathBuf::from("snaps/00042.png"));
+    }
+
+    #[test]
+    fn test_animation_format() {
+        assert_eq!(AnimationFormat::from_path(Path::new("a.gif")).unwrap(), AnimationFormat::Gif);
+        assert_eq!(AnimationFormat::from_path(Path::new("a.PNG")).unwrap(),
+                   AnimationFormat::Apng);
+        assert_eq!(AnimationFormat::from_path(Path::new("a.apng")).unwrap(),
+                   AnimationFormat::Apng);
+        assert_eq!(AnimationFormat::from_path(Path::new("a.webp")).unwrap(),
+                   AnimationFormat::WebP);
+        assert!(AnimationFormat::from_path(Path::new("a.mp4")).is_err());
+        assert!(AnimationFormat::from_path(Path::new("a")).is_err());
+    }
+
+    #[test]
+    fn test_apng() {
+        let mut buf: RgbaImage = ImageBuffer::new(3, 2);
+        let mut encoder = ApngEncoder::new(io::Cursor::new(vec![]), (3, 2)).unwrap();
+        encoder.write_frame(&buf, 4).unwrap();
+        buf.put_pixel(1, 1, Rgba([10, 20, 30, 255]));
+        encoder.write_frame(&buf, 200).unwrap();
+        encoder.finish().unwrap();
+        let data = encoder.writer.into_inner();
+
+        assert!(data.starts_with(&PNG_SIGNATURE));
+        let mut chunks = vec![];
+        let mut offset = PNG_SIGNATURE.len();
+        while offset < data.len() {
+            let length = png_writer::read_u32(&data[offset..]) as usize;
+            let kind = String::from_utf8(data[offset + 4..offset + 8].to_vec()).unwrap();
+            let body = &data[offset + 8..offset + 8 + length];
+            let crc = png_writer::read_u32(&data[offset + 8 + length..]);
+            assert_eq!(crc, png_writer::crc32(&data[offset + 4..offset + 8 + length]));
+            chunks.push((kind, body.to_vec()));
+            offset += length + 12;
+        }
+
+        let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"]);
+        // Two frames, looping forever.
+        assert_eq!(chunks[1].1, [0, 0, 0, 2, 0, 0, 0, 0]);
+        // Sequence numbers are shared between fcTL and fdAT chunks.
+        assert_eq!(png_writer::read_u32(&chunks[4].1), 1);
+        assert_eq!(png_writer::read_u32(&chunks[5].1), 2);
+        assert_eq!(&chunks[4].1[20..22], &[0, 200]);
+    }
+
+    #[test]
+    fn test_gif_frame() {
+        let mut buf: RgbaImage = ImageBuffer::new(4, 3);
+        for x in 0..4 {
+            buf.put_pixel(x, 0, Rgba([255, 0, 0, 255]));
+        }
+        let frame = gif_frame(&buf, 7);
+        assert_eq!((frame.width, frame.height), (4, 3));
+        assert_eq!(frame.delay, 7);
+        assert_eq!(frame.buffer.len(), 12);
+        // The rows that haven't been filled in yet stay transparent.
+        let transparent = frame.transparent.unwrap();
+        assert!(frame.buffer[4..].iter().all(|&index| index == transparent));
+        assert!(frame.buffer[..4].iter().all(|&index| index != transparent));
+    }
+}
@@ -0,0 +1,120 @@
+use image::FilterType;
+use regex::Regex;
+
+use rolling_shutter::{ErrorKind, Region, Result, ResultExt};
+
+/// Parse a size of the form `WxH`.
+///
+/// # Errors
+/// This fails if the string is not of that form, or if either dimension is zero.
+pub fn parse_size(s: &str) -> Result<(u32, u32)> {
+    let re = Regex::new(r"^(\d+)[xX](\d+)$").unwrap();
+    let cap = match re.captures(s.trim()) {
+        Some(cap) => cap,
+        None => bail!(ErrorKind::CouldNotParseSize(s.into())),
+    };
+    let width: u32 = cap[1].parse().chain_err(|| ErrorKind::CouldNotParseSize(s.into()))?;
+    let height: u32 = cap[2].parse().chain_err(|| ErrorKind::CouldNotParseSize(s.into()))?;
+    if width == 0 || height == 0 {
+        bail!(ErrorKind::CouldNotParseSize(s.into()));
+    }
+    Ok((width, height))
+}
+
+/// Parse a region of the form `x,y,w,h`.
+///
+/// # Errors
+/// This fails if the string does not contain exactly four comma-separated integers, or if the
+/// region would be empty.
+pub fn parse_region(s: &str) -> Result<Region> {
+    let re = Regex::new(r"^\s*(\d+)\s*,\s*(\d+)\s*,\s*(\d+)\s*,\s*(\d+)\s*$").unwrap();
+    let cap = match re.captures(s) {
+        Some(cap) => cap,
+        None => bail!(ErrorKind::CouldNotParseRegion(s.into())),
+    };
+    let field = |i: usize| -> Result<u32> {
+        cap.get(i)
+            .unwrap()
+            .as_str()
+            .parse()
+            .chain_err(|| ErrorKind::CouldNotParseRegion(s.into()))
+    };
+    let region = Region {
+        x: field(1)?,
+        y: field(2)?,
+        width: field(3)?,
+        height: field(4)?,
+    };
+    if region.width == 0 || region.height == 0 {
+        bail!(ErrorKind::CouldNotParseRegion(s.into()));
+    }
+    Ok(region)
+}
+
+/// Converts a filter name as accepted on the command line into an `image` filter.
+///
+/// # Errors
+/// This fails if the name is not `nearest`, `bilinear`, `bicubic` or `lanczos`.
+pub fn parse_filter(s: &str) -> Result<FilterType> {
+    match s {
+        "nearest" => Ok(FilterType::Nearest),
+        "bilinear" => Ok(FilterType::Triangle),
+        "bicubic" => Ok(FilterType::CatmullRom),
+        "lanczos" => Ok(FilterType::Lanczos3),
+        _ => {
+            let expected = "nearest, bilinear, bicubic or lanczos";
+            bail!(ErrorKind::CouldNotParseOption("filter", s.into(), expected))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rolling_shutter::Error;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1920x1080").unwrap(), (1920, 1080));
+        assert_eq!(parse_size("640X480").unwrap(), (640, 480));
+        assert!(parse_size("1920").is_err());
+        assert!(parse_size("0x1080").is_err());
+        assert!(parse_size("axb").is_err());
+    }
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(parse_region("10,20,300,400").unwrap(),
+                   Region {
+                       x: 10,
+                       y: 20,
+                       width: 300,
+                       height: 400,
+                   });
+        assert_eq!(parse_region(" 0, 0, 1, 1 ").unwrap(),
+                   Region {
+                       x: 0,
+                       y: 0,
+                       width: 1,
+                       height: 1,
+                   });
+        assert!(parse_region("10,20,300").is_err());
+        assert!(parse_region("10,20,300,400,5").is_err());
+        assert!(parse_region("a,b,c,d").is_err());
+        assert!(parse_region("10,20,0,400").is_err());
+        assert!(parse_region("-10,20,300,400").is_err());
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        match parse_filter("bicubic") {
+            Ok(FilterType::CatmullRom) => (),
+            result => assert!(false, "expected the bicubic filter, got {:?}.", result.is_ok()),
+        }
+        match parse_filter("gaussian") {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "gaussian"),
+            result => assert!(false, "expected a parse error, got {:?}.", result.is_ok()),
+        }
+    }
+}
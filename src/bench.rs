@@ -0,0 +1,370 @@
+use image::{self, DynamicImage, RgbaImage};
+
+use std::fmt::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ::Direction;
+use ::errors::Result;
+use ::generate::{self, Pattern};
+use ::gpu::GpuCompositor;
+use ::image_processing::{self, Dither, DitherMode, RenderOptions};
+use ::jpeg;
+use ::output_processing::{self, EncoderSettings, OutputFormat};
+
+/// How many distinct frames the bands are copied from in turn, and decoded each round.
+const FRAMES: u32 = 8;
+
+/// The directions the bands are copied in.
+const DIRECTIONS: [Direction; 4] = [Direction::N, Direction::E, Direction::S, Direction::W];
+
+/// A way of copying bands into the composite.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CopyPath {
+    /// A row of the band at a time, as RGBA8 frames without dithering are copied.
+    Rows,
+    /// Pixel by pixel, as bands placed by a custom geometry are copied.
+    Pixels,
+    /// Pixel by pixel, picking the band of each, as dithered bands are copied.
+    Dithered,
+    /// On the GPU, as `--gpu` composites.
+    Gpu,
+}
+
+impl CopyPath {
+    /// The name the path is listed under.
+    pub fn name(self) -> &'static str {
+        match self {
+            CopyPath::Rows => "rows",
+            CopyPath::Pixels => "pixels",
+            CopyPath::Dithered => "dithered",
+            CopyPath::Gpu => "gpu",
+        }
+    }
+}
+
+/// Every copy path, in the order they're measured.
+pub const COPY_PATHS: [CopyPath; 4] = [CopyPath::Rows,
+                                       CopyPath::Pixels,
+                                       CopyPath::Dithered,
+                                       CopyPath::Gpu];
+
+/// How long some bands took to copy, or some frames took to decode.
+#[derive(Clone, Debug)]
+pub struct Measurement {
+    /// What was measured, such as `N rows` or `JPEG (image)`.
+    pub label: String,
+    /// How many bands were copied, or frames decoded.
+    pub count: u64,
+    /// How many bytes of pixels they came to, at four bytes a pixel.
+    pub bytes: u64,
+    /// How long they took, or why they couldn't be measured, such as a backend missing from the
+    /// build.
+    pub elapsed: ::std::result::Result<Duration, String>,
+}
+
+impl Measurement {
+    /// How many bands were copied, or frames decoded, a second.
+    pub fn per_second(&self) -> Option<f64> {
+        self.elapsed.as_ref().ok().map(|elapsed| self.count as f64 / seconds(*elapsed))
+    }
+
+    /// How many megabytes of pixels went through a second.
+    pub fn megabytes_per_second(&self) -> Option<f64> {
+        self.elapsed.as_ref().ok().map(|elapsed| self.bytes as f64 / 1e6 / seconds(*elapsed))
+    }
+}
+
+/// Guards against dividing by zero for measurements too quick for the clock.
+fn seconds(elapsed: Duration) -> f64 {
+    elapsed.as_secs_f64().max(1e-9)
+}
+
+/// The results of a benchmark.
+#[derive(Clone, Debug)]
+pub struct Benchmark {
+    /// The dimensions of the frames and the composite.
+    pub dimensions: (u32, u32),
+    /// How many times each composite was built, and each frame decoded.
+    pub rounds: u32,
+    /// Copying every band of a composite, for each direction and copy path.
+    pub copies: Vec<Measurement>,
+    /// Decoding frames with each decoder.
+    pub decodes: Vec<Measurement>,
+}
+
+/// The options a composite is built with along a copy path.
+fn path_options(direction: Direction, path: CopyPath) -> RenderOptions {
+    let mut options = RenderOptions {
+        direction,
+        ..RenderOptions::default()
+    };
+    match path {
+        // The direction given as a geometry takes the general path, without changing the bands.
+        CopyPath::Pixels => options.geometry = Some(Arc::new(direction)),
+        CopyPath::Dithered => {
+            options.dither = Some(Dither {
+                mode: DitherMode::Ordered,
+                radius: 2,
+            })
+        }
+        CopyPath::Rows | CopyPath::Gpu => {}
+    }
+    options
+}
+
+/// Builds a composite with one band from each of `bands` frames, taken from `frames` in turn,
+/// along a copy path. Returns the composite and how long copying the bands took, which for the GPU
+/// leaves out setting it up but takes in reading the composite back.
+fn composite(frames: &mut [RgbaImage],
+             bands: u32,
+             direction: Direction,
+             path: CopyPath)
+             -> Result<(RgbaImage, Duration)> {
+    let options = path_options(direction, path);
+    let (width, height) = frames[0].dimensions();
+    let mut buf = RgbaImage::new(width, height);
+    if path == CopyPath::Gpu {
+        let map = image_processing::band_map((width, height), &options, bands, bands);
+        let mut compositor = GpuCompositor::new(&buf, &map)?;
+        let start = Instant::now();
+        for band in 0..bands {
+            compositor.copy_band(band, &frames[(band % FRAMES) as usize])?;
+        }
+        let buf = compositor.finish()?;
+        return Ok((buf, start.elapsed()));
+    }
+    let start = Instant::now();
+    for band in 0..bands {
+        let frame = &mut frames[(band % FRAMES) as usize];
+        image_processing::copy_band(&mut buf, frame, band, bands, band..band + 1, &options)?;
+    }
+    Ok((buf, start.elapsed()))
+}
+
+/// Times copying every band of a composite `rounds` times along a copy path.
+///
+/// # Errors
+/// This fails if a band couldn't be copied on the CPU. The GPU failing is recorded in the
+/// measurement instead, since it may be missing from the build or the machine.
+fn measure_copy(frames: &mut [RgbaImage],
+                direction: Direction,
+                path: CopyPath,
+                rounds: u32)
+                -> Result<Measurement> {
+    let (width, height) = frames[0].dimensions();
+    let bands = match direction {
+        Direction::N | Direction::S => height,
+        Direction::E | Direction::W => width,
+    };
+    let mut elapsed = Ok(Duration::from_secs(0));
+    for _ in 0..rounds {
+        match composite(frames, bands, direction, path) {
+            Ok((_, round)) => elapsed = elapsed.map(|total| total + round),
+            Err(e) if path == CopyPath::Gpu => {
+                elapsed = Err(e.to_string());
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Measurement {
+        label: format!("{:?} {}", direction, path.name()),
+        count: bands as u64 * rounds as u64,
+        bytes: width as u64 * height as u64 * 4 * rounds as u64,
+        elapsed,
+    })
+}
+
+/// Times decoding each of `data` `rounds` times with `decode`, which returns `None` if it can't.
+fn measure_decode<F>(label: &str,
+                     data: &[Vec<u8>],
+                     dimensions: (u32, u32),
+                     rounds: u32,
+                     decode: F)
+                     -> Measurement
+    where F: Fn(&[u8]) -> Option<DynamicImage>
+{
+    let count = data.len() as u64 * rounds as u64;
+    let start = Instant::now();
+    let mut decoded = true;
+    for _ in 0..rounds {
+        for frame in data {
+            decoded &= decode(frame).is_some();
+        }
+    }
+    let elapsed = start.elapsed();
+    Measurement {
+        label: label.into(),
+        count,
+        bytes: dimensions.0 as u64 * dimensions.1 as u64 * 4 * count,
+        elapsed: if decoded {
+            Ok(elapsed)
+        } else {
+            Err("couldn't decode the frames".into())
+        },
+    }
+}
+
+/// Measures how fast this machine copies bands along every copy path, in every direction, and
+/// decodes frames with every decoder, on synthetic frames generated in memory. Backends missing
+/// from the build or the machine are listed with the reason they couldn't be measured.
+///
+/// # Arguments
+/// * `dimensions` - The dimensions of the frames and the composite.
+/// * `rounds` - How many times each composite is built, and each frame decoded. There must be at
+///   least one.
+///
+/// # Errors
+/// This fails if the frames couldn't be encoded to decode, or a band couldn't be copied on the
+/// CPU.
+pub fn run(dimensions: (u32, u32), rounds: u32) -> Result<Benchmark> {
+    let mut frames: Vec<RgbaImage> =
+        (0..FRAMES).map(|i| generate::frame(Pattern::Spinner, dimensions, i, FRAMES)).collect();
+
+    let mut copies = vec![];
+    for &direction in &DIRECTIONS {
+        for &path in &COPY_PATHS {
+            copies.push(measure_copy(&mut frames, direction, path, rounds)?);
+        }
+    }
+
+    let encode = |format: OutputFormat| -> Result<Vec<Vec<u8>>> {
+        frames.iter()
+            .map(|frame| {
+                output_processing::encode(frame,
+                                          Path::new("-"),
+                                          format,
+                                          None,
+                                          None,
+                                          &EncoderSettings::default())
+            })
+            .collect()
+    };
+    let (png, jpegs) = (encode(OutputFormat::Png)?, encode(OutputFormat::Jpeg)?);
+    let load = |data: &[u8]| image::load_from_memory(data).ok();
+    let mut decodes = vec![measure_decode("PNG (image)", &png, dimensions, rounds, load),
+                           measure_decode("JPEG (image)", &jpegs, dimensions, rounds, load)];
+    if cfg!(feature = "fast-jpeg") {
+        decodes.push(measure_decode("JPEG (zune-jpeg)", &jpegs, dimensions, rounds, jpeg::decode));
+    } else {
+        decodes.push(Measurement {
+            label: "JPEG (zune-jpeg)".into(),
+            count: 0,
+            bytes: 0,
+            elapsed: Err("not built in; rebuild with --features fast-jpeg".into()),
+        });
+    }
+
+    Ok(Benchmark {
+        dimensions,
+        rounds,
+        copies,
+        decodes,
+    })
+}
+
+/// Writes a table of measurements, under a heading naming what was counted.
+fn write_table(out: &mut String, unit: &str, measurements: &[Measurement]) {
+    writeln!(out, "{:<18}  {:>12}  {:>10}", "", format!("{}/s", unit), "MB/s").unwrap();
+    for measurement in measurements {
+        match (measurement.per_second(), measurement.megabytes_per_second()) {
+            (Some(per_second), Some(megabytes)) => {
+                writeln!(out,
+                         "{:<18}  {:>12.1}  {:>10.1}",
+                         measurement.label,
+                         per_second,
+                         megabytes)
+                    .unwrap()
+            }
+            _ => {
+                let reason = measurement.elapsed.as_ref().err().map_or("", String::as_str);
+                writeln!(out, "{:<18}  unavailable: {}", measurement.label, reason).unwrap()
+            }
+        }
+    }
+}
+
+/// Describes the results of a benchmark as tables of bands copied and frames decoded a second.
+fn describe(benchmark: &Benchmark) -> String {
+    let (width, height) = benchmark.dimensions;
+    let repeated = match benchmark.rounds {
+        1 => String::new(),
+        rounds => format!(", {} times over", rounds),
+    };
+    let mut out = String::new();
+    writeln!(out,
+             "Copying every band of a {}x{} composite{}:",
+             width,
+             height,
+             repeated)
+        .unwrap();
+    write_table(&mut out, "Bands", &benchmark.copies);
+    writeln!(out).unwrap();
+    writeln!(out, "Decoding {} {}x{} frames{}:", FRAMES, width, height, repeated).unwrap();
+    write_table(&mut out, "Frames", &benchmark.decodes);
+    out
+}
+
+impl fmt::Display for Benchmark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&describe(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_paths_agree() {
+        let mut frames: Vec<RgbaImage> =
+            (0..FRAMES).map(|i| generate::frame(Pattern::Bars, (16, 12), i, FRAMES)).collect();
+        for &direction in &DIRECTIONS {
+            let bands = if let Direction::N | Direction::S = direction { 12 } else { 16 };
+            let (rows, _) = composite(&mut frames, bands, direction, CopyPath::Rows).unwrap();
+            let (pixels, _) = composite(&mut frames, bands, direction, CopyPath::Pixels).unwrap();
+            assert_eq!(rows.into_raw(), pixels.into_raw());
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let benchmark = run((16, 12), 2).unwrap();
+        assert_eq!(benchmark.copies.len(), 16);
+        let rows = &benchmark.copies[0];
+        assert_eq!(rows.label, "N rows");
+        assert_eq!((rows.count, rows.bytes), (24, 16 * 12 * 4 * 2));
+        assert!(rows.per_second().is_some());
+        assert_eq!(benchmark.copies[5].label, "E pixels");
+        assert!(benchmark.decodes[..2].iter().all(|decode| decode.elapsed.is_ok()));
+    }
+
+    #[test]
+    fn test_describe() {
+        let measurement = |label: &str, elapsed| {
+            Measurement {
+                label: label.into(),
+                count: 100,
+                bytes: 4_000_000,
+                elapsed,
+            }
+        };
+        let benchmark = Benchmark {
+            dimensions: (40, 10),
+            rounds: 2,
+            copies: vec![measurement("N rows", Ok(Duration::from_millis(500))),
+                         measurement("N gpu", Err("no GPU was found".into()))],
+            decodes: vec![measurement("PNG (image)", Ok(Duration::from_secs(2)))],
+        };
+        let description = describe(&benchmark);
+        let lines: Vec<&str> = description.lines().collect();
+        assert_eq!(lines[0], "Copying every band of a 40x10 composite, 2 times over:");
+        assert_eq!(lines[1], "                         Bands/s        MB/s");
+        assert_eq!(lines[2], "N rows                     200.0         8.0");
+        assert_eq!(lines[3], "N gpu               unavailable: no GPU was found");
+        assert_eq!(lines[5], "Decoding 8 40x10 frames, 2 times over:");
+        assert_eq!(lines[7], "PNG (image)                 50.0         2.0");
+    }
+}
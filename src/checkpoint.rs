@@ -0,0 +1,252 @@
+use image::RgbaImage;
+use toml::{self, Value};
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use ::errors::{ErrorKind, Result, ResultExt};
+use ::image_processing::FinishedComposite;
+use ::observer::{Flow, RenderObserver};
+use ::output_processing::{self, EncoderSettings, OutputFormat};
+use ::output_sink::OutputSink;
+use ::report::{Report, ReportOutput};
+
+/// Where an interrupted render stopped, saved next to its first output so that the work done so
+/// far isn't lost.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Checkpoint {
+    /// The first band that wasn't finished; every band before it is in the partial image.
+    pub next_band: u32,
+    /// How many input frames the render was given.
+    pub input_frames: usize,
+    /// The partially filled composite, which is saved next to the checkpoint.
+    pub partial: PathBuf,
+}
+
+/// Builds a path next to the output with the given suffix in place of its extension. Standard
+/// output has no path to go next to, so those are saved in the working directory instead.
+fn sibling_path(output: &Path, suffix: &str) -> PathBuf {
+    let output = if output == Path::new("-") {
+        Path::new("rolling-shutter")
+    } else {
+        output
+    };
+    let stem = output.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    output.with_file_name(format!("{}.{}", stem, suffix))
+}
+
+/// Builds the path the partial render is saved to, e.g. `out.partial.png` for `out.jpg`.
+pub fn partial_path(output: &Path) -> PathBuf {
+    sibling_path(output, "partial.png")
+}
+
+/// Builds the path the checkpoint is saved to, e.g. `out.checkpoint.toml` for `out.jpg`.
+pub fn checkpoint_path(output: &Path) -> PathBuf {
+    sibling_path(output, "checkpoint.toml")
+}
+
+impl Checkpoint {
+    /// Serializes the checkpoint in the same format as config files.
+    pub fn to_toml(&self) -> String {
+        let partial = self.partial.file_name().map(|name| name.to_string_lossy().into_owned());
+        let mut table = toml::value::Table::new();
+        table.insert("next_band".into(), Value::Integer(self.next_band as i64));
+        table.insert("input_frames".into(), Value::Integer(self.input_frames as i64));
+        table.insert("partial".into(), Value::String(partial.unwrap_or_default()));
+        // A table of plain values always has a TOML form.
+        toml::to_string(&table).unwrap()
+    }
+
+    /// Reads a checkpoint saved by an interrupted render.
+    ///
+    /// # Errors
+    /// This fails if the file cannot be read, or isn't a checkpoint.
+    pub fn load(path: &Path) -> Result<Checkpoint> {
+        let mut s = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut s))
+            .chain_err(|| ErrorKind::InvalidCheckpoint(path.to_path_buf()))?;
+        let table: toml::value::Table =
+            toml::from_str(&s).chain_err(|| ErrorKind::InvalidCheckpoint(path.to_path_buf()))?;
+        match (table.get("next_band"), table.get("input_frames"), table.get("partial")) {
+            (Some(&Value::Integer(next_band)),
+             Some(&Value::Integer(input_frames)),
+             Some(&Value::String(ref partial))) if next_band >= 0 && input_frames >= 0 => {
+                Ok(Checkpoint {
+                    next_band: next_band as u32,
+                    input_frames: input_frames as usize,
+                    partial: path.with_file_name(partial),
+                })
+            }
+            _ => bail!(ErrorKind::InvalidCheckpoint(path.to_path_buf())),
+        }
+    }
+
+    /// Writes the checkpoint to the given path.
+    ///
+    /// # Errors
+    /// This fails if the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        File::create(path)
+            .and_then(|mut file| file.write_all(self.to_toml().as_bytes()))
+            .chain_err(|| ErrorKind::CouldNotWriteCheckpoint(path.to_path_buf()))
+    }
+}
+
+/// Saves the composite as it stands, with a checkpoint to resume from, every so many bands, so
+/// that a render that crashes or loses power partway through leaves the bands it finished. The
+/// files are those an interrupt saves, and are removed once the render has finished.
+pub struct Autosave {
+    partial: PathBuf,
+    checkpoint: PathBuf,
+    every: u32,
+    /// The first band not in the composite yet.
+    next_band: u32,
+    input_frames: usize,
+    /// The number of bands copied since the last save.
+    pending: u32,
+    suppress_output: bool,
+}
+
+impl Autosave {
+    /// Creates an autosave next to `output` every `every` bands of a render of `input_frames`
+    /// frames, which starts from the band at `start_band`. Saves that fail are warned about unless
+    /// `suppress_output` is set.
+    pub fn new(output: &Path,
+               every: u32,
+               start_band: u32,
+               input_frames: usize,
+               suppress_output: bool)
+               -> Autosave {
+        Autosave {
+            partial: partial_path(output),
+            checkpoint: checkpoint_path(output),
+            every: ::std::cmp::max(every, 1),
+            next_band: start_band,
+            input_frames,
+            pending: 0,
+            suppress_output,
+        }
+    }
+
+    /// Saves the composite, then the checkpoint. The composite is written to a temporary file
+    /// first, so that a crash while saving it leaves the last one whole.
+    fn save(&self, buf: &RgbaImage) -> Result<()> {
+        let temporary = self.partial.with_extension("png.tmp");
+        output_processing::save(buf,
+                                &temporary,
+                                Some(OutputFormat::Png),
+                                None,
+                                None,
+                                &EncoderSettings::default())
+            .and_then(|_| fs::rename(&temporary, &self.partial).map_err(Into::into))
+            .chain_err(|| ErrorKind::CouldNotSaveOutput(self.partial.clone()))?;
+        // A crash before the checkpoint is written leaves one from an earlier save, which resumes
+        // by copying a few bands that are already there again.
+        let checkpoint = Checkpoint {
+            next_band: self.next_band,
+            input_frames: self.input_frames,
+            partial: self.partial.clone(),
+        };
+        checkpoint.save(&self.checkpoint)
+    }
+}
+
+impl RenderObserver for Autosave {
+    /// Notes that another band has been copied into the composite, saving it if enough bands have
+    /// been copied since the last save. A save that fails is only warned about, since it shouldn't
+    /// stop the render it is there to protect.
+    fn snapshot_ready(&mut self, buf: &RgbaImage) -> Result<Flow> {
+        self.next_band += 1;
+        self.pending += 1;
+        if self.pending < self.every {
+            return Ok(Flow::Continue);
+        }
+        self.pending = 0;
+        if let Err(ref e) = self.save(buf) {
+            if !self.suppress_output {
+                warn!("couldn't autosave the render: {}", e);
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Removes the autosave, now that the outputs have been saved.
+    fn render_finished(&mut self, _report: &Report) -> Result<()> {
+        let _ = fs::remove_file(&self.partial);
+        let _ = fs::remove_file(&self.checkpoint);
+        Ok(())
+    }
+}
+
+impl OutputSink for Autosave {
+    fn finish(&mut self, _composite: &FinishedComposite) -> Result<Option<ReportOutput>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImage;
+    use ::testing::frame_dir;
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(partial_path(Path::new("renders/out.jpg")),
+                   PathBuf::from("renders/out.partial.png"));
+        assert_eq!(checkpoint_path(Path::new("renders/out.jpg")),
+                   PathBuf::from("renders/out.checkpoint.toml"));
+        assert_eq!(partial_path(Path::new("-")), PathBuf::from("rolling-shutter.partial.png"));
+    }
+
+    #[test]
+    fn test_to_toml() {
+        let checkpoint = Checkpoint {
+            next_band: 120,
+            input_frames: 4000,
+            partial: "renders/out.partial.png".into(),
+        };
+        assert_eq!(checkpoint.to_toml(),
+                   "next_band = 120\ninput_frames = 4000\npartial = \"out.partial.png\"\n");
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = frame_dir("checkpoint", vec![]);
+        let path = dir.join("out.checkpoint.toml");
+        let checkpoint = Checkpoint {
+            next_band: 7,
+            input_frames: 20,
+            partial: dir.join("out.partial.png"),
+        };
+        checkpoint.save(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), checkpoint);
+
+        File::create(&path).and_then(|mut file| file.write_all(b"next_band = 7\n")).unwrap();
+        assert!(Checkpoint::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_autosave() {
+        let dir = frame_dir("autosave", vec![]);
+        let output = dir.join("out.jpg");
+        let mut autosave = Autosave::new(&output, 2, 5, 40, true);
+        let buf = RgbaImage::new(4, 3);
+
+        autosave.snapshot_ready(&buf).unwrap();
+        assert!(!partial_path(&output).exists());
+        autosave.snapshot_ready(&buf).unwrap();
+        let checkpoint = Checkpoint::load(&checkpoint_path(&output)).unwrap();
+        assert_eq!(checkpoint.next_band, 7);
+        assert_eq!(checkpoint.input_frames, 40);
+        assert_eq!(::image::open(&checkpoint.partial).unwrap().dimensions(), (4, 3));
+        assert!(!dir.join("out.partial.png.tmp").exists());
+
+        autosave.render_finished(&Report::default()).unwrap();
+        assert!(!partial_path(&output).exists());
+        assert!(!checkpoint_path(&output).exists());
+    }
+}
@@ -0,0 +1,487 @@
+use image::{ImageBuffer, Primitive, Rgba, RgbaImage};
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use ::errors::{Error, ErrorKind, Result, ResultExt};
+
+/// A color channel type that images can be composited and saved in.
+pub trait Channel: Primitive + 'static {
+    /// The number of bits per channel.
+    const BIT_DEPTH: u8;
+
+    /// Converts the value to the range `[0, 1]`.
+    fn to_unit(self) -> f32;
+
+    /// Converts a value in `[0, 1]` back, clamping it to the valid range.
+    fn from_unit(value: f32) -> Self;
+
+    /// Serializes channel data as the big-endian bytes image encoders expect.
+    fn to_bytes(data: &[Self]) -> Vec<u8>;
+}
+
+impl Channel for u8 {
+    const BIT_DEPTH: u8 = 8;
+
+    fn to_unit(self) -> f32 {
+        self as f32 / 255.0
+    }
+
+    fn from_unit(value: f32) -> u8 {
+        (value.clamp(0.0, 1.0) * 255.0).round() as u8
+    }
+
+    fn to_bytes(data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+}
+
+impl Channel for u16 {
+    const BIT_DEPTH: u8 = 16;
+
+    fn to_unit(self) -> f32 {
+        self as f32 / 65535.0
+    }
+
+    fn from_unit(value: f32) -> u16 {
+        (value.clamp(0.0, 1.0) * 65535.0).round() as u16
+    }
+
+    fn to_bytes(data: &[u16]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(data.len() * 2);
+        for &value in data {
+            bytes.push((value >> 8) as u8);
+            bytes.push(value as u8);
+        }
+        bytes
+    }
+}
+
+/// An RGBA image with the given channel type.
+pub type RgbaBuffer<C> = ImageBuffer<Rgba<C>, Vec<C>>;
+
+/// An RGBA image with 16 bits per channel.
+pub type Rgba16Image = RgbaBuffer<u16>;
+
+/// Widens an 8-bit image to 16 bits per channel, without changing any colors.
+pub fn to_16_bit(image: &RgbaImage) -> Rgba16Image {
+    let (width, height) = image.dimensions();
+    let data = image.iter().map(|&value| value as u16 * 257).collect();
+    ImageBuffer::from_raw(width, height, data).unwrap()
+}
+
+/// Narrows a 16-bit image to 8 bits per channel, rounding to the nearest value.
+pub fn to_8_bit(image: &Rgba16Image) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let data = image.iter().map(|&value| u8::from_unit(value.to_unit())).collect();
+    ImageBuffer::from_raw(width, height, data).unwrap()
+}
+
+/// An image whose color channels hold linear-light values in `[0, 1]`, and whose alpha channel is
+/// scaled to `[0, 1]`.
+pub type LinearImage = ImageBuffer<Rgba<f32>, Vec<f32>>;
+
+/// Decodes an sRGB-encoded value in `[0, 1]` to linear light.
+pub(crate) fn decode_srgb(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a linear-light value in `[0, 1]` to sRGB, clamping it to the valid range.
+pub(crate) fn encode_srgb(value: f32) -> f32 {
+    let v = value.clamp(0.0, 1.0);
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts an sRGB-encoded channel value to linear light, in `[0, 1]`.
+pub fn srgb_to_linear(value: u8) -> f32 {
+    decode_srgb(value.to_unit())
+}
+
+/// Converts a linear-light channel value to sRGB encoding, clamping it to the valid range.
+pub fn linear_to_srgb<C: Channel>(value: f32) -> C {
+    C::from_unit(encode_srgb(value))
+}
+
+/// Builds a table of the linear-light value of every sRGB-encoded channel value.
+pub fn srgb_to_linear_table() -> [f32; 256] {
+    let mut table = [0f32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        *entry = srgb_to_linear(i as u8);
+    }
+    table
+}
+
+/// Decodes an sRGB image to linear light.
+pub fn to_linear<C: Channel>(image: &RgbaBuffer<C>) -> LinearImage {
+    let (width, height) = image.dimensions();
+    let mut linear: LinearImage = ImageBuffer::new(width, height);
+    for (out, pixel) in linear.pixels_mut().zip(image.pixels()) {
+        *out = Rgba([decode_srgb(pixel[0].to_unit()),
+                     decode_srgb(pixel[1].to_unit()),
+                     decode_srgb(pixel[2].to_unit()),
+                     pixel[3].to_unit()]);
+    }
+    linear
+}
+
+/// Encodes a linear-light image back to sRGB.
+pub fn from_linear<C: Channel>(image: &LinearImage) -> RgbaBuffer<C> {
+    let (width, height) = image.dimensions();
+    let mut encoded: RgbaBuffer<C> = ImageBuffer::new(width, height);
+    for (out, pixel) in encoded.pixels_mut().zip(image.pixels()) {
+        *out = Rgba([linear_to_srgb(pixel[0]),
+                     linear_to_srgb(pixel[1]),
+                     linear_to_srgb(pixel[2]),
+                     C::from_unit(pixel[3])]);
+    }
+    encoded
+}
+
+/// A 3D color lookup table, as read from a `.cube` file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut {
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    /// The table entries, with red varying fastest and blue slowest.
+    table: Vec<[f32; 3]>,
+}
+
+/// Parses three whitespace-separated floats.
+fn parse_triple(fields: &[&str]) -> Option<[f32; 3]> {
+    if fields.len() != 3 {
+        return None;
+    }
+    let mut triple = [0f32; 3];
+    for (value, field) in triple.iter_mut().zip(fields) {
+        *value = field.parse().ok()?;
+    }
+    Some(triple)
+}
+
+impl Lut {
+    /// Reads a LUT from a `.cube` file.
+    ///
+    /// # Errors
+    /// This fails if the file cannot be read or is not a valid 3D `.cube` LUT.
+    pub fn open(path: &Path) -> Result<Lut> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .chain_err(|| ErrorKind::CouldNotOpenLut(path.to_path_buf()))?;
+        Lut::parse(&contents).chain_err(|| ErrorKind::CouldNotOpenLut(path.to_path_buf()))
+    }
+
+    /// Parses the contents of a `.cube` file.
+    ///
+    /// # Errors
+    /// This fails if the contents are not a valid 3D `.cube` LUT; 1D LUTs are not supported.
+    pub fn parse(contents: &str) -> Result<Lut> {
+        let mut size = None;
+        let mut domain_min = [0f32; 3];
+        let mut domain_max = [1f32; 3];
+        let mut table = vec![];
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || ErrorKind::InvalidLut(format!("invalid line {}", number + 1));
+            match fields[0] {
+                "TITLE" => (),
+                "LUT_3D_SIZE" => {
+                    let n: usize = fields.get(1)
+                        .and_then(|s| s.parse().ok())
+                        .ok_or_else(invalid)?;
+                    if n < 2 {
+                        bail!(ErrorKind::InvalidLut(format!("size {} is too small", n)));
+                    }
+                    size = Some(n);
+                }
+                "LUT_1D_SIZE" => bail!(ErrorKind::InvalidLut("1D LUTs are not supported".into())),
+                "DOMAIN_MIN" => domain_min = parse_triple(&fields[1..]).ok_or_else(invalid)?,
+                "DOMAIN_MAX" => domain_max = parse_triple(&fields[1..]).ok_or_else(invalid)?,
+                _ => table.push(parse_triple(&fields).ok_or_else(invalid)?),
+            }
+        }
+
+        let size = match size {
+            Some(size) => size,
+            None => bail!(ErrorKind::InvalidLut("missing LUT_3D_SIZE".into())),
+        };
+        if table.len() != size * size * size {
+            bail!(ErrorKind::InvalidLut(format!("expected {} entries, found {}",
+                                                size * size * size,
+                                                table.len())));
+        }
+        Ok(Lut {
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    fn entry(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+        self.table[r + self.size * (g + self.size * b)]
+    }
+
+    /// Looks up a color, with each channel in `[0, 1]`, using trilinear interpolation.
+    pub fn lookup(&self, color: [f32; 3]) -> [f32; 3] {
+        let max_index = (self.size - 1) as f32;
+        let mut low = [0usize; 3];
+        let mut high = [0usize; 3];
+        let mut frac = [0f32; 3];
+        for c in 0..3 {
+            let range = self.domain_max[c] - self.domain_min[c];
+            let t = if range > 0.0 {
+                (color[c] - self.domain_min[c]) / range
+            } else {
+                0.0
+            };
+            let position = t.clamp(0.0, 1.0) * max_index;
+            low[c] = position.floor() as usize;
+            high[c] = ::std::cmp::min(low[c] + 1, self.size - 1);
+            frac[c] = position - low[c] as f32;
+        }
+
+        let mut result = [0f32; 3];
+        for corner in 0..8 {
+            let pick = |c: usize| if corner & (1 << c) != 0 { high[c] } else { low[c] };
+            let weight = (0..3).fold(1.0, |w, c| {
+                w * if corner & (1 << c) != 0 {
+                    frac[c]
+                } else {
+                    1.0 - frac[c]
+                }
+            });
+            if weight == 0.0 {
+                continue;
+            }
+            let value = self.entry(pick(0), pick(1), pick(2));
+            for c in 0..3 {
+                result[c] += weight * value[c];
+            }
+        }
+        result
+    }
+
+    /// Applies the LUT to every pixel of an image, leaving alpha alone.
+    pub fn apply<C: Channel>(&self, image: &mut RgbaBuffer<C>) {
+        for pixel in image.pixels_mut() {
+            let graded = self.lookup([pixel[0].to_unit(), pixel[1].to_unit(), pixel[2].to_unit()]);
+            for (channel, &value) in pixel.data.iter_mut().zip(&graded) {
+                *channel = C::from_unit(value);
+            }
+        }
+    }
+}
+
+/// Simple tonal adjustments applied to every frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Adjustments {
+    /// An offset added to every channel, in the range `[-255, 255]`.
+    pub brightness: f32,
+    /// The contrast change in percent; positive values increase contrast. At -100 every value is
+    /// mid-grey, and anything below that is taken as -100.
+    pub contrast: f32,
+    /// The gamma correction; values above 1 brighten midtones.
+    pub gamma: f32,
+}
+
+impl Default for Adjustments {
+    fn default() -> Adjustments {
+        Adjustments {
+            brightness: 0.0,
+            contrast: 0.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl Adjustments {
+    /// Whether these adjustments leave every value unchanged.
+    pub fn is_identity(&self) -> bool {
+        *self == Adjustments::default()
+    }
+
+    /// Builds a table mapping every channel value to its adjusted value.
+    fn table(&self) -> [u8; 256] {
+        let mut table = [0u8; 256];
+        // Squared, the factor would rise again below -100, raising contrast instead.
+        let contrast = ((100.0 + self.contrast.max(-100.0)) / 100.0).powi(2);
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut value = (i as f32 + self.brightness) / 255.0;
+            value = ((value - 0.5) * contrast + 0.5).clamp(0.0, 1.0);
+            value = value.powf(1.0 / self.gamma);
+            *entry = (value * 255.0).round() as u8;
+        }
+        table
+    }
+
+    /// Applies the adjustments to every pixel of an image, leaving alpha alone.
+    pub fn apply(&self, image: &mut RgbaImage) {
+        let table = self.table();
+        for pixel in image.pixels_mut() {
+            for channel in pixel.data.iter_mut().take(3) {
+                *channel = table[*channel as usize];
+            }
+        }
+    }
+}
+
+/// Where in the pipeline a LUT should be applied.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LutTarget {
+    /// Grade every frame before its band is copied.
+    Frames,
+    /// Grade the final composite.
+    Output,
+}
+
+impl FromStr for LutTarget {
+    type Err = Error;
+
+    /// Parses a LUT target from its name: `frames` or `output`.
+    fn from_str(s: &str) -> Result<LutTarget> {
+        match s {
+            "frames" => Ok(LutTarget::Frames),
+            "output" => Ok(LutTarget::Output),
+            _ => bail!(ErrorKind::CouldNotParseOption("LUT target", s.into(), "frames or output")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: &str = "TITLE \"identity\"
+# A comment
+LUT_3D_SIZE 2
+
+0 0 0
+1 0 0
+0 1 0
+1 1 0
+0 0 1
+1 0 1
+0 1 1
+1 1 1
+";
+
+    #[test]
+    fn test_parse_lut() {
+        let lut = Lut::parse(IDENTITY).unwrap();
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.entry(1, 0, 1), [1.0, 0.0, 1.0]);
+
+        assert!(Lut::parse("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+        assert!(Lut::parse("0 0 0\n").is_err());
+        assert!(Lut::parse("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n").is_err());
+        assert!(Lut::parse("LUT_3D_SIZE 2\n0 0 zero\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_lut_target() {
+        assert_eq!("output".parse::<LutTarget>().unwrap(), LutTarget::Output);
+        match "both".parse::<LutTarget>() {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "both"),
+            result => assert!(false, "expected a parse error, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn test_srgb_round_trip() {
+        for value in 0..256 {
+            assert_eq!(linear_to_srgb::<u8>(srgb_to_linear(value as u8)), value as u8);
+        }
+        assert_eq!(srgb_to_linear(0), 0.0);
+        assert_eq!(srgb_to_linear(255), 1.0);
+        // Mid-gray in sRGB is much darker than half intensity.
+        assert!((srgb_to_linear(128) - 0.2158).abs() < 1e-3);
+        assert_eq!(linear_to_srgb::<u8>(2.0), 255);
+        assert_eq!(linear_to_srgb::<u8>(-1.0), 0);
+        assert_eq!(linear_to_srgb::<u16>(1.0), 65535);
+    }
+
+    #[test]
+    fn test_channels() {
+        assert_eq!(u8::from_unit(200u8.to_unit()), 200);
+        assert_eq!(u16::from_unit(40000u16.to_unit()), 40000);
+        assert_eq!(u16::to_bytes(&[0x1234, 0xabcd]), vec![0x12, 0x34, 0xab, 0xcd]);
+
+        let image: RgbaImage = ImageBuffer::from_pixel(2, 2, Rgba([0, 128, 255, 255]));
+        let wide = to_16_bit(&image);
+        assert_eq!(wide.get_pixel(1, 1).data, [0, 128 * 257, 65535, 65535]);
+        assert_eq!(to_8_bit(&wide).into_raw(), image.into_raw());
+    }
+
+    #[test]
+    fn test_adjustments() {
+        let identity = Adjustments::default().table();
+        for (i, &value) in identity.iter().enumerate() {
+            assert_eq!(i as u8, value);
+        }
+
+        let brighter = Adjustments { brightness: 10.0, ..Adjustments::default() }.table();
+        assert_eq!(brighter[0], 10);
+        assert_eq!(brighter[250], 255);
+
+        let contrast = Adjustments { contrast: 100.0, ..Adjustments::default() }.table();
+        assert_eq!(contrast[0], 0);
+        assert!(contrast[64] < 64);
+        assert!(contrast[192] > 192);
+
+        let lower = Adjustments { contrast: -50.0, ..Adjustments::default() }.table();
+        assert!(lower[0] > 0 && lower[64] > 64);
+        assert!(lower[192] < 192 && lower[255] < 255);
+        let flat = Adjustments { contrast: -100.0, ..Adjustments::default() }.table();
+        assert!(flat.iter().all(|&value| value == 128));
+        // Going further than -100 can't bring contrast back.
+        for &contrast in &[-200.0, -300.0] {
+            assert_eq!(&Adjustments { contrast, ..Adjustments::default() }.table()[..],
+                       &flat[..]);
+        }
+
+        let gamma = Adjustments { gamma: 2.0, ..Adjustments::default() }.table();
+        assert_eq!(gamma[0], 0);
+        assert_eq!(gamma[255], 255);
+        assert_eq!(gamma[64], 128);
+    }
+
+    #[test]
+    fn test_lut_lookup() {
+        let lut = Lut::parse(IDENTITY).unwrap();
+        for &color in &[[0.0, 0.0, 0.0], [0.25, 0.5, 0.75], [1.0, 1.0, 1.0]] {
+            let result = lut.lookup(color);
+            for c in 0..3 {
+                assert!((result[c] - color[c]).abs() < 1e-6,
+                        "{:?} mapped to {:?}",
+                        color,
+                        result);
+            }
+        }
+
+        let inverted = Lut::parse(&IDENTITY.replace("0", "x").replace("1", "0").replace("x", "1"))
+            .unwrap();
+        let result = inverted.lookup([0.25, 0.5, 0.75]);
+        assert!((result[0] - 0.75).abs() < 1e-6);
+        assert!((result[1] - 0.5).abs() < 1e-6);
+        assert!((result[2] - 0.25).abs() < 1e-6);
+    }
+}
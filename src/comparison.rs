@@ -0,0 +1,75 @@
+use image::{GenericImage, ImageBuffer, Rgba, RgbaImage};
+
+use std::path::{Path, PathBuf};
+
+/// The color of the gap between the two halves of a comparison.
+const BACKGROUND: Rgba<u8> = Rgba { data: [0, 0, 0, 255] };
+
+/// Which frame the result is compared against.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CompareWith {
+    /// The first input frame.
+    First,
+    /// The input frame halfway through the sequence.
+    Middle,
+    /// Any other image, which is scaled to fit the composite.
+    Path(PathBuf),
+}
+
+impl<'a> From<&'a str> for CompareWith {
+    fn from(s: &'a str) -> CompareWith {
+        match s {
+            "first" => CompareWith::First,
+            "middle" => CompareWith::Middle,
+            path => CompareWith::Path(path.into()),
+        }
+    }
+}
+
+/// Builds the path the comparison is saved to, next to the output, e.g. `out.compare.png` for
+/// `out.jpg`.
+pub fn comparison_path(output: &Path) -> PathBuf {
+    let stem = output.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    output.with_file_name(format!("{}.compare.png", stem))
+}
+
+/// Places the reference frame and the result side by side, reference on the left, with a small
+/// gap between them. Both are expected to have the same dimensions.
+pub fn side_by_side(reference: &RgbaImage, result: &RgbaImage) -> RgbaImage {
+    let (width, height) = result.dimensions();
+    let gap = ::std::cmp::max(4, width / 50);
+    let mut buf = ImageBuffer::from_pixel(2 * width + gap, height, BACKGROUND);
+    buf.copy_from(reference, 0, 0);
+    buf.copy_from(result, width + gap, 0);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_with() {
+        assert_eq!(CompareWith::from("first"), CompareWith::First);
+        assert_eq!(CompareWith::from("middle"), CompareWith::Middle);
+        assert_eq!(CompareWith::from("ref.png"), CompareWith::Path("ref.png".into()));
+    }
+
+    #[test]
+    fn test_comparison_path() {
+        assert_eq!(comparison_path(Path::new("renders/out.jpg")),
+                   PathBuf::from("renders/out.compare.png"));
+    }
+
+    #[test]
+    fn test_side_by_side() {
+        let reference = ImageBuffer::from_pixel(3, 2, Rgba([255, 0, 0, 255]));
+        let result = ImageBuffer::from_pixel(3, 2, Rgba([0, 0, 255, 255]));
+        let buf = side_by_side(&reference, &result);
+        assert_eq!(buf.dimensions(), (10, 2));
+        assert_eq!(buf.get_pixel(2, 1).data, [255, 0, 0, 255]);
+        assert_eq!(buf.get_pixel(3, 1).data, [0, 0, 0, 255]);
+        assert_eq!(buf.get_pixel(7, 0).data, [0, 0, 255, 255]);
+    }
+}
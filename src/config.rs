@@ -0,0 +1,395 @@
+use toml;
+
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use rolling_shutter::{ErrorKind, Result, ResultExt};
+
+/// The prefix of the environment variables that set options. It is followed by the option's long
+/// name in upper case with underscores, as in `ROLLING_SHUTTER_OUTPUT_SUFFIX`.
+const ENV_PREFIX: &str = "ROLLING_SHUTTER_";
+
+/// A value in a configuration file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    /// A quoted string.
+    String(String),
+    /// A whole number.
+    Integer(i64),
+    /// A number with a fraction or exponent.
+    Float(f64),
+    /// `true` or `false`.
+    Boolean(bool),
+    /// A list of values in square brackets.
+    Array(Vec<Value>),
+}
+
+/// The entries before any table header, and those under each `[[table]]` header, in order.
+pub type Document = (Vec<(String, Value)>, Vec<Vec<(String, Value)>>);
+
+/// Takes the options out of a table, in the order they are given. No option is a table, so any
+/// table in it is refused with `unsupported` as the reason.
+fn entries(table: toml::value::Table, unsupported: &str) -> Result<Vec<(String, Value)>> {
+    table.into_iter()
+        .map(|(key, value)| {
+            let nested = match value {
+                toml::Value::Table(_) => true,
+                toml::Value::Array(ref values) => values.iter().any(toml::Value::is_table),
+                _ => false,
+            };
+            if nested {
+                bail!(ErrorKind::InvalidConfig(unsupported.into()));
+            }
+            match value.try_into() {
+                Ok(value) => Ok((key, value)),
+                Err(_) => {
+                    bail!(ErrorKind::InvalidConfig(format!("'{}' can't hold a date", key)))
+                }
+            }
+        })
+        .collect()
+}
+
+/// Parses a TOML document of `key = value` pairs. If `table` is given, `[[table]]` headers start a
+/// new set of entries; no other tables are supported.
+fn parse_document(s: &str, table: Option<&str>) -> Result<Document> {
+    // Read as a `toml::Value` rather than straight into a table, which is what refuses keys given
+    // more than once.
+    let document = match toml::from_str(s).map_err(|e| ErrorKind::InvalidConfig(e.to_string()))? {
+        toml::Value::Table(document) => document,
+        _ => unreachable!("a TOML document is a table"),
+    };
+    let unsupported = match table {
+        Some(table) => format!("only [[{}]] tables are supported", table),
+        None => "tables aren't supported".into(),
+    };
+    let mut top = toml::value::Table::new();
+    let mut tables = vec![];
+    for (key, value) in document {
+        match value {
+            toml::Value::Array(values) if Some(key.as_str()) == table &&
+                                          values.iter().all(toml::Value::is_table) => {
+                for value in values {
+                    if let toml::Value::Table(job) = value {
+                        tables.push(entries(job, &unsupported)?);
+                    }
+                }
+            }
+            value => {
+                top.insert(key, value);
+            }
+        }
+    }
+    Ok((entries(top, &unsupported)?, tables))
+}
+
+/// Parses a configuration file: a flat TOML document of `key = value` pairs, with comments.
+/// Tables aren't supported, since every option lives at the top level.
+///
+/// # Errors
+/// This fails if the document isn't valid, or if a key is given twice.
+pub fn parse(s: &str) -> Result<Vec<(String, Value)>> {
+    parse_document(s, None).map(|(entries, _)| entries)
+}
+
+/// Parses a batch file: options shared by every job, followed by a `[[job]]` table for each job
+/// with its own options. Returns the shared options and each job's.
+///
+/// # Errors
+/// This fails if the document isn't valid, or if a key is given twice in the same job.
+pub fn parse_jobs(s: &str) -> Result<Document> {
+    parse_document(s, Some("job"))
+}
+
+fn read(path: &Path) -> Result<String> {
+    let mut s = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut s))
+        .chain_err(|| ErrorKind::CouldNotReadConfig(path.to_path_buf()))?;
+    Ok(s)
+}
+
+/// Reads and parses a configuration file.
+///
+/// # Errors
+/// This fails if the file cannot be read or isn't valid.
+pub fn load(path: &Path) -> Result<Vec<(String, Value)>> {
+    parse(&read(path)?).chain_err(|| ErrorKind::CouldNotReadConfig(path.to_path_buf()))
+}
+
+/// Reads and parses a batch file.
+///
+/// # Errors
+/// This fails if the file cannot be read or isn't valid.
+pub fn load_jobs(path: &Path) -> Result<Document> {
+    parse_jobs(&read(path)?).chain_err(|| ErrorKind::CouldNotReadConfig(path.to_path_buf()))
+}
+
+/// Turns an entry into the command line arguments it stands for: a flag for `true`, nothing for
+/// `false`, and an option for anything else, repeated for every element of an array.
+///
+/// # Errors
+/// This fails for arrays of booleans or nested arrays, which no option takes.
+pub fn to_args(key: &str, value: &Value) -> Result<Vec<String>> {
+    let scalar = |value: &Value| -> Result<String> {
+        match *value {
+            Value::String(ref s) => Ok(s.clone()),
+            Value::Integer(i) => Ok(i.to_string()),
+            Value::Float(f) => Ok(f.to_string()),
+            Value::Boolean(_) | Value::Array(_) => {
+                bail!(ErrorKind::InvalidConfig(format!("'{}' can't hold that kind of array", key)))
+            }
+        }
+    };
+    match *value {
+        Value::Boolean(true) => Ok(vec![format!("--{}", key)]),
+        Value::Boolean(false) => Ok(vec![]),
+        Value::Array(ref values) => {
+            values.iter().map(|value| Ok(format!("--{}={}", key, scalar(value)?))).collect()
+        }
+        _ => Ok(vec![format!("--{}={}", key, scalar(value)?)]),
+    }
+}
+
+/// Reads the options set by environment variables among `vars`. `true` and `false` turn flags on
+/// and off, and anything else is taken as the option's value. Variables that don't name an option
+/// `known` accepts, such as one set for another version or misspelled, are ignored with a warning.
+///
+/// # Errors
+/// This fails if a variable that sets an option isn't valid Unicode.
+pub fn from_env<I, F>(vars: I, known: F) -> Result<Vec<(String, Value)>>
+    where I: Iterator<Item = (OsString, OsString)>,
+          F: Fn(&str) -> bool
+{
+    let mut entries = vec![];
+    for (name, value) in vars {
+        let key = match name.to_str().and_then(|name| name.strip_prefix(ENV_PREFIX)) {
+            Some(key) if !key.is_empty() => key.to_lowercase().replace('_', "-"),
+            _ => continue,
+        };
+        if !known(&key) {
+            warn!("ignoring {}, as there is no --{} option.", name.to_string_lossy(), key);
+            continue;
+        }
+        let value = match value.into_string() {
+            Ok(ref value) if value == "true" => Value::Boolean(true),
+            Ok(ref value) if value == "false" => Value::Boolean(false),
+            Ok(value) => Value::String(value),
+            Err(_) => bail!(ErrorKind::InvalidEnvironmentVariable(name.to_string_lossy().into())),
+        };
+        entries.push((key, value));
+    }
+    // The environment is in no particular order, so errors about it would vary between runs.
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(entries)
+}
+
+/// Serializes entries as a document that `parse` reads back.
+pub fn to_toml(entries: &[(String, Value)]) -> String {
+    // Every value has a TOML form, and the keys are strings, so neither step can fail.
+    let table: toml::value::Table = entries.iter()
+        .map(|entry| (entry.0.clone(), toml::Value::try_from(&entry.1).unwrap()))
+        .collect();
+    toml::to_string(&table).unwrap()
+}
+
+/// Finds the directory presets are kept in, under the platform's user configuration directory.
+fn presets_dir() -> Result<PathBuf> {
+    let config_dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| Path::new(&home).join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    match config_dir {
+        Some(dir) => Ok(dir.join("rolling-shutter").join("presets")),
+        None => bail!(ErrorKind::NoConfigDir),
+    }
+}
+
+/// Reads and parses a named preset.
+///
+/// # Errors
+/// This fails if there is no preset with that name, or it cannot be read.
+pub fn load_preset(name: &str) -> Result<Vec<(String, Value)>> {
+    let path = presets_dir()?.join(format!("{}.toml", name));
+    if !path.is_file() {
+        bail!(ErrorKind::UnknownPreset(name.into()));
+    }
+    load(&path)
+}
+
+/// Saves entries as a named preset, replacing any preset with that name, and returns the path
+/// it was saved to.
+///
+/// # Errors
+/// This fails if the preset cannot be written.
+pub fn save_preset(name: &str, entries: &[(String, Value)]) -> Result<PathBuf> {
+    let dir = presets_dir()?;
+    let path = dir.join(format!("{}.toml", name));
+    fs::create_dir_all(&dir)
+        .and_then(|_| File::create(&path))
+        .and_then(|mut file| file.write_all(to_toml(entries).as_bytes()))
+        .chain_err(|| ErrorKind::CouldNotWritePreset(path.clone()))?;
+    Ok(path)
+}
+
+/// Lists the names of every saved preset, in sorted order.
+///
+/// # Errors
+/// This fails if the presets directory exists but cannot be read.
+pub fn list_presets() -> Result<Vec<String>> {
+    let dir = presets_dir()?;
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut names = vec![];
+    for entry in fs::read_dir(&dir).chain_err(|| ErrorKind::CouldNotReadConfig(dir.clone()))? {
+        let path = entry.chain_err(|| ErrorKind::CouldNotReadConfig(dir.clone()))?.path();
+        if path.extension() == Some(OsStr::new("toml")) {
+            if let Some(stem) = path.file_stem() {
+                names.push(stem.to_string_lossy().into_owned());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let document = r#"
+# A comment on its own line.
+input = "frames/%03d.png"
+direction='W'  # A comment after a value.
+output = [
+    "out.png",
+    "out.jpg",
+]
+brightness = -10
+scale = 0.5
+stabilize = true
+"quoted-key" = "tab\there é"
+jpeg-quality = 1_00
+"#;
+        let entries = parse(document).unwrap();
+        let expected = vec![("input", Value::String("frames/%03d.png".into())),
+                            ("direction", Value::String("W".into())),
+                            ("output",
+                             Value::Array(vec![Value::String("out.png".into()),
+                                               Value::String("out.jpg".into())])),
+                            ("brightness", Value::Integer(-10)),
+                            ("scale", Value::Float(0.5)),
+                            ("stabilize", Value::Boolean(true)),
+                            ("quoted-key", Value::String("tab\there \u{e9}".into())),
+                            ("jpeg-quality", Value::Integer(100))];
+        assert_eq!(entries.len(), expected.len());
+        for (entry, expected) in entries.iter().zip(&expected) {
+            assert_eq!(entry.0, expected.0);
+            assert_eq!(entry.1, expected.1);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for document in &["[render]\ndirection = \"N\"",
+                          "direction \"N\"",
+                          "direction = \"N",
+                          "direction = N",
+                          "direction = \"N\" \"S\"",
+                          "output = [\"a\" \"b\"]",
+                          "scale = 1\nscale = 2"] {
+            assert!(parse(document).is_err(), "{:?} should not parse", document);
+        }
+
+        match *parse("\n\ndirection = ").unwrap_err().kind() {
+            ErrorKind::InvalidConfig(ref reason) => {
+                assert!(reason.contains("line 3"), "{}", reason)
+            }
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_parse_jobs() {
+        let document = r#"
+input = "frames/%03d.png"
+
+[[job]]
+direction = "N"
+output = "n.png"
+
+[[job]]  # A comment after a header.
+direction = "W"
+"#;
+        let (shared, jobs) = parse_jobs(document).unwrap();
+        assert_eq!(shared, vec![("input".into(), Value::String("frames/%03d.png".into()))]);
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0],
+                   vec![("direction".into(), Value::String("N".into())),
+                        ("output".into(), Value::String("n.png".into()))]);
+        assert_eq!(jobs[1], vec![("direction".into(), Value::String("W".into()))]);
+
+        assert!(parse_jobs("[[jobs]]\n").is_err());
+        assert!(parse_jobs("[job]\n").is_err());
+        assert!(parse_jobs("[[job]] x = 1\n").is_err());
+        assert!(parse_jobs("[[job]]\nx = 1\nx = 2\n").is_err());
+        assert!(parse_jobs("[[job]]\n[job.render]\n").is_err());
+        assert!(parse("[[job]]\n").is_err());
+    }
+
+    #[test]
+    fn test_from_env() {
+        let vars = vec![("ROLLING_SHUTTER_OUTPUT_SUFFIX", "true"),
+                        ("PATH", "/usr/bin"),
+                        ("ROLLING_SHUTTER_DIRECTION", "W"),
+                        ("ROLLING_SHUTTER_STABILIZE", "false"),
+                        ("ROLLING_SHUTTER_", "x"),
+                        ("ROLLING_SHUTTER_HOME", "/opt/rolling-shutter")];
+        let vars = vars.into_iter().map(|(name, value)| (name.into(), value.into()));
+        let known = |key: &str| key != "home";
+        assert_eq!(from_env(vars, known).unwrap(),
+                   vec![("direction".into(), Value::String("W".into())),
+                        ("output-suffix".into(), Value::Boolean(true)),
+                        ("stabilize".into(), Value::Boolean(false))]);
+    }
+
+    #[test]
+    fn test_to_args() {
+        assert_eq!(to_args("stabilize", &Value::Boolean(true)).unwrap(), vec!["--stabilize"]);
+        assert!(to_args("stabilize", &Value::Boolean(false)).unwrap().is_empty());
+        assert_eq!(to_args("brightness", &Value::Integer(-10)).unwrap(),
+                   vec!["--brightness=-10"]);
+        assert_eq!(to_args("scale", &Value::Float(0.5)).unwrap(), vec!["--scale=0.5"]);
+        let outputs = Value::Array(vec![Value::String("a.png".into()),
+                                        Value::String("b.jpg".into())]);
+        assert_eq!(to_args("output", &outputs).unwrap(),
+                   vec!["--output=a.png", "--output=b.jpg"]);
+        assert!(to_args("output", &Value::Array(vec![Value::Boolean(true)])).is_err());
+    }
+
+    #[test]
+    fn test_to_toml() {
+        let entries = vec![("direction".to_string(), Value::String("W".into())),
+                           ("dither-bands".to_string(), Value::String("a \"b\"\\c\n".into())),
+                           ("stabilize".to_string(), Value::Boolean(true)),
+                           ("scale".to_string(), Value::Float(1.0)),
+                           ("output".to_string(),
+                            Value::Array(vec![Value::String("a.png".into()),
+                                              Value::Integer(2)]))];
+        let document = to_toml(&entries);
+        assert_eq!(document.lines().next(), Some("direction = \"W\""));
+        assert_eq!(parse(&document).unwrap(), entries);
+    }
+}
@@ -0,0 +1,96 @@
+use image::{GenericImage, ImageBuffer, Rgba, RgbaImage};
+
+use std::path::PathBuf;
+
+use ::debug_overlay;
+use ::errors::Result;
+use ::frame_info::FrameInfo;
+use ::frame_processing::{self, FrameTransforms, ResizePolicy};
+use ::progress::ProgressSink;
+
+/// The color behind and between thumbnails.
+const BACKGROUND: Rgba<u8> = Rgba { data: [32, 32, 32, 255] };
+
+/// The space between thumbnails, and around the edge of the sheet.
+const GAP: u32 = 4;
+
+/// The phase progress through the frames is reported under.
+const BUILDING: &str = "Building contact sheet:";
+
+/// Computes the size of each thumbnail, keeping the first frame's aspect ratio.
+fn thumbnail_size(frame: (u32, u32), thumb_width: u32) -> (u32, u32) {
+    let height = (frame.1 as u64 * thumb_width as u64 + frame.0 as u64 / 2) / frame.0 as u64;
+    (thumb_width, ::std::cmp::max(height, 1) as u32)
+}
+
+/// Computes the top left corner of the `index`th thumbnail in a grid with the given number of
+/// columns.
+fn cell_origin(index: u32, columns: u32, thumb: (u32, u32)) -> (u32, u32) {
+    (GAP + (index % columns) * (thumb.0 + GAP), GAP + (index / columns) * (thumb.1 + GAP))
+}
+
+/// Builds a grid of thumbnails of every frame, in order, each labelled with its index, so that the
+/// ordering and content of a sequence can be checked before rendering it.
+///
+/// # Arguments
+/// * `paths` - The frame paths, in order. There must be at least one.
+/// * `transforms` - The transformations to apply to each frame before it is shrunk.
+/// * `columns` - How many thumbnails go in each row.
+/// * `thumb_width` - The width of each thumbnail. Their height follows the first frame's aspect
+///   ratio, and frames of other sizes are fit inside it.
+/// * `progress` - Where to report progress through the frames.
+///
+/// # Errors
+/// This fails if any frame cannot be opened or transformed.
+pub fn build(paths: &[PathBuf],
+             transforms: &FrameTransforms,
+             columns: u32,
+             thumb_width: u32,
+             progress: &mut dyn ProgressSink)
+             -> Result<RgbaImage> {
+    let count = paths.len() as u32;
+    let rows = count.div_ceil(columns);
+    let columns = ::std::cmp::min(columns, count);
+
+    let mut sheet = None;
+    let mut thumb = (0, 0);
+    for (i, path) in paths.iter().enumerate() {
+        progress.start(BUILDING, i as u64, count as u64, &FrameInfo::read(i, path));
+        let frame = frame_processing::load_frame(path, transforms)?;
+        let sheet = sheet.get_or_insert_with(|| {
+            thumb = thumbnail_size(frame.dimensions(), thumb_width);
+            ImageBuffer::from_pixel(GAP + columns * (thumb.0 + GAP),
+                                    GAP + rows * (thumb.1 + GAP),
+                                    BACKGROUND)
+        });
+
+        let thumbnail = frame_processing::conform_frame(frame, thumb, ResizePolicy::Fit, path)?;
+        let (x, y) = cell_origin(i as u32, columns, thumb);
+        sheet.copy_from(&thumbnail.to_rgba(), x, y);
+        debug_overlay::draw_number(sheet, i as u32, x as i64 + 2, y as i64 + 2, 1);
+
+        progress.done(BUILDING, i as u64 + 1, count as u64);
+    }
+    progress.finish(BUILDING);
+
+    Ok(sheet.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thumbnail_size() {
+        assert_eq!(thumbnail_size((1920, 1080), 160), (160, 90));
+        assert_eq!(thumbnail_size((1080, 1920), 160), (160, 284));
+        assert_eq!(thumbnail_size((10000, 1), 160), (160, 1));
+    }
+
+    #[test]
+    fn test_cell_origin() {
+        assert_eq!(cell_origin(0, 3, (10, 5)), (4, 4));
+        assert_eq!(cell_origin(2, 3, (10, 5)), (32, 4));
+        assert_eq!(cell_origin(3, 3, (10, 5)), (4, 13));
+    }
+}
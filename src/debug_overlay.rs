@@ -0,0 +1,232 @@
+use image::RgbaImage;
+
+use std::path::{Path, PathBuf};
+
+use ::Direction;
+use ::image_processing::{band_lines, line_to_coords};
+
+/// The color ticks, labels and the direction arrow are drawn in.
+const INK: [u8; 3] = [255, 0, 255];
+
+/// The color drawn behind labels so they stay legible.
+const SHADOW: [u8; 3] = [0, 0, 0];
+
+/// A 3x5 pixel font for the digits 0 to 9. Each entry is a row, with the leftmost pixel in the
+/// highest of the three bits.
+const DIGITS: [[u8; 5]; 10] = [[0b111, 0b101, 0b101, 0b101, 0b111],
+                               [0b010, 0b110, 0b010, 0b010, 0b111],
+                               [0b111, 0b001, 0b111, 0b100, 0b111],
+                               [0b111, 0b001, 0b111, 0b001, 0b111],
+                               [0b101, 0b101, 0b111, 0b001, 0b001],
+                               [0b111, 0b100, 0b111, 0b001, 0b111],
+                               [0b111, 0b100, 0b111, 0b101, 0b111],
+                               [0b111, 0b001, 0b001, 0b001, 0b001],
+                               [0b111, 0b101, 0b111, 0b101, 0b111],
+                               [0b111, 0b101, 0b111, 0b001, 0b111]];
+
+/// The most labelled ticks drawn along the shutter's path.
+const MAX_TICKS: u32 = 20;
+
+/// Builds the path the debug overlay is saved to, next to the output, e.g. `out.debug.png` for
+/// `out.jpg`. The overlay is always saved as a PNG so that it stays sharp.
+pub fn debug_path(output: &Path) -> PathBuf {
+    let stem = output.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    output.with_file_name(format!("{}.debug.png", stem))
+}
+
+/// Picks how many frames apart labelled ticks are, from 1, 2, 5, 10, 20, 50 and so on, so that
+/// there are at most `MAX_TICKS` of them.
+fn tick_interval(num_frames: u32) -> u32 {
+    let mut magnitude = 1u32;
+    loop {
+        for &step in &[1, 2, 5] {
+            let interval = magnitude.saturating_mul(step);
+            if interval.saturating_mul(MAX_TICKS) >= num_frames {
+                return interval;
+            }
+        }
+        magnitude = magnitude.saturating_mul(10);
+    }
+}
+
+/// Blends a color over a single pixel, ignoring coordinates outside the image.
+fn blend(buf: &mut RgbaImage, x: i64, y: i64, color: [u8; 3], alpha: f32) {
+    let (width, height) = buf.dimensions();
+    if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+        return;
+    }
+    let pixel = buf.get_pixel_mut(x as u32, y as u32);
+    for (channel, &color) in pixel.data.iter_mut().zip(&color) {
+        let value = color as f32 * alpha + *channel as f32 * (1.0 - alpha);
+        *channel = value.round() as u8;
+    }
+    pixel.data[3] = ::std::cmp::max(pixel.data[3], (alpha * 255.0).round() as u8);
+}
+
+/// Fills a `size` by `size` square whose top left corner is at `(x, y)`.
+fn fill_square(buf: &mut RgbaImage, x: i64, y: i64, size: i64, color: [u8; 3], alpha: f32) {
+    for dy in 0..size {
+        for dx in 0..size {
+            blend(buf, x + dx, y + dy, color, alpha);
+        }
+    }
+}
+
+/// The width in pixels of a number drawn at the given scale.
+fn number_width(value: u32, scale: i64) -> i64 {
+    (value.to_string().len() as i64 * 4 - 1) * scale
+}
+
+/// Draws a number on a dark backing, with its top left corner at `(x, y)`.
+pub fn draw_number(buf: &mut RgbaImage, value: u32, x: i64, y: i64, scale: i64) {
+    let width = number_width(value, scale);
+    for dy in -scale..6 * scale {
+        for dx in -scale..width + scale {
+            blend(buf, x + dx, y + dy, SHADOW, 0.5);
+        }
+    }
+
+    for (i, digit) in value.to_string().bytes().enumerate() {
+        let glyph = DIGITS[(digit - b'0') as usize];
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                fill_square(buf,
+                            x + (i as i64 * 4 + column) * scale,
+                            y + row as i64 * scale,
+                            scale,
+                            INK,
+                            1.0);
+            }
+        }
+    }
+}
+
+/// Draws an arrow through the middle of the image, pointing the way the shutter travels.
+fn draw_arrow(buf: &mut RgbaImage, direction: Direction, scale: i64) {
+    let (width, height) = buf.dimensions();
+    let (cx, cy) = (width as i64 / 2, height as i64 / 2);
+    // The shutter travels away from the side it starts at.
+    let (ux, uy) = match direction {
+        Direction::N => (0, 1),
+        Direction::S => (0, -1),
+        Direction::W => (1, 0),
+        Direction::E => (-1, 0),
+    };
+    let half = ::std::cmp::min(width, height) as i64 / 8;
+    let offset = scale / 2;
+
+    for t in -half..half + 1 {
+        fill_square(buf, cx + ux * t - offset, cy + uy * t - offset, scale, INK, 1.0);
+    }
+
+    // Two strokes going back from the tip at 45 degrees. Since the arrow is axis-aligned, swapping
+    // its components gives a perpendicular.
+    let (tip_x, tip_y) = (cx + ux * half, cy + uy * half);
+    for t in 0..half / 2 + 1 {
+        for &side in &[-1, 1] {
+            fill_square(buf,
+                        tip_x - ux * t + uy * t * side - offset,
+                        tip_y - uy * t + ux * t * side - offset,
+                        scale,
+                        INK,
+                        1.0);
+        }
+    }
+}
+
+/// Draws the debug overlay onto a composite: a faint line at the start of every few bands, with a
+/// solid tick at either end and the index of the frame the band came from, and an arrow showing
+/// which way the shutter travels.
+///
+/// `lines` is how many lines the bands were spread over, which is more than `num_frames` if they
+/// were stretched. `frames_per_line` is how many input frames were blended into each band's frame,
+/// so that labels show the index of the first input frame.
+pub fn draw(buf: &mut RgbaImage,
+            direction: Direction,
+            num_frames: u32,
+            lines: u32,
+            frames_per_line: u32) {
+    let (width, height) = buf.dimensions();
+    let bounds = (0, 0, width, height);
+    let cross_len = match direction {
+        Direction::N | Direction::S => width,
+        Direction::E | Direction::W => height,
+    };
+    let scale = ::std::cmp::max(1, ::std::cmp::min(width, height) / 240) as i64;
+    let tick_len = 6 * scale as u32;
+    let interval = tick_interval(num_frames);
+
+    for band in (0..num_frames).filter(|band| band % interval == 0) {
+        let line = band_lines(band, num_frames, lines).start;
+        for cross in 0..cross_len {
+            let (x, y) = line_to_coords(bounds, line, cross, direction);
+            let alpha = if cross < tick_len || cross >= cross_len.saturating_sub(tick_len) {
+                1.0
+            } else {
+                0.3
+            };
+            blend(buf, x as i64, y as i64, INK, alpha);
+        }
+
+        // The label sits just past the tick, on the side of the line its band covers.
+        let label_cross = ::std::cmp::min(tick_len + 2 * scale as u32, cross_len.saturating_sub(1));
+        let (x, y) = line_to_coords(bounds, line, label_cross, direction);
+        let (x, y) = (x as i64, y as i64);
+        let label = band.saturating_mul(frames_per_line);
+        let (x, y) = match direction {
+            Direction::N => (x, y + scale),
+            Direction::S => (x, y - 6 * scale),
+            Direction::W => (x + 2 * scale, y),
+            Direction::E => (x - 2 * scale - number_width(label, scale), y),
+        };
+        draw_number(buf, label, x, y, scale);
+    }
+
+    draw_arrow(buf, direction, scale);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{ImageBuffer, Rgba};
+
+    #[test]
+    fn test_tick_interval() {
+        assert_eq!(tick_interval(0), 1);
+        assert_eq!(tick_interval(20), 1);
+        assert_eq!(tick_interval(21), 2);
+        assert_eq!(tick_interval(100), 5);
+        assert_eq!(tick_interval(480), 50);
+        assert_eq!(tick_interval(1080), 100);
+    }
+
+    #[test]
+    fn test_debug_path() {
+        assert_eq!(debug_path(Path::new("renders/out.jpg")),
+                   PathBuf::from("renders/out.debug.png"));
+        assert_eq!(debug_path(Path::new("out")), PathBuf::from("out.debug.png"));
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut buf: RgbaImage = ImageBuffer::from_pixel(50, 40, Rgba([0, 0, 0, 255]));
+        draw(&mut buf, Direction::N, 40, 40, 1);
+        // Every other line starts a labelled band, with a solid tick at the edge.
+        assert_eq!(buf.get_pixel(0, 0).data, [255, 0, 255, 255]);
+        assert_eq!(buf.get_pixel(0, 1).data, [0, 0, 0, 255]);
+        assert_eq!(buf.get_pixel(0, 2).data, [255, 0, 255, 255]);
+        // Away from the ticks, the line is only tinted.
+        let faint = buf.get_pixel(30, 2).data;
+        assert!(faint[0] > 0 && faint[0] < 255);
+
+        // Tiny images shouldn't cause anything to be drawn out of bounds.
+        for &direction in &[Direction::N, Direction::E, Direction::S, Direction::W] {
+            let mut tiny: RgbaImage = ImageBuffer::new(1, 1);
+            draw(&mut tiny, direction, 1, 1, 3);
+        }
+    }
+}
@@ -1,49 +1,611 @@
 use image;
 
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
 use std::path::PathBuf;
 
-error_chain! {
-    foreign_links {
-        Image(image::ImageError);
-        Io(::std::io::Error);
-    }
+use ::frame_info::FrameInfo;
 
-    errors {
-        Unimplemented {
-            description("unimplemented")
-            display("The desired operation on line {} of {} is unimplemented.", line!(), file!())
-        }
-        CouldNotOpenImage(filename: PathBuf) {
-            description("could not open image")
-            display("Could not open image {}.", filename.display())
-        }
-        CouldNotProcessImage(filename: PathBuf) {
-            description("could not process image")
-            display("Could not process image {}.", filename.display())
-        }
-        CouldNotSaveOutput(filename: PathBuf) {
-            description("could not save image")
-            display("Could not save image {}.", filename.display())
-        }
-        CouldNotParseFilemask(mask: String) {
-            description("could not parse file mask")
-            display("Could not parse file mask '{}'.", mask)
-        }
-        CouldNotGetPaths {
-            description("could not get file paths")
-            display("Could not get file paths to process.")
-        }
-        NoFileMaskFound {
-            description("could not find file mask")
-            display("Could not find file mask.")
-        }
-        NoFilesFound {
-            description("could not find any files")
-            display("Could not find any files with the provided file mask or folder.")
+/// Returns early from a function with an error of the given kind.
+#[macro_export]
+macro_rules! bail {
+    ($kind:expr) => {
+        return Err($kind.into())
+    };
+}
+
+/// Everything that can go wrong. Each kind carries what is needed to explain itself: the paths,
+/// dimensions and counts involved.
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// Something that is not implemented yet: the feature.
+    Unimplemented(&'static str),
+    /// Could not open image: the filename.
+    CouldNotOpenImage(PathBuf),
+    /// Could not process image: the filename.
+    CouldNotProcessImage(PathBuf),
+    /// A frame failed to load or go through the frame stages: where it came from.
+    Frame(FrameInfo),
+    /// Frame has the wrong dimensions: `(filename, expected, actual)`.
+    FrameSizeMismatch(PathBuf, (u32, u32), (u32, u32)),
+    /// Image file is empty: the filename.
+    EmptyFile(PathBuf),
+    /// Unsupported image format: the filename.
+    UnsupportedFormat(PathBuf),
+    /// Frame has more than 8 bits per channel: `(filename, bits)`.
+    UnsupportedFrameDepth(PathBuf, u8),
+    /// Some frames are invalid: the problems.
+    InvalidFrames(Vec<String>),
+    /// Sequence would not render cleanly: the problems.
+    CheckFailed(Vec<String>),
+    /// Frames have no region in common.
+    NoCommonRegion,
+    /// Invalid ICC profile: the reason.
+    InvalidIccProfile(String),
+    /// Output format does not support bit depth: `(filename, bits)`.
+    UnsupportedBitDepth(PathBuf, u8),
+    /// Output is too large to animate: `(filename, dimensions)`.
+    AnimationTooLarge(PathBuf, (u32, u32)),
+    /// Unsupported animation format: the filename.
+    UnsupportedAnimationFormat(PathBuf),
+    /// Could not run ffmpeg.
+    CouldNotRunFfmpeg,
+    /// Ffmpeg failed: the code.
+    FfmpegFailed(Option<i32>),
+    /// Could not save image: the filename.
+    CouldNotSaveOutput(PathBuf),
+    /// Output already exists: the filename.
+    OutputExists(PathBuf),
+    /// Could not write report: the filename.
+    CouldNotWriteReport(PathBuf),
+    /// Could not handle interrupts.
+    CouldNotHandleInterrupts,
+    /// Render interrupted: `(partial, checkpoint)`.
+    Interrupted(PathBuf, PathBuf),
+    /// Render in strips interrupted: the partial output.
+    InterruptedStrips(PathBuf),
+    /// Invalid checkpoint: the filename.
+    InvalidCheckpoint(PathBuf),
+    /// Checkpoint is for a different input: `(expected, actual)`.
+    CheckpointMismatch(usize, usize),
+    /// Could not write checkpoint: the filename.
+    CouldNotWriteCheckpoint(PathBuf),
+    /// Preview window not available.
+    PreviewUnavailable,
+    /// Could not open preview window.
+    CouldNotOpenPreview,
+    /// Render aborted from the preview.
+    PreviewAborted,
+    /// Invalid preview options: the reason.
+    InvalidPreview(String),
+    /// Terminal UI not available.
+    TuiUnavailable,
+    /// Could not run terminal UI.
+    CouldNotRunTui,
+    /// GPU compositing not available.
+    GpuUnavailable,
+    /// Could not composite on the GPU: the reason.
+    CouldNotUseGpu(String),
+    /// Invalid batch job: the reason.
+    InvalidJob(String),
+    /// Some batch jobs failed: `(failed, total)`.
+    BatchFailed(usize, usize),
+    /// Could not read config file: the filename.
+    CouldNotReadConfig(PathBuf),
+    /// Invalid config file: the reason.
+    InvalidConfig(String),
+    /// Invalid environment variable: the name.
+    InvalidEnvironmentVariable(String),
+    /// No user configuration directory.
+    NoConfigDir,
+    /// Unknown preset: the name.
+    UnknownPreset(String),
+    /// Could not write preset: the filename.
+    CouldNotWritePreset(PathBuf),
+    /// Could not parse file mask: the mask.
+    CouldNotParseFilemask(String),
+    /// Could not parse region: the region.
+    CouldNotParseRegion(String),
+    /// Could not parse size: the size.
+    CouldNotParseSize(String),
+    /// Could not parse direction: the direction.
+    CouldNotParseDirection(String),
+    /// Could not parse an option's value: `(option, value, expected)`.
+    CouldNotParseOption(&'static str, String, &'static str),
+    /// Crop region is out of bounds: `(filename, dimensions)`.
+    CropOutOfBounds(PathBuf, (u32, u32)),
+    /// Could not open LUT: the filename.
+    CouldNotOpenLut(PathBuf),
+    /// Invalid LUT: the reason.
+    InvalidLut(String),
+    /// Could not get file paths.
+    CouldNotGetPaths,
+    /// Could not find file mask.
+    NoFileMaskFound,
+    /// Option needs a cardinal direction: the option.
+    UnsupportedByGeometry(&'static str),
+    /// Options can't be used together: the options.
+    IncompatibleOptions(&'static str, &'static str),
+    /// No frames given.
+    NoFrames,
+    /// No outputs given.
+    NoOutputs,
+    /// Frame stream ended early: `(found, expected)`.
+    StreamEnded(usize, usize),
+    /// Could not find any files: `(tried, total)`.
+    NoFilesFound(Vec<PathBuf>, usize),
+    /// Too many file masks.
+    MultipleFileMasks,
+    /// An image could not be decoded or encoded.
+    Image(image::ImageError),
+    /// Reading or writing failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::Unimplemented(ref feature) => {
+                write!(f, "{} is not implemented yet.", feature)
+            }
+            ErrorKind::CouldNotOpenImage(ref filename) => {
+                write!(f, "Could not open image {}.", filename.display())
+            }
+            ErrorKind::CouldNotProcessImage(ref filename) => {
+                write!(f, "Could not process image {}.", filename.display())
+            }
+            ErrorKind::Frame(ref frame) => write!(f, "Failed on {}.", frame),
+            ErrorKind::FrameSizeMismatch(ref filename, ref expected, ref actual) => {
+                write!(f,
+                       "Image {} is {}x{}, but the first frame is {}x{}.",
+                       filename.display(),
+                       actual.0,
+                       actual.1,
+                       expected.0,
+                       expected.1)
+            }
+            ErrorKind::EmptyFile(ref filename) => {
+                write!(f, "Image {} is empty.", filename.display())
+            }
+            ErrorKind::UnsupportedFormat(ref filename) => {
+                write!(f, "Image {} is not in a supported format.", filename.display())
+            }
+            ErrorKind::UnsupportedFrameDepth(ref filename, bits) => {
+                write!(f,
+                       "Frame {} has {} bits per channel, but only 8-bit frames can be rendered.",
+                       filename.display(),
+                       bits)
+            }
+            ErrorKind::InvalidFrames(ref problems) => {
+                write!(f,
+                       "{} frame(s) failed validation:\n  {}",
+                       problems.len(),
+                       problems.join("\n  "))
+            }
+            ErrorKind::CheckFailed(ref problems) => {
+                write!(f,
+                       "The sequence wouldn't render cleanly; found {} problem(s):\n  {}",
+                       problems.len(),
+                       problems.join("\n  "))
+            }
+            ErrorKind::NoCommonRegion => {
+                write!(f,
+                       "The stabilized frames do not have any region in common to crop to.")
+            }
+            ErrorKind::InvalidIccProfile(ref reason) => {
+                write!(f, "Invalid ICC profile: {}.", reason)
+            }
+            ErrorKind::UnsupportedBitDepth(ref filename, ref bits) => {
+                write!(f,
+                       "Cannot save {} with {} bits per channel; use a .png or .tiff output \
+                        instead.",
+                       filename.display(),
+                       bits)
+            }
+            ErrorKind::AnimationTooLarge(ref filename, ref dimensions) => {
+                write!(f,
+                       "Cannot save animation {}; GIFs can be at most 65535x65535, but the output \
+                        is {}x{}. Try a .png animation or --animate-video instead.",
+                       filename.display(),
+                       dimensions.0,
+                       dimensions.1)
+            }
+            ErrorKind::UnsupportedAnimationFormat(ref filename) => {
+                write!(f,
+                       "Cannot save animation {}; use a .gif, .png, or .webp extension.",
+                       filename.display())
+            }
+            ErrorKind::CouldNotRunFfmpeg => {
+                write!(f,
+                       "Could not run ffmpeg; make sure it is installed and on your PATH.")
+            }
+            ErrorKind::FfmpegFailed(ref code) => {
+                write!(f,
+                       "ffmpeg failed to encode the animation{}.",
+                       code.map(|code| format!(" (exit code {})", code)).unwrap_or_default())
+            }
+            ErrorKind::CouldNotSaveOutput(ref filename) => {
+                write!(f, "Could not save image {}.", filename.display())
+            }
+            ErrorKind::OutputExists(ref filename) => {
+                write!(f,
+                       "{} already exists; use --force to overwrite it, or --output-suffix to save \
+                        to a new name.",
+                       filename.display())
+            }
+            ErrorKind::CouldNotWriteReport(ref filename) => {
+                write!(f, "Could not write report {}.", filename.display())
+            }
+            ErrorKind::CouldNotHandleInterrupts => write!(f, "Could not set up Ctrl-C handling."),
+            ErrorKind::Interrupted(ref partial, ref checkpoint) => {
+                write!(f,
+                       "Interrupted; saved the bands done so far to {} and a checkpoint to {}.",
+                       partial.display(),
+                       checkpoint.display())
+            }
+            ErrorKind::InterruptedStrips(ref partial) => {
+                write!(f,
+                       "Interrupted; saved the strips done so far to {}. Renders in strips can't \
+                        be resumed.",
+                       partial.display())
+            }
+            ErrorKind::InvalidCheckpoint(ref filename) => {
+                write!(f, "{} is not a checkpoint from an interrupted render.", filename.display())
+            }
+            ErrorKind::CheckpointMismatch(ref expected, ref actual) => {
+                write!(f,
+                       "The checkpoint is for a render of {} frames, but {} were found; resume \
+                        with the same input.",
+                       expected,
+                       actual)
+            }
+            ErrorKind::CouldNotWriteCheckpoint(ref filename) => {
+                write!(f, "Could not write checkpoint {}.", filename.display())
+            }
+            ErrorKind::PreviewUnavailable => {
+                write!(f,
+                       "This build can't open a preview window; rebuild with --features preview.")
+            }
+            ErrorKind::CouldNotOpenPreview => write!(f, "Could not open the preview window."),
+            ErrorKind::PreviewAborted => {
+                write!(f,
+                       "Aborted from the preview window; nothing was saved.")
+            }
+            ErrorKind::InvalidPreview(ref reason) => write!(f, "Invalid preview: {}", reason),
+            ErrorKind::TuiUnavailable => {
+                write!(f,
+                       "This build has no terminal UI; rebuild with --features tui.")
+            }
+            ErrorKind::CouldNotRunTui => write!(f, "Could not run the terminal UI."),
+            ErrorKind::GpuUnavailable => {
+                write!(f, "This build can't composite on the GPU; rebuild with --features gpu.")
+            }
+            ErrorKind::CouldNotUseGpu(ref reason) => {
+                write!(f, "Could not composite on the GPU: {}", reason)
+            }
+            ErrorKind::InvalidJob(ref reason) => write!(f, "Invalid job: {}", reason),
+            ErrorKind::BatchFailed(ref failed, ref total) => {
+                write!(f, "{} of {} jobs failed.", failed, total)
+            }
+            ErrorKind::CouldNotReadConfig(ref filename) => {
+                write!(f, "Could not read config file {}.", filename.display())
+            }
+            ErrorKind::InvalidConfig(ref reason) => write!(f, "Invalid config file: {}.", reason),
+            ErrorKind::InvalidEnvironmentVariable(ref name) => {
+                write!(f, "Environment variable {} is not valid Unicode.", name)
+            }
+            ErrorKind::NoConfigDir => {
+                write!(f,
+                       "Could not find a user configuration directory to keep presets in.")
+            }
+            ErrorKind::UnknownPreset(ref name) => {
+                write!(f,
+                       "There is no preset named '{}'; use --list-presets to see the saved ones.",
+                       name)
+            }
+            ErrorKind::CouldNotWritePreset(ref filename) => {
+                write!(f, "Could not write preset {}.", filename.display())
+            }
+            ErrorKind::CouldNotParseFilemask(ref mask) => {
+                write!(f,
+                       "Could not parse file mask '{}'. A file mask needs exactly one variable for \
+                        the frame number, such as %03d for frames numbered 000, 001, 002 and so \
+                        on, as in frames/%03d.png, or %3d for numbers that aren't zero-padded.",
+                       mask)
+            }
+            ErrorKind::CouldNotParseRegion(ref region) => {
+                write!(f,
+                       "Could not parse region '{}'; expected the form x,y,width,height.",
+                       region)
+            }
+            ErrorKind::CouldNotParseSize(ref size) => {
+                write!(f, "Could not parse size '{}'; expected the form WIDTHxHEIGHT.", size)
+            }
+            ErrorKind::CouldNotParseDirection(ref direction) => {
+                write!(f,
+                       "Could not parse direction '{}'; expected N, E, S or W, or north, east, \
+                        south or west.",
+                       direction)
+            }
+            ErrorKind::CouldNotParseOption(option, ref value, expected) => {
+                write!(f, "Could not parse {} '{}'; expected {}.", option, value, expected)
+            }
+            ErrorKind::CropOutOfBounds(ref filename, ref dimensions) => {
+                write!(f,
+                       "The crop region does not fit inside image {}, which is {}x{}.",
+                       filename.display(),
+                       dimensions.0,
+                       dimensions.1)
+            }
+            ErrorKind::CouldNotOpenLut(ref filename) => {
+                write!(f, "Could not open LUT {}.", filename.display())
+            }
+            ErrorKind::InvalidLut(ref reason) => write!(f, "Invalid LUT: {}.", reason),
+            ErrorKind::CouldNotGetPaths => write!(f, "Could not get file paths to process."),
+            ErrorKind::NoFileMaskFound => write!(f, "Could not find file mask."),
+            ErrorKind::UnsupportedByGeometry(ref option) => {
+                write!(f,
+                       "Cannot use {} with a custom geometry, only with the cardinal directions.",
+                       option)
+            }
+            ErrorKind::IncompatibleOptions(first, second) => {
+                write!(f, "Cannot use {} together with {}.", first, second)
+            }
+            ErrorKind::NoFrames => write!(f, "No frames were given to render."),
+            ErrorKind::NoOutputs => write!(f, "No outputs were given to save the render to."),
+            ErrorKind::StreamEnded(found, expected) => {
+                write!(f,
+                       "The frame stream ended after {} frames, but {} were expected.",
+                       found,
+                       expected)
+            }
+            ErrorKind::NoFilesFound(ref tried, ref total) => {
+                write!(f,
+                       "Could not find any files with the provided file mask or folder; tried {}. \
+                        Check the directory, and that the mask's padding matches the file names.",
+                       describe_candidates(tried, *total))
+            }
+            ErrorKind::MultipleFileMasks => {
+                write!(f,
+                       "Only one sequential file mask variable is allowed.")
+            }
+            ErrorKind::Image(ref e) => write!(f, "{}", e),
+            ErrorKind::Io(ref e) => write!(f, "{}", e),
         }
-        MultipleFileMasks {
-            description("too many file masks")
-            display("Only one sequential file mask variable is allowed.")
+    }
+}
+
+/// An error: what went wrong, and the error that caused it, if any.
+#[derive(Debug)]
+pub struct Error(pub ErrorKind, pub Option<Box<dyn StdError + Send + Sync>>);
+
+/// The errors of a chain, starting with the outermost one and following their sources.
+pub struct Iter<'a>(Option<&'a (dyn StdError + 'static)>);
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a (dyn StdError + 'static);
+
+    fn next(&mut self) -> Option<&'a (dyn StdError + 'static)> {
+        let current = self.0.take()?;
+        self.0 = current.source();
+        Some(current)
+    }
+}
+
+impl Error {
+    /// What went wrong.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0
+    }
+
+    /// This error followed by the errors that caused it.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter(Some(self))
+    }
+
+    /// Where the frame this error happened on came from, if it happened on one.
+    pub fn frame(&self) -> Option<&FrameInfo> {
+        self.iter()
+            .filter_map(|e| e.downcast_ref::<Error>())
+            .filter_map(|e| match *e.kind() {
+                ErrorKind::Frame(ref frame) => Some(frame),
+                _ => None,
+            })
+            .next()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self.1 {
+            Some(ref cause) => Some(&**cause),
+            None => None,
         }
     }
 }
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error(kind, None)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Error {
+        Error(ErrorKind::Image(e), None)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error(ErrorKind::Io(e), None)
+    }
+}
+
+/// The result of anything that can fail here.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Wraps the errors of other results in an error of a kind that says what was being done.
+pub trait ResultExt<T> {
+    /// Turns an error into one of the kind `kind` returns, caused by the original error.
+    fn chain_err<F>(self, kind: F) -> Result<T> where F: FnOnce() -> ErrorKind;
+}
+
+impl<T, E> ResultExt<T> for ::std::result::Result<T, E>
+    where E: StdError + Send + Sync + 'static
+{
+    fn chain_err<F>(self, kind: F) -> Result<T>
+        where F: FnOnce() -> ErrorKind
+    {
+        self.map_err(|e| Error(kind(), Some(Box::new(e))))
+    }
+}
+
+/// Lists the candidate paths tried when looking for input files, noting how many more there were.
+fn describe_candidates(tried: &[PathBuf], total: usize) -> String {
+    let mut list = tried.iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if total > tried.len() {
+        list.push_str(&format!(" and {} more", total - tried.len()));
+    }
+    list
+}
+
+// Exit codes for each class of failure, so that scripts can tell what went wrong without parsing
+// messages. These are part of the interface and must not change.
+
+/// Any other failure.
+pub const EXIT_FAILURE: i32 = 1;
+/// No input frames were found.
+pub const EXIT_NO_INPUT: i32 = 2;
+/// A frame couldn't be read or decoded.
+pub const EXIT_DECODE_FAILED: i32 = 3;
+/// An output couldn't be saved.
+pub const EXIT_SAVE_FAILED: i32 = 4;
+/// The output already exists and overwriting it wasn't allowed.
+pub const EXIT_OUTPUT_EXISTS: i32 = 5;
+/// An option, config file, preset, LUT or checkpoint is invalid.
+pub const EXIT_INVALID_OPTIONS: i32 = 6;
+/// Some jobs of a batch failed.
+pub const EXIT_BATCH_FAILED: i32 = 7;
+/// The render was interrupted or aborted.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// The code to exit the process with after an error, by the class of failure it belongs to.
+pub fn exit_code(error: &Error) -> i32 {
+    match *error.kind() {
+        ErrorKind::CouldNotGetPaths |
+        ErrorKind::NoFileMaskFound |
+        ErrorKind::NoFilesFound(..) |
+        ErrorKind::NoFrames |
+        ErrorKind::StreamEnded(..) |
+        ErrorKind::MultipleFileMasks |
+        ErrorKind::CouldNotParseFilemask(_) => EXIT_NO_INPUT,
+        ErrorKind::Image(_) |
+        ErrorKind::CouldNotOpenImage(_) |
+        ErrorKind::CouldNotProcessImage(_) |
+        ErrorKind::Frame(_) |
+        ErrorKind::FrameSizeMismatch(..) |
+        ErrorKind::EmptyFile(_) |
+        ErrorKind::UnsupportedFormat(_) |
+        ErrorKind::UnsupportedFrameDepth(..) |
+        ErrorKind::InvalidFrames(_) |
+        ErrorKind::CheckFailed(_) |
+        ErrorKind::InvalidIccProfile(_) |
+        ErrorKind::UnsupportedBitDepth(..) |
+        ErrorKind::CropOutOfBounds(..) => EXIT_DECODE_FAILED,
+        ErrorKind::CouldNotSaveOutput(_) |
+        ErrorKind::AnimationTooLarge(..) |
+        ErrorKind::UnsupportedAnimationFormat(_) |
+        ErrorKind::CouldNotRunFfmpeg |
+        ErrorKind::FfmpegFailed(_) |
+        ErrorKind::CouldNotWriteReport(_) |
+        ErrorKind::CouldNotWriteCheckpoint(_) |
+        ErrorKind::CouldNotWritePreset(_) => EXIT_SAVE_FAILED,
+        ErrorKind::OutputExists(_) => EXIT_OUTPUT_EXISTS,
+        ErrorKind::InvalidCheckpoint(_) |
+        ErrorKind::CheckpointMismatch(..) |
+        ErrorKind::PreviewUnavailable |
+        ErrorKind::InvalidPreview(_) |
+        ErrorKind::TuiUnavailable |
+        ErrorKind::GpuUnavailable |
+        ErrorKind::InvalidJob(_) |
+        ErrorKind::CouldNotReadConfig(_) |
+        ErrorKind::InvalidConfig(_) |
+        ErrorKind::InvalidEnvironmentVariable(_) |
+        ErrorKind::NoConfigDir |
+        ErrorKind::UnknownPreset(_) |
+        ErrorKind::CouldNotParseRegion(_) |
+        ErrorKind::CouldNotParseSize(_) |
+        ErrorKind::CouldNotParseDirection(_) |
+        ErrorKind::CouldNotParseOption(..) |
+        ErrorKind::CouldNotOpenLut(_) |
+        ErrorKind::InvalidLut(_) |
+        ErrorKind::NoOutputs |
+        ErrorKind::UnsupportedByGeometry(_) |
+        ErrorKind::IncompatibleOptions(..) => EXIT_INVALID_OPTIONS,
+        ErrorKind::BatchFailed(..) => EXIT_BATCH_FAILED,
+        ErrorKind::Interrupted(..) |
+        ErrorKind::InterruptedStrips(_) |
+        ErrorKind::PreviewAborted => EXIT_INTERRUPTED,
+        _ => EXIT_FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        let code = |kind: ErrorKind| exit_code(&kind.into());
+        assert_eq!(code(ErrorKind::NoFilesFound(vec![], 0)), EXIT_NO_INPUT);
+        assert_eq!(code(ErrorKind::CouldNotOpenImage("a.png".into())), EXIT_DECODE_FAILED);
+        assert_eq!(code(ErrorKind::CouldNotSaveOutput("out.png".into())), EXIT_SAVE_FAILED);
+        assert_eq!(code(ErrorKind::OutputExists("out.png".into())), EXIT_OUTPUT_EXISTS);
+        assert_eq!(code(ErrorKind::UnknownPreset("fan".into())), EXIT_INVALID_OPTIONS);
+        assert_eq!(code(ErrorKind::BatchFailed(1, 2)), EXIT_BATCH_FAILED);
+        assert_eq!(code(ErrorKind::PreviewAborted), EXIT_INTERRUPTED);
+        assert_eq!(code(ErrorKind::NoCommonRegion), EXIT_FAILURE);
+    }
+
+    #[test]
+    fn test_describe_candidates() {
+        let tried = vec!["000.png".into(), "001.png".into()];
+        assert_eq!(describe_candidates(&tried, 2), "000.png, 001.png");
+        assert_eq!(describe_candidates(&tried, 1000), "000.png, 001.png and 998 more");
+    }
+
+    #[test]
+    fn test_chain_err() {
+        let result: ::std::result::Result<(), io::Error> =
+            Err(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+        let error = result.chain_err(|| ErrorKind::CouldNotReadConfig("a.toml".into()))
+            .unwrap_err();
+        let messages: Vec<_> = error.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages,
+                   vec!["Could not read config file a.toml.".to_string(),
+                        "no such file".to_string()]);
+        assert_eq!(exit_code(&error), EXIT_INVALID_OPTIONS);
+        assert_eq!(error.frame(), None);
+    }
+
+    #[test]
+    fn test_frame() {
+        let result: Result<()> = Err(ErrorKind::CouldNotOpenImage("003.png".into()).into());
+        let error = result.chain_err(|| ErrorKind::Frame(FrameInfo::unnamed(3)))
+            .chain_err(|| ErrorKind::InvalidJob("north".into()))
+            .unwrap_err();
+        assert_eq!(error.frame(), Some(&FrameInfo::unnamed(3)));
+        assert_eq!(error.iter().nth(1).unwrap().to_string(), "Failed on frame 3.");
+    }
+}
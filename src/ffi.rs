@@ -0,0 +1,239 @@
+use image::{self, ImageBuffer};
+
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use ::Direction;
+use ::errors::{self, Result};
+use ::image_processing::{self, RenderOptions};
+use ::progress::NoProgress;
+
+/// What `rs_render` returns when it succeeds. Failures return the exit code for their class of
+/// failure, as the command line tool would exit with.
+pub const RS_OK: c_int = 0;
+
+/// What `rs_render` returns when it is given a null pointer or a frame with the wrong amount of
+/// data, or when the output buffer is too small for the composite.
+pub const RS_INVALID_ARGUMENT: c_int = -1;
+
+/// What `rs_render` returns if rendering panicked.
+pub const RS_PANICKED: c_int = -2;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+/// A frame of 8-bit RGBA pixels, row by row with no padding, so `width * height * 4` bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RsFrame {
+    /// The pixel data.
+    pub data: *const u8,
+    /// The width of the frame in pixels.
+    pub width: u32,
+    /// The height of the frame in pixels.
+    pub height: u32,
+}
+
+/// The options of a render, as far as they can be set from C. Fill it in with `rs_config_init`
+/// before changing any of it, so that fields added later get their defaults.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RsConfig {
+    /// Where the shutter starts from: 0 for the top, 1 for the right, 2 for the bottom and 3 for
+    /// the left.
+    pub direction: c_int,
+    /// How many consecutive frames are averaged into each frame the shutter sees.
+    pub preblend: u32,
+    /// Whether to stretch the bands over the whole image when there are too few frames for a line
+    /// each, if nonzero.
+    pub auto_stretch: c_int,
+}
+
+impl RsConfig {
+    fn options(&self) -> Option<RenderOptions> {
+        let direction = match self.direction {
+            0 => Direction::N,
+            1 => Direction::E,
+            2 => Direction::S,
+            3 => Direction::W,
+            _ => return None,
+        };
+        Some(RenderOptions {
+            direction,
+            preblend: self.preblend as usize,
+            auto_stretch: self.auto_stretch != 0,
+            suppress_output: true,
+            ..RenderOptions::default()
+        })
+    }
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Fills in `config` with the defaults of the command line tool.
+///
+/// # Safety
+/// `config` must be null or point to an `RsConfig`.
+#[no_mangle]
+pub unsafe extern "C" fn rs_config_init(config: *mut RsConfig) {
+    if let Some(config) = config.as_mut() {
+        let defaults = RenderOptions::default();
+        *config = RsConfig {
+            direction: match defaults.direction {
+                Direction::N => 0,
+                Direction::E => 1,
+                Direction::S => 2,
+                Direction::W => 3,
+            },
+            preblend: defaults.preblend as u32,
+            auto_stretch: defaults.auto_stretch as c_int,
+        };
+    }
+}
+
+/// Renders the composite of `n` frames into `out_buf`, as 8-bit RGBA pixels the size of the
+/// first frame, and stores its dimensions in `out_width` and `out_height`.
+///
+/// Returns `RS_OK` on success. Otherwise `rs_last_error` describes what went wrong.
+///
+/// # Safety
+/// `frames` must point to `n` frames, each with `width * height * 4` bytes of data, and `out_buf`
+/// to `out_len` writable bytes. `config` may be null for the defaults.
+#[no_mangle]
+pub unsafe extern "C" fn rs_render(frames: *const RsFrame,
+                                   n: usize,
+                                   config: *const RsConfig,
+                                   out_buf: *mut u8,
+                                   out_len: usize,
+                                   out_width: *mut u32,
+                                   out_height: *mut u32)
+                                   -> c_int {
+    if frames.is_null() || out_buf.is_null() || out_width.is_null() || out_height.is_null() {
+        set_last_error("A required pointer is null.".into());
+        return RS_INVALID_ARGUMENT;
+    }
+    let mut defaults = RsConfig {
+        direction: 0,
+        preblend: 0,
+        auto_stretch: 0,
+    };
+    rs_config_init(&mut defaults);
+    let options = match config.as_ref().unwrap_or(&defaults).options() {
+        Some(options) => options,
+        None => {
+            set_last_error("The direction must be 0, 1, 2 or 3.".into());
+            return RS_INVALID_ARGUMENT;
+        }
+    };
+
+    let mut images = Vec::with_capacity(n);
+    for (index, frame) in slice::from_raw_parts(frames, n).iter().enumerate() {
+        let len = frame.width as usize * frame.height as usize * 4;
+        let data = if frame.data.is_null() {
+            &[][..]
+        } else {
+            slice::from_raw_parts(frame.data, len)
+        };
+        match ImageBuffer::from_raw(frame.width, frame.height, data.to_vec()) {
+            Some(buf) => images.push(image::DynamicImage::ImageRgba8(buf)),
+            None => {
+                set_last_error(format!("Frame {} has no data.", index));
+                return RS_INVALID_ARGUMENT;
+            }
+        }
+    }
+
+    let result: Result<_> = match panic::catch_unwind(AssertUnwindSafe(|| {
+        image_processing::composite_frames(images, &options, &mut NoProgress)
+    })) {
+        Ok(result) => result,
+        Err(_) => {
+            set_last_error("Rendering panicked.".into());
+            return RS_PANICKED;
+        }
+    };
+    let composite = match result {
+        Ok(composite) => composite,
+        Err(e) => {
+            let code = errors::exit_code(&e);
+            set_last_error(e.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(" "));
+            return code;
+        }
+    };
+
+    let (width, height) = composite.dimensions();
+    let data = composite.into_raw();
+    if data.len() > out_len {
+        set_last_error(format!("The composite needs {} bytes, but the buffer only has {}.",
+                               data.len(),
+                               out_len));
+        return RS_INVALID_ARGUMENT;
+    }
+    ptr::copy_nonoverlapping(data.as_ptr(), out_buf, data.len());
+    *out_width = width;
+    *out_height = height;
+    RS_OK
+}
+
+/// A description of the last error on this thread, or null if there hasn't been one. It stays
+/// valid until the next call to `rs_render` on the same thread.
+#[no_mangle]
+pub extern "C" fn rs_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_render() {
+        let pixels: Vec<Vec<u8>> = (0..3).map(|i| vec![10 * (i + 1); 2 * 3 * 4]).collect();
+        let frames: Vec<RsFrame> = pixels.iter()
+            .map(|data| {
+                RsFrame {
+                    data: data.as_ptr(),
+                    width: 2,
+                    height: 3,
+                }
+            })
+            .collect();
+        let mut out = vec![0u8; 2 * 3 * 4];
+        let (mut width, mut height) = (0, 0);
+        let code = unsafe {
+            rs_render(frames.as_ptr(),
+                      frames.len(),
+                      ptr::null(),
+                      out.as_mut_ptr(),
+                      out.len(),
+                      &mut width,
+                      &mut height)
+        };
+        assert_eq!(code, RS_OK);
+        assert_eq!((width, height), (2, 3));
+        assert_eq!(&out[8..12], &[20, 20, 20, 20]);
+
+        let code = unsafe {
+            rs_render(frames.as_ptr(),
+                      frames.len(),
+                      ptr::null(),
+                      out.as_mut_ptr(),
+                      4,
+                      &mut width,
+                      &mut height)
+        };
+        assert_eq!(code, RS_INVALID_ARGUMENT);
+        let message = unsafe { CStr::from_ptr(rs_last_error()) };
+        assert!(message.to_str().unwrap().contains("needs 24 bytes"));
+    }
+}
@@ -1,27 +1,78 @@
+use image::{self, ColorType, ImageDecoder, ImageFormat, GenericImage};
 use regex::Regex;
 
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::str;
 
-use ::errors::{ErrorKind, Result, ResultExt};
+use ::errors::{Error, ErrorKind, Result, ResultExt};
+use ::frame_processing::OpenFile;
+
+/// How many of the paths tried are listed when no files are found.
+const CANDIDATES_SHOWN: usize = 3;
 
 /// A file mask variable description.
 ///
 /// Encodes whether the variable is zero-padded, and how many digits the full variable would have.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub(crate) struct FileMask {
+pub struct FileMask {
     zero_padded: bool,
     digits: usize,
 }
 
+impl FileMask {
+    /// The path of the `index`th frame of a file mask split into the parts either side of this
+    /// variable.
+    fn path(&self, left: &str, index: u32, right: &str) -> PathBuf {
+        let width = if self.zero_padded { self.digits } else { 0 };
+        format!("{}{:0width$}{}", left, index, right, width = width).into()
+    }
+
+    /// How many frame numbers the variable has room for.
+    fn len(&self) -> u32 {
+        10u32.pow(self.digits as u32)
+    }
+}
+
+/// A run of frames missing from the middle of a sequence.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gap {
+    /// The path of the first missing frame.
+    pub first: PathBuf,
+    /// How many frames in a row are missing.
+    pub missing: u32,
+    /// How many frames exist after the gap. Rendering stops at the first gap, so none of these are
+    /// rendered.
+    pub following: u32,
+}
+
 /// A description of what the path provided actually means; is the path the user provided a folder,
 /// or is it a file mask of some kind?
 #[derive(Clone, Debug, PartialEq, Hash)]
-pub enum PathMode<'a> {
+pub enum PathMode {
     /// A file mask with a variable.
-    FileMask(&'a str),
+    FileMask(String),
     /// A folder path.
-    Folder(&'a str),
+    Folder(PathBuf),
+}
+
+impl From<PathBuf> for PathMode {
+    /// A path to a directory that exists is a folder; anything else is taken to be a file mask.
+    fn from(path: PathBuf) -> PathMode {
+        if path.is_dir() {
+            PathMode::Folder(path)
+        } else {
+            PathMode::FileMask(path.to_string_lossy().into_owned())
+        }
+    }
+}
+
+impl<'a> From<&'a Path> for PathMode {
+    /// A path to a directory that exists is a folder; anything else is taken to be a file mask.
+    fn from(path: &'a Path) -> PathMode {
+        PathMode::from(path.to_path_buf())
+    }
 }
 
 /// Parse a given file mask.
@@ -35,7 +86,7 @@ pub enum PathMode<'a> {
 /// # Errors
 /// This will fail if there is not *exactly* one variable in the file mask. No variables means there
 /// is no sequence to be used, and multiple means ambiguity in which sequence variable to use.
-pub(crate) fn parse_filemask<S: Into<String>>(s: S) -> Result<(String, FileMask, String)> {
+pub fn parse_filemask<S: Into<String>>(s: S) -> Result<(String, FileMask, String)> {
     let s = s.into();
     // This matches something of the format %Nd or %0Nd where N is the number of digits in the mask.
     let re = Regex::new(r"%(0)?([\d]+)d").unwrap();
@@ -64,6 +115,78 @@ pub(crate) fn parse_filemask<S: Into<String>>(s: S) -> Result<(String, FileMask,
     }
 }
 
+/// The frames a `PathMode` names, found one at a time as they are asked for rather than all up
+/// front, so long sequences and sources that are still being written don't have to be listed
+/// before the first frame is used.
+///
+/// The frames of a file mask start at the first one that exists, and end at the first one missing
+/// after that.
+#[derive(Clone, Debug)]
+pub struct Paths {
+    left: String,
+    mask: FileMask,
+    right: String,
+    /// The next frame number to try.
+    index: u32,
+    started: bool,
+    /// The first few paths tried, for the error when none of them exist.
+    tried: Vec<PathBuf>,
+}
+
+impl Iterator for Paths {
+    type Item = PathBuf;
+
+    fn next(&mut self) -> Option<PathBuf> {
+        while self.index < self.mask.len() {
+            let buf = self.mask.path(&self.left, self.index, &self.right);
+            self.index += 1;
+            if self.tried.len() < CANDIDATES_SHOWN {
+                self.tried.push(buf.clone());
+            }
+
+            if buf.exists() {
+                self.started = true;
+                return Some(buf);
+            } else if self.started {
+                // If we already started, then let's just end (i.e. we want the first actual
+                // sequence of files).
+                self.index = self.mask.len();
+            }
+        }
+        None
+    }
+}
+
+/// Given a `PathMode`, finds its image paths lazily, as the returned iterator is advanced.
+///
+/// # Caveats
+/// There is no guarantee about the order of image paths provided using the `PathMode::Folder` mode.
+/// The returned order will likely be system-dependent.
+///
+/// # Errors
+/// This fails if given a `PathMode::FileMask` that cannot be parsed. Unlike `get_paths`, finding no
+/// images is not an error; the iterator is just empty.
+pub fn iter_paths(path_mode: &PathMode) -> Result<Paths> {
+    match *path_mode {
+        PathMode::FileMask(ref filemask) => {
+            let (left, mask, right) = parse_filemask(filemask.as_str())
+                .chain_err(|| ErrorKind::CouldNotParseFilemask(filemask.clone()))?;
+            Ok(Paths {
+                left,
+                mask,
+                right,
+                index: 0,
+                started: false,
+                tried: vec![],
+            })
+        }
+        PathMode::Folder(_) => {
+            // TODO
+            bail!(ErrorKind::Unimplemented("Reading frames from a folder"))
+        }
+    }
+}
+
 /// Given a `PathMode`, retrieve the set of image paths.
 ///
 /// # Caveats
@@ -78,40 +201,202 @@ pub(crate) fn parse_filemask<S: Into<String>>(s: S) -> Result<(String, FileMask,
 /// that exist in the sequence the file mask provides. If a folder is provided, this will fail if
 /// the folder does not exist or if there are no images in that directory.
 pub fn get_paths(path_mode: &PathMode) -> Result<Vec<PathBuf>> {
-    match *path_mode {
-        PathMode::FileMask(filemask) => {
-            let (left, mask, right) = parse_filemask(filemask)
-                .chain_err(|| ErrorKind::CouldNotParseFilemask(filemask.into()))?;
-
-            let mut paths = vec![];
-            let width = if mask.zero_padded { mask.digits } else { 0 };
-            for i in 0..10u32.pow(mask.digits as u32) {
-                let filename = format!("{}{:0width$}{}", left, i, right, width = width);
-                let buf: PathBuf = filename.into();
-
-                if !buf.exists() {
-                    if !paths.is_empty() {
-                        // If we already started, then let's just end (i.e. we want the first actual
-                        // sequence of files).
-                        break;
-                    }
-                    // Otherwise do nothing
-                } else {
-                    paths.push(buf);
-                }
-            }
+    let mut found = iter_paths(path_mode)?;
+    let paths: Vec<PathBuf> = found.by_ref().collect();
+    if paths.is_empty() {
+        bail!(ErrorKind::NoFilesFound(found.tried, found.mask.len() as usize));
+    }
+    Ok(paths)
+}
 
-            if paths.is_empty() {
-                bail!(ErrorKind::NoFilesFound);
-            }
+/// Finds the gaps in the sequence a file mask describes, between its first and last frames. Since
+/// rendering stops at the first gap, any frames after it are left out.
+///
+/// # Errors
+/// This fails if the file mask cannot be parsed.
+pub fn find_gaps(filemask: &str) -> Result<Vec<Gap>> {
+    let (left, mask, right) = parse_filemask(filemask)
+        .chain_err(|| ErrorKind::CouldNotParseFilemask(filemask.into()))?;
+    let present: Vec<u32> = (0..mask.len())
+        .filter(|&i| mask.path(&left, i, &right).exists())
+        .collect();
 
-            Ok(paths)
+    let mut gaps = vec![];
+    for (n, pair) in present.windows(2).enumerate() {
+        if pair[1] > pair[0] + 1 {
+            gaps.push(Gap {
+                first: mask.path(&left, pair[0] + 1, &right),
+                missing: pair[1] - pair[0] - 1,
+                following: (present.len() - n - 1) as u32,
+            });
         }
-        PathMode::Folder(_folder) => {
-            // TODO
-            bail!(ErrorKind::Unimplemented)
+    }
+    Ok(gaps)
+}
+
+/// How a frame is stored, as read from its header.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FrameLayout {
+    /// The format it is encoded in.
+    pub format: ImageFormat,
+    /// Its width and height.
+    pub dimensions: (u32, u32),
+    /// The color type of its pixels.
+    pub color: ColorType,
+}
+
+/// Reads the dimensions of an image from its header, without decoding the image data.
+///
+/// Formats whose headers can't be read on their own are fully decoded instead.
+///
+/// # Errors
+/// This fails if the file cannot be read, or if it is not in a supported image format.
+pub fn read_dimensions(path: &Path) -> Result<(u32, u32)> {
+    read_layout(path).map(|layout| layout.dimensions)
+}
+
+/// Reads the format, dimensions and color type of an image from its header, without decoding the
+/// image data.
+///
+/// Formats whose headers can't be read on their own are fully decoded instead.
+///
+/// # Errors
+/// This fails if the file cannot be read, or if it is not in a supported image format.
+pub fn read_layout(path: &Path) -> Result<FrameLayout> {
+    let _open = OpenFile::wait();
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 16];
+    let len = file.read(&mut header)?;
+    if len == 0 {
+        bail!(ErrorKind::EmptyFile(path.to_path_buf()));
+    }
+    let format = image::guess_format(&header[..len])
+        .chain_err(|| ErrorKind::UnsupportedFormat(path.to_path_buf()))?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let reader = BufReader::new(file);
+    let read = match format {
+        ImageFormat::PNG => header_layout(image::png::PNGDecoder::new(reader)),
+        ImageFormat::JPEG => header_layout(image::jpeg::JPEGDecoder::new(reader)),
+        ImageFormat::GIF => header_layout(image::gif::Decoder::new(reader)),
+        ImageFormat::BMP => header_layout(image::bmp::BMPDecoder::new(reader)),
+        _ => {
+            let frame = image::open(path).map_err(|e| decode_error(path, e))?;
+            Ok((frame.dimensions(), frame.color()))
         }
+    };
+    let (dimensions, color) = read.chain_err(|| ErrorKind::CouldNotOpenImage(path.to_path_buf()))?;
+    Ok(FrameLayout {
+        format,
+        dimensions,
+        color,
+    })
+}
+
+/// Reads the dimensions and color type from the header a decoder starts with.
+/// How many bits each channel of a pixel of `color` has.
+fn channel_bits(color: ColorType) -> u8 {
+    match color {
+        ColorType::Gray(bits) |
+        ColorType::RGB(bits) |
+        ColorType::Palette(bits) |
+        ColorType::GrayA(bits) |
+        ColorType::RGBA(bits) => bits,
+    }
+}
+
+/// Checks that a frame in `format` with pixels of `color` can be rendered without losing
+/// precision. Frames are rendered at 8 bits per channel, so deeper ones, and HDR frames with their
+/// floating point channels, are refused rather than truncated.
+///
+/// # Errors
+/// This fails with `ErrorKind::UnsupportedFrameDepth` if the frame has more than 8 bits per
+/// channel.
+pub fn check_depth(path: &Path, format: ImageFormat, color: ColorType) -> Result<()> {
+    let bits = match format {
+        ImageFormat::HDR => 32,
+        _ => channel_bits(color),
+    };
+    if bits > 8 {
+        bail!(ErrorKind::UnsupportedFrameDepth(path.to_path_buf(), bits));
+    }
+    Ok(())
+}
+
+/// The error for a frame the `image` crate failed to decode: that its channels are too deep if
+/// that is why, and otherwise that it could not be opened.
+pub fn decode_error(path: &Path, error: image::ImageError) -> Error {
+    match error {
+        image::ImageError::UnsupportedColor(color) if channel_bits(color) > 8 => {
+            ErrorKind::UnsupportedFrameDepth(path.to_path_buf(), channel_bits(color)).into()
+        }
+        error => Error(ErrorKind::CouldNotOpenImage(path.to_path_buf()), Some(Box::new(error))),
+    }
+}
+
+fn header_layout<D: ImageDecoder>(mut decoder: D) -> image::ImageResult<((u32, u32), ColorType)> {
+    Ok((decoder.dimensions()?, decoder.colortype()?))
+}
+
+/// Checks every frame's header before any rendering happens, so that all problems are reported at
+/// once instead of one at a time partway through a render.
+///
+/// # Arguments
+/// * `paths` - The frame paths, in order. The first frame determines the expected dimensions.
+/// * `check_dimensions` - Whether frames with dimensions different from the first frame's count as
+///   a problem.
+///
+/// # Errors
+/// This fails with `ErrorKind::InvalidFrames` listing every problem found if any frame can't be
+/// read, is in an unsupported format, has more than 8 bits per channel, or (if requested) has the
+/// wrong dimensions.
+pub fn validate_paths(paths: &[PathBuf], check_dimensions: bool) -> Result<()> {
+    validate_layout(paths, check_dimensions).map(|_| ())
+}
+
+/// Checks every frame's header like `validate_paths`, and returns the layout they all share, if
+/// they do: the same format, dimensions and color type. Renders of such sequences take a faster
+/// path that decodes every frame the same way.
+///
+/// # Errors
+/// This fails like `validate_paths`.
+pub fn validate_layout(paths: &[PathBuf], check_dimensions: bool) -> Result<Option<FrameLayout>> {
+    let mut expected = None;
+    let mut shared = None;
+    let mut uniform = true;
+    let problems: Vec<String> = paths.iter()
+        .filter_map(|path| match check_frame(path, &mut expected, check_dimensions) {
+            Ok(layout) => {
+                uniform &= *shared.get_or_insert(layout) == layout;
+                None
+            }
+            Err(e) => Some(e.to_string()),
+        })
+        .collect();
+
+    if !problems.is_empty() {
+        bail!(ErrorKind::InvalidFrames(problems));
+    }
+    Ok(shared.filter(|_| uniform))
+}
+
+/// Reads a frame's header, taking its dimensions as the expected ones if it is the first frame
+/// read, and otherwise checking them against the expected ones if `check_dimensions`.
+fn check_frame(path: &Path,
+               expected: &mut Option<(u32, u32)>,
+               check_dimensions: bool)
+               -> Result<FrameLayout> {
+    let layout = read_layout(path)?;
+    check_depth(path, layout.format, layout.color)?;
+    let dimensions = layout.dimensions;
+    match *expected {
+        None => *expected = Some(dimensions),
+        Some(expected) if check_dimensions && expected != dimensions => {
+            bail!(ErrorKind::FrameSizeMismatch(path.to_path_buf(), expected, dimensions));
+        }
+        Some(_) => (),
     }
+    Ok(layout)
 }
 
 #[cfg(test)]
@@ -119,6 +404,7 @@ mod tests {
     use super::*;
 
     use ::errors::*;
+    use ::testing::frame_dir;
 
     #[test]
     fn test_parse_filemask() {
@@ -148,8 +434,8 @@ mod tests {
             Err(e) => {
                 assert!(false,
                         "expected `{}`, got `{}` instead.",
-                        ErrorKind::NoFileMaskFound.description(),
-                        e.description())
+                        ErrorKind::NoFileMaskFound,
+                        e)
             }
             Ok(result) => {
                 assert!(false,
@@ -162,8 +448,8 @@ mod tests {
             Err(Error(e, _)) => {
                 assert!(false,
                         "expected `{}`, got `{}` instead.",
-                        ErrorKind::MultipleFileMasks.description(),
-                        e.description())
+                        ErrorKind::MultipleFileMasks,
+                        e)
             }
             Ok(result) => {
                 assert!(false,
@@ -172,4 +458,115 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_get_paths_candidates() {
+        let mask = "does-not-exist/%02d.png";
+        match get_paths(&PathMode::FileMask(mask.into())) {
+            Err(Error(ErrorKind::NoFilesFound(tried, total), _)) => {
+                let expected: Vec<PathBuf> = vec!["does-not-exist/00.png".into(),
+                                                  "does-not-exist/01.png".into(),
+                                                  "does-not-exist/02.png".into()];
+                assert_eq!(tried, expected);
+                assert_eq!(total, 100);
+            }
+            result => assert!(false, "expected no files found error, got {:?}.", result),
+        }
+        match get_paths(&PathMode::FileMask("frames.png".into())) {
+            Err(Error(ErrorKind::CouldNotParseFilemask(_), _)) => (),
+            result => assert!(false, "expected file mask parse error, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn test_path_mode_from_path() {
+        let dir = ::std::env::temp_dir();
+        assert_eq!(PathMode::from(dir.clone()), PathMode::Folder(dir.clone()));
+        let mask = dir.join("%03d.png");
+        assert_eq!(PathMode::from(mask.as_path()),
+                   PathMode::FileMask(mask.to_string_lossy().into_owned()));
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let dir = frame_dir("gaps", vec![]);
+        for i in &[1, 2, 5, 6, 7, 9] {
+            File::create(dir.join(format!("{:02}.png", i))).unwrap();
+        }
+        let mask = dir.join("%02d.png");
+        let gaps = find_gaps(mask.to_str().unwrap()).unwrap();
+        assert_eq!(gaps,
+                   vec![Gap {
+                            first: dir.join("03.png"),
+                            missing: 2,
+                            following: 4,
+                        },
+                        Gap {
+                            first: dir.join("08.png"),
+                            missing: 1,
+                            following: 1,
+                        }]);
+
+        match read_dimensions(&dir.join("01.png")) {
+            Err(Error(ErrorKind::EmptyFile(_), _)) => (),
+            result => assert!(false, "expected empty file error, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn test_validate_layout() {
+        use image::{ImageBuffer, Rgb, Rgba};
+
+        let dir = frame_dir("layout", vec![]);
+        let paths: Vec<PathBuf> = (0..3).map(|i| dir.join(format!("{}.png", i))).collect();
+        for path in &paths {
+            ImageBuffer::from_pixel(4, 2, Rgb([1u8, 2, 3])).save(path).unwrap();
+        }
+        let layout = FrameLayout {
+            format: ImageFormat::PNG,
+            dimensions: (4, 2),
+            color: ColorType::RGB(8),
+        };
+        assert_eq!(validate_layout(&paths, true).unwrap(), Some(layout));
+
+        // A frame with an alpha channel makes the sequence mixed, though it is still valid.
+        ImageBuffer::from_pixel(4, 2, Rgba([1u8, 2, 3, 4])).save(&paths[1]).unwrap();
+        assert_eq!(validate_layout(&paths, true).unwrap(), None);
+    }
+
+    #[test]
+    fn test_validate_deep_frames() {
+        use deflate::Compression;
+        use std::fs;
+
+        let dir = frame_dir("deep", vec![]);
+        let path = dir.join("0.png");
+        let png = ::png_writer::encode(&[0x12; 2 * 2 * 8], 2, 2, 16, Compression::Fast);
+        fs::write(&path, png).unwrap();
+
+        match check_frame(&path, &mut None, true) {
+            Err(Error(ErrorKind::UnsupportedFrameDepth(ref frame, bits), _)) => {
+                assert_eq!((frame, bits), (&path, 16))
+            }
+            result => assert!(false, "expected a frame depth error, got {:?}.", result.is_ok()),
+        }
+        match validate_paths(&[path], true) {
+            Err(Error(ErrorKind::InvalidFrames(ref problems), _)) => assert_eq!(problems.len(), 1),
+            result => assert!(false, "expected invalid frames, got {:?}.", result.is_ok()),
+        }
+    }
+
+    #[test]
+    fn test_iter_paths() {
+        let dir = frame_dir("iter-paths", vec![]);
+        for i in &[1, 2, 5] {
+            File::create(dir.join(format!("{:02}.png", i))).unwrap();
+        }
+        let mask = PathMode::FileMask(dir.join("%02d.png").to_string_lossy().into_owned());
+        let mut paths = iter_paths(&mask).unwrap();
+        assert_eq!(paths.next(), Some(dir.join("01.png")));
+        // Frames are only looked for once they are asked for, so one added now is still found.
+        File::create(dir.join("03.png")).unwrap();
+        assert_eq!(paths.collect::<Vec<_>>(), vec![dir.join("02.png"), dir.join("03.png")]);
+    }
 }
@@ -0,0 +1,209 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use ::frame_processing::OpenFile;
+
+/// How much of the start of a file is searched for EXIF data. It is written in a segment of at most
+/// 64 KiB near the start of a JPEG, so this leaves room for a few other segments before it.
+const HEADER_LEN: u64 = 128 * 1024;
+
+/// The identifier at the start of the JPEG APP1 segment that holds EXIF data.
+const JPEG_EXIF_IDENTIFIER: &[u8] = b"Exif\0\0";
+
+/// The EXIF tag of the time the image was last changed.
+const TAG_DATE_TIME: u16 = 0x0132;
+/// The EXIF tag pointing to the EXIF sub-IFD, which holds the time the photo was taken.
+const TAG_EXIF_IFD: u16 = 0x8769;
+/// The EXIF tag of the time the photo was taken.
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+/// Where a frame came from, carried along with it through a render so that a frame that failed,
+/// was slow or was left out can be traced back to its source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameInfo {
+    /// The position of the frame among the frames of the render.
+    pub index: usize,
+    /// The path the frame was read from. Frames that weren't read from a file go by `frame N`.
+    pub path: PathBuf,
+    /// When the file was last modified, if the frame was read from one.
+    pub modified: Option<SystemTime>,
+    /// When the frame was taken, as its EXIF data records it: `YYYY:MM:DD HH:MM:SS`.
+    pub taken: Option<String>,
+}
+
+impl FrameInfo {
+    /// A frame that wasn't read from a file, such as one already in memory.
+    pub fn unnamed(index: usize) -> FrameInfo {
+        FrameInfo {
+            index,
+            path: format!("frame {}", index).into(),
+            modified: None,
+            taken: None,
+        }
+    }
+
+    /// A frame still encoded as `data`, with the time it was taken if its EXIF data records it.
+    pub fn encoded(index: usize, data: &[u8]) -> FrameInfo {
+        FrameInfo { taken: exif_time(data), ..FrameInfo::unnamed(index) }
+    }
+
+    /// A frame read from the file at `path`. Only the start of the file is read, and anything
+    /// that can't be read is left out rather than failing, since the frame itself is read
+    /// separately.
+    pub fn read(index: usize, path: &Path) -> FrameInfo {
+        let mut header = vec![];
+        let read = {
+            let _open = OpenFile::wait();
+            File::open(path).and_then(|file| file.take(HEADER_LEN).read_to_end(&mut header))
+        };
+        if read.is_err() {
+            header.clear();
+        }
+        FrameInfo::from_contents(index, path, &header)
+    }
+
+    /// A frame read from the file at `path` whose contents, or the start of them, are `data`, so
+    /// that the file isn't opened again to find when the frame was taken.
+    pub fn from_contents(index: usize, path: &Path, data: &[u8]) -> FrameInfo {
+        FrameInfo {
+            index,
+            path: path.to_path_buf(),
+            modified: fs::metadata(path).and_then(|metadata| metadata.modified()).ok(),
+            taken: exif_time(data),
+        }
+    }
+}
+
+impl fmt::Display for FrameInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unnamed = FrameInfo::unnamed(self.index);
+        write!(f, "{}", unnamed.path.display())?;
+        if self.path != unnamed.path {
+            write!(f, " ({})", self.path.display())?;
+        }
+        if let Some(ref taken) = self.taken {
+            write!(f, ", taken {}", taken)?;
+        }
+        Ok(())
+    }
+}
+
+/// Extracts the TIFF structure EXIF data is stored as from the APP1 segment of JPEG data.
+fn jpeg_exif(data: &[u8]) -> Option<&[u8]> {
+    let mut offset = 2;
+    while offset + 4 <= data.len() && data[offset] == 0xff {
+        let marker = data[offset + 1];
+        // Start of scan; no more metadata segments follow.
+        if marker == 0xda {
+            break;
+        }
+        let length = ((data[offset + 2] as usize) << 8) | data[offset + 3] as usize;
+        let end = offset + 2 + length;
+        if length < 2 || end > data.len() {
+            break;
+        }
+        let segment = &data[offset + 4..end];
+        if marker == 0xe1 && segment.starts_with(JPEG_EXIF_IDENTIFIER) {
+            return Some(&segment[JPEG_EXIF_IDENTIFIER.len()..]);
+        }
+        offset = end;
+    }
+    None
+}
+
+/// Finds when a photo was taken in the EXIF data of a JPEG or at the start of a TIFF, falling
+/// back to when it was last changed. Data that is malformed or truncated gives `None`.
+fn exif_time(data: &[u8]) -> Option<String> {
+    let tiff = if data.starts_with(&[0xff, 0xd8]) { jpeg_exif(data)? } else { data };
+    let big_endian = match tiff.get(0..4)? {
+        b"MM\0*" => true,
+        b"II*\0" => false,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| {
+        let b = tiff.get(offset..offset + 2)?;
+        let (high, low) = if big_endian { (b[0], b[1]) } else { (b[1], b[0]) };
+        Some((high as u16) << 8 | low as u16)
+    };
+    let read_u32 = |offset: usize| {
+        let high = read_u16(offset + if big_endian { 0 } else { 2 })? as u32;
+        let low = read_u16(offset + if big_endian { 2 } else { 0 })? as u32;
+        Some((high << 16 | low) as usize)
+    };
+    // Each entry of an IFD is 12 bytes: the tag, the type, the count, and the value itself if it
+    // fits in four bytes or else where it is.
+    let find = |ifd: usize, tag: u16| {
+        let count = read_u16(ifd)? as usize;
+        (0..count).map(|i| ifd + 2 + 12 * i).find(|&entry| read_u16(entry) == Some(tag))
+    };
+    let ascii = |entry: usize| {
+        let count = read_u32(entry + 4)?;
+        let start = if count <= 4 { entry + 8 } else { read_u32(entry + 8)? };
+        let text = tiff.get(start..start + count)?.split(|&b| b == 0).next()?;
+        String::from_utf8(text.to_vec()).ok().filter(|text| !text.trim().is_empty())
+    };
+
+    let ifd0 = read_u32(4)?;
+    find(ifd0, TAG_EXIF_IFD)
+        .and_then(|entry| read_u32(entry + 8))
+        .and_then(|exif| find(exif, TAG_DATE_TIME_ORIGINAL))
+        .and_then(&ascii)
+        .or_else(|| find(ifd0, TAG_DATE_TIME).and_then(&ascii))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A little-endian TIFF header whose first IFD holds a `DateTime` and points to an EXIF IFD
+    /// holding a `DateTimeOriginal`.
+    fn exif() -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(&[8, 0, 0, 0]);
+        // IFD0 at 8: two entries and no next IFD, taking 2 + 24 + 4 bytes.
+        tiff.extend(&[2, 0]);
+        tiff.extend(&[0x32, 0x01, 2, 0, 20, 0, 0, 0, 56, 0, 0, 0]);
+        tiff.extend(&[0x69, 0x87, 4, 0, 1, 0, 0, 0, 38, 0, 0, 0]);
+        tiff.extend(&[0, 0, 0, 0]);
+        // The EXIF IFD at 38: one entry and no next IFD.
+        tiff.extend(&[1, 0]);
+        tiff.extend(&[0x03, 0x90, 2, 0, 20, 0, 0, 0, 76, 0, 0, 0]);
+        tiff.extend(&[0, 0, 0, 0]);
+        tiff.extend(b"2024:05:06 07:08:09\0");
+        tiff.extend(b"2024:05:06 07:00:00\0");
+        tiff
+    }
+
+    #[test]
+    fn test_exif_time() {
+        let tiff = exif();
+        assert_eq!(exif_time(&tiff), Some("2024:05:06 07:00:00".into()));
+        assert_eq!(exif_time(&tiff[..40]), None);
+        // Without the EXIF IFD, the time it was last changed is used instead.
+        let mut changed = tiff.clone();
+        changed[22..24].copy_from_slice(&[0, 0]);
+        assert_eq!(exif_time(&changed), Some("2024:05:06 07:08:09".into()));
+
+        // SOI, an empty APP0 segment, the EXIF segment, and then start of scan.
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x02, 0xff, 0xe1];
+        let length = 2 + JPEG_EXIF_IDENTIFIER.len() + tiff.len();
+        jpeg.extend(&[(length >> 8) as u8, length as u8]);
+        jpeg.extend(JPEG_EXIF_IDENTIFIER);
+        jpeg.extend(&tiff);
+        jpeg.extend(&[0xff, 0xda, 0x00, 0x02]);
+        assert_eq!(FrameInfo::encoded(3, &jpeg).taken, Some("2024:05:06 07:00:00".into()));
+        assert_eq!(exif_time(&jpeg[..20]), None);
+    }
+
+    #[test]
+    fn test_display() {
+        let mut info = FrameInfo::unnamed(3);
+        assert_eq!(info.to_string(), "frame 3");
+        info.path = "frames/003.png".into();
+        info.taken = Some("2024:05:06 07:00:00".into());
+        assert_eq!(info.to_string(), "frame 3 (frames/003.png), taken 2024:05:06 07:00:00");
+    }
+}
@@ -0,0 +1,1336 @@
+use image::{self, DynamicImage, FilterType, GenericImage, ImageBuffer, ImageFormat, RgbaImage};
+#[cfg(not(target_arch = "wasm32"))]
+use memmap2::Mmap;
+use png::{self, BitDepth, ColorType, HasParameters};
+
+use std::collections::VecDeque;
+use std::io;
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ::color_processing::{self, Channel, Rgba16Image};
+use ::errors::{Error, ErrorKind, Result, ResultExt};
+use ::file_processing::{self, FrameLayout};
+use ::jpeg;
+
+/// A rectangular region of an image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Region {
+    /// The left edge of the region.
+    pub x: u32,
+    /// The top edge of the region.
+    pub y: u32,
+    /// The width of the region.
+    pub width: u32,
+    /// The height of the region.
+    pub height: u32,
+}
+
+/// A rotation applied to every frame, clockwise.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Rotation {
+    /// A quarter turn.
+    R90,
+    /// A half turn.
+    R180,
+    /// Three quarters of a turn.
+    R270,
+}
+
+impl FromStr for Rotation {
+    type Err = Error;
+
+    /// Parses a rotation from its angle in degrees: `90`, `180` or `270`.
+    fn from_str(s: &str) -> Result<Rotation> {
+        match s {
+            "90" => Ok(Rotation::R90),
+            "180" => Ok(Rotation::R180),
+            "270" => Ok(Rotation::R270),
+            _ => bail!(ErrorKind::CouldNotParseOption("rotation", s.into(), "90, 180 or 270")),
+        }
+    }
+}
+
+/// A mirroring applied to every frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Flip {
+    /// Mirror left to right.
+    Horizontal,
+    /// Mirror top to bottom.
+    Vertical,
+}
+
+impl FromStr for Flip {
+    type Err = Error;
+
+    /// Parses a flip from its letter: `h` to mirror left to right, or `v` to mirror top to bottom.
+    fn from_str(s: &str) -> Result<Flip> {
+        match s {
+            "h" => Ok(Flip::Horizontal),
+            "v" => Ok(Flip::Vertical),
+            _ => bail!(ErrorKind::CouldNotParseOption("flip", s.into(), "h or v")),
+        }
+    }
+}
+
+/// The transformations applied to every frame as it is loaded, in the order they are applied.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FrameTransforms {
+    /// The rotation to apply, if any.
+    pub rotate: Option<Rotation>,
+    /// The mirroring to apply, if any.
+    pub flip: Option<Flip>,
+    /// The region of the (rotated and flipped) frame to keep, if any.
+    pub crop: Option<Region>,
+    /// The factor to shrink the (cropped) frame by for a quick preview, if any.
+    pub scale: Option<f64>,
+}
+
+impl FrameTransforms {
+    /// The dimensions a frame of the given dimensions has once transformed, without decoding it.
+    pub fn dimensions(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        let (width, height) = match self.rotate {
+            Some(Rotation::R90) | Some(Rotation::R270) => (height, width),
+            _ => (width, height),
+        };
+        let (width, height) = match self.crop {
+            Some(region) => (region.width, region.height),
+            None => (width, height),
+        };
+        match self.scale {
+            Some(scale) => (scale_dimension(width, scale), scale_dimension(height, scale)),
+            None => (width, height),
+        }
+    }
+}
+
+/// Scales a frame dimension by `scale`, keeping it at least one pixel.
+fn scale_dimension(size: u32, scale: f64) -> u32 {
+    ::std::cmp::max(1, (size as f64 * scale).round() as u32)
+}
+
+/// How many frame files are open at once by default, across every thread and render reading them.
+pub const DEFAULT_OPEN_FILES: usize = 32;
+
+/// The window of frame files open at once in the process.
+static OPEN_FILES: FileWindow = FileWindow::new(DEFAULT_OPEN_FILES);
+
+/// Sets how many frame files may be open at once, across every thread and render in the process,
+/// so that renders of huge sequences stay within a restrictive limit on open files, and don't
+/// swamp network mounts with requests. Threads that would open more wait for a file to be closed.
+/// It is at least 1.
+pub fn set_open_file_limit(limit: usize) {
+    OPEN_FILES.set_limit(limit);
+}
+
+/// How many frame files may be open at once.
+pub fn open_file_limit() -> usize {
+    OPEN_FILES.limit.load(Ordering::SeqCst)
+}
+
+/// A limit on how many files are open at once, and how many are.
+struct FileWindow {
+    limit: AtomicUsize,
+    open: Mutex<usize>,
+    closed: Condvar,
+}
+
+impl FileWindow {
+    const fn new(limit: usize) -> FileWindow {
+        FileWindow {
+            limit: AtomicUsize::new(limit),
+            open: Mutex::new(0),
+            closed: Condvar::new(),
+        }
+    }
+
+    fn set_limit(&self, limit: usize) {
+        self.limit.store(::std::cmp::max(limit, 1), Ordering::SeqCst);
+        self.closed.notify_all();
+    }
+
+    fn wait(&'static self) -> OpenFile {
+        let mut open = self.open.lock().unwrap();
+        while *open >= self.limit.load(Ordering::SeqCst) {
+            open = self.closed.wait(open).unwrap();
+        }
+        *open += 1;
+        OpenFile(self)
+    }
+
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    fn try_take(&'static self) -> Option<OpenFile> {
+        let mut open = self.open.lock().unwrap();
+        if *open + 1 >= self.limit.load(Ordering::SeqCst) {
+            return None;
+        }
+        *open += 1;
+        Some(OpenFile(self))
+    }
+}
+
+/// A frame file's place in the window of files open at once, given up when it is dropped. It must
+/// outlive the file it is for.
+pub(crate) struct OpenFile(&'static FileWindow);
+
+impl OpenFile {
+    /// Takes a place in the window, waiting for one if it is full.
+    pub(crate) fn wait() -> OpenFile {
+        OPEN_FILES.wait()
+    }
+
+    /// Takes a place in the window if that leaves another free. Files read ahead are only given
+    /// their places back once their reads are collected, so they never take the last one, which a
+    /// thread opening a file the usual way could otherwise wait on for ever.
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    pub(crate) fn try_take() -> Option<OpenFile> {
+        OPEN_FILES.try_take()
+    }
+}
+
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        *self.0.open.lock().unwrap() -= 1;
+        self.0.closed.notify_one();
+    }
+}
+
+/// The contents of a frame file, mapped into memory where the platform allows, so that it is
+/// decoded straight from the page cache without being copied into a buffer first.
+pub(crate) enum FrameData {
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl Deref for FrameData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match *self {
+            #[cfg(not(target_arch = "wasm32"))]
+            FrameData::Mapped(ref map) => map,
+            FrameData::Read(ref data) => data,
+        }
+    }
+}
+
+/// Reads a frame file, mapping it into memory unless it is empty, which can't be mapped. The file
+/// is closed once it is mapped, so only the frames being read at the moment count towards the
+/// limit on open files.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn read_frame(path: &Path) -> io::Result<FrameData> {
+    let _open = OpenFile::wait();
+    let file = ::std::fs::File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(FrameData::Read(vec![]));
+    }
+    // Safe as long as the file isn't changed or truncated while the frame is decoded, which a
+    // render already relies on however its frames are read.
+    unsafe { Mmap::map(&file) }.map(FrameData::Mapped)
+}
+
+/// Reads a frame file into memory.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn read_frame(path: &Path) -> io::Result<FrameData> {
+    let _open = OpenFile::wait();
+    ::std::fs::read(path).map(FrameData::Read)
+}
+
+/// Opens a frame and applies any per-frame transformations to it. JPEGs are decoded with the faster
+/// decoder when it is built in.
+///
+/// # Arguments
+/// * `path` - The path of the frame.
+/// * `transforms` - The transformations to apply.
+///
+/// # Errors
+/// This fails if the frame cannot be opened or has more than 8 bits per channel, or if the crop
+/// region does not fit inside it.
+pub fn load_frame(path: &Path, transforms: &FrameTransforms) -> Result<DynamicImage> {
+    let data = read_frame(path).chain_err(|| ErrorKind::CouldNotOpenImage(path.into()))?;
+    decode_frame(&data, path, transforms)
+}
+
+/// Decodes a frame from the contents of its file, like `load_frame`.
+fn decode_frame(data: &[u8], path: &Path, transforms: &FrameTransforms) -> Result<DynamicImage> {
+    let frame = if image::guess_format(data).is_ok() {
+        decode_image(data, path)?
+    } else {
+        // Formats without a signature to recognize them by, such as TGA, go by the extension.
+        let _open = OpenFile::wait();
+        image::open(path).map_err(|e| file_processing::decode_error(path, e))?
+    };
+    transform_frame(frame, transforms, path)
+}
+
+/// Decodes an image from the contents of its file, with the faster JPEG decoder when it is built
+/// in. Errors name the image by `path`.
+///
+/// # Errors
+/// This fails if the data is not an image in a format recognized by its signature, or if the image
+/// has more than 8 bits per channel.
+pub fn decode_image(data: &[u8], path: &Path) -> Result<DynamicImage> {
+    if let Some(image) = jpeg::decode(data) {
+        return Ok(image);
+    }
+    if let Ok(ImageFormat::HDR) = image::guess_format(data) {
+        bail!(ErrorKind::UnsupportedFrameDepth(path.into(), 32));
+    }
+    image::load_from_memory(data).map_err(|e| file_processing::decode_error(path, e))
+}
+
+/// Buffers left over from frames that are no longer needed, which later frames of the same
+/// dimensions are decoded into instead of a newly allocated buffer each. Clones share the same
+/// buffers, so the threads decoding frames at once can all take from it. At most `capacity`
+/// buffers are kept; the rest are freed.
+#[derive(Clone)]
+pub struct FramePool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+    capacity: usize,
+}
+
+impl FramePool {
+    /// Creates an empty pool keeping at most `capacity` buffers.
+    pub fn new(capacity: usize) -> FramePool {
+        FramePool {
+            buffers: Arc::new(Mutex::new(vec![])),
+            capacity,
+        }
+    }
+
+    /// A buffer of `len` bytes, reused if there is one to spare. Its contents are left over from
+    /// the frame it held, so every byte has to be written.
+    fn take(&self, len: usize) -> Vec<u8> {
+        let buffer = self.buffers.lock().unwrap().pop();
+        match buffer {
+            Some(mut buffer) => {
+                buffer.resize(len, 0);
+                buffer
+            }
+            None => vec![0; len],
+        }
+    }
+
+    /// Keeps the buffer of a frame that is no longer needed for a later frame, if it is RGBA, as
+    /// the frames decoded from the pool are.
+    pub fn recycle(&self, frame: DynamicImage) {
+        if let DynamicImage::ImageRgba8(frame) = frame {
+            let mut buffers = self.buffers.lock().unwrap();
+            if buffers.len() < self.capacity {
+                buffers.push(frame.into_raw());
+            }
+        }
+    }
+
+    /// How many buffers are kept.
+    pub fn len(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+
+    /// Whether no buffers are kept.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Opens a frame like `load_frame`, decoding a PNG of the given dimensions into a buffer from
+/// `pool` rather than a new one. Other frames are opened as `load_frame` does.
+///
+/// # Errors
+/// This fails if the frame cannot be opened or has more than 8 bits per channel, or if the crop
+/// region does not fit inside it.
+pub fn load_frame_pooled(path: &Path,
+                         transforms: &FrameTransforms,
+                         dimensions: (u32, u32),
+                         pool: &FramePool)
+                         -> Result<DynamicImage> {
+    let data = read_frame(path).chain_err(|| ErrorKind::CouldNotOpenImage(path.into()))?;
+    load_frame_pooled_from(&data, path, transforms, dimensions, pool)
+}
+
+/// Decodes a frame like `load_frame_pooled`, from the contents of its file.
+pub(crate) fn load_frame_pooled_from(data: &[u8],
+                                     path: &Path,
+                                     transforms: &FrameTransforms,
+                                     dimensions: (u32, u32),
+                                     pool: &FramePool)
+                                     -> Result<DynamicImage> {
+    match load_rows_from(data, 0..dimensions.1, dimensions, pool) {
+        Some(frame) => transform_frame(frame, transforms, path),
+        None => decode_frame(data, path, transforms),
+    }
+}
+
+/// Decodes only the given rows of a PNG frame, for renders that take nothing else from it, and
+/// leaves the rest of it transparent. PNG stores rows from the top down, so the rows below them
+/// aren't even decompressed, which makes this far quicker than decoding the whole frame for bands
+/// near its top. The frame is decoded as RGBA into a buffer from `pool`.
+///
+/// Returns `None` if the frame isn't a PNG of the given dimensions that this can decode, such as
+/// an interlaced or 16-bit one, or if it can't be read, so that it can be decoded in full instead,
+/// failing there if it is broken.
+pub fn load_rows(path: &Path,
+                 rows: Range<u32>,
+                 dimensions: (u32, u32),
+                 pool: &FramePool)
+                 -> Option<DynamicImage> {
+    // Only the pages holding the rows down to the band are read from a mapped file.
+    let data = read_frame(path).ok()?;
+    load_rows_from(&data, rows, dimensions, pool)
+}
+
+/// Decodes only the given rows of a PNG frame like `load_rows`, from the contents of its file.
+pub(crate) fn load_rows_from(data: &[u8],
+                             rows: Range<u32>,
+                             dimensions: (u32, u32),
+                             pool: &FramePool)
+                             -> Option<DynamicImage> {
+    let (width, height) = dimensions;
+    if rows.end > height {
+        return None;
+    }
+    let stride = width as usize * 4;
+    let mut frame = pool.take(stride * height as usize);
+    // Whatever an earlier frame left outside the rows is cleared.
+    frame[..rows.start as usize * stride].fill(0);
+    frame[rows.end as usize * stride..].fill(0);
+    let range = rows.start as usize * stride..rows.end as usize * stride;
+    decode_png_rows(data, rows, dimensions, &mut frame[range])?;
+    ImageBuffer::from_raw(width, height, frame).map(DynamicImage::ImageRgba8)
+}
+
+/// Decodes a strip of the given rows of a frame, as an RGBA image of just those rows, for renders
+/// that build the composite a strip at a time. Only the rows down to the strip are decoded from
+/// PNGs that `load_rows` can decode; other frames are decoded whole, conformed to the dimensions by
+/// the resize policy and cropped.
+///
+/// # Errors
+/// This fails if the frame can't be decoded, or can't be conformed to the dimensions.
+pub fn load_strip(path: &Path,
+                  rows: Range<u32>,
+                  dimensions: (u32, u32),
+                  policy: ResizePolicy)
+                  -> Result<DynamicImage> {
+    let (width, height) = (dimensions.0, rows.end - rows.start);
+    let data = read_frame(path).chain_err(|| ErrorKind::CouldNotOpenImage(path.into()))?;
+    let mut strip = vec![0; width as usize * height as usize * 4];
+    if decode_png_rows(&data, rows.clone(), dimensions, &mut strip).is_some() {
+        return Ok(DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, strip).unwrap()));
+    }
+    let frame = decode_frame(&data, path, &FrameTransforms::default())?;
+    let mut frame = conform_frame(frame, dimensions, policy, path)?;
+    Ok(frame.crop(0, rows.start, width, height))
+}
+
+/// Decodes `rows` of a PNG frame of the given dimensions into `out` as RGBA, one row after
+/// another. Returns `None` if the frame isn't a PNG of the given dimensions that this can decode.
+fn decode_png_rows(data: &[u8],
+                   rows: Range<u32>,
+                   dimensions: (u32, u32),
+                   out: &mut [u8])
+                   -> Option<()> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set(png::TRANSFORM_EXPAND);
+    let (info, mut reader) = decoder.read_info().ok()?;
+    if (info.width, info.height) != dimensions || rows.end > info.height ||
+       reader.info().interlaced || reader.info().bit_depth != BitDepth::Eight {
+        return None;
+    }
+    let channels = match reader.output_color_type() {
+        (ColorType::Grayscale, BitDepth::Eight) => 1,
+        (ColorType::GrayscaleAlpha, BitDepth::Eight) => 2,
+        (ColorType::RGB, BitDepth::Eight) => 3,
+        (ColorType::RGBA, BitDepth::Eight) => 4,
+        _ => return None,
+    };
+    let stride = info.width as usize * 4;
+    debug_assert_eq!(out.len(), (rows.end - rows.start) as usize * stride);
+    for y in 0..rows.end {
+        let row = reader.next_row().ok()??;
+        if y < rows.start {
+            continue;
+        }
+        let start = (y - rows.start) as usize * stride;
+        expand_to_rgba(row, channels, &mut out[start..start + stride]);
+    }
+    Some(())
+}
+
+/// Expands 8-bit gray, gray and alpha, RGB or RGBA pixels, with 1 to 4 `channels`, into RGBA in
+/// `out`. The layout is settled once for all of them rather than for every pixel, and RGBA pixels
+/// are copied as they are.
+pub(crate) fn expand_to_rgba(pixels: &[u8], channels: usize, out: &mut [u8]) {
+    debug_assert_eq!(pixels.len() / channels, out.len() / 4);
+    match channels {
+        1 => {
+            for (&gray, rgba) in pixels.iter().zip(out.chunks_mut(4)) {
+                rgba.copy_from_slice(&[gray, gray, gray, 255]);
+            }
+        }
+        2 => {
+            for (pixel, rgba) in pixels.chunks(2).zip(out.chunks_mut(4)) {
+                rgba.copy_from_slice(&[pixel[0], pixel[0], pixel[0], pixel[1]]);
+            }
+        }
+        3 => {
+            for (pixel, rgba) in pixels.chunks(3).zip(out.chunks_mut(4)) {
+                rgba[..3].copy_from_slice(pixel);
+                rgba[3] = 255;
+            }
+        }
+        _ => out.copy_from_slice(pixels),
+    }
+}
+
+/// Decodes a frame of a sequence whose frames all share `layout`, as `validate_layout` found,
+/// straight into an RGBA buffer from `pool`, so that no frame has to be converted on its way into
+/// the composite: PNGs a row at a time like `load_rows`, and RGB and grayscale JPEGs whole. Returns
+/// `None` for other layouts, or for a frame that turns out not to have the layout after all, so
+/// that it is decoded the usual way.
+pub(crate) fn load_uniform(data: &[u8],
+                           layout: &FrameLayout,
+                           rows: Range<u32>,
+                           pool: &FramePool)
+                           -> Option<DynamicImage> {
+    let (width, height) = layout.dimensions;
+    match (layout.format, layout.color) {
+        (image::ImageFormat::PNG, _) => load_rows_from(data, rows, layout.dimensions, pool),
+        (image::ImageFormat::JPEG, image::ColorType::RGB(8)) |
+        (image::ImageFormat::JPEG, image::ColorType::Gray(8)) => {
+            let mut frame = pool.take(width as usize * height as usize * 4);
+            jpeg::decode_rgba(data, layout.dimensions, &mut frame)?;
+            ImageBuffer::from_raw(width, height, frame).map(DynamicImage::ImageRgba8)
+        }
+        _ => None,
+    }
+}
+
+/// Applies any per-frame transformations to a frame that is already in memory.
+///
+/// # Arguments
+/// * `frame` - The frame.
+/// * `transforms` - The transformations to apply.
+/// * `path` - The path the frame was loaded from, for error reporting.
+///
+/// # Errors
+/// This fails if the crop region does not fit inside the frame.
+pub fn transform_frame(mut frame: DynamicImage,
+                       transforms: &FrameTransforms,
+                       path: &Path)
+                       -> Result<DynamicImage> {
+    frame = match transforms.rotate {
+        Some(Rotation::R90) => frame.rotate90(),
+        Some(Rotation::R180) => frame.rotate180(),
+        Some(Rotation::R270) => frame.rotate270(),
+        None => frame,
+    };
+    frame = match transforms.flip {
+        Some(Flip::Horizontal) => frame.fliph(),
+        Some(Flip::Vertical) => frame.flipv(),
+        None => frame,
+    };
+    if let Some(region) = transforms.crop {
+        let (width, height) = frame.dimensions();
+        if region.x as u64 + region.width as u64 > width as u64 ||
+           region.y as u64 + region.height as u64 > height as u64 {
+            bail!(ErrorKind::CropOutOfBounds(path.into(), (width, height)));
+        }
+        frame = frame.crop(region.x, region.y, region.width, region.height);
+    }
+    if let Some(scale) = transforms.scale {
+        let (width, height) = frame.dimensions();
+        frame = frame.resize_exact(scale_dimension(width, scale),
+                                   scale_dimension(height, scale),
+                                   FilterType::Triangle);
+    }
+    Ok(frame)
+}
+
+/// Computes the mean Rec. 709 luminance of an image, in the range `[0, 255]`.
+fn mean_luminance(image: &RgbaImage) -> f64 {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return 0.0;
+    }
+    let total: f64 = image.pixels()
+        .map(|p| 0.2126 * p[0] as f64 + 0.7152 * p[1] as f64 + 0.0722 * p[2] as f64)
+        .sum();
+    total / (width as f64 * height as f64)
+}
+
+/// Removes exposure flicker by pulling each frame's mean luminance towards the average luminance of
+/// the frames before it.
+#[derive(Clone, Debug)]
+pub struct Deflicker {
+    window: usize,
+    history: VecDeque<f64>,
+}
+
+impl Deflicker {
+    /// Creates a deflicker pass that averages over the last `window` frames, including the current
+    /// one.
+    pub fn new(window: usize) -> Deflicker {
+        Deflicker {
+            window: ::std::cmp::max(window, 1),
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Normalizes a frame's exposure towards the rolling average, and records it for the frames
+    /// that follow.
+    pub fn apply(&mut self, frame: DynamicImage) -> DynamicImage {
+        let mut image = frame.to_rgba();
+        let mean = mean_luminance(&image);
+
+        if self.history.len() == self.window {
+            self.history.pop_front();
+        }
+        self.history.push_back(mean);
+        let target = self.history.iter().sum::<f64>() / self.history.len() as f64;
+
+        if mean <= 0.0 || (target - mean).abs() < f64::EPSILON {
+            return DynamicImage::ImageRgba8(image);
+        }
+        let gain = target / mean;
+        apply_gains(&mut image, [gain; 3]);
+        DynamicImage::ImageRgba8(image)
+    }
+}
+
+/// Computes the mean of each color channel of an image.
+fn channel_means(image: &RgbaImage) -> [f64; 3] {
+    let (width, height) = image.dimensions();
+    let mut totals = [0f64; 3];
+    for pixel in image.pixels() {
+        for (total, &channel) in totals.iter_mut().zip(pixel.data.iter()) {
+            *total += channel as f64;
+        }
+    }
+    let count = ::std::cmp::max(width as u64 * height as u64, 1) as f64;
+    [totals[0] / count, totals[1] / count, totals[2] / count]
+}
+
+/// Multiplies each color channel of an image by the corresponding gain, leaving alpha alone.
+fn apply_gains(image: &mut RgbaImage, gains: [f64; 3]) {
+    for pixel in image.pixels_mut() {
+        for (channel, gain) in pixel.data.iter_mut().zip(gains.iter()) {
+            *channel = (*channel as f64 * gain).round().min(255.0) as u8;
+        }
+    }
+}
+
+/// How white balance should be estimated when normalizing it across frames.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum WhiteBalanceMode {
+    /// Assume every frame averages out to gray, and balance its channels accordingly.
+    GrayWorld,
+    /// Match every frame's channel balance to that of the first frame.
+    Reference,
+}
+
+impl FromStr for WhiteBalanceMode {
+    type Err = Error;
+
+    /// Parses a white balance mode from its name: `gray-world` or `reference`.
+    fn from_str(s: &str) -> Result<WhiteBalanceMode> {
+        match s {
+            "gray-world" => Ok(WhiteBalanceMode::GrayWorld),
+            "reference" => Ok(WhiteBalanceMode::Reference),
+            _ => {
+                let expected = "gray-world or reference";
+                bail!(ErrorKind::CouldNotParseOption("white balance mode", s.into(), expected))
+            }
+        }
+    }
+}
+
+/// Aligns white balance across frames, so that white balance drift doesn't show up as colored
+/// stripes in the output.
+#[derive(Clone, Debug)]
+pub struct WhiteBalance {
+    mode: WhiteBalanceMode,
+    reference: Option<[f64; 3]>,
+}
+
+impl WhiteBalance {
+    /// Creates a white balance normalization pass.
+    pub fn new(mode: WhiteBalanceMode) -> WhiteBalance {
+        WhiteBalance {
+            mode,
+            reference: None,
+        }
+    }
+
+    /// Normalizes the white balance of a frame.
+    pub fn apply(&mut self, frame: DynamicImage) -> DynamicImage {
+        let mut image = frame.to_rgba();
+        let means = channel_means(&image);
+        let target = match self.mode {
+            WhiteBalanceMode::GrayWorld => {
+                let gray = (means[0] + means[1] + means[2]) / 3.0;
+                [gray; 3]
+            }
+            WhiteBalanceMode::Reference => *self.reference.get_or_insert(means),
+        };
+
+        let mut gains = [1f64; 3];
+        for ((gain, &target), &mean) in gains.iter_mut().zip(target.iter()).zip(means.iter()) {
+            if mean > 0.0 {
+                *gain = target / mean;
+            }
+        }
+        apply_gains(&mut image, gains);
+        DynamicImage::ImageRgba8(image)
+    }
+}
+
+/// What to do with a frame whose dimensions differ from those of the first frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ResizePolicy {
+    /// Scale the frame so it fits inside the canvas, letterboxing the rest with transparency.
+    Fit,
+    /// Scale the frame so it covers the whole canvas, cropping whatever overflows.
+    Fill,
+    /// Scale the frame to the exact canvas size, ignoring its aspect ratio.
+    Stretch,
+    /// Refuse to process the frame.
+    Error,
+}
+
+impl FromStr for ResizePolicy {
+    type Err = Error;
+
+    /// Parses a resize policy from its name: `fit`, `fill`, `stretch` or `error`.
+    fn from_str(s: &str) -> Result<ResizePolicy> {
+        match s {
+            "fit" => Ok(ResizePolicy::Fit),
+            "fill" => Ok(ResizePolicy::Fill),
+            "stretch" => Ok(ResizePolicy::Stretch),
+            "error" => Ok(ResizePolicy::Error),
+            _ => {
+                let expected = "fit, fill, stretch or error";
+                bail!(ErrorKind::CouldNotParseOption("resize policy", s.into(), expected))
+            }
+        }
+    }
+}
+
+/// Computes the size a frame should be scaled to under the given policy, along with the offset at
+/// which the scaled frame should be placed onto the canvas. A negative offset means the scaled
+/// frame overflows the canvas and must be cropped.
+fn scaled_placement(frame: (u32, u32),
+                    canvas: (u32, u32),
+                    policy: ResizePolicy)
+                    -> ((u32, u32), (i64, i64)) {
+    let (fw, fh) = (frame.0 as f64, frame.1 as f64);
+    let (cw, ch) = (canvas.0 as f64, canvas.1 as f64);
+    let scale = match policy {
+        ResizePolicy::Fit => (cw / fw).min(ch / fh),
+        ResizePolicy::Fill => (cw / fw).max(ch / fh),
+        ResizePolicy::Stretch | ResizePolicy::Error => return (canvas, (0, 0)),
+    };
+    let width = ::std::cmp::max((fw * scale).round() as u32, 1);
+    let height = ::std::cmp::max((fh * scale).round() as u32, 1);
+    let x = (canvas.0 as i64 - width as i64) / 2;
+    let y = (canvas.1 as i64 - height as i64) / 2;
+    ((width, height), (x, y))
+}
+
+/// Makes sure a frame has the same dimensions as the canvas, rescaling it according to `policy` if
+/// it does not.
+///
+/// # Arguments
+/// * `frame` - The decoded frame.
+/// * `canvas` - The dimensions of the output canvas.
+/// * `policy` - How to deal with a frame of the wrong size.
+/// * `path` - The path the frame was loaded from, for error reporting.
+///
+/// # Errors
+/// This fails if the frame has the wrong size and the policy is `ResizePolicy::Error`.
+pub fn conform_frame(frame: DynamicImage,
+                     canvas: (u32, u32),
+                     policy: ResizePolicy,
+                     path: &Path)
+                     -> Result<DynamicImage> {
+    let dimensions = frame.dimensions();
+    if dimensions == canvas {
+        return Ok(frame);
+    }
+
+    match policy {
+        ResizePolicy::Error => {
+            bail!(ErrorKind::FrameSizeMismatch(path.to_path_buf(), canvas, dimensions))
+        }
+        ResizePolicy::Stretch => Ok(frame.resize_exact(canvas.0, canvas.1, FilterType::Triangle)),
+        ResizePolicy::Fit | ResizePolicy::Fill => {
+            let ((width, height), (x, y)) = scaled_placement(dimensions, canvas, policy);
+            let mut scaled = frame.resize_exact(width, height, FilterType::Triangle);
+            if x < 0 || y < 0 {
+                // The scaled frame overflows the canvas, so take the middle of it.
+                let crop_x = ::std::cmp::max(-x, 0) as u32;
+                let crop_y = ::std::cmp::max(-y, 0) as u32;
+                scaled = scaled.crop(crop_x,
+                                     crop_y,
+                                     ::std::cmp::min(width, canvas.0),
+                                     ::std::cmp::min(height, canvas.1));
+            }
+            let mut buf: RgbaImage = ImageBuffer::new(canvas.0, canvas.1);
+            buf.copy_from(&scaled,
+                          ::std::cmp::max(x, 0) as u32,
+                          ::std::cmp::max(y, 0) as u32);
+            Ok(DynamicImage::ImageRgba8(buf))
+        }
+    }
+}
+
+/// The filter used to denoise frames.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DenoiseFilter {
+    /// Averages each pixel's neighbourhood.
+    Box,
+    /// Takes the median of each pixel's neighbourhood, per channel.
+    Median,
+    /// Averages each pixel's neighbourhood, weighted by both distance and color similarity so that
+    /// edges are preserved.
+    Bilateral,
+}
+
+impl FromStr for DenoiseFilter {
+    type Err = Error;
+
+    /// Parses a denoise filter from its name: `box`, `median` or `bilateral`.
+    fn from_str(s: &str) -> Result<DenoiseFilter> {
+        match s {
+            "box" => Ok(DenoiseFilter::Box),
+            "median" => Ok(DenoiseFilter::Median),
+            "bilateral" => Ok(DenoiseFilter::Bilateral),
+            _ => {
+                let expected = "box, median or bilateral";
+                bail!(ErrorKind::CouldNotParseOption("denoise filter", s.into(), expected))
+            }
+        }
+    }
+}
+
+/// Describes how frames should be denoised.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Denoise {
+    /// The filter to use.
+    pub filter: DenoiseFilter,
+    /// The radius of the neighbourhood around each pixel.
+    pub radius: u32,
+}
+
+/// The color distance at which the bilateral filter's range weight falls to `1/e`.
+const BILATERAL_RANGE_SIGMA: f32 = 25.0;
+
+impl Denoise {
+    /// Denoises an image, averaging in linear light if `linear` is set.
+    pub fn apply(&self, image: &RgbaImage, linear: bool) -> RgbaImage {
+        // Maps each channel value to the space it is averaged in, and back again.
+        let table = if linear {
+            color_processing::srgb_to_linear_table()
+        } else {
+            let mut table = [0f32; 256];
+            for (i, entry) in table.iter_mut().enumerate() {
+                *entry = i as f32;
+            }
+            table
+        };
+        let encode = |value: f32| if linear {
+            color_processing::linear_to_srgb(value)
+        } else {
+            value.round().clamp(0.0, 255.0) as u8
+        };
+
+        let (width, height) = image.dimensions();
+        let r = self.radius as i64;
+        let spatial_sigma = ::std::cmp::max(self.radius, 1) as f32;
+        let mut neighbourhood = Vec::with_capacity(((2 * r + 1) * (2 * r + 1)) as usize);
+
+        let mut buf: RgbaImage = ImageBuffer::new(width, height);
+        for (x, y, out) in buf.enumerate_pixels_mut() {
+            neighbourhood.clear();
+            for dy in -r..(r + 1) {
+                for dx in -r..(r + 1) {
+                    let nx = x as i64 + dx;
+                    let ny = y as i64 + dy;
+                    if nx >= 0 && ny >= 0 && nx < width as i64 && ny < height as i64 {
+                        neighbourhood.push((dx, dy, *image.get_pixel(nx as u32, ny as u32)));
+                    }
+                }
+            }
+
+            let center = *image.get_pixel(x, y);
+            let mut result = center;
+            for c in 0..3 {
+                result.data[c] = match self.filter {
+                    DenoiseFilter::Box => {
+                        let total: f32 =
+                            neighbourhood.iter().map(|n| table[n.2[c] as usize]).sum();
+                        encode(total / neighbourhood.len() as f32)
+                    }
+                    DenoiseFilter::Median => {
+                        let mut values: Vec<u8> = neighbourhood.iter().map(|n| n.2[c]).collect();
+                        values.sort();
+                        values[values.len() / 2]
+                    }
+                    DenoiseFilter::Bilateral => {
+                        let mut total = 0f32;
+                        let mut weights = 0f32;
+                        for &(dx, dy, pixel) in &neighbourhood {
+                            let distance = (dx * dx + dy * dy) as f32;
+                            let range = pixel[c] as f32 - center[c] as f32;
+                            let weight = (-distance / (2.0 * spatial_sigma * spatial_sigma) -
+                                          range * range /
+                                          (2.0 * BILATERAL_RANGE_SIGMA * BILATERAL_RANGE_SIGMA))
+                                .exp();
+                            total += weight * pixel[c] as f32;
+                            weights += weight;
+                        }
+                        (total / weights).round() as u8
+                    }
+                };
+            }
+            *out = result;
+        }
+        buf
+    }
+}
+
+/// Averages a group of consecutive frames into a single frame.
+///
+/// A group of one frame is passed through untouched.
+#[derive(Clone)]
+pub struct Preblend {
+    linear: bool,
+    first: Option<DynamicImage>,
+    sum: Vec<f32>,
+    dimensions: (u32, u32),
+    count: u32,
+}
+
+impl Preblend {
+    /// Creates an empty group, which averages in linear light if `linear` is set.
+    pub fn new(linear: bool) -> Preblend {
+        Preblend {
+            linear,
+            first: None,
+            sum: vec![],
+            dimensions: (0, 0),
+            count: 0,
+        }
+    }
+
+    fn accumulate(&mut self, frame: &DynamicImage) {
+        let image = frame.to_rgba();
+        if self.sum.is_empty() {
+            self.dimensions = image.dimensions();
+            self.sum = vec![0.0; image.len()];
+        }
+        let table = color_processing::srgb_to_linear_table();
+        for (i, (total, &value)) in self.sum.iter_mut().zip(image.iter()).enumerate() {
+            // Alpha is never gamma-encoded.
+            *total += if self.linear && i % 4 != 3 {
+                table[value as usize]
+            } else {
+                value as f32
+            };
+        }
+    }
+
+    /// Adds a frame to the group. Every frame must have the same dimensions.
+    pub fn add(&mut self, frame: DynamicImage) {
+        if self.count == 0 {
+            self.first = Some(frame);
+        } else {
+            if let Some(first) = self.first.take() {
+                self.accumulate(&first);
+            }
+            self.accumulate(&frame);
+        }
+        self.count += 1;
+    }
+
+    /// Produces the average of every frame in the group, or `None` if it is empty.
+    pub fn finish(self) -> Option<DynamicImage> {
+        if self.count <= 1 {
+            return self.first;
+        }
+        let count = self.count as f32;
+        let linear = self.linear;
+        let data = self.sum
+            .iter()
+            .enumerate()
+            .map(|(i, &total)| if linear && i % 4 != 3 {
+                color_processing::linear_to_srgb::<u8>(total / count)
+            } else {
+                (total / count).round().clamp(0.0, 255.0) as u8
+            })
+            .collect();
+        let (width, height) = self.dimensions;
+        ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+    }
+
+    /// Produces the average of every frame in the group at 16 bits per channel, keeping the
+    /// precision that averaging gains over the 8-bit frames, or `None` if it is empty.
+    pub fn finish_wide(self) -> Option<Rgba16Image> {
+        if self.count <= 1 {
+            return self.first.map(|first| color_processing::to_16_bit(&first.to_rgba()));
+        }
+        let count = self.count as f32;
+        let linear = self.linear;
+        let data = self.sum
+            .iter()
+            .enumerate()
+            .map(|(i, &total)| if linear && i % 4 != 3 {
+                color_processing::linear_to_srgb::<u16>(total / count)
+            } else {
+                u16::from_unit(total / count / 255.0)
+            })
+            .collect();
+        let (width, height) = self.dimensions;
+        ImageBuffer::from_raw(width, height, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use ::testing::frame_dir;
+
+    #[test]
+    fn test_load_frame() {
+        let dir = frame_dir("load-frame", vec![]);
+        let path = dir.join("frame.png");
+        let frame = ImageBuffer::from_fn(3, 4, |x, y| image::Rgb([x as u8, 10 * y as u8, 7]));
+        frame.save(&path).unwrap();
+        let loaded = load_frame(&path, &FrameTransforms::default()).unwrap();
+        assert_eq!(loaded.to_rgb().into_raw(), frame.into_raw());
+
+        let pool = FramePool::new(1);
+        let pooled = load_frame_pooled(&path, &FrameTransforms::default(), (3, 4), &pool).unwrap();
+        assert_eq!(pooled.to_rgba().into_raw(), loaded.to_rgba().into_raw());
+
+        let empty = dir.join("empty.png");
+        ::std::fs::write(&empty, b"").unwrap();
+        assert!(load_frame(&empty, &FrameTransforms::default()).is_err());
+        assert!(load_frame(&dir.join("missing.png"), &FrameTransforms::default()).is_err());
+    }
+
+    #[test]
+    fn test_frame_pool() {
+        let pool = FramePool::new(1);
+        let frame = || DynamicImage::ImageRgba8(ImageBuffer::new(2, 2));
+        pool.recycle(frame());
+        pool.recycle(frame());
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.take(16).len(), 16);
+        assert!(pool.is_empty());
+        // Only RGBA frames can be decoded into again.
+        pool.recycle(DynamicImage::ImageRgb8(ImageBuffer::new(2, 2)));
+        assert!(pool.is_empty());
+        assert_eq!(pool.take(8), vec![0; 8]);
+    }
+
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    #[test]
+    fn test_file_window() {
+        static WINDOW: FileWindow = FileWindow::new(2);
+        let first = WINDOW.try_take().unwrap();
+        // The last place is left for files opened the usual way.
+        assert!(WINDOW.try_take().is_none());
+        let second = WINDOW.wait();
+        let waiting = ::std::thread::spawn(|| {
+            WINDOW.wait();
+        });
+        drop(first);
+        waiting.join().unwrap();
+        // Raising the limit makes room straight away.
+        WINDOW.set_limit(3);
+        let _third = WINDOW.try_take().unwrap();
+        drop(second);
+        assert_eq!(*WINDOW.open.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_load_rows() {
+        let dir = frame_dir("load-rows", vec![]);
+        let path = dir.join("frame.png");
+        let frame = ImageBuffer::from_fn(3, 4, |x, y| image::Rgb([x as u8, 10 * y as u8, 7]));
+        frame.save(&path).unwrap();
+
+        let pool = FramePool::new(1);
+        // A buffer left over from another frame is cleared outside the rows.
+        pool.recycle(DynamicImage::ImageRgba8(ImageBuffer::from_pixel(3, 4, Rgba([9; 4]))));
+        let rows = load_rows(&path, 1..3, (3, 4), &pool).unwrap().to_rgba();
+        assert!(pool.is_empty());
+        for (x, y, pixel) in rows.enumerate_pixels() {
+            match y {
+                1 | 2 => assert_eq!(pixel, &Rgba([x as u8, 10 * y as u8, 7, 255])),
+                _ => assert_eq!(pixel, &Rgba([0, 0, 0, 0])),
+            }
+        }
+        assert!(load_rows(&path, 1..3, (4, 3), &pool).is_none());
+        assert!(load_rows(&path, 3..5, (3, 4), &pool).is_none());
+
+        let text = dir.join("frame.txt");
+        ::std::fs::write(&text, b"not an image").unwrap();
+        assert!(load_rows(&text, 1..3, (3, 4), &pool).is_none());
+    }
+
+    #[test]
+    fn test_load_uniform() {
+        use image::ColorType;
+        use image::jpeg::JPEGEncoder;
+
+        let frame = ImageBuffer::from_fn(8, 4, |x, y| image::Rgb([x as u8 * 30, y as u8 * 60, 90]));
+        let mut data = vec![];
+        JPEGEncoder::new_with_quality(&mut data, 100)
+            .encode(&frame, 8, 4, ColorType::RGB(8))
+            .unwrap();
+        let mut layout = FrameLayout {
+            format: image::ImageFormat::JPEG,
+            dimensions: (8, 4),
+            color: ColorType::RGB(8),
+        };
+        let pool = FramePool::new(1);
+        match load_uniform(&data, &layout, 0..4, &pool) {
+            Some(DynamicImage::ImageRgba8(ref frame)) => assert_eq!(frame.dimensions(), (8, 4)),
+            _ => assert!(false, "expected the frame to be decoded straight into RGBA."),
+        }
+        // A frame without the layout after all is left to be decoded the usual way.
+        layout.dimensions = (4, 8);
+        assert!(load_uniform(&data, &layout, 0..8, &pool).is_none());
+        layout.format = image::ImageFormat::GIF;
+        assert!(load_uniform(&data, &layout, 0..8, &pool).is_none());
+
+        let mut out = [0; 8];
+        expand_to_rgba(&[1, 2], 1, &mut out);
+        assert_eq!(out, [1, 1, 1, 255, 2, 2, 2, 255]);
+        expand_to_rgba(&[1, 2, 3, 4], 2, &mut out);
+        assert_eq!(out, [1, 1, 1, 2, 3, 3, 3, 4]);
+        expand_to_rgba(&[1, 2, 3, 4, 5, 6], 3, &mut out);
+        assert_eq!(out, [1, 2, 3, 255, 4, 5, 6, 255]);
+    }
+
+    #[test]
+    fn test_load_strip() {
+        let dir = frame_dir("load-strip", vec![]);
+        let path = dir.join("frame.png");
+        let frame = ImageBuffer::from_fn(3, 4, |x, y| image::Rgb([x as u8, 10 * y as u8, 7]));
+        frame.save(&path).unwrap();
+
+        let strip = load_strip(&path, 1..3, (3, 4), ResizePolicy::Error).unwrap().to_rgba();
+        assert_eq!(strip.dimensions(), (3, 2));
+        for (x, y, pixel) in strip.enumerate_pixels() {
+            assert_eq!(pixel, &Rgba([x as u8, 10 * (y as u8 + 1), 7, 255]));
+        }
+
+        // Frames of other dimensions are decoded in full and conformed first.
+        let strip = load_strip(&path, 2..6, (6, 8), ResizePolicy::Stretch).unwrap();
+        assert_eq!(strip.dimensions(), (6, 4));
+        assert!(load_strip(&path, 2..6, (6, 8), ResizePolicy::Error).is_err());
+    }
+
+    #[test]
+    fn test_parse_transforms() {
+        assert_eq!("270".parse::<Rotation>().unwrap(), Rotation::R270);
+        assert_eq!("v".parse::<Flip>().unwrap(), Flip::Vertical);
+        match "45".parse::<Rotation>() {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "45"),
+            result => assert!(false, "expected a parse error, got {:?}.", result),
+        }
+        match "x".parse::<Flip>() {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "x"),
+            result => assert!(false, "expected a parse error, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn test_transformed_dimensions() {
+        let mut transforms = FrameTransforms::default();
+        assert_eq!(transforms.dimensions((1920, 1080)), (1920, 1080));
+        transforms.rotate = Some(Rotation::R90);
+        assert_eq!(transforms.dimensions((1920, 1080)), (1080, 1920));
+        transforms.crop = Some(Region {
+            x: 0,
+            y: 0,
+            width: 1000,
+            height: 800,
+        });
+        transforms.scale = Some(0.5);
+        assert_eq!(transforms.dimensions((1920, 1080)), (500, 400));
+    }
+
+    #[test]
+    fn test_scale_dimension() {
+        assert_eq!(scale_dimension(1920, 0.25), 480);
+        assert_eq!(scale_dimension(1081, 0.5), 541);
+        assert_eq!(scale_dimension(3, 0.1), 1);
+    }
+
+    #[test]
+    fn test_deflicker() {
+        let frame = |value: u8| {
+            DynamicImage::ImageRgba8(ImageBuffer::from_pixel(4, 4, ::image::Rgba([value; 4])))
+        };
+
+        let mut deflicker = Deflicker::new(2);
+        // The first frame has nothing to be compared against.
+        assert_eq!(deflicker.apply(frame(100)).to_rgba().get_pixel(0, 0)[0], 100);
+        // The second frame is pulled towards the average of the two.
+        assert_eq!(deflicker.apply(frame(120)).to_rgba().get_pixel(0, 0)[0], 110);
+        // The window only covers the last two frames.
+        assert_eq!(deflicker.apply(frame(120)).to_rgba().get_pixel(0, 0)[0], 120);
+        // Alpha is left alone.
+        assert_eq!(deflicker.apply(frame(100)).to_rgba().get_pixel(0, 0)[3], 100);
+    }
+
+    #[test]
+    fn test_parse_white_balance_mode() {
+        assert_eq!("gray-world".parse::<WhiteBalanceMode>().unwrap(), WhiteBalanceMode::GrayWorld);
+        match "auto".parse::<WhiteBalanceMode>() {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "auto"),
+            result => assert!(false, "expected a parse error, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn test_white_balance() {
+        let frame = |r: u8, g: u8, b: u8| {
+            DynamicImage::ImageRgba8(ImageBuffer::from_pixel(4, 4, ::image::Rgba([r, g, b, 255])))
+        };
+
+        let mut gray_world = WhiteBalance::new(WhiteBalanceMode::GrayWorld);
+        let balanced = gray_world.apply(frame(90, 100, 110)).to_rgba();
+        assert_eq!(balanced.get_pixel(0, 0).data, [100, 100, 100, 255]);
+
+        let mut reference = WhiteBalance::new(WhiteBalanceMode::Reference);
+        let first = reference.apply(frame(90, 100, 110)).to_rgba();
+        assert_eq!(first.get_pixel(0, 0).data, [90, 100, 110, 255]);
+        let second = reference.apply(frame(45, 50, 55)).to_rgba();
+        assert_eq!(second.get_pixel(0, 0).data, [90, 100, 110, 255]);
+    }
+
+    #[test]
+    fn test_parse_denoise_filter() {
+        assert_eq!("median".parse::<DenoiseFilter>().unwrap(), DenoiseFilter::Median);
+        match "gaussian".parse::<DenoiseFilter>() {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "gaussian"),
+            result => assert!(false, "expected a parse error, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn test_denoise() {
+        // A flat gray image with a single hot pixel in the middle.
+        let gray = ::image::Rgba([100, 100, 100, 255]);
+        let mut image: RgbaImage = ImageBuffer::from_pixel(5, 5, gray);
+        image.put_pixel(2, 2, ::image::Rgba([190, 190, 190, 255]));
+
+        let median = Denoise {
+            filter: DenoiseFilter::Median,
+            radius: 1,
+        };
+        assert_eq!(median.apply(&image, false).get_pixel(2, 2).data, [100, 100, 100, 255]);
+
+        let box_filter = Denoise {
+            filter: DenoiseFilter::Box,
+            radius: 1,
+        };
+        assert_eq!(box_filter.apply(&image, false).get_pixel(2, 2).data, [110, 110, 110, 255]);
+        assert_eq!(box_filter.apply(&image, false).get_pixel(0, 0).data, [100, 100, 100, 255]);
+
+        let bilateral = Denoise {
+            filter: DenoiseFilter::Bilateral,
+            radius: 1,
+        };
+        let smoothed = bilateral.apply(&image, false);
+        assert_eq!(smoothed.get_pixel(0, 0).data, [100, 100, 100, 255]);
+        assert!(smoothed.get_pixel(1, 1)[0] < 105);
+
+        // Averaging black and white in linear light gives a brighter result than in sRGB.
+        let mut split: RgbaImage = ImageBuffer::from_pixel(2, 1, ::image::Rgba([0, 0, 0, 255]));
+        split.put_pixel(1, 0, ::image::Rgba([255, 255, 255, 255]));
+        assert_eq!(box_filter.apply(&split, false).get_pixel(0, 0)[0], 128);
+        assert_eq!(box_filter.apply(&split, true).get_pixel(0, 0)[0], 188);
+    }
+
+    #[test]
+    fn test_preblend() {
+        let frame = |value: u8| {
+            let image = ImageBuffer::from_pixel(2, 2, image::Rgba([value, 0, 255, 255]));
+            DynamicImage::ImageRgba8(image)
+        };
+
+        assert!(Preblend::new(false).finish().is_none());
+
+        let mut single = Preblend::new(false);
+        single.add(frame(7));
+        assert_eq!(single.finish().unwrap().to_rgba().get_pixel(0, 0).data, [7, 0, 255, 255]);
+
+        let mut group = Preblend::new(false);
+        for &value in &[0, 100, 200] {
+            group.add(frame(value));
+        }
+        assert_eq!(group.finish().unwrap().to_rgba().get_pixel(1, 1).data, [100, 0, 255, 255]);
+
+        // Averaging black and white in linear light gives a lighter gray than in sRGB.
+        let mut linear = Preblend::new(true);
+        linear.add(frame(0));
+        linear.add(frame(255));
+        assert_eq!(linear.finish().unwrap().to_rgba().get_pixel(0, 0).data, [188, 0, 255, 255]);
+
+        // A third of the way from 0 to 1 rounds to 0 at 8 bits, but not at 16.
+        let mut wide = Preblend::new(false);
+        for &value in &[0, 0, 1] {
+            wide.add(frame(value));
+        }
+        assert_eq!(wide.finish_wide().unwrap().get_pixel(0, 0).data, [86, 0, 65535, 65535]);
+        let mut single = Preblend::new(false);
+        single.add(frame(7));
+        assert_eq!(single.finish_wide().unwrap().get_pixel(0, 0).data, [7 * 257, 0, 65535, 65535]);
+    }
+
+    #[test]
+    fn test_parse_resize_policy() {
+        assert_eq!("fill".parse::<ResizePolicy>().unwrap(), ResizePolicy::Fill);
+        assert_eq!("error".parse::<ResizePolicy>().unwrap(), ResizePolicy::Error);
+        match "crop".parse::<ResizePolicy>() {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "crop"),
+            result => assert!(false, "expected a parse error, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn test_scaled_placement() {
+        // A frame with the same aspect ratio is simply scaled.
+        assert_eq!(scaled_placement((320, 240), (640, 480), ResizePolicy::Fit),
+                   ((640, 480), (0, 0)));
+        assert_eq!(scaled_placement((320, 240), (640, 480), ResizePolicy::Fill),
+                   ((640, 480), (0, 0)));
+
+        // A square frame is letterboxed when fitting, and cropped when filling.
+        assert_eq!(scaled_placement((100, 100), (640, 480), ResizePolicy::Fit),
+                   ((480, 480), (80, 0)));
+        assert_eq!(scaled_placement((100, 100), (640, 480), ResizePolicy::Fill),
+                   ((640, 640), (0, -80)));
+
+        assert_eq!(scaled_placement((100, 100), (640, 480), ResizePolicy::Stretch),
+                   ((640, 480), (0, 0)));
+    }
+}
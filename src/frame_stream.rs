@@ -0,0 +1,182 @@
+use futures_core::Stream;
+use image::{DynamicImage, RgbaImage};
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use ::errors::{ErrorKind, Result, ResultExt};
+use ::frame_info::FrameInfo;
+use ::frame_processing;
+use ::image_processing::{Compositor, RenderOptions};
+use ::progress::ProgressSink;
+
+/// A render from a stream of frames, such as frames fetched over HTTP or split out of an MJPEG
+/// stream, that resolves to the composite once it is done.
+///
+/// Only waiting for frames is asynchronous: each band is composited as soon as its frames have
+/// arrived, on whichever task polls the render, so it works with tokio or any other runtime.
+pub struct StreamRender<'a, S, P> {
+    frames: S,
+    /// The index of the frame the stream yields next.
+    position: usize,
+    /// The composite being built, until the render resolves.
+    compositor: Option<Compositor<'a>>,
+    progress: P,
+}
+
+/// Renders the composite of `count` frames from `frames`, the way `image_processing::render`
+/// does, waiting for each frame without blocking the thread.
+///
+/// Frames that no band is taken from are still read from the stream, but are dropped without being
+/// decoded further or transformed. Once the last band is in the render resolves, without waiting
+/// for the frames after it.
+///
+/// # Errors
+/// The render fails for the same reasons as `image_processing::composite_frames`, if the stream
+/// yields an error, or if it ends before `count` frames. This returns an error straight away if
+/// `count` is zero, or if the options ask for something a custom geometry doesn't support.
+pub fn render<S, P>(options: &RenderOptions,
+                    count: usize,
+                    frames: S,
+                    progress: P)
+                    -> Result<StreamRender<S, P>>
+    where S: Stream<Item = Result<DynamicImage>> + Unpin,
+          P: ProgressSink + Unpin
+{
+    Ok(StreamRender {
+        frames,
+        position: 0,
+        compositor: Some(Compositor::new(count, options)?),
+        progress,
+    })
+}
+
+impl<'a, S, P> Future for StreamRender<'a, S, P>
+    where S: Stream<Item = Result<DynamicImage>> + Unpin,
+          P: ProgressSink + Unpin
+{
+    type Output = Result<RgbaImage>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<RgbaImage>> {
+        let this = self.get_mut();
+        loop {
+            let index = {
+                let compositor = this.compositor
+                    .as_ref()
+                    .expect("the render was polled after it resolved");
+                compositor.next_index()
+            };
+            let index = match index {
+                Some(index) => index,
+                None => {
+                    let compositor = this.compositor.take().unwrap();
+                    return Poll::Ready(compositor.finish(&mut this.progress));
+                }
+            };
+
+            let frame = match Pin::new(&mut this.frames).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Ok(frame))) => frame,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                Poll::Ready(None) => {
+                    let count = this.compositor.as_ref().unwrap().count;
+                    let error = ErrorKind::StreamEnded(this.position, count);
+                    return Poll::Ready(Err(error.into()));
+                }
+            };
+            this.position += 1;
+            if this.position <= index {
+                continue;
+            }
+
+            let compositor = this.compositor.as_mut().unwrap();
+            let progress = &mut this.progress;
+            let info = FrameInfo::unnamed(index);
+            let result = frame_processing::transform_frame(frame,
+                                                           &compositor.options.transforms,
+                                                           &info.path)
+                .and_then(|frame| compositor.push(frame, &info, progress))
+                .chain_err(|| ErrorKind::Frame(info));
+            if let Err(e) = result {
+                this.compositor = None;
+                return Poll::Ready(Err(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{ImageBuffer, Rgba};
+
+    use std::collections::VecDeque;
+    use std::task::Waker;
+
+    use ::Direction;
+    use ::errors::Error;
+    use ::progress::NoProgress;
+
+    /// Yields its frames, but only every other time it is polled, like a slow network source.
+    struct Slow {
+        frames: VecDeque<DynamicImage>,
+        ready: bool,
+    }
+
+    impl Stream for Slow {
+        type Item = Result<DynamicImage>;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<DynamicImage>>> {
+            let this = self.get_mut();
+            this.ready = !this.ready;
+            if this.ready {
+                Poll::Ready(this.frames.pop_front().map(Ok))
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    fn solid(value: u8) -> DynamicImage {
+        DynamicImage::ImageRgba8(ImageBuffer::from_pixel(2, 3, Rgba([value, value, value, 255])))
+    }
+
+    fn block_on<F: Future + Unpin>(mut future: F) -> F::Output {
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = Pin::new(&mut future).poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let options = RenderOptions {
+            direction: Direction::N,
+            ..RenderOptions::default()
+        };
+        let slow = |count: u8| {
+            Slow {
+                frames: (0..count).map(|i| solid(10 * (i + 1))).collect(),
+                ready: false,
+            }
+        };
+        let composite = block_on(render(&options, 3, slow(3), NoProgress).unwrap()).unwrap();
+        assert_eq!(composite.get_pixel(0, 1), &Rgba([20, 20, 20, 255]));
+
+        // The three lines take bands from the first three frames, so the last two are left unread.
+        let mut frames = slow(5);
+        let composite = block_on(render(&options, 5, &mut frames, NoProgress).unwrap()).unwrap();
+        assert_eq!(composite.get_pixel(0, 2), &Rgba([30, 30, 30, 255]));
+        assert_eq!(frames.frames.len(), 2);
+
+        match block_on(render(&options, 3, slow(2), NoProgress).unwrap()) {
+            Err(Error(ErrorKind::StreamEnded(2, 3), _)) => (),
+            result => assert!(false, "expected the stream to end early, got {:?}.", result.is_ok()),
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use image::{ImageBuffer, Rgba, RgbaImage};
+
+use std::f64::consts::PI;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use ::debug_overlay;
+use ::errors::{Error, ErrorKind, Result, ResultExt};
+use ::frame_info::FrameInfo;
+use ::output_processing::{self, EncoderSettings, OutputFormat};
+use ::progress::ProgressSink;
+
+/// The colors the patterns are drawn in.
+const BACKGROUND: Rgba<u8> = Rgba { data: [24, 24, 32, 255] };
+const FOREGROUND: Rgba<u8> = Rgba { data: [240, 240, 240, 255] };
+const ACCENT: Rgba<u8> = Rgba { data: [220, 60, 40, 255] };
+const SHADE: Rgba<u8> = Rgba { data: [48, 48, 64, 255] };
+
+/// How many times the spinner turns over the whole sequence. A fast spinner shows off the effect
+/// best, since each blade is caught at many angles by the time the shutter has passed it.
+const SPINNER_TURNS: f64 = 2.0;
+
+/// How many blades the spinner has.
+const SPINNER_BLADES: u32 = 3;
+
+/// How many bars there are across the frame.
+const BAR_COUNT: u32 = 8;
+
+/// How many numbered stripes a calibration frame has. One is lit in each frame, moving along by
+/// one every frame.
+const CALIBRATION_STRIPES: u32 = 10;
+
+/// The phase progress through the frames is reported under.
+const GENERATING: &str = "Generating frames:";
+
+/// A synthetic animation to generate frames of.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Pattern {
+    /// A propeller turning about the center of the frame.
+    Spinner,
+    /// Vertical bars sliding across the frame, moving one frame width over the sequence.
+    Bars,
+    /// A clock face whose hand sweeps once around it over the sequence.
+    Clock,
+    /// A timing grid for measuring a real camera's rolling shutter: numbered stripes, one of which
+    /// is lit in each frame in turn, and a line turning once over the sequence. Photographed off a
+    /// screen playing the frames, the lit stripes and the bend of the line show how many frames
+    /// the camera takes to read out its sensor.
+    Calibrate,
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    /// Parses a pattern from its name: `spinner`, `bars`, `clock` or `calibrate`.
+    fn from_str(s: &str) -> Result<Pattern> {
+        match s {
+            "spinner" => Ok(Pattern::Spinner),
+            "bars" => Ok(Pattern::Bars),
+            "clock" => Ok(Pattern::Clock),
+            "calibrate" => Ok(Pattern::Calibrate),
+            _ => {
+                let expected = "spinner, bars, clock or calibrate";
+                bail!(ErrorKind::CouldNotParseOption("pattern", s.into(), expected))
+            }
+        }
+    }
+}
+
+/// Whether a point, relative to the center of the frame, lies on the segment that runs from
+/// `from` to `to` along the ray at `angle`, measured clockwise from the right, with the given half
+/// width.
+fn on_segment((dx, dy): (f64, f64), angle: f64, from: f64, to: f64, half_width: f64) -> bool {
+    let (sin, cos) = angle.sin_cos();
+    let along = dx * cos + dy * sin;
+    let across = dy * cos - dx * sin;
+    (from..=to).contains(&along) && across.abs() <= half_width
+}
+
+/// Computes one pixel of the `index`th of `frames` frames of a pattern.
+fn pattern_pixel(pattern: Pattern,
+                 (x, y): (u32, u32),
+                 (width, height): (u32, u32),
+                 index: u32,
+                 frames: u32)
+                 -> Rgba<u8> {
+    let t = index as f64 / frames as f64;
+    let point = (x as f64 + 0.5 - width as f64 / 2.0, y as f64 + 0.5 - height as f64 / 2.0);
+    let distance = (point.0 * point.0 + point.1 * point.1).sqrt();
+    let radius = ::std::cmp::min(width, height) as f64 * 0.45;
+    match pattern {
+        Pattern::Spinner => {
+            let blade = (0..SPINNER_BLADES).any(|blade| {
+                let angle = 2.0 * PI * (SPINNER_TURNS * t + blade as f64 / SPINNER_BLADES as f64);
+                on_segment(point, angle, 0.0, radius, radius * 0.08)
+            });
+            if distance <= radius * 0.12 {
+                ACCENT
+            } else if blade {
+                FOREGROUND
+            } else {
+                BACKGROUND
+            }
+        }
+        Pattern::Bars => {
+            let period = width as f64 / BAR_COUNT as f64;
+            let shifted = (x as f64 + 0.5 - t * width as f64).rem_euclid(period);
+            if shifted < period / 2.0 {
+                FOREGROUND
+            } else {
+                ACCENT
+            }
+        }
+        Pattern::Clock => {
+            // The hand starts at twelve o'clock.
+            let hand = 2.0 * PI * t - PI / 2.0;
+            let tick = (0..12).any(|hour| {
+                let angle = 2.0 * PI * hour as f64 / 12.0;
+                on_segment(point, angle, radius * 0.8, radius, radius * 0.03)
+            });
+            if distance > radius {
+                BACKGROUND
+            } else if on_segment(point, hand, 0.0, radius * 0.9, radius * 0.03) {
+                ACCENT
+            } else if tick || distance >= radius * 0.97 {
+                BACKGROUND
+            } else {
+                FOREGROUND
+            }
+        }
+        Pattern::Calibrate => {
+            let stripe = x * CALIBRATION_STRIPES / width;
+            let half_width = (radius * 0.02).max(1.0);
+            if on_segment(point, 2.0 * PI * t, -radius, radius, half_width) {
+                FOREGROUND
+            } else if stripe == index % CALIBRATION_STRIPES {
+                ACCENT
+            } else if stripe % 2 == 1 {
+                SHADE
+            } else {
+                BACKGROUND
+            }
+        }
+    }
+}
+
+/// Labels each stripe of a calibration frame with its number, and the frame with its index.
+fn label_calibration(buf: &mut RgbaImage, index: u32) {
+    let (width, height) = buf.dimensions();
+    let scale = ::std::cmp::max(1, height / 120) as i64;
+    for stripe in 0..CALIBRATION_STRIPES {
+        let x = (stripe * width / CALIBRATION_STRIPES) as i64 + 2 * scale;
+        debug_overlay::draw_number(buf, stripe, x, 2 * scale, scale);
+    }
+    debug_overlay::draw_number(buf, index, 2 * scale, height as i64 - 7 * scale, scale);
+}
+
+/// Draws the `index`th of `frames` frames of a pattern.
+pub fn frame(pattern: Pattern, size: (u32, u32), index: u32, frames: u32) -> RgbaImage {
+    let mut buf = ImageBuffer::from_fn(size.0, size.1, |x, y| {
+        pattern_pixel(pattern, (x, y), size, index, frames)
+    });
+    if pattern == Pattern::Calibrate {
+        label_calibration(&mut buf, index);
+    }
+    buf
+}
+
+/// The number of digits frame numbers are padded to, so that they sort in order.
+fn frame_digits(frames: u32) -> usize {
+    ::std::cmp::max(3, (frames - 1).to_string().len())
+}
+
+/// Generates a sequence of frames of a synthetic animation, saved into `dir` as numbered PNGs, to
+/// try out the effect without having to find footage. Returns the file mask that renders them.
+///
+/// # Arguments
+/// * `pattern` - What the frames show.
+/// * `frames` - How many frames to generate. There must be at least one.
+/// * `size` - The dimensions of each frame.
+/// * `dir` - The directory to save them to. It is created if it doesn't exist.
+/// * `progress` - Where to report progress through the frames.
+///
+/// # Errors
+/// This fails if the directory can't be created or a frame can't be saved.
+pub fn generate(pattern: Pattern,
+                frames: u32,
+                size: (u32, u32),
+                dir: &Path,
+                progress: &mut dyn ProgressSink)
+                -> Result<PathBuf> {
+    fs::create_dir_all(dir).chain_err(|| ErrorKind::CouldNotSaveOutput(dir.to_path_buf()))?;
+
+    let digits = frame_digits(frames);
+    for i in 0..frames {
+        let path = dir.join(format!("frame_{:0width$}.png", i, width = digits));
+        let info = FrameInfo { path: path.clone(), ..FrameInfo::unnamed(i as usize) };
+        progress.start(GENERATING, i as u64, frames as u64, &info);
+        let image = frame(pattern, size, i, frames);
+        output_processing::save(&image,
+                                &path,
+                                Some(OutputFormat::Png),
+                                None,
+                                None,
+                                &EncoderSettings::default())
+            .chain_err(|| ErrorKind::CouldNotSaveOutput(path.clone()))?;
+        progress.done(GENERATING, i as u64 + 1, frames as u64);
+    }
+    progress.finish(GENERATING);
+
+    Ok(dir.join(format!("frame_%0{}d.png", digits)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pattern() {
+        assert_eq!("clock".parse::<Pattern>().unwrap(), Pattern::Clock);
+        match "stripes".parse::<Pattern>() {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "stripes"),
+            result => assert!(false, "expected a parse error, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn test_on_segment() {
+        assert!(on_segment((5.0, 0.5), 0.0, 0.0, 10.0, 1.0));
+        assert!(!on_segment((-5.0, 0.0), 0.0, 0.0, 10.0, 1.0));
+        assert!(!on_segment((5.0, 2.0), 0.0, 0.0, 10.0, 1.0));
+        // Angles go clockwise, since y points down.
+        assert!(on_segment((0.0, 5.0), PI / 2.0, 0.0, 10.0, 1.0));
+    }
+
+    #[test]
+    fn test_frame_digits() {
+        assert_eq!(frame_digits(1), 3);
+        assert_eq!(frame_digits(1000), 3);
+        assert_eq!(frame_digits(1001), 4);
+    }
+
+    #[test]
+    fn test_frame() {
+        let bars = frame(Pattern::Bars, (16, 4), 0, 4);
+        assert_eq!(bars.get_pixel(0, 0), &FOREGROUND);
+        assert_eq!(bars.get_pixel(1, 3), &ACCENT);
+        // A quarter of the way through, the bars have moved a quarter of the width.
+        let moved = frame(Pattern::Bars, (16, 4), 1, 4);
+        assert_eq!(moved.get_pixel(4, 0), &FOREGROUND);
+        assert_eq!(moved.get_pixel(5, 0), &ACCENT);
+
+        let spinner = frame(Pattern::Spinner, (40, 40), 0, 4);
+        assert_eq!(spinner.get_pixel(20, 20), &ACCENT);
+        assert_eq!(spinner.get_pixel(30, 20), &FOREGROUND);
+        assert_eq!(spinner.get_pixel(20, 30), &BACKGROUND);
+
+        let clock = frame(Pattern::Clock, (40, 40), 1, 4);
+        assert_eq!(clock.get_pixel(28, 20), &ACCENT);
+        assert_eq!(clock.get_pixel(20, 12), &FOREGROUND);
+        assert_eq!(clock.get_pixel(0, 0), &BACKGROUND);
+
+        // The fourth frame lights the fourth stripe, with the line turned a little past level.
+        let calibration = frame(Pattern::Calibrate, (100, 60), 3, 120);
+        assert_eq!(calibration.get_pixel(35, 30), &ACCENT);
+        assert_eq!(calibration.get_pixel(15, 30), &SHADE);
+        assert_eq!(calibration.get_pixel(5, 30), &BACKGROUND);
+        assert_eq!(calibration.get_pixel(55, 30), &FOREGROUND);
+    }
+}
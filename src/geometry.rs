@@ -0,0 +1,57 @@
+use std::fmt::Debug;
+
+use ::Direction;
+use ::image_processing;
+
+/// The pixels a single band covers.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BandRegion {
+    /// A rectangle, given as its left and top edges, width and height.
+    Rect(u32, u32, u32, u32),
+    /// Any set of pixels, given as their coordinates, for patterns that don't sweep in straight
+    /// lines.
+    Pixels(Vec<(u32, u32)>),
+}
+
+/// A scan pattern: where each band of the composite is taken from as the shutter moves over the
+/// image. The cardinal directions are the built-in ones; other crates can add radial sweeps,
+/// spirals and the like by implementing this.
+pub trait ShutterGeometry: Debug + Send + Sync {
+    /// How many bands it takes to cover an image of the given dimensions, one line each.
+    fn band_count(&self, dimensions: (u32, u32)) -> u32;
+
+    /// The region the band at `index` covers in an image of the given dimensions, or `None` if
+    /// `index` is past the last band.
+    fn region_for_frame(&self, index: u32, dimensions: (u32, u32)) -> Option<BandRegion>;
+}
+
+impl ShutterGeometry for Direction {
+    fn band_count(&self, (width, height): (u32, u32)) -> u32 {
+        match *self {
+            Direction::N | Direction::S => height,
+            Direction::E | Direction::W => width,
+        }
+    }
+
+    fn region_for_frame(&self, index: u32, (width, height): (u32, u32)) -> Option<BandRegion> {
+        image_processing::generage_subimage_coords((0, 0, width, height), index, *self)
+            .map(|(x, y, width, height)| BandRegion::Rect(x, y, width, height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_direction() {
+        assert_eq!(Direction::E.band_count((4, 3)), 4);
+        assert_eq!(Direction::S.band_count((4, 3)), 3);
+        assert_eq!(Direction::S.region_for_frame(0, (4, 3)),
+                   Some(BandRegion::Rect(0, 2, 4, 1)));
+        assert_eq!(Direction::E.region_for_frame(1, (4, 3)),
+                   Some(BandRegion::Rect(2, 0, 1, 3)));
+        assert_eq!(Direction::N.region_for_frame(3, (4, 3)), None);
+    }
+}
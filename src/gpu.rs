@@ -0,0 +1,269 @@
+// Without the gpu feature only the stand-in compositor below is built, so the constants go unused.
+#![cfg_attr(not(feature = "gpu"), allow(dead_code))]
+
+/// Marks the pixels of the band map that no band reaches.
+pub const NO_BAND: u32 = u32::MAX;
+
+/// How many pixels each workgroup of the shader covers.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The most workgroups a dispatch may have along each axis.
+const MAX_WORKGROUPS: u32 = 65535;
+
+/// Copies the pixels of the frame whose band is the one being copied into the composite. Both
+/// are RGBA8, a pixel to a `u32`. Workgroups are laid out in rows of `stride` pixels, since there
+/// may be more of them than a single row of a dispatch holds.
+const SHADER: &str = r#"
+struct Params {
+    band: u32,
+    pixels: u32,
+    stride: u32,
+    padding: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> bands: array<u32>;
+@group(0) @binding(2) var<storage, read> frame: array<u32>;
+@group(0) @binding(3) var<storage, read_write> composite: array<u32>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.y * params.stride + id.x;
+    if i < params.pixels && bands[i] == params.band {
+        composite[i] = frame[i];
+    }
+}
+"#;
+
+/// The workgroups to dispatch for `pixels` pixels, as `(x, y)`, the first filling rows of up to
+/// `MAX_WORKGROUPS`.
+fn workgroups(pixels: u32) -> (u32, u32) {
+    let groups = ::std::cmp::max(pixels.div_ceil(WORKGROUP_SIZE), 1);
+    let x = ::std::cmp::min(groups, MAX_WORKGROUPS);
+    (x, groups.div_ceil(x))
+}
+
+#[cfg(feature = "gpu")]
+mod compositor {
+    use image::{ImageBuffer, RgbaImage};
+    use pollster;
+    use wgpu;
+    use wgpu::util::DeviceExt;
+
+    use std::sync::mpsc;
+
+    use ::errors::{ErrorKind, Result};
+    use super::{SHADER, WORKGROUP_SIZE, workgroups};
+
+    /// A composite held on the GPU. Every band is copied in by uploading its frame and running a
+    /// shader that takes the pixels the band map gives to the band.
+    pub struct GpuCompositor {
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        pipeline: wgpu::ComputePipeline,
+        bind_group: wgpu::BindGroup,
+        params: wgpu::Buffer,
+        frame: wgpu::Buffer,
+        composite: wgpu::Buffer,
+        dimensions: (u32, u32),
+    }
+
+    impl GpuCompositor {
+        /// Uploads the composite to start from and the map of the band each of its pixels is
+        /// taken from, row by row.
+        ///
+        /// # Errors
+        /// This fails if there is no GPU to use, or the composite is too large for it.
+        pub fn new(composite: &RgbaImage, bands: &[u32]) -> Result<GpuCompositor> {
+            let dimensions = composite.dimensions();
+            let size = dimensions.0 as u64 * dimensions.1 as u64 * 4;
+            debug_assert_eq!(bands.len() as u64 * 4, size);
+
+            let instance = wgpu::Instance::default();
+            let options = wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                ..Default::default()
+            };
+            let adapter = match pollster::block_on(instance.request_adapter(&options)) {
+                Some(adapter) => adapter,
+                None => bail!(ErrorKind::CouldNotUseGpu("no GPU was found".into())),
+            };
+            let limits = adapter.limits();
+            if size > limits.max_storage_buffer_binding_size as u64 {
+                bail!(ErrorKind::CouldNotUseGpu(format!("a {}x{} composite is larger than the \
+                                                         {} MiB the GPU can work on at once",
+                                                        dimensions.0,
+                                                        dimensions.1,
+                                                        limits.max_storage_buffer_binding_size >>
+                                                        20)));
+            }
+            let descriptor = wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::empty(),
+                required_limits: limits,
+            };
+            let (device, queue) = pollster::block_on(adapter.request_device(&descriptor, None))
+                .map_err(|e| ErrorKind::CouldNotUseGpu(e.to_string()))?;
+
+            let storage = |label, contents: &[u8], usage| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(label),
+                    contents,
+                    usage: wgpu::BufferUsages::STORAGE | usage,
+                })
+            };
+            let band_bytes: Vec<u8> = bands.iter().flat_map(|band| band.to_le_bytes()).collect();
+            let bands = storage("bands", &band_bytes, wgpu::BufferUsages::empty());
+            let composite = storage("composite", composite, wgpu::BufferUsages::COPY_SRC);
+            let frame = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("frame"),
+                size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let params = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("params"),
+                size: 16,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+            });
+            let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: None,
+                module: &module,
+                entry_point: "main",
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &[wgpu::BindGroupEntry {
+                               binding: 0,
+                               resource: params.as_entire_binding(),
+                           },
+                           wgpu::BindGroupEntry {
+                               binding: 1,
+                               resource: bands.as_entire_binding(),
+                           },
+                           wgpu::BindGroupEntry {
+                               binding: 2,
+                               resource: frame.as_entire_binding(),
+                           },
+                           wgpu::BindGroupEntry {
+                               binding: 3,
+                               resource: composite.as_entire_binding(),
+                           }],
+            });
+
+            Ok(GpuCompositor {
+                device,
+                queue,
+                pipeline,
+                bind_group,
+                params,
+                frame,
+                composite,
+                dimensions,
+            })
+        }
+
+        /// Copies the pixels of the band at `band` from `frame`, which has the dimensions of the
+        /// composite. The copy runs in the background; uploading the next frame waits for it.
+        pub fn copy_band(&mut self, band: u32, frame: &RgbaImage) -> Result<()> {
+            let pixels = self.dimensions.0 * self.dimensions.1;
+            let (x, y) = workgroups(pixels);
+            let params: Vec<u8> = [band, pixels, x * WORKGROUP_SIZE, 0]
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+            self.queue.write_buffer(&self.params, 0, &params);
+            self.queue.write_buffer(&self.frame, 0, frame);
+
+            let mut encoder = self.device.create_command_encoder(&Default::default());
+            {
+                let mut pass = encoder.begin_compute_pass(&Default::default());
+                pass.set_pipeline(&self.pipeline);
+                pass.set_bind_group(0, &self.bind_group, &[]);
+                pass.dispatch_workgroups(x, y, 1);
+            }
+            self.queue.submit(Some(encoder.finish()));
+            Ok(())
+        }
+
+        /// Waits for the bands to be copied and reads the composite back.
+        ///
+        /// # Errors
+        /// This fails if the composite couldn't be read back from the GPU.
+        pub fn finish(self) -> Result<RgbaImage> {
+            let (width, height) = self.dimensions;
+            let size = width as u64 * height as u64 * 4;
+            let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("readback"),
+                size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let mut encoder = self.device.create_command_encoder(&Default::default());
+            encoder.copy_buffer_to_buffer(&self.composite, 0, &readback, 0, size);
+            self.queue.submit(Some(encoder.finish()));
+
+            let slice = readback.slice(..);
+            let (sender, receiver) = mpsc::channel();
+            slice.map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+            self.device.poll(wgpu::Maintain::Wait);
+            match receiver.recv() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => bail!(ErrorKind::CouldNotUseGpu(e.to_string())),
+                Err(_) => bail!(ErrorKind::CouldNotUseGpu("the composite was never read".into())),
+            }
+            let data = slice.get_mapped_range().to_vec();
+            Ok(ImageBuffer::from_raw(width, height, data).unwrap())
+        }
+    }
+}
+
+#[cfg(not(feature = "gpu"))]
+mod compositor {
+    use image::RgbaImage;
+
+    use ::errors::{ErrorKind, Result};
+
+    /// Stands in for the GPU compositor in builds without the gpu feature.
+    pub struct GpuCompositor;
+
+    impl GpuCompositor {
+        pub fn new(_composite: &RgbaImage, _bands: &[u32]) -> Result<GpuCompositor> {
+            bail!(ErrorKind::GpuUnavailable)
+        }
+
+        pub fn copy_band(&mut self, _band: u32, _frame: &RgbaImage) -> Result<()> {
+            Ok(())
+        }
+
+        pub fn finish(self) -> Result<RgbaImage> {
+            bail!(ErrorKind::GpuUnavailable)
+        }
+    }
+}
+
+pub use self::compositor::GpuCompositor;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_workgroups() {
+        assert_eq!(workgroups(0), (1, 1));
+        assert_eq!(workgroups(64), (1, 1));
+        assert_eq!(workgroups(65), (2, 1));
+        // A 24 megapixel composite needs more workgroups than a single row holds.
+        assert_eq!(workgroups(6000 * 4000), (65535, 6));
+    }
+}
@@ -0,0 +1,209 @@
+use deflate;
+use inflate;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use ::errors::{ErrorKind, Result, ResultExt};
+use ::png_writer::{self, PNG_SIGNATURE, read_u32};
+
+/// The identifier at the start of every JPEG APP2 segment that holds part of an ICC profile.
+const JPEG_ICC_IDENTIFIER: &[u8] = b"ICC_PROFILE\0";
+
+/// The most profile data a single JPEG APP2 segment can hold, after its length, identifier, and
+/// sequence numbers.
+const JPEG_ICC_CHUNK_SIZE: usize = 65535 - 2 - 12 - 2;
+
+/// Extracts the ICC profile from the `iCCP` chunk of PNG data.
+fn png_profile(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut offset = PNG_SIGNATURE.len();
+    while offset + 8 <= data.len() {
+        let length = read_u32(&data[offset..]) as usize;
+        let kind = &data[offset + 4..offset + 8];
+        let start = offset + 8;
+        let end = start + length;
+        if end > data.len() {
+            bail!(ErrorKind::InvalidIccProfile("truncated PNG chunk".into()));
+        }
+        match kind {
+            b"iCCP" => {
+                let chunk = &data[start..end];
+                // The chunk is a null-terminated profile name, a compression method byte, and then
+                // the zlib-compressed profile.
+                let name_end = match chunk.iter().position(|&b| b == 0) {
+                    Some(name_end) => name_end,
+                    None => bail!(ErrorKind::InvalidIccProfile("unterminated name".into())),
+                };
+                if name_end + 2 > chunk.len() {
+                    bail!(ErrorKind::InvalidIccProfile("truncated iCCP chunk".into()));
+                }
+                let profile = inflate::inflate_bytes_zlib(&chunk[name_end + 2..])
+                    .map_err(ErrorKind::InvalidIccProfile)?;
+                return Ok(Some(profile));
+            }
+            // The profile must come before the image data, so there's no point looking further.
+            b"IDAT" | b"IEND" => return Ok(None),
+            _ => (),
+        }
+        offset = end + 4;
+    }
+    Ok(None)
+}
+
+/// Extracts the ICC profile from the APP2 segments of JPEG data.
+fn jpeg_profile(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut chunks: Vec<(u8, &[u8])> = vec![];
+    let mut offset = 2;
+    while offset + 4 <= data.len() && data[offset] == 0xff {
+        let marker = data[offset + 1];
+        // Start of scan; no more metadata segments follow.
+        if marker == 0xda {
+            break;
+        }
+        let length = ((data[offset + 2] as usize) << 8) | data[offset + 3] as usize;
+        let start = offset + 4;
+        let end = offset + 2 + length;
+        if length < 2 || end > data.len() {
+            bail!(ErrorKind::InvalidIccProfile("truncated JPEG segment".into()));
+        }
+        let segment = &data[start..end];
+        if marker == 0xe2 && segment.starts_with(JPEG_ICC_IDENTIFIER) &&
+           segment.len() >= JPEG_ICC_IDENTIFIER.len() + 2 {
+            let sequence = segment[JPEG_ICC_IDENTIFIER.len()];
+            chunks.push((sequence, &segment[JPEG_ICC_IDENTIFIER.len() + 2..]));
+        }
+        offset = end;
+    }
+
+    if chunks.is_empty() {
+        return Ok(None);
+    }
+    chunks.sort_by_key(|&(sequence, _)| sequence);
+    Ok(Some(chunks.iter().flat_map(|&(_, chunk)| chunk.iter().cloned()).collect()))
+}
+
+/// Reads the ICC profile embedded in a PNG or JPEG file.
+///
+/// # Errors
+/// This fails if the file cannot be read, or if it contains a malformed profile. Files in other
+/// formats, or without a profile, result in `Ok(None)`.
+pub fn read_profile(path: &Path) -> Result<Option<Vec<u8>>> {
+    let mut data = vec![];
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .chain_err(|| ErrorKind::CouldNotOpenImage(path.to_path_buf()))?;
+
+    if data.starts_with(&PNG_SIGNATURE) {
+        png_profile(&data)
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        jpeg_profile(&data)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Builds the `iCCP` chunk that holds an ICC profile in a PNG.
+pub fn png_chunk(profile: &[u8]) -> Vec<u8> {
+    // A profile name, the compression method, and then the compressed profile.
+    let mut body = b"ICC profile\0\0".to_vec();
+    body.extend(deflate::deflate_bytes_zlib(profile));
+    png_writer::chunk(b"iCCP", &body)
+}
+
+/// Inserts an ICC profile into encoded PNG data, right after the `IHDR` chunk.
+fn embed_png(data: &[u8], profile: &[u8]) -> Result<Vec<u8>> {
+    // The signature is followed by the IHDR chunk: length, type, 13 bytes of data, and a CRC.
+    let ihdr_end = PNG_SIGNATURE.len() + 8 + 13 + 4;
+    if data.len() < ihdr_end || !data.starts_with(&PNG_SIGNATURE) {
+        bail!(ErrorKind::InvalidIccProfile("encoded PNG is malformed".into()));
+    }
+
+    let chunk = png_chunk(profile);
+
+    let mut out = Vec::with_capacity(data.len() + chunk.len());
+    out.extend_from_slice(&data[..ihdr_end]);
+    out.extend_from_slice(&chunk);
+    out.extend_from_slice(&data[ihdr_end..]);
+    Ok(out)
+}
+
+/// Inserts an ICC profile into encoded JPEG data as a series of APP2 segments, after the start of
+/// image marker and any APP0 segment.
+fn embed_jpeg(data: &[u8], profile: &[u8]) -> Result<Vec<u8>> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        bail!(ErrorKind::InvalidIccProfile("encoded JPEG is malformed".into()));
+    }
+    let mut insert_at = 2;
+    if data.len() >= 6 && data[2] == 0xff && data[3] == 0xe0 {
+        insert_at += 2 + (((data[4] as usize) << 8) | data[5] as usize);
+    }
+
+    let chunks: Vec<&[u8]> = profile.chunks(JPEG_ICC_CHUNK_SIZE).collect();
+    if chunks.len() > 255 {
+        bail!(ErrorKind::InvalidIccProfile("profile is too large to embed in a JPEG".into()));
+    }
+
+    let mut out = Vec::with_capacity(data.len() + profile.len() + chunks.len() * 18);
+    out.extend_from_slice(&data[..insert_at]);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let length = 2 + JPEG_ICC_IDENTIFIER.len() + 2 + chunk.len();
+        out.extend_from_slice(&[0xff, 0xe2, (length >> 8) as u8, length as u8]);
+        out.extend_from_slice(JPEG_ICC_IDENTIFIER);
+        out.extend_from_slice(&[(i + 1) as u8, chunks.len() as u8]);
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&data[insert_at..]);
+    Ok(out)
+}
+
+/// Inserts an ICC profile into encoded PNG or JPEG data.
+///
+/// # Errors
+/// This fails if the data is neither PNG nor JPEG, or if the profile can't be embedded.
+pub fn embed_profile(data: &[u8], profile: &[u8]) -> Result<Vec<u8>> {
+    if data.starts_with(&PNG_SIGNATURE) {
+        embed_png(data, profile)
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        embed_jpeg(data, profile)
+    } else {
+        bail!(ErrorKind::InvalidIccProfile("only PNG and JPEG outputs can hold a profile".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal PNG: the signature, an IHDR chunk, and an IEND chunk. Only the structure matters.
+    fn minimal_png() -> Vec<u8> {
+        let mut data = PNG_SIGNATURE.to_vec();
+        data.extend(png_writer::ihdr_chunk(1, 1, 8));
+        data.extend(png_writer::chunk(b"IEND", &[]));
+        data
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let profile: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let png = minimal_png();
+        assert_eq!(png_profile(&png).unwrap(), None);
+
+        let embedded = embed_profile(&png, &profile).unwrap();
+        assert_eq!(png_profile(&embedded).unwrap(), Some(profile));
+        assert!(embedded.ends_with(&png[png.len() - 12..]));
+    }
+
+    #[test]
+    fn test_jpeg_round_trip() {
+        let len = 2 * JPEG_ICC_CHUNK_SIZE + 10;
+        let profile: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        // SOI, an empty APP0 segment, and then start of scan.
+        let jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x02, 0xff, 0xda, 0x00, 0x02];
+        assert_eq!(jpeg_profile(&jpeg).unwrap(), None);
+
+        let embedded = embed_profile(&jpeg, &profile).unwrap();
+        assert_eq!(&embedded[..6], &jpeg[..6]);
+        assert_eq!(jpeg_profile(&embedded).unwrap(), Some(profile));
+    }
+}
@@ -1,15 +1,189 @@
-use image::{self, GenericImage, ImageBuffer};
-use pbr::ProgressBar;
+use image::{self, DynamicImage, FilterType, GenericImage, ImageBuffer, Rgba, RgbaImage};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use rayon::prelude::*;
 
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{PathBuf, Path};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Instant;
 
 use ::Direction;
-use ::errors::{ErrorKind, Result, ResultExt};
+use ::animation::{Animation, AnimationSettings, SnapshotSettings, Snapshots};
+use ::checkpoint::{self, Autosave, Checkpoint};
+use ::color_processing::{self, Adjustments, Channel, Lut, LutTarget, Rgba16Image, RgbaBuffer};
+use ::comparison::{self, CompareWith};
+use ::debug_overlay;
+use ::file_processing::{self, FrameLayout};
+use ::frame_info::FrameInfo;
+use ::frame_processing::{self, Deflicker, Denoise, FrameData, FramePool, FrameTransforms, Preblend,
+                         Region, ResizePolicy, WhiteBalance, WhiteBalanceMode};
+use ::geometry::{BandRegion, ShutterGeometry};
+use ::gpu::{self, GpuCompositor};
+use ::icc;
+use ::interrupt;
+use ::metadata::Metadata;
+use ::output_processing::{self, BitDepth, EncoderSettings, OutputFormat, OutputSize, Overlay};
+use ::observer::{Flow, ProgressObserver, RenderObserver};
+use ::output_sink::{self, OutputSink};
+use ::png_writer::StreamingEncoder;
+use ::preview::PreviewWindow;
+use ::progress::ProgressSink;
+use ::report::{Report, ReportOutput};
+use ::stabilization::Stabilizer;
+use ::uring::ReadAhead;
+use ::errors::{Error, ErrorKind, Result, ResultExt};
 
-fn generage_subimage_coords(bounds: (u32, u32, u32, u32),
-                            index: u32,
-                            direction: Direction)
-                            -> Option<(u32, u32, u32, u32)> {
+/// The phase progress through the frames is reported under.
+const PROCESSING: &str = "Processing frames:";
+
+/// The 8x8 Bayer matrix used for ordered dithering, with values in `0..64`.
+const BAYER_8X8: [[u8; 8]; 8] = [[0, 32, 8, 40, 2, 34, 10, 42],
+                                 [48, 16, 56, 24, 50, 18, 58, 26],
+                                 [12, 44, 4, 36, 14, 46, 6, 38],
+                                 [60, 28, 52, 20, 62, 30, 54, 22],
+                                 [3, 35, 11, 43, 1, 33, 9, 41],
+                                 [51, 19, 59, 27, 49, 17, 57, 25],
+                                 [15, 47, 7, 39, 13, 45, 5, 37],
+                                 [63, 31, 55, 23, 61, 29, 53, 21]];
+
+/// The pattern used to dither band boundaries.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DitherMode {
+    /// A repeating 8x8 Bayer matrix.
+    Ordered,
+    /// Interleaved gradient noise, which approximates blue noise without needing a lookup table.
+    BlueNoise,
+}
+
+impl FromStr for DitherMode {
+    type Err = Error;
+
+    /// Parses a dither mode from its name on the command line: `ordered` or `blue-noise`.
+    fn from_str(s: &str) -> Result<DitherMode> {
+        match s {
+            "ordered" => Ok(DitherMode::Ordered),
+            "blue-noise" => Ok(DitherMode::BlueNoise),
+            _ => {
+                let expected = "ordered or blue-noise";
+                bail!(ErrorKind::CouldNotParseOption("dither mode", s.into(), expected))
+            }
+        }
+    }
+}
+
+/// Describes how band boundaries should be dithered.
+///
+/// Each pixel within `radius` lines of its own band may be reassigned to a neighbouring frame,
+/// which breaks up the straight seams between bands.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Dither {
+    /// The dithering pattern.
+    pub mode: DitherMode,
+    /// How many lines on either side of a band a pixel may be moved by.
+    pub radius: u32,
+}
+
+impl Dither {
+    /// Returns a threshold in `[0, 1)` for the pixel at the given line and position along it.
+    fn threshold(&self, line: u32, cross: u32, seed: Seed) -> f32 {
+        let (line_offset, cross_offset) = seed.offsets();
+        let (line, cross) = (line.wrapping_add(line_offset), cross.wrapping_add(cross_offset));
+        match self.mode {
+            DitherMode::Ordered => {
+                BAYER_8X8[(line % 8) as usize][(cross % 8) as usize] as f32 / 64.0
+            }
+            DitherMode::BlueNoise => {
+                let inner = (0.06711056 * cross as f32 + 0.00583715 * line as f32).fract();
+                (52.982_918 * inner).fract()
+            }
+        }
+    }
+
+    /// Determines which frame the pixel at the given line and position along it is taken from.
+    ///
+    /// The result is always clamped to `0..num_frames`.
+    fn frame_index(&self, line: u32, cross: u32, num_frames: u32, seed: Seed) -> u32 {
+        let span = 2 * self.radius + 1;
+        let offset = (self.threshold(line, cross, seed) * span as f32) as i64 - self.radius as i64;
+        let index = line as i64 + offset;
+        if index < 0 {
+            0
+        } else if index >= num_frames as i64 {
+            num_frames - 1
+        } else {
+            index as u32
+        }
+    }
+}
+
+/// Seeds the randomized parts of a render, which so far is just where dithering moves pixels.
+///
+/// A render is a pure function of its frames, its options and this seed: rendering the same
+/// frames with the same options gives byte-identical outputs on every platform, so a render can
+/// be reproduced exactly from its settings. Different seeds give differently placed, but equally
+/// good, dither patterns.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Seed(pub u64);
+
+impl Seed {
+    /// How far the seed shifts the dither pattern, along and across the lines. The default seed
+    /// leaves it in place.
+    fn offsets(self) -> (u32, u32) {
+        // The MurmurHash3 finalizer, which scatters nearby seeds and maps 0 to 0.
+        let mut hash = self.0;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        hash ^= hash >> 33;
+        // Small offsets keep the noise's coordinates exact as floats.
+        ((hash & 0xfff) as u32, (hash >> 32 & 0xfff) as u32)
+    }
+}
+
+/// The lines covered by the band at `index` when `bands` bands are spread evenly over `lines`
+/// lines. Each band covers a single line unless they were stretched.
+pub fn band_lines(index: u32, bands: u32, lines: u32) -> Range<u32> {
+    let start = |index: u32| (index as u64 * lines as u64 / bands as u64) as u32;
+    start(index)..start(index + 1)
+}
+
+/// The band that covers `line` when `bands` bands are spread evenly over `lines` lines; the
+/// inverse of `band_lines`.
+fn line_band(line: u32, bands: u32, lines: u32) -> u32 {
+    (((line as u64 + 1) * bands as u64).div_ceil(lines as u64) - 1) as u32
+}
+
+/// Converts a shutter line and a position along that line into image coordinates.
+pub fn line_to_coords(bounds: (u32, u32, u32, u32),
+                      line: u32,
+                      cross: u32,
+                      direction: Direction)
+                      -> (u32, u32) {
+    let (bx, by, bw, bh) = bounds;
+    match direction {
+        Direction::N => (bx + cross, by + line),
+        Direction::S => (bx + cross, by + bh - line - 1),
+        Direction::W => (bx + line, by + cross),
+        Direction::E => (bx + bw - line - 1, by + cross),
+    }
+}
+
+pub(crate) fn generage_subimage_coords(bounds: (u32, u32, u32, u32),
+                                       index: u32,
+                                       direction: Direction)
+                                       -> Option<(u32, u32, u32, u32)> {
     let (bx, by, bw, bh) = bounds;
     match direction {
         Direction::N => {
@@ -17,127 +191,2732 @@ fn generage_subimage_coords(bounds: (u32, u32, u32, u32),
             if index >= bh {
                 return None;
             }
-            Some((bx, by + index, bw, 1))
+            Some((bx, by + index, bw, 1))
+        }
+        Direction::S => {
+            // S -> N
+            if index >= bh {
+                return None;
+            }
+            Some((bx, by + bh - index - 1, bw, 1))
+        }
+        Direction::W => {
+            // W -> E
+            if index >= bw {
+                return None;
+            }
+            Some((bx + index, by, 1, bh))
+        }
+        Direction::E => {
+            // E -> W
+            if index >= bw {
+                return None;
+            }
+            Some((bx + bw - index - 1, by, 1, bh))
+        }
+    }
+}
+
+/// Computes the region covered by a range of lines, which must not be empty, or `None` if it
+/// doesn't fall entirely inside the image.
+fn band_coords(bounds: (u32, u32, u32, u32),
+               lines: Range<u32>,
+               direction: Direction)
+               -> Option<(u32, u32, u32, u32)> {
+    let first = generage_subimage_coords(bounds, lines.start, direction)?;
+    let last = generage_subimage_coords(bounds, lines.end - 1, direction)?;
+    let (x, y) = (::std::cmp::min(first.0, last.0), ::std::cmp::min(first.1, last.1));
+    Some((x,
+          y,
+          ::std::cmp::max(first.0 + first.2, last.0 + last.2) - x,
+          ::std::cmp::max(first.1 + first.3, last.1 + last.3) - y))
+}
+
+fn process_image<I, J>(current_buffer: &mut I,
+                       image: &mut J,
+                       index: usize,
+                       geometry: &dyn ShutterGeometry)
+                       -> Result<bool>
+    where I: GenericImage,
+          I::Pixel: 'static,
+          J: GenericImage<Pixel = I::Pixel> + 'static
+{
+    let (image_width, image_height) = image.dimensions();
+    match geometry.region_for_frame(index as u32, (image_width, image_height)) {
+        Some(BandRegion::Rect(x, y, width, height)) => {
+            if x as u64 + width as u64 > image_width as u64 ||
+               y as u64 + height as u64 > image_height as u64 {
+                return Ok(false);
+            }
+            let subimage = image.sub_image(x, y, width, height);
+            Ok(current_buffer.copy_from(&subimage, x, y))
+        }
+        Some(BandRegion::Pixels(pixels)) => {
+            for (x, y) in pixels {
+                if x < image_width && y < image_height {
+                    current_buffer.put_pixel(x, y, image.get_pixel(x, y));
+                }
+            }
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Copies the band covering `lines` from a frame into a composite of the same dimensions a row of
+/// the band at a time, rather than pixel by pixel as `copy_from` does. Rows spanning the whole
+/// width are contiguous, so bands of rows are copied in one go. Returns whether every line falls
+/// inside the image.
+fn copy_lines(buf: &mut RgbaImage,
+              frame: &RgbaImage,
+              lines: Range<u32>,
+              direction: Direction)
+              -> bool {
+    let (width, height) = frame.dimensions();
+    let count = direction.band_count((width, height));
+    let end = ::std::cmp::min(lines.end, count);
+    if lines.start < end {
+        if let Some((x, y, band_width, rows)) =
+            band_coords((0, 0, width, height), lines.start..end, direction) {
+            let (buf, frame): (&mut [u8], &[u8]) = (&mut **buf, &**frame);
+            let stride = width as usize * 4;
+            let (start, len) = (x as usize * 4, band_width as usize * 4);
+            if band_width == width {
+                let range = y as usize * stride..(y + rows) as usize * stride;
+                buf[range.clone()].copy_from_slice(&frame[range]);
+            } else {
+                for row in y as usize..(y + rows) as usize {
+                    let range = row * stride + start..row * stride + start + len;
+                    buf[range.clone()].copy_from_slice(&frame[range]);
+                }
+            }
+        }
+    }
+    lines.start >= lines.end || lines.end <= count
+}
+
+/// Copies the band covering `lines` from a frame into a composite held transposed, with its
+/// dimensions swapped, for bands of columns. Each column of the band is a row of the composite, so
+/// it is written in one go, rather than a pixel to every row of the composite as `copy_lines`
+/// does. Returns whether every line falls inside the image.
+fn copy_columns(buf: &mut RgbaImage,
+                frame: &RgbaImage,
+                lines: Range<u32>,
+                direction: Direction)
+                -> bool {
+    debug_assert!(direction == Direction::E || direction == Direction::W);
+    let (width, height) = frame.dimensions();
+    let end = ::std::cmp::min(lines.end, width);
+    if lines.start < end {
+        if let Some((x, _, columns, _)) =
+            band_coords((0, 0, width, height), lines.start..end, direction) {
+            let (buf, frame): (&mut [u8], &[u8]) = (&mut **buf, &**frame);
+            let (stride, column_len) = (width as usize * 4, height as usize * 4);
+            for column in x as usize..(x + columns) as usize {
+                let row = &mut buf[column * column_len..(column + 1) * column_len];
+                for (y, pixel) in row.chunks_mut(4).enumerate() {
+                    let start = y * stride + column * 4;
+                    pixel.copy_from_slice(&frame[start..start + 4]);
+                }
+            }
+        }
+    }
+    lines.start >= lines.end || lines.end <= width
+}
+
+/// Swaps the rows and columns of an image. This goes a tile at a time, so that the rows read and
+/// the rows written stay in cache.
+fn transpose(buf: &RgbaImage) -> RgbaImage {
+    const TILE: u32 = 64;
+    let (width, height) = buf.dimensions();
+    let mut transposed = RgbaImage::new(height, width);
+    for tile_y in (0..height).step_by(TILE as usize) {
+        for tile_x in (0..width).step_by(TILE as usize) {
+            for y in tile_y..::std::cmp::min(tile_y + TILE, height) {
+                for x in tile_x..::std::cmp::min(tile_x + TILE, width) {
+                    transposed.put_pixel(y, x, *buf.get_pixel(x, y));
+                }
+            }
+        }
+    }
+    transposed
+}
+
+/// Copies every pixel whose dithered band belongs to the frame at `index`.
+///
+/// Returns whether anything could have been copied, i.e. whether `index` still falls inside the
+/// image.
+fn process_image_dithered<I, J>(current_buffer: &mut I,
+                                image: &J,
+                                index: u32,
+                                num_frames: u32,
+                                direction: Direction,
+                                dither: Dither,
+                                seed: Seed)
+                                -> Result<bool>
+    where I: GenericImage,
+          J: GenericImage<Pixel = I::Pixel>
+{
+    if index >= num_frames {
+        return Ok(false);
+    }
+    let bounds = image.bounds();
+    let (_, _, width, height) = bounds;
+    let cross_len = match direction {
+        Direction::N | Direction::S => width,
+        Direction::E | Direction::W => height,
+    };
+
+    let first_line = index.saturating_sub(dither.radius);
+    let last_line = ::std::cmp::min(index + dither.radius + 1, num_frames);
+    for line in first_line..last_line {
+        for cross in 0..cross_len {
+            if dither.frame_index(line, cross, num_frames, seed) != index {
+                continue;
+            }
+            let (x, y) = line_to_coords(bounds, line, cross, direction);
+            current_buffer.put_pixel(x, y, image.get_pixel(x, y));
+        }
+    }
+    Ok(true)
+}
+
+/// Options controlling how a set of frames is turned into an output image.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct RenderOptions {
+    /// The direction from which the shutter *starts* moving.
+    pub direction: Direction,
+    /// A custom scan pattern to place the bands by instead of `direction`, if any. Dithering, time
+    /// maps, the debug overlay and exported bands need a cardinal direction, so they can't be
+    /// used with one.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub geometry: Option<Arc<dyn ShutterGeometry>>,
+    /// How to dither band boundaries, if at all.
+    pub dither: Option<Dither>,
+    /// The seed for the randomized parts of the render, such as dithering.
+    pub seed: Seed,
+    /// How many frames are decoded at once, each on its own thread, ahead of the bands they are
+    /// for. 0 decodes one per CPU core. The next batch is decoded while this one is composited, so
+    /// up to twice as many decoded frames are held in memory at a time.
+    pub threads: usize,
+    /// Roughly how many bytes of decoded frames the render may hold at once, if it is limited.
+    /// Fewer frames are decoded ahead to stay within it.
+    pub max_memory: Option<u64>,
+    /// Whether to composite on the GPU, copying each frame's pixels into the composite with a
+    /// compute shader. Needs the `gpu` feature. Renders that look at the composite after every
+    /// band, such as for an animation or the preview, are still composited on the CPU.
+    pub gpu: bool,
+    /// Whether to align every frame to the first to compensate for camera shake.
+    pub stabilize: bool,
+    /// How many consecutive frames are averaged into each frame the shutter sees.
+    pub preblend: usize,
+    /// Whether to crop the output to the region every stabilized frame covers.
+    pub autocrop: bool,
+    /// The number of frames to average exposure over when deflickering, if at all.
+    pub deflicker: Option<usize>,
+    /// How to normalize white balance across frames, if at all.
+    pub white_balance: Option<WhiteBalanceMode>,
+    /// How to denoise every frame, if at all.
+    pub denoise: Option<Denoise>,
+    /// Brightness, contrast and gamma adjustments applied to every frame.
+    pub adjustments: Adjustments,
+    /// A color grading LUT to apply, if any.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub lut: Option<Lut>,
+    /// Whether the LUT is applied to every frame or to the output.
+    pub lut_target: LutTarget,
+    /// What to do with frames whose dimensions differ from the first frame's.
+    pub resize_policy: ResizePolicy,
+    /// The transformations applied to every frame as it is loaded.
+    pub transforms: FrameTransforms,
+    /// The size to rescale the output to, if any.
+    pub output_size: Option<OutputSize>,
+    /// The filter used when rescaling the output.
+    #[cfg_attr(feature = "serde", serde(with = "::output_processing::serde_filter"))]
+    pub output_filter: FilterType,
+    /// The amount and blur radius of the unsharp mask applied to the output, if any.
+    pub sharpen: Option<(f32, f32)>,
+    /// An image to composite on top of the output, if any.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub overlay: Option<Overlay>,
+    /// The format to encode outputs in when their extension doesn't determine one. Standard
+    /// output is written as PNG unless another format is given.
+    pub output_format: Option<OutputFormat>,
+    /// Settings passed on to the image encoders.
+    pub encoder: EncoderSettings,
+    /// The number of bits per channel of the output.
+    pub bit_depth: BitDepth,
+    /// Whether blending and averaging is done in linear light rather than on sRGB-encoded values.
+    pub linear_blend: bool,
+    /// Whether to copy the first frame's ICC profile into the output.
+    pub preserve_icc: bool,
+    /// What to record about the render in the output, if anything. The direction and frame counts
+    /// are added to it once they are known.
+    pub metadata: Option<Metadata>,
+    /// Where and how often to record the composite as it is built up, if at all.
+    pub animation: Option<AnimationSettings>,
+    /// Where and how often to save snapshots of the composite as it is built up, if at all.
+    pub snapshots: Option<SnapshotSettings>,
+    /// How many rows of the composite to render at a time, streaming each strip into the output
+    /// before starting the next, for images too large to hold whole, if it is rendered in strips.
+    pub tile_rows: Option<u32>,
+    /// How many bands are copied between saves of the composite next to the first output, with a
+    /// checkpoint to resume it from, so that a render that crashes partway through can be
+    /// recovered, if it is autosaved at all.
+    pub autosave_every: Option<u32>,
+    /// Whether to also save a copy of the output annotated with band ticks, frame indices and the
+    /// shutter direction.
+    pub debug_overlay: bool,
+    /// Which frame to save a side-by-side comparison of the output with, if any.
+    pub compare_with: Option<CompareWith>,
+    /// The directory to save the band taken from each frame to, as its own image, if any.
+    pub export_bands: Option<PathBuf>,
+    /// Where to save the map of which frame each pixel was taken from, if anywhere.
+    pub time_map: Option<PathBuf>,
+    /// The layout every frame shares, if `file_processing::validate_layout` found one. Frames are
+    /// then decoded the same way every time, straight into RGBA, instead of their format and color
+    /// type being worked out one frame at a time.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub frame_layout: Option<FrameLayout>,
+    /// The checkpoint of an interrupted render to continue, if any.
+    pub resume: Option<Checkpoint>,
+    /// Whether to suppress the status messages and warnings the render logs through `log`.
+    pub suppress_output: bool,
+    /// Whether to stretch the bands over the whole image when there are too few frames for a line
+    /// each, rather than leave the end of it empty. Dithered bands can't be stretched.
+    pub auto_stretch: bool,
+    /// The label of the render when several run at once, which report when each one finishes
+    /// themselves.
+    pub job_label: Option<String>,
+    /// Whether to show the composite in a window as it is built up.
+    pub preview: bool,
+}
+
+impl Default for RenderOptions {
+    /// The options a render uses unless told otherwise, with the shutter starting from the top.
+    /// Status messages are logged, and so go nowhere until the program installs a logger.
+    fn default() -> RenderOptions {
+        RenderOptions {
+            direction: Direction::N,
+            geometry: None,
+            dither: None,
+            seed: Seed::default(),
+            threads: 0,
+            max_memory: None,
+            gpu: false,
+            stabilize: false,
+            preblend: 1,
+            autocrop: false,
+            deflicker: None,
+            white_balance: None,
+            denoise: None,
+            adjustments: Adjustments::default(),
+            lut: None,
+            lut_target: LutTarget::Frames,
+            resize_policy: ResizePolicy::Error,
+            transforms: FrameTransforms::default(),
+            output_size: None,
+            output_filter: FilterType::Lanczos3,
+            sharpen: None,
+            overlay: None,
+            output_format: None,
+            encoder: EncoderSettings::default(),
+            bit_depth: BitDepth::Eight,
+            linear_blend: false,
+            preserve_icc: true,
+            metadata: None,
+            animation: None,
+            snapshots: None,
+            tile_rows: None,
+            autosave_every: None,
+            debug_overlay: false,
+            compare_with: None,
+            export_bands: None,
+            time_map: None,
+            frame_layout: None,
+            resume: None,
+            suppress_output: false,
+            auto_stretch: false,
+            job_label: None,
+            preview: false,
+        }
+    }
+}
+
+impl RenderOptions {
+    /// The scan pattern the bands are placed by: the custom geometry if there is one, and the
+    /// direction otherwise.
+    pub fn shutter_geometry(&self) -> &dyn ShutterGeometry {
+        match self.geometry {
+            Some(ref geometry) => &**geometry,
+            None => &self.direction,
+        }
+    }
+}
+
+/// Checks that none of the options that need a cardinal direction are used with a custom geometry,
+/// and that dithering isn't used with stretched bands, since dithered pixels are placed a line
+/// per band. Renders to memory only dither, so `files` says whether the options that write files
+/// are checked.
+///
+/// # Errors
+/// This fails naming the first such option that is used.
+fn check_options(options: &RenderOptions, files: bool) -> Result<()> {
+    if options.dither.is_some() && options.auto_stretch {
+        bail!(ErrorKind::IncompatibleOptions("dithering", "stretched bands"));
+    }
+    if options.geometry.is_none() {
+        return Ok(());
+    }
+    let unsupported = [("dithering", options.dither.is_some()),
+                       ("a time map", files && options.time_map.is_some()),
+                       ("the debug overlay", files && options.debug_overlay),
+                       ("exported bands", files && options.export_bands.is_some())];
+    match unsupported.iter().find(|&&(_, used)| used) {
+        Some(&(option, _)) => bail!(ErrorKind::UnsupportedByGeometry(option)),
+        None => Ok(()),
+    }
+}
+
+/// The stages each band's frame goes through between loading and compositing, along with the state
+/// the stabilizer, deflicker and white balance carry from one frame to the next.
+struct FrameStages<'a> {
+    options: &'a RenderOptions,
+    stabilizer: Option<Stabilizer>,
+    deflicker: Option<Deflicker>,
+    white_balance: Option<WhiteBalance>,
+}
+
+impl<'a> FrameStages<'a> {
+    fn new(options: &'a RenderOptions) -> FrameStages<'a> {
+        FrameStages {
+            options,
+            stabilizer: if options.stabilize {
+                Some(Stabilizer::new())
+            } else {
+                None
+            },
+            deflicker: options.deflicker.map(Deflicker::new),
+            white_balance: options.white_balance.map(WhiteBalance::new),
+        }
+    }
+
+    /// The region to crop the output to, if autocropping, which every stabilized frame covers.
+    ///
+    /// # Errors
+    /// This fails if the stabilized frames have no region in common.
+    fn autocrop(&self, dimensions: (u32, u32)) -> Result<Option<Region>> {
+        match (self.options.autocrop, self.stabilizer.as_ref()) {
+            (true, Some(stabilizer)) => {
+                match stabilizer.common_region(dimensions) {
+                    Some(region) => Ok(Some(region)),
+                    None => bail!(ErrorKind::NoCommonRegion),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Stabilizes and blends a group of frames into the frame a band is taken from, then corrects
+    /// and grades it.
+    fn process<I>(&mut self, frames: I) -> Result<image::DynamicImage>
+        where I: IntoIterator<Item = Result<image::DynamicImage>>
+    {
+        let mut blend = Preblend::new(self.options.linear_blend);
+        for frame in frames {
+            self.add(&mut blend, frame?);
+        }
+        self.finish(blend)
+    }
+
+    /// Stabilizes and blends a group of frames into the frame a band is taken from, like `process`,
+    /// but at 16 bits per channel. Only a LUT on the frames can follow the blend at that depth, so
+    /// the other corrections must be off, as `composites_wide` checks.
+    fn process_wide<I>(&mut self, frames: I) -> Result<Rgba16Image>
+        where I: IntoIterator<Item = Result<image::DynamicImage>>
+    {
+        let options = self.options;
+        let mut blend = Preblend::new(options.linear_blend);
+        for frame in frames {
+            self.add(&mut blend, frame?);
+        }
+        let mut cur_img = blend.finish_wide().unwrap();
+        if let (Some(lut), LutTarget::Frames) = (options.lut.as_ref(), options.lut_target) {
+            lut.apply(&mut cur_img);
+        }
+        Ok(cur_img)
+    }
+
+    /// Stabilizes a frame and adds it to the blend of its group.
+    fn add(&mut self, blend: &mut Preblend, mut frame: image::DynamicImage) {
+        if let Some(ref mut stabilizer) = self.stabilizer {
+            frame = stabilizer.apply(frame);
+        }
+        blend.add(frame);
+    }
+
+    /// Finishes the blend of a group of frames, which must not be empty, into the frame a band is
+    /// taken from, then corrects and grades it.
+    fn finish(&mut self, blend: Preblend) -> Result<image::DynamicImage> {
+        let options = self.options;
+        let mut cur_img = blend.finish().unwrap();
+        if let Some(ref mut white_balance) = self.white_balance {
+            cur_img = white_balance.apply(cur_img);
+        }
+        if let Some(ref mut deflicker) = self.deflicker {
+            cur_img = deflicker.apply(cur_img);
+        }
+        if let Some(denoise) = options.denoise {
+            let denoised = denoise.apply(&cur_img.to_rgba(), options.linear_blend);
+            cur_img = image::DynamicImage::ImageRgba8(denoised);
+        }
+        if !options.adjustments.is_identity() {
+            let mut adjusted = cur_img.to_rgba();
+            options.adjustments.apply(&mut adjusted);
+            cur_img = image::DynamicImage::ImageRgba8(adjusted);
+        }
+        if let (Some(lut), LutTarget::Frames) = (options.lut.as_ref(), options.lut_target) {
+            let mut graded = cur_img.to_rgba();
+            lut.apply(&mut graded);
+            cur_img = image::DynamicImage::ImageRgba8(graded);
+        }
+        Ok(cur_img)
+    }
+}
+
+/// Copies the band at `index`, covering `lines`, from a frame into the composite. Returns whether
+/// anything could have been copied, i.e. whether the band still falls inside the image.
+pub(crate) fn copy_band<I, J>(buf: &mut I,
+                              frame: &mut J,
+                              index: u32,
+                              num_frames: u32,
+                              lines: Range<u32>,
+                              options: &RenderOptions)
+                              -> Result<bool>
+    where I: GenericImage + 'static,
+          I::Pixel: 'static,
+          J: GenericImage<Pixel = I::Pixel> + 'static
+{
+    // Frames and composites that are both RGBA8 of the same dimensions share their layout, so the
+    // band is copied a row at a time.
+    if let (None, None) = (options.dither, options.geometry.as_ref()) {
+        let frame = &*frame as &dyn Any;
+        let rgba = match frame.downcast_ref::<DynamicImage>() {
+            Some(DynamicImage::ImageRgba8(frame)) => Some(frame),
+            Some(_) => None,
+            None => frame.downcast_ref::<RgbaImage>(),
+        };
+        let buf = (&mut *buf as &mut dyn Any).downcast_mut::<RgbaImage>();
+        if let (Some(buf), Some(frame)) = (buf, rgba) {
+            if buf.dimensions() == frame.dimensions() {
+                return Ok(copy_lines(buf, frame, lines, options.direction));
+            }
+        }
+    }
+    match options.dither {
+        Some(dither) => {
+            process_image_dithered(buf,
+                                   frame,
+                                   index,
+                                   num_frames,
+                                   options.direction,
+                                   dither,
+                                   options.seed)
+        }
+        None => {
+            let geometry = options.shutter_geometry();
+            lines.map(|line| process_image(buf, frame, line as usize, geometry))
+                .collect::<Result<Vec<_>>>()
+                .map(|copied| copied.into_iter().all(|copied| copied))
+        }
+    }
+}
+
+/// Applies the output stages to the composite: the autocrop, the LUT if it targets the output,
+/// rescaling, sharpening and the overlay.
+fn output_stages<C: Channel>(mut buf: RgbaBuffer<C>,
+                             options: &RenderOptions,
+                             autocrop: Option<Region>)
+                             -> RgbaBuffer<C> {
+    if let Some(region) = autocrop {
+        buf = output_processing::crop(buf, region);
+    }
+
+    if let (Some(lut), LutTarget::Output) = (options.lut.as_ref(), options.lut_target) {
+        lut.apply(&mut buf);
+    }
+
+    if let Some(size) = options.output_size {
+        buf =
+            output_processing::rescale(buf, size, options.output_filter, options.linear_blend);
+    }
+
+    if let Some((amount, sigma)) = options.sharpen {
+        output_processing::sharpen(&mut buf, amount, sigma, options.linear_blend);
+    }
+
+    if let Some(ref overlay) = options.overlay {
+        overlay.apply(&mut buf, options.linear_blend);
+    }
+    buf
+}
+
+/// Applies the output stages to the composite and saves it, returning its final dimensions.
+///
+/// The composite is at 16 bits per channel if the output stages should blend at that depth, having
+/// either been composited at it or widened once finished.
+fn finish_output<C: Channel>(buf: RgbaBuffer<C>,
+                             output: &Path,
+                             options: &RenderOptions,
+                             autocrop: Option<Region>,
+                             profile: Option<&[u8]>,
+                             metadata: Option<&Metadata>)
+                             -> Result<(u32, u32)> {
+    let buf = output_stages(buf, options, autocrop);
+    if output == Path::new("-") {
+        let format = options.output_format.unwrap_or(OutputFormat::Png);
+        output_processing::write_stdout(&buf, format, profile, metadata, &options.encoder)?;
+    } else {
+        output_processing::save(&buf,
+                                output,
+                                options.output_format,
+                                profile,
+                                metadata,
+                                &options.encoder)?;
+    }
+    Ok(buf.dimensions())
+}
+
+/// The finished composite, as it is handed to each output sink.
+pub struct FinishedComposite<'a> {
+    composite: &'a image::RgbaImage,
+    /// The composite widened to 16 bits per channel, if the outputs are saved at that depth.
+    wide: Option<&'a Rgba16Image>,
+    options: &'a RenderOptions,
+    autocrop: Option<Region>,
+    profile: Option<&'a [u8]>,
+    metadata: Option<&'a Metadata>,
+}
+
+impl<'a> FinishedComposite<'a> {
+    /// The composite as it was built up, before the output stages.
+    pub fn composite(&self) -> &image::RgbaImage {
+        self.composite
+    }
+
+    /// The composite after the output stages, at 8 bits per channel.
+    pub fn output(&self) -> image::RgbaImage {
+        output_stages(self.composite.clone(), self.options, self.autocrop)
+    }
+
+    /// Applies the output stages to the composite and saves it to `path`, or writes it to standard
+    /// output if `path` is `-`, at the bit depth and in the format the options ask for. Returns its
+    /// final dimensions.
+    ///
+    /// # Errors
+    /// This fails if the output cannot be encoded or written.
+    pub fn save(&self, path: &Path) -> Result<(u32, u32)> {
+        let options = self.options;
+        match self.wide {
+            None => {
+                finish_output(self.composite.clone(),
+                              path,
+                              options,
+                              self.autocrop,
+                              self.profile,
+                              self.metadata)
+            }
+            Some(wide) => {
+                finish_output(wide.clone(),
+                              path,
+                              options,
+                              self.autocrop,
+                              self.profile,
+                              self.metadata)
+            }
+        }
+    }
+}
+
+/// Where finished lines of the composite go.
+enum Composite {
+    /// The whole composite is kept in memory until every frame has been processed.
+    Buffer(image::RgbaImage),
+    /// Each row is encoded as soon as it is finished, without keeping the composite around.
+    Stream(Box<StreamedOutput>),
+    /// The composite is kept on the GPU, which copies each band into it, until every frame has
+    /// been processed.
+    Gpu(GpuCompositor),
+    /// The composite is kept in memory transposed, so that bands of columns are copied in as rows,
+    /// and flipped back once every frame has been processed.
+    Transposed(image::RgbaImage),
+}
+
+/// Picks the group of frames each band of a render scaled down by `scale` is taken from: the
+/// group that the matching band of the full-size render would use, so that the two line up.
+fn scaled_bands(groups: usize, scale: f64) -> Vec<usize> {
+    (0usize..)
+        .map(|band| (band as f64 / scale) as usize)
+        .take_while(|&group| group < groups)
+        .collect()
+}
+
+/// Picks the group of frames each band is taken from, out of `groups` groups of blended frames.
+pub(crate) fn band_groups(groups: usize, options: &RenderOptions) -> Vec<usize> {
+    match options.transforms.scale {
+        Some(scale) => scaled_bands(groups, scale),
+        None => (0..groups).collect(),
+    }
+}
+
+impl RenderOptions {
+    /// How many of `count` input frames a render can take bands from, given the dimensions of the
+    /// first frame as it is stored, before any transforms. Once every line of the composite has its
+    /// band the rest are never read, so callers can leave them out before checking or rendering the
+    /// frames.
+    pub fn usable_frames(&self, count: usize, dimensions: (u32, u32)) -> usize {
+        let preblend = ::std::cmp::max(self.preblend, 1);
+        let bands = band_groups(count.div_ceil(preblend), self);
+        let dimensions = self.transforms.dimensions(dimensions);
+        let num_lines = self.shutter_geometry().band_count(dimensions) as usize;
+        let num_frames = ::std::cmp::min(bands.len(), num_lines);
+        bands[..num_frames]
+            .iter()
+            .map(|&g| ::std::cmp::min((g + 1) * preblend, count))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Whether the composite can be streamed straight into the output instead of being held in
+/// memory. This is only possible when the shutter finishes rows from the top down, as PNG stores
+/// them, and nothing else needs the composite once its rows are written, including the `followers`
+/// the caller gave.
+fn can_stream<P: AsRef<Path>>(options: &RenderOptions, outputs: &[P], followers: usize) -> bool {
+    options.direction == Direction::N && streams_rows(options, outputs, followers)
+}
+
+/// Whether the rows of the composite can be written straight into a single PNG output once they
+/// are finished: nothing needs the composite as it is built up or once its rows are written,
+/// including the `followers` the caller gave, and each of its pixels comes from a single band.
+fn streams_rows<P: AsRef<Path>>(options: &RenderOptions, outputs: &[P], followers: usize) -> bool {
+    let output = outputs[0].as_ref();
+    let lut_on_output = options.lut.is_some() && options.lut_target == LutTarget::Output;
+    outputs.len() == 1 && output != Path::new("-") &&
+    OutputFormat::from_path(output).or(options.output_format) == Some(OutputFormat::Png) &&
+    options.dither.is_none() && !options.autocrop && !lut_on_output &&
+    options.output_size.is_none() && options.sharpen.is_none() && options.overlay.is_none() &&
+    options.bit_depth == BitDepth::Eight && options.animation.is_none() &&
+    options.snapshots.is_none() && options.autosave_every.is_none() && !options.debug_overlay &&
+    options.compare_with.is_none() && options.resume.is_none() && !options.preview &&
+    options.geometry.is_none() && followers == 0
+}
+
+/// Whether the composite can be rendered a strip of rows at a time: its rows can be streamed into
+/// the output, nothing else needs every pixel of the image, and the frame stages only need the
+/// rows of the strip, which the transforms, the stabilizer, deflickering, white balance and
+/// denoising don't.
+fn can_tile<P: AsRef<Path>>(options: &RenderOptions, outputs: &[P], followers: usize) -> bool {
+    streams_rows(options, outputs, followers) && options.export_bands.is_none() &&
+    options.time_map.is_none() &&
+    options.transforms == FrameTransforms::default() && !options.stabilize &&
+    options.deflicker.is_none() && options.white_balance.is_none() && options.denoise.is_none()
+}
+
+/// Works out how many bands a render of `count` bands has room for in an image of the given
+/// dimensions, and how many lines they cover, warning if they leave some of it empty.
+fn coverage(options: &RenderOptions, count: u64, dimensions: (u32, u32)) -> (u64, u32) {
+    let num_lines = options.shutter_geometry().band_count(dimensions) as u64;
+    let num_frames = ::std::cmp::min(count, num_lines);
+    // With too few frames for a line each, the bands either leave the end of the image empty or
+    // are stretched to cover all of it.
+    let lines_covered = if options.auto_stretch { num_lines } else { num_frames } as u32;
+    if count < num_lines && !options.auto_stretch && !options.suppress_output {
+        warn!("there are only {} bands for the {} lines of the image, so only {:.1}% of it will be \
+               filled. Use --auto-stretch to stretch the bands over all of it.",
+              count,
+              num_lines,
+              count as f64 * 100.0 / num_lines as f64);
+    }
+    (num_frames, lines_covered)
+}
+
+/// The metadata the options ask to record in the output, with what the render was made from added.
+fn render_metadata(options: &RenderOptions,
+                   input_frames: usize,
+                   preblend: usize,
+                   num_frames: u64)
+                   -> Option<Metadata> {
+    options.metadata.clone().map(|mut metadata| {
+        metadata.push("Direction", format!("{:?}", options.shutter_geometry()));
+        metadata.push("Input frames", input_frames);
+        metadata.push("Frames per band", preblend);
+        metadata.push("Bands", num_frames);
+        if options.dither.is_some() {
+            metadata.push("Seed", options.seed.0);
+        }
+        metadata
+    })
+}
+
+/// A composite streamed into a PNG output a row at a time. The rows go into a temporary file next
+/// to the output, which only takes its place once the image is finished, so that a render that
+/// fails partway leaves whatever was there before rather than a truncated image.
+struct StreamedOutput {
+    /// The encoder, until the image is finished.
+    encoder: Option<StreamingEncoder<BufWriter<File>>>,
+    output: PathBuf,
+    temporary: PathBuf,
+}
+
+impl StreamedOutput {
+    /// Filters and compresses the next row, returning `false` if the image already has all of its
+    /// rows.
+    fn write_row(&mut self, row: &[u8]) -> Result<bool> {
+        let encoder = self.encoder.as_mut().expect("rows are only written until it is finished");
+        encoder.write_row(row).chain_err(|| ErrorKind::CouldNotSaveOutput(self.output.clone()))
+    }
+
+    /// Fills any rows that were never written with transparent black, finishes the image and moves
+    /// it to `path`, which is the output unless the image was left incomplete.
+    fn finish(mut self, path: &Path) -> Result<()> {
+        let encoder = self.encoder.take().expect("the image is only finished once");
+        encoder.finish()
+            .and_then(|writer| writer.into_inner().map_err(|e| e.into_error()))
+            .and_then(|_| fs::rename(&self.temporary, path))
+            .chain_err(|| ErrorKind::CouldNotSaveOutput(path.to_path_buf()))
+    }
+}
+
+impl Drop for StreamedOutput {
+    /// Removes the temporary file of an image that was never finished.
+    fn drop(&mut self) {
+        if self.encoder.take().is_some() {
+            let _ = fs::remove_file(&self.temporary);
+        }
+    }
+}
+
+/// Starts streaming a composite of the given dimensions into a PNG output, with the color profile
+/// and metadata in its header.
+fn stream_encoder(output: &Path,
+                  (width, height): (u32, u32),
+                  options: &RenderOptions,
+                  profile: Option<&[u8]>,
+                  metadata: Option<&Metadata>)
+                  -> Result<StreamedOutput> {
+    let mut header = vec![];
+    if let Some(profile) = profile {
+        header.extend(icc::png_chunk(profile));
+    }
+    if let Some(metadata) = metadata {
+        header.extend(metadata.png_chunks());
+    }
+    let mut name = output.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".tmp");
+    let temporary = output.with_file_name(name);
+    let encoder = File::create(&temporary)
+        .and_then(|file| {
+            StreamingEncoder::new(BufWriter::new(file),
+                                  width,
+                                  height,
+                                  8,
+                                  options.encoder.png_compression.deflate(),
+                                  &header)
+        })
+        .chain_err(|| ErrorKind::CouldNotSaveOutput(output.to_path_buf()));
+    match encoder {
+        Ok(encoder) => {
+            Ok(StreamedOutput {
+                encoder: Some(encoder),
+                output: output.to_path_buf(),
+                temporary,
+            })
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&temporary);
+            Err(e)
+        }
+    }
+}
+
+/// Renders the composite a strip of `tile_rows` rows at a time, streaming each into the output once
+/// it is done, for canvases too large to hold whole. Every strip goes over the frames whose bands
+/// reach it again, decoding only its rows where it can, so no more than a strip of the composite
+/// and of a frame are held at once. Bands of rows only reach one strip, but bands of columns reach
+/// them all, so E and W renders decode each frame once for every strip.
+///
+/// Strips can't be resumed, so an interrupted render only keeps the strips done so far.
+fn render_tiled(paths: &[PathBuf],
+                output: &Path,
+                tile_rows: u32,
+                options: &RenderOptions,
+                profile: Option<&[u8]>,
+                progress: &mut dyn ProgressSink,
+                render_start: Instant)
+                -> Result<Report> {
+    let preblend = ::std::cmp::max(options.preblend, 1);
+    let groups: Vec<&[PathBuf]> = paths.chunks(preblend).collect();
+    let bands = band_groups(groups.len(), options);
+    let dimensions = file_processing::read_dimensions(&paths[0])
+        .chain_err(|| ErrorKind::Frame(FrameInfo::read(0, &paths[0])))?;
+    let (width, height) = dimensions;
+    let (num_frames, lines_covered) = coverage(options, bands.len() as u64, dimensions);
+    let num_frames = num_frames as u32;
+    let metadata = render_metadata(options, paths.len(), preblend, num_frames as u64);
+    let mut encoder = stream_encoder(output, dimensions, options, profile, metadata.as_ref())?;
+
+    // The region each band covers, if it falls inside the image.
+    let regions: Vec<_> = (0..num_frames)
+        .map(|band| {
+            band_coords((0, 0, width, height),
+                        band_lines(band, num_frames, lines_covered),
+                        options.direction)
+        })
+        .collect();
+    let tile_rows = ::std::cmp::max(tile_rows, 1);
+    let strips: Vec<Range<u32>> = (0..height)
+        .step_by(tile_rows as usize)
+        .map(|top| top..::std::cmp::min(top + tile_rows, height))
+        .collect();
+    let reaches = |region: &Option<(u32, u32, u32, u32)>, strip: &Range<u32>| {
+        region.is_some_and(|(_, y, _, rows)| y < strip.end && y + rows > strip.start)
+    };
+    let total: u64 = strips.iter()
+        .map(|strip| regions.iter().filter(|region| reaches(region, strip)).count() as u64)
+        .sum();
+
+    let mut stages = FrameStages::new(options);
+    let mut done = 0;
+    for strip in &strips {
+        let mut buf = RgbaImage::new(width, strip.end - strip.start);
+        for (band, region) in regions.iter().enumerate() {
+            let (x, y, band_width, rows) = match *region {
+                Some(coords) if reaches(region, strip) => coords,
+                _ => continue,
+            };
+            let group = groups[bands[band]];
+            let info = FrameInfo::read(bands[band] * preblend, &group[0]);
+            progress.start(PROCESSING, done, total, &info);
+            let frames = group.iter().map(|path| {
+                frame_processing::load_strip(path, strip.clone(), dimensions, options.resize_policy)
+            });
+            let frame = stages.process(frames).chain_err(|| ErrorKind::Frame(info.clone()))?;
+            let frame = frame.to_rgba();
+            let (buf, frame): (&mut [u8], &[u8]) = (&mut *buf, &*frame);
+            let stride = width as usize * 4;
+            let (start, len) = (x as usize * 4, band_width as usize * 4);
+            let top = ::std::cmp::max(y, strip.start);
+            let bottom = ::std::cmp::min(y + rows, strip.end);
+            for row in (top - strip.start) as usize..(bottom - strip.start) as usize {
+                let range = row * stride + start..row * stride + start + len;
+                buf[range.clone()].copy_from_slice(&frame[range]);
+            }
+            done += 1;
+            progress.done(PROCESSING, done, total);
+        }
+        for row in buf.chunks(width as usize * 4) {
+            encoder.write_row(row)?;
+        }
+        if interrupt::interrupted() && strip.end < height {
+            let partial = checkpoint::partial_path(output);
+            encoder.finish(&partial)?;
+            bail!(ErrorKind::InterruptedStrips(partial));
+        }
+    }
+    encoder.finish(output)?;
+    progress.finish(PROCESSING);
+
+    let used: Vec<usize> = (0..num_frames as usize).filter(|&band| regions[band].is_some())
+        .map(|band| bands[band])
+        .collect();
+    let frames_used = used.last().map_or(0, |&g| g * preblend + groups[g].len());
+    if !options.suppress_output && options.job_label.is_none() {
+        info!("Done.");
+    }
+    Ok(Report {
+        frames_discovered: paths.len(),
+        frames_used,
+        skipped: paths[frames_used..].to_vec(),
+        frames: used.iter().map(|&g| FrameInfo::read(g * preblend, &groups[g][0])).collect(),
+        frame_dimensions: dimensions,
+        outputs: vec![ReportOutput {
+                          path: output.to_path_buf(),
+                          dimensions,
+                      }],
+        timings: vec![("render", render_start.elapsed())],
+    })
+}
+
+/// Builds a map of which frame every pixel of the composite was taken from, as a grayscale image
+/// where black is the first frame and white is the last. Pixels that no frame reached are left
+/// transparent.
+fn time_map(dimensions: (u32, u32),
+            direction: Direction,
+            num_frames: u32,
+            lines_covered: u32,
+            dither: Option<Dither>,
+            seed: Seed)
+            -> Rgba16Image {
+    let (width, height) = dimensions;
+    let bounds = (0, 0, width, height);
+    let (num_lines, cross_len) = match direction {
+        Direction::N | Direction::S => (height, width),
+        Direction::E | Direction::W => (width, height),
+    };
+    let last = ::std::cmp::max(num_frames, 2) - 1;
+
+    let mut map: Rgba16Image = ImageBuffer::new(width, height);
+    for line in 0..::std::cmp::min(num_lines, lines_covered) {
+        for cross in 0..cross_len {
+            let index = match dither {
+                Some(dither) => dither.frame_index(line, cross, num_frames, seed),
+                None => line_band(line, num_frames, lines_covered),
+            };
+            let value = (index as u64 * 65535 / last as u64) as u16;
+            let (x, y) = line_to_coords(bounds, line, cross, direction);
+            map.put_pixel(x, y, Rgba([value, value, value, 65535]));
+        }
+    }
+    map
+}
+
+/// Holds the composite, starting from `buf`, on the GPU if the options ask for it and nothing needs
+/// to look at it after every band, or in memory otherwise. In memory, a composite built from bands
+/// of columns is held transposed, as long as nothing looks at it, including the `followers` the
+/// caller gave.
+fn composite_buffer(buf: image::RgbaImage,
+                    options: &RenderOptions,
+                    num_frames: u32,
+                    lines_covered: u32,
+                    followers: usize)
+                    -> Result<Composite> {
+    let recorded = options.animation.is_some() || options.snapshots.is_some() ||
+                   options.autosave_every.is_some() || options.preview;
+    if !options.gpu {
+        let columns = options.direction == Direction::E || options.direction == Direction::W;
+        // Dithered bands and custom geometries aren't copied a line at a time.
+        if columns && options.dither.is_none() && options.geometry.is_none() && !recorded &&
+           followers == 0 {
+            return Ok(Composite::Transposed(transpose(&buf)));
+        }
+        return Ok(Composite::Buffer(buf));
+    }
+    if recorded {
+        if !options.suppress_output {
+            warn!("compositing on the CPU, since animations, snapshots, autosaves and the preview \
+                   look at the composite as it is built up.");
+        }
+        return Ok(Composite::Buffer(buf));
+    }
+    let map = band_map(buf.dimensions(), options, num_frames, lines_covered);
+    Ok(Composite::Gpu(GpuCompositor::new(&buf, &map)?))
+}
+
+/// Builds a map of the band every pixel of the composite is taken from, row by row, as compositing
+/// the bands one after another on the CPU would. Pixels that no band reaches are `gpu::NO_BAND`.
+pub(crate) fn band_map(dimensions: (u32, u32),
+                       options: &RenderOptions,
+                       num_frames: u32,
+                       lines_covered: u32)
+                       -> Vec<u32> {
+    let (width, height) = dimensions;
+    let mut map = vec![gpu::NO_BAND; width as usize * height as usize];
+    let mut set = |x: u32, y: u32, band: u32| map[(y * width + x) as usize] = band;
+    if let Some(dither) = options.dither {
+        let bounds = (0, 0, width, height);
+        let direction = options.direction;
+        let (num_lines, cross_len) = match direction {
+            Direction::N | Direction::S => (height, width),
+            Direction::E | Direction::W => (width, height),
+        };
+        for line in 0..::std::cmp::min(num_lines, num_frames) {
+            for cross in 0..cross_len {
+                // Each band only reaches the lines within the radius of its own.
+                let band = dither.frame_index(line, cross, num_frames, options.seed);
+                if band.saturating_sub(dither.radius) <= line && line <= band + dither.radius {
+                    let (x, y) = line_to_coords(bounds, line, cross, direction);
+                    set(x, y, band);
+                }
+            }
+        }
+    } else {
+        let geometry = options.shutter_geometry();
+        for band in 0..num_frames {
+            for line in band_lines(band, num_frames, lines_covered) {
+                match geometry.region_for_frame(line, dimensions) {
+                    Some(BandRegion::Rect(x, y, band_width, band_height)) => {
+                        if x as u64 + band_width as u64 > width as u64 ||
+                           y as u64 + band_height as u64 > height as u64 {
+                            continue;
+                        }
+                        for y in y..y + band_height {
+                            for x in x..x + band_width {
+                                set(x, y, band);
+                            }
+                        }
+                    }
+                    Some(BandRegion::Pixels(pixels)) => {
+                        for (x, y) in pixels {
+                            if x < width && y < height {
+                                set(x, y, band);
+                            }
+                        }
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+    map
+}
+
+/// Builds the path a band is exported to from its index, padded so that bands sort in order.
+fn band_path(dir: &Path, index: u32) -> PathBuf {
+    dir.join(format!("{:05}.png", index))
+}
+
+/// Extracts a row of a frame as 8-bit RGBA data.
+fn frame_row(image: &image::DynamicImage, y: u32) -> Vec<u8> {
+    (0..image.width()).flat_map(|x| image.get_pixel(x, y).data.to_vec()).collect()
+}
+
+/// Saves the bands finished before an interrupt next to the first output, with a checkpoint of
+/// where the render stopped. Bands that weren't reached are left transparent.
+///
+/// # Errors
+/// This always fails once the partial render is saved, so that the interrupt is reported, or
+/// earlier if it cannot be saved.
+fn save_partial(composite: Composite,
+                output: &Path,
+                next_band: u32,
+                input_frames: usize)
+                -> Result<Report> {
+    let partial = checkpoint::partial_path(output);
+    let buf = match composite {
+        Composite::Stream(encoder) => {
+            encoder.finish(&partial)?;
+            None
+        }
+        Composite::Buffer(buf) => Some(buf),
+        Composite::Gpu(gpu) => Some(gpu.finish()?),
+        Composite::Transposed(buf) => Some(transpose(&buf)),
+    };
+    if let Some(buf) = buf {
+        output_processing::save(&buf,
+                                &partial,
+                                Some(OutputFormat::Png),
+                                None,
+                                None,
+                                &EncoderSettings::default())
+            .chain_err(|| ErrorKind::CouldNotSaveOutput(partial.clone()))?;
+    }
+
+    let checkpoint_path = checkpoint::checkpoint_path(output);
+    let checkpoint = Checkpoint {
+        next_band,
+        input_frames,
+        partial: partial.clone(),
+    };
+    checkpoint.save(&checkpoint_path)?;
+    bail!(ErrorKind::Interrupted(partial, checkpoint_path))
+}
+
+impl RenderOptions {
+    /// The files saved next to the first output and named after it, as far as the options ask
+    /// for them: the copy marked with the debug overlay, and the comparison.
+    pub fn sibling_outputs(&self, output: &Path) -> Vec<PathBuf> {
+        let mut siblings = vec![];
+        if self.debug_overlay {
+            siblings.push(debug_overlay::debug_path(output));
+        }
+        if self.compare_with.is_some() {
+            siblings.push(comparison::comparison_path(output));
+        }
+        siblings
+    }
+}
+
+/// Given a set of image paths, output paths, and rendering options, generate an output image and
+/// save it to every output path. Returns a report of what was rendered, with the time taken to
+/// render and save.
+///
+/// However many outputs there are, the frames are decoded in a single pass over the bands: the
+/// outputs, the animation and snapshots, the exported bands and the time map are all taken from
+/// it, and the first frame is kept for comparing with rather than decoded twice.
+///
+/// # Arguments
+/// * `paths` - An iterator of `PathBuf`s that describe the input images, in the correct order.
+/// * `outputs` - The output image paths.
+/// * `options` - The options controlling the render.
+/// * `sinks` - Anywhere else the composite goes once it is finished, besides the outputs and the
+///   animation and snapshots the options ask for.
+/// * `observers` - Anything else following the render as it goes.
+/// * `progress` - Where to report progress through the frames.
+///
+/// # Errors
+/// This fails if there are no frames or no outputs, and may fail if an individual image cannot be
+/// opened or processed or has more than 8 bits per channel, or if an output cannot be properly
+/// saved.
+pub fn process_images<I, P>(paths: I,
+                            outputs: &[P],
+                            options: &RenderOptions,
+                            sinks: &mut [&mut dyn OutputSink],
+                            observers: &mut [&mut dyn RenderObserver],
+                            progress: &mut dyn ProgressSink)
+                            -> Result<Report>
+    where I: Iterator<Item = PathBuf> + ExactSizeIterator,
+          P: AsRef<Path>
+{
+    let render_start = Instant::now();
+    check_options(options, true)?;
+    let paths: Vec<PathBuf> = paths.collect();
+    if paths.is_empty() {
+        bail!(ErrorKind::NoFrames);
+    }
+    if outputs.is_empty() {
+        bail!(ErrorKind::NoOutputs);
+    }
+    let preblend = ::std::cmp::max(options.preblend, 1);
+
+    let groups: Vec<&[PathBuf]> = paths.chunks(preblend).collect();
+    let bands = band_groups(groups.len(), options);
+    let count = bands.len() as u64;
+
+    let first_path = paths[0].clone();
+    let direction = options.direction;
+    let suppress_output = options.suppress_output;
+
+    let profile = if options.preserve_icc {
+        match icc::read_profile(&first_path) {
+            Ok(profile) => profile,
+            Err(e) => {
+                if !suppress_output {
+                    warn!("ignoring the color profile of {}: {}", first_path.display(), e);
+                }
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    if let Some(tile_rows) = options.tile_rows {
+        if can_tile(options, outputs, sinks.len() + observers.len()) {
+            return render_tiled(&paths,
+                                outputs[0].as_ref(),
+                                tile_rows,
+                                options,
+                                profile.as_deref(),
+                                progress,
+                                render_start);
+        }
+        if !suppress_output {
+            warn!("rendering the whole image at once, since strips can only be rendered straight \
+                   into a single PNG output, by bands that don't need more than their rows of \
+                   each frame.");
+        }
+    }
+
+    let first_frame = frame_processing::load_frame(&first_path, &options.transforms)
+        .chain_err(|| ErrorKind::Frame(FrameInfo::read(0, &first_path)))?;
+    let (width, height) = first_frame.dimensions();
+    // The first frame is decoded whole, so it is kept if the output is compared with it.
+    let first_reference = match options.compare_with {
+        Some(CompareWith::First) => Some(first_frame.clone()),
+        _ => None,
+    };
+    let mut first_frame = Some(first_frame);
+
+    let (num_frames, lines_covered) = coverage(options, count, (width, height));
+
+    let profile = profile.as_deref();
+    let metadata = render_metadata(options, paths.len(), preblend, num_frames);
+
+    // Everything but the outputs looks at the 8-bit composite, so the 16-bit one is kept alongside.
+    let mut wide_composite: Option<Rgba16Image> = if composites_wide(options) {
+        Some(ImageBuffer::new(width, height))
+    } else {
+        None
+    };
+    let mut composite = if can_stream(options, outputs, sinks.len() + observers.len()) {
+        Composite::Stream(Box::new(stream_encoder(outputs[0].as_ref(),
+                                                  (width, height),
+                                                  options,
+                                                  profile,
+                                                  metadata.as_ref())?))
+    } else if let Some(ref checkpoint) = options.resume {
+        if checkpoint.input_frames != paths.len() {
+            bail!(ErrorKind::CheckpointMismatch(checkpoint.input_frames, paths.len()));
+        }
+        let partial = image::open(&checkpoint.partial)
+            .chain_err(|| ErrorKind::CouldNotOpenImage(checkpoint.partial.clone()))?
+            .to_rgba();
+        if partial.dimensions() != (width, height) {
+            bail!(ErrorKind::FrameSizeMismatch(checkpoint.partial.clone(),
+                                               (width, height),
+                                               partial.dimensions()));
+        }
+        if let Some(ref mut wide) = wide_composite {
+            *wide = color_processing::to_16_bit(&partial);
+        }
+        composite_buffer(partial,
+                         options,
+                         num_frames as u32,
+                         lines_covered,
+                         sinks.len() + observers.len())?
+    } else {
+        composite_buffer(ImageBuffer::new(width, height),
+                         options,
+                         num_frames as u32,
+                         lines_covered,
+                         sinks.len() + observers.len())?
+    };
+    let start_band = options.resume.as_ref().map_or(0, |checkpoint| checkpoint.next_band as usize);
+    let mut stages = FrameStages::new(options);
+
+    let mut recorders: Vec<Box<dyn OutputSink>> = vec![];
+    if let Some(ref settings) = options.animation {
+        recorders.push(Box::new(Animation::create(settings, (width, height))?));
+    }
+    if let Some(ref settings) = options.snapshots {
+        recorders.push(Box::new(Snapshots::create(settings)?));
+    }
+    if let Some(every) = options.autosave_every {
+        recorders.push(Box::new(Autosave::new(outputs[0].as_ref(),
+                                              every,
+                                              start_band as u32,
+                                              paths.len(),
+                                              suppress_output)));
+    }
+    let mut files: Vec<Box<dyn OutputSink>> =
+        outputs.iter().map(|output| output_sink::for_path(output.as_ref())).collect();
+    // The outputs are saved last, so that they are reported in the order they were given. Each
+    // sink is cast on its own, so that the caller's are reborrowed for no longer than the local
+    // ones rather than tying those to the caller's lifetime.
+    let sinks: Vec<&mut dyn OutputSink> = recorders.iter_mut()
+        .map(|sink| &mut **sink as &mut dyn OutputSink)
+        .chain(sinks.iter_mut().map(|sink| &mut **sink as &mut dyn OutputSink))
+        .chain(files.iter_mut().map(|sink| &mut **sink as &mut dyn OutputSink))
+        .collect();
+
+    if let Some(ref dir) = options.export_bands {
+        fs::create_dir_all(dir).chain_err(|| ErrorKind::CouldNotSaveOutput(dir.clone()))?;
+    }
+
+    let mut preview = if options.preview {
+        Some(PreviewWindow::open((width, height))?)
+    } else {
+        None
+    };
+    let mut progress = ProgressObserver::new(PROCESSING, progress);
+    let mut followers = Followers {
+        sinks,
+        observers: observers.iter_mut()
+            .map(|observer| &mut **observer as &mut dyn RenderObserver)
+            .collect(),
+    };
+    followers.observers.extend(preview.as_mut().map(|preview| preview as &mut dyn RenderObserver));
+    followers.observers.push(&mut progress);
+
+    let remaining = num_frames.saturating_sub(start_band as u64);
+    // Frames are decoded a batch at a time, one on each thread of the pool, and handed on in
+    // order. The next batch is decoded in the background while this one is composited. Without
+    // threads to spawn, as in the browser, they are decoded one at a time here.
+    let pool = ThreadPoolBuilder::new().num_threads(options.threads).build().ok();
+    let frame_bytes = width as u64 * height as u64 * 4;
+    // The frame being blended and the composite, unless it is streamed, are held regardless, as are
+    // the first frame if it is kept to compare with and the 16-bit composite.
+    let kept = first_reference.as_ref().map_or(0, |_| frame_bytes) +
+               wide_composite.as_ref().map_or(0, |_| 2 * frame_bytes);
+    let held = match composite {
+        Composite::Stream(_) => frame_bytes,
+        Composite::Buffer(_) | Composite::Gpu(_) | Composite::Transposed(_) => 2 * frame_bytes,
+    } + kept;
+    if let Some(max_memory) = options.max_memory {
+        if held > max_memory && !suppress_output {
+            warn!("the render needs about {} MiB however few frames it decodes ahead, more than \
+                   the {} MiB --max-memory allows.",
+                  held >> 20,
+                  max_memory >> 20);
+        }
+    }
+    let threads = pool.as_ref().map_or(1, ThreadPool::current_num_threads);
+    let (batch, decode_ahead) = decode_batch(threads, options.max_memory, frame_bytes, held);
+    let decoder = Decoder {
+        transforms: options.transforms.clone(),
+        resize_policy: options.resize_policy,
+        dimensions: (width, height),
+        rows_only: if rows_only(options) {
+            Some((direction, num_frames as u32, lines_covered))
+        } else {
+            None
+        },
+        // Each band's frame is handed back once it is copied, for a frame of the next batch.
+        pool: FramePool::new(batch),
+        read_ahead: ReadAhead::new().map(Arc::new),
+        // Frames are only decoded straight into RGBA when they need nothing done to them first.
+        layout: options.frame_layout.filter(|layout| {
+            layout.dimensions == (width, height) && options.transforms == FrameTransforms::default()
+        }),
+    };
+    let mut decoded = VecDeque::new();
+    let mut prefetch: Option<Prefetch> = None;
+    // The first band that hasn't been decoded or started decoding yet.
+    let mut queued = start_band;
+
+    let mut frames_used = 0;
+    let mut band_frames = vec![];
+    // Every line has its band after the first `num_frames`, so there is no room for the rest.
+    for (i, &g) in bands.iter().take(num_frames as usize).enumerate() {
+        let group = groups[g];
+        let path = &group[0];
+        // Bands before the checkpoint are already in the resumed composite.
+        if i < start_band {
+            frames_used = g * preblend + group.len();
+            // The first frame only tells the dimensions when resuming, so it mustn't be used as
+            // the first band done.
+            first_frame = None;
+            continue;
+        }
+        if decoded.is_empty() {
+            let upcoming = &bands[..num_frames as usize];
+            decoded = match prefetch.take() {
+                Some(prefetch) => prefetch.wait(),
+                None => {
+                    let (mut jobs, end) = batch_jobs(upcoming, &groups, preblend, queued, batch);
+                    queued = end;
+                    // The first frame was already decoded to find the dimensions.
+                    if first_frame.is_some() {
+                        jobs.remove(0);
+                    }
+                    decoder.queue(&jobs);
+                    match pool {
+                        Some(ref pool) => pool.install(|| decode_frames(&jobs, &decoder, true)),
+                        None => decode_frames(&jobs, &decoder, false),
+                    }
+                }
+            }.into();
+            if let (Some(pool), true) = (pool.as_ref(), decode_ahead) {
+                let (jobs, end) = batch_jobs(upcoming, &groups, preblend, queued, batch);
+                queued = end;
+                if !jobs.is_empty() {
+                    decoder.queue(&jobs);
+                    prefetch = Some(Prefetch::start(pool, jobs, decoder.clone()));
+                }
+            }
+        }
+        let info = match decoded.front() {
+            Some((info, _)) if first_frame.is_none() => info.clone(),
+            _ => FrameInfo::read(g * preblend, path),
+        };
+        let index = (i - start_band) as u64;
+        band_frames.push(info.clone());
+        let frames = group.iter().map(|path| -> Result<_> {
+            let (frame_info, frame) = match first_frame.take() {
+                Some(frame) => (info.clone(), frame),
+                None => {
+                    let (frame_info, frame) = decoded.pop_front().unwrap();
+                    (frame_info, frame?)
+                }
+            };
+            debug_assert_eq!(&frame_info.path, path);
+            followers.frame_decoded(index, remaining, &frame_info)?;
+            Ok(frame)
+        });
+        let (mut cur_img, wide_img) = match wide_composite {
+            Some(_) => {
+                let frame = stages.process_wide(frames)?;
+                (DynamicImage::ImageRgba8(color_processing::to_8_bit(&frame)), Some(frame))
+            }
+            None => (stages.process(frames)?, None),
+        };
+        let lines = band_lines(i as u32, num_frames as u32, lines_covered);
+        if let (Some(wide), Some(mut frame)) = (wide_composite.as_mut(), wide_img) {
+            copy_band(wide, &mut frame, i as u32, num_frames as u32, lines.clone(), options)
+                .chain_err(|| ErrorKind::CouldNotProcessImage(path.clone()))?;
+        }
+        if let Some(ref dir) = options.export_bands {
+            let coords = band_coords(cur_img.bounds(), lines.clone(), direction);
+            if let Some((x, y, width, height)) = coords {
+                let band = cur_img.crop(x, y, width, height).to_rgba();
+                let path = band_path(dir, i as u32);
+                output_processing::save(&band,
+                                        &path,
+                                        Some(OutputFormat::Png),
+                                        None,
+                                        None,
+                                        &EncoderSettings::default())
+                    .chain_err(|| ErrorKind::CouldNotSaveOutput(path.clone()))?;
+            }
+        }
+        let process_result = match composite {
+            Composite::Stream(ref mut encoder) => {
+                let mut written = false;
+                for line in lines.take_while(|&line| line < height) {
+                    written = encoder.write_row(&frame_row(&cur_img, line))?;
+                }
+                written
+            }
+            Composite::Buffer(ref mut buf) => {
+                copy_band(buf, &mut cur_img, i as u32, num_frames as u32, lines, options)
+                    .chain_err(|| ErrorKind::CouldNotProcessImage(path.clone()))?
+            }
+            Composite::Gpu(ref mut gpu) => {
+                // The band map already leaves out whatever falls outside the image.
+                gpu.copy_band(i as u32, &cur_img.to_rgba())?;
+                true
+            }
+            Composite::Transposed(ref mut buf) => {
+                match cur_img {
+                    DynamicImage::ImageRgba8(ref frame) => {
+                        copy_columns(buf, frame, lines, direction)
+                    }
+                    ref frame => copy_columns(buf, &frame.to_rgba(), lines, direction),
+                }
+            }
+        };
+        decoder.pool.recycle(cur_img);
+        if !process_result {
+            // Ran out of space to do shutters, so don't continue.
+            break;
+        }
+        frames_used = g * preblend + group.len();
+        followers.band_written(index, remaining, &info)?;
+        if let Composite::Buffer(ref buf) = composite {
+            if followers.snapshot_ready(buf)? == Flow::Stop {
+                break;
+            }
+        }
+        if interrupt::interrupted() && (i as u64 + 1) < num_frames {
+            return save_partial(composite, outputs[0].as_ref(), i as u32 + 1, paths.len());
+        }
+    }
+
+    if let Some(ref path) = options.time_map {
+        let map = time_map((width, height),
+                           direction,
+                           num_frames as u32,
+                           lines_covered,
+                           options.dither,
+                           options.seed);
+        output_processing::save(&map,
+                                path,
+                                Some(OutputFormat::Png),
+                                None,
+                                None,
+                                &EncoderSettings::default())
+            .chain_err(|| ErrorKind::CouldNotSaveOutput(path.clone()))?;
+    }
+
+    let report = |outputs, timings| {
+        Report {
+            frames_discovered: paths.len(),
+            frames_used,
+            skipped: paths[frames_used..].to_vec(),
+            frames: band_frames.clone(),
+            frame_dimensions: (width, height),
+            outputs,
+            timings,
+        }
+    };
+
+    let buf = match composite {
+        Composite::Stream(encoder) => {
+            let output = outputs[0].as_ref();
+            encoder.finish(output)?;
+            let outputs = vec![ReportOutput {
+                                   path: output.to_path_buf(),
+                                   dimensions: (width, height),
+                               }];
+            // Rendering and saving happen together, so they are timed as one.
+            let report = report(outputs, vec![("render", render_start.elapsed())]);
+            followers.render_finished(&report)?;
+            if !suppress_output && options.job_label.is_none() {
+                info!("Done.");
+            }
+            return Ok(report);
+        }
+        Composite::Buffer(buf) => buf,
+        Composite::Gpu(gpu) => gpu.finish()?,
+        Composite::Transposed(buf) => transpose(&buf),
+    };
+
+    let autocrop = stages.autocrop((width, height))?;
+
+    if options.debug_overlay {
+        let mut debug = buf.clone();
+        debug_overlay::draw(&mut debug,
+                            direction,
+                            num_frames as u32,
+                            lines_covered,
+                            preblend as u32);
+        let debug_path = debug_overlay::debug_path(outputs[0].as_ref());
+        output_processing::save(&debug,
+                                &debug_path,
+                                Some(OutputFormat::Png),
+                                None,
+                                None,
+                                &EncoderSettings::default())
+            .chain_err(|| ErrorKind::CouldNotSaveOutput(debug_path.clone()))?;
+    }
+
+    if let Some(ref compare_with) = options.compare_with {
+        let reference = match *compare_with {
+            CompareWith::First | CompareWith::Middle => {
+                match first_reference {
+                    Some(frame) => frame,
+                    None => {
+                        let path = &paths[paths.len() / 2];
+                        let frame = frame_processing::load_frame(path, &options.transforms)?;
+                        frame_processing::conform_frame(frame,
+                                                        (width, height),
+                                                        options.resize_policy,
+                                                        path)?
+                    }
+                }
+            }
+            CompareWith::Path(ref path) => {
+                let image = image::open(path)
+                    .chain_err(|| ErrorKind::CouldNotOpenImage(path.clone()))?;
+                frame_processing::conform_frame(image, (width, height), ResizePolicy::Fit, path)?
+            }
+        };
+        let (mut reference, mut result) = (reference.to_rgba(), buf.clone());
+        if let Some(region) = autocrop {
+            reference = output_processing::crop(reference, region);
+            result = output_processing::crop(result, region);
+        }
+        let comparison = comparison::side_by_side(&reference, &result);
+        let comparison_path = comparison::comparison_path(outputs[0].as_ref());
+        output_processing::save(&comparison,
+                                &comparison_path,
+                                Some(OutputFormat::Png),
+                                None,
+                                None,
+                                &EncoderSettings::default())
+            .chain_err(|| ErrorKind::CouldNotSaveOutput(comparison_path.clone()))?;
+    }
+
+    let render_time = render_start.elapsed();
+    let output_start = Instant::now();
+
+    let wide = match options.bit_depth {
+        BitDepth::Eight => None,
+        BitDepth::Sixteen => {
+            Some(wide_composite.unwrap_or_else(|| color_processing::to_16_bit(&buf)))
+        }
+    };
+    let finished = FinishedComposite {
+        composite: &buf,
+        wide: wide.as_ref(),
+        options,
+        autocrop,
+        profile,
+        metadata: metadata.as_ref(),
+    };
+    let mut report_outputs = vec![];
+    for sink in followers.sinks.iter_mut() {
+        if let Some(output) = sink.finish(&finished)? {
+            report_outputs.push(output);
+        }
+    }
+    let report = report(report_outputs,
+                        vec![("render", render_time), ("output", output_start.elapsed())]);
+    followers.render_finished(&report)?;
+    // Labelled renders are part of a batch, which reports when each one finishes itself.
+    if !suppress_output && options.job_label.is_none() {
+        info!("Done.");
+    }
+
+    Ok(report)
+}
+
+/// A frame decoded ahead of the band it is for, with where it came from.
+type Decoded = (FrameInfo, Result<image::DynamicImage>);
+
+/// A frame to decode: the band it is for, its index among the frames, and its path.
+type Job = (u32, usize, PathBuf);
+
+/// What it takes to decode a frame, owned so that it can be sent to the threads decoding ahead.
+#[derive(Clone)]
+struct Decoder {
+    transforms: FrameTransforms,
+    resize_policy: ResizePolicy,
+    dimensions: (u32, u32),
+    /// The direction, the number of bands and the lines they cover, when only the rows of each
+    /// band are decoded.
+    rows_only: Option<(Direction, u32, u32)>,
+    /// The buffers of frames already composited, which frames are decoded into.
+    pool: FramePool,
+    /// The reads of the frames queued ahead of decoding them, if the platform can queue them.
+    read_ahead: Option<Arc<ReadAhead>>,
+    /// The layout every frame shares, if they do and are decoded straight into the composite's.
+    layout: Option<FrameLayout>,
+}
+
+impl Decoder {
+    /// Reads and decodes a frame and conforms it to the dimensions, decoding only the rows of its
+    /// band when that is enough.
+    ///
+    /// Where the frame came from is found in the contents read for decoding it, since opening the
+    /// file again could wait on a place in the window of open files held by a read queued ahead,
+    /// which is only given back once this thread collects it.
+    fn decode(&self, &(band, index, ref path): &Job) -> Decoded {
+        let data = self.read(path);
+        let info = match data {
+            Ok(ref data) => FrameInfo::from_contents(index, path, data),
+            Err(_) => FrameInfo::from_contents(index, path, &[]),
+        };
+        let frame = data.and_then(|data| {
+                let rows = self.rows(band);
+                let decoded = match self.layout {
+                    Some(ref layout) => {
+                        let rows = rows.unwrap_or(0..self.dimensions.1);
+                        frame_processing::load_uniform(&data, layout, rows, &self.pool)
+                    }
+                    None => {
+                        rows.and_then(|rows| {
+                            frame_processing::load_rows_from(&data,
+                                                             rows,
+                                                             self.dimensions,
+                                                             &self.pool)
+                        })
+                    }
+                };
+                match decoded {
+                    Some(frame) => Ok(frame),
+                    None => {
+                        frame_processing::load_frame_pooled_from(&data,
+                                                                 path,
+                                                                 &self.transforms,
+                                                                 self.dimensions,
+                                                                 &self.pool)
+                            .and_then(|frame| {
+                                frame_processing::conform_frame(frame,
+                                                                self.dimensions,
+                                                                self.resize_policy,
+                                                                path)
+                            })
+                    }
+                }
+            })
+            .chain_err(|| ErrorKind::Frame(info.clone()));
+        (info, frame)
+    }
+
+    /// Reads a frame file, or takes its contents if they were read ahead.
+    fn read(&self, path: &Path) -> Result<FrameData> {
+        match self.read_ahead.as_ref().and_then(|read_ahead| read_ahead.take(path)) {
+            Some(data) => Ok(FrameData::Read(data)),
+            None => {
+                frame_processing::read_frame(path)
+                    .chain_err(|| ErrorKind::CouldNotOpenImage(path.to_path_buf()))
+            }
         }
-        Direction::S => {
-            // S -> N
-            if index >= bh {
-                return None;
-            }
-            Some((bx, by + bh - index - 1, bw, 1))
+    }
+
+    /// Queues reads of the frames of `jobs`, if the platform can, so that they are in flight while
+    /// earlier frames decode.
+    fn queue(&self, jobs: &[Job]) {
+        if let Some(ref read_ahead) = self.read_ahead {
+            read_ahead.queue(jobs.iter().map(|(_, _, path)| path.as_path()));
         }
-        Direction::W => {
-            // W -> E
-            if index >= bw {
-                return None;
+    }
+
+    /// The rows the band at `band` covers, if only they are decoded.
+    fn rows(&self, band: u32) -> Option<Range<u32>> {
+        let (direction, bands, lines) = self.rows_only?;
+        let (width, height) = self.dimensions;
+        let lines = band_lines(band, bands, lines);
+        if lines.start >= lines.end {
+            return None;
+        }
+        let (_, y, _, rows) = band_coords((0, 0, width, height), lines, direction)?;
+        Some(y..y + rows)
+    }
+}
+
+/// Whether the bands are also composited at 16 bits per channel: the output is saved at that depth,
+/// and frames are blended with nothing after the blend that only works at 8 bits, so the blend
+/// keeps the precision averaging gains. Otherwise the 8-bit composite is widened once it's done,
+/// which loses nothing.
+fn composites_wide(options: &RenderOptions) -> bool {
+    options.bit_depth == BitDepth::Sixteen && options.preblend > 1 &&
+    options.white_balance.is_none() && options.deflicker.is_none() &&
+    options.denoise.is_none() && options.adjustments.is_identity()
+}
+
+/// Whether only the rows of each band need to be decoded: the bands are rows, and nothing looks at
+/// the rest of the frame. Stabilizing, deflickering, white balance and denoising all do, as does
+/// dithering, which takes pixels from around the band, and the transforms move the rows about.
+fn rows_only(options: &RenderOptions) -> bool {
+    options.geometry.is_none() &&
+    (options.direction == Direction::N || options.direction == Direction::S) &&
+    options.transforms == FrameTransforms::default() && !options.stabilize &&
+    options.deflicker.is_none() && options.white_balance.is_none() &&
+    options.denoise.is_none() && options.dither.is_none()
+}
+
+/// How many frames to decode in each batch, and whether to decode the next batch while the one
+/// before it is composited, given how many `threads` there are to decode them on. With a
+/// `max_memory` budget, the batches are kept small enough for two of them to fit in what the render
+/// doesn't already need to hold, or it decodes a frame at a time without working ahead.
+fn decode_batch(threads: usize,
+                max_memory: Option<u64>,
+                frame_bytes: u64,
+                held: u64)
+                -> (usize, bool) {
+    let fits = match max_memory {
+        Some(max_memory) => max_memory.saturating_sub(held) / ::std::cmp::max(frame_bytes, 1),
+        None => return (threads, true),
+    };
+    if fits >= 2 * threads as u64 {
+        (threads, true)
+    } else if fits >= 2 {
+        (fits as usize / 2, true)
+    } else {
+        (1, false)
+    }
+}
+
+/// The frames of the next bands from `from` on, taking whole bands until there are at least
+/// `batch` of them. Returns them with the band after the last one taken.
+fn batch_jobs(bands: &[usize],
+              groups: &[&[PathBuf]],
+              preblend: usize,
+              from: usize,
+              batch: usize)
+              -> (Vec<Job>, usize) {
+    let mut jobs = vec![];
+    let mut end = from;
+    while end < bands.len() && jobs.len() < batch {
+        let start = bands[end] * preblend;
+        let group = groups[bands[end]].iter().enumerate();
+        jobs.extend(group.map(|(k, path)| (end as u32, start + k, path.clone())));
+        end += 1;
+    }
+    (jobs, end)
+}
+
+/// Decodes frames in order, at once on the threads of the current pool if `parallel`, or else one
+/// at a time.
+fn decode_frames(jobs: &[Job], decoder: &Decoder, parallel: bool) -> Vec<Decoded> {
+    if parallel {
+        jobs.par_iter().map(|job| decoder.decode(job)).collect()
+    } else {
+        jobs.iter().map(|job| decoder.decode(job)).collect()
+    }
+}
+
+/// A batch of frames being decoded in the background while the bands before them are composited.
+struct Prefetch(Receiver<thread::Result<Vec<Decoded>>>);
+
+impl Prefetch {
+    /// Starts decoding the frames on the threads of `pool`.
+    fn start(pool: &ThreadPool, jobs: Vec<Job>, decoder: Decoder) -> Prefetch {
+        let (sender, receiver) = mpsc::channel();
+        pool.spawn(move || {
+            let decoded = panic::catch_unwind(AssertUnwindSafe(|| {
+                decode_frames(&jobs, &decoder, true)
+            }));
+            // The render may have stopped without waiting for them.
+            let _ = sender.send(decoded);
+        });
+        Prefetch(receiver)
+    }
+
+    /// Waits for the frames to be decoded. A panic while decoding them carries on here, as if they
+    /// had been decoded on this thread.
+    fn wait(self) -> Vec<Decoded> {
+        match self.0.recv().expect("frames are always sent once decoded") {
+            Ok(decoded) => decoded,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+/// Everything following a render: its sinks, which are also given the finished composite, and its
+/// other observers. Each event goes to the sinks first, in order, and then to the observers.
+///
+/// They are borrowed for `'a`, which is kept apart from the `'b` their own borrows must outlive,
+/// since tying the two together would make every local follower live as long as the caller's.
+struct Followers<'a, 'b> {
+    sinks: Vec<&'a mut (dyn OutputSink + 'b)>,
+    observers: Vec<&'a mut (dyn RenderObserver + 'b)>,
+}
+
+impl<'a, 'b> RenderObserver for Followers<'a, 'b> {
+    fn frame_decoded(&mut self, index: u64, total: u64, frame: &FrameInfo) -> Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.frame_decoded(index, total, frame)?;
+        }
+        for observer in self.observers.iter_mut() {
+            observer.frame_decoded(index, total, frame)?;
+        }
+        Ok(())
+    }
+
+    fn band_written(&mut self, index: u64, total: u64, frame: &FrameInfo) -> Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.band_written(index, total, frame)?;
+        }
+        for observer in self.observers.iter_mut() {
+            observer.band_written(index, total, frame)?;
+        }
+        Ok(())
+    }
+
+    /// Shows every sink and observer the composite, and stops the render if any of them asks to,
+    /// once they all have.
+    fn snapshot_ready(&mut self, composite: &image::RgbaImage) -> Result<Flow> {
+        let mut flow = Flow::Continue;
+        for sink in self.sinks.iter_mut() {
+            if sink.snapshot_ready(composite)? == Flow::Stop {
+                flow = Flow::Stop;
             }
-            Some((bx + index, by, 1, bh))
         }
-        Direction::E => {
-            // E -> W
-            if index >= bw {
-                return None;
+        for observer in self.observers.iter_mut() {
+            if observer.snapshot_ready(composite)? == Flow::Stop {
+                flow = Flow::Stop;
             }
-            Some((bx + bw - index - 1, by, 1, bh))
         }
+        Ok(flow)
+    }
+
+    fn render_finished(&mut self, report: &Report) -> Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.render_finished(report)?;
+        }
+        for observer in self.observers.iter_mut() {
+            observer.render_finished(report)?;
+        }
+        Ok(())
     }
 }
 
-fn process_image<I, J>(current_buffer: &mut I,
-                       image: &mut J,
-                       index: usize,
-                       direction: Direction)
-                       -> Result<bool>
-    where I: GenericImage,
-          I::Pixel: 'static,
-          J: GenericImage<Pixel = I::Pixel> + 'static
-{
-    if let Some((x, y, width, height)) = generage_subimage_coords(image.bounds(),
-                                                                  index as u32,
-                                                                  direction) {
-        let subimage = image.sub_image(x, y, width, height);
-        Ok(current_buffer.copy_from(&subimage, x, y))
-    } else {
-        Ok(false)
+/// Where the frames of a render to memory come from. Every source is rendered at 8 bits per
+/// channel, and frames with deeper channels are refused rather than truncated.
+pub enum FrameSource {
+    /// Frames to read from files, in order, as `get_paths` finds them.
+    Paths(Vec<PathBuf>),
+    /// Frames that are already in memory, in order.
+    Frames(Vec<image::DynamicImage>),
+    /// Frames still encoded in any supported image format, in order, such as files dropped into a
+    /// browser. Each is only decoded once a band is taken from it.
+    Encoded(Vec<Vec<u8>>),
+}
+
+/// Renders the composite and returns it instead of saving it, leaving encoding it, or doing
+/// anything else with it, to the caller.
+///
+/// This is `composite_frames` for any kind of source; frames read from files or given encoded are
+/// only decoded once a band is taken from them, and only one group of them is held decoded at a
+/// time. Like it, this works at 8 bits per channel throughout.
+///
+/// # Arguments
+/// * `options` - The options controlling the render. Those that only apply to files are ignored,
+///   as in `composite_frames`.
+/// * `source` - Where the frames come from.
+/// * `progress` - Where to report progress through the frames.
+///
+/// # Errors
+/// This fails for the same reasons as `composite_frames`, and if a frame cannot be read or has more
+/// than 8 bits per channel.
+pub fn render(options: &RenderOptions,
+              source: FrameSource,
+              progress: &mut dyn ProgressSink)
+              -> Result<image::RgbaImage> {
+    match source {
+        FrameSource::Paths(paths) => {
+            let transforms = &options.transforms;
+            let describe = |index: usize| FrameInfo::read(index, &paths[index]);
+            let load = |frame: &FrameInfo| frame_processing::load_frame(&frame.path, transforms);
+            composite(paths.len(), describe, load, options, progress)
+        }
+        FrameSource::Frames(frames) => composite_frames(frames, options, progress),
+        FrameSource::Encoded(data) => {
+            let transforms = &options.transforms;
+            let describe = |index: usize| FrameInfo::encoded(index, &data[index]);
+            let load = |frame: &FrameInfo| {
+                let image = frame_processing::decode_image(&data[frame.index], &frame.path)?;
+                frame_processing::transform_frame(image, transforms, &frame.path)
+            };
+            composite(data.len(), describe, load, options, progress)
+        }
     }
 }
 
-/// Given a set of image paths, an output path, and a shutter direction, generate an output image.
+/// Renders the composite from frames that are already in memory and returns it, without reading or
+/// writing any files.
+///
+/// The frames go through the same stages as in `process_images`, and the composite through the
+/// same output stages before it would be saved, at 8 bits per channel. The options that only apply
+/// to files are ignored: the output format and encoder settings, the bit depth, ICC profiles,
+/// metadata, animations, snapshots, exported bands, time maps, the debug overlay, comparisons,
+/// checkpoints and the preview.
 ///
 /// # Arguments
-/// * `paths` - An iterator of `PathBuf`s that describe the input images, in the correct order.
-/// * `output` - The output image path.
-/// * `direction` - The direction from which the shutter *starts* moving.
-/// * `suppress_output` - Whether to suppress output or not.
+/// * `frames` - The frames, in order. Frames whose dimensions differ from the first frame's are
+///   dealt with by `options.resize_policy`.
+/// * `options` - The options controlling the render.
+/// * `progress` - Where to report progress through the frames. Frames in memory have no path, so
+///   they go by `frame N`.
 ///
 /// # Errors
-/// This may fail if an individual image cannot be opened or processed, or if the output cannot be
-/// properly saved.
-pub(crate) fn process_images<I, P>(paths: I,
-                                   output: P,
-                                   direction: Direction,
-                                   suppress_output: bool)
-                                   -> Result<()>
-    where I: Iterator<Item = PathBuf> + ExactSizeIterator,
-          P: AsRef<Path>
+/// This fails if there are no frames, if the crop region doesn't fit inside a frame, if a frame has
+/// the wrong dimensions for the resize policy, or if the stabilized frames have no region in common
+/// to crop to.
+pub fn composite_frames<I>(frames: I,
+                           options: &RenderOptions,
+                           progress: &mut dyn ProgressSink)
+                           -> Result<image::RgbaImage>
+    where I: IntoIterator<Item = image::DynamicImage>,
+          I::IntoIter: ExactSizeIterator
 {
-    let mut iter = paths.peekable();
-
-    let count = iter.len() as u64;
-
-    // Note that we can access the first item without checking because we already ensured that only
-    // non-empty sets of paths will be allowed in.
-    let first_path = iter.peek().unwrap().clone();
-    let mut cur_img =
-        image::open(&first_path).chain_err(|| ErrorKind::CouldNotOpenImage(first_path.clone()))?;
-    let (width, height) = cur_img.dimensions();
-    let mut buf: image::RgbaImage = ImageBuffer::new(width, height);
-
-    let num_frames = ::std::cmp::min(count,
-                                     match direction {
-                                         Direction::N | Direction::S => height,
-                                         Direction::E | Direction::W => width,
-                                     } as u64);
-    let mut frame_pb = ProgressBar::new(num_frames);
-    frame_pb.message("Processing frames: ");
-    frame_pb.set_max_refresh_rate(Some(::std::time::Duration::from_millis(50)));
-
-    for (i, path) in iter.enumerate() {
-        if i > 0 {
-            cur_img = image::open(&path).chain_err(|| ErrorKind::CouldNotOpenImage(path.clone()))?;
-        }
-        let process_result = process_image(&mut buf, &mut cur_img, i, direction)
-            .chain_err(|| ErrorKind::CouldNotProcessImage(path.clone()))?;
-        if process_result {
-            // This is sort of an arbitrary number at which to show progress. Could probably turn
-            // this into a progress bar sort of thing.
-            if !suppress_output {
-                frame_pb.inc();
-            }
-        } else {
-            // Ran out of space to do shutters, so don't continue.
-            break;
+    let mut frames = frames.into_iter();
+    let count = frames.len();
+    // Frames are asked for in order, but those that no band is taken from are passed over.
+    let mut next = 0;
+    let load = |info: &FrameInfo| {
+        let frame = frames.nth(info.index - next).expect("the frames ran out before their length");
+        next = info.index + 1;
+        frame_processing::transform_frame(frame, &options.transforms, &info.path)
+    };
+    composite(count, FrameInfo::unnamed, load, options, progress)
+}
+
+/// Renders the composite of `count` frames, calling `describe` with a frame's index for where it
+/// came from, and `load` with that for the transformed frame, once a band is taken from it.
+/// Frames are loaded in order, each at most once.
+fn composite<D, F>(count: usize,
+                   mut describe: D,
+                   mut load: F,
+                   options: &RenderOptions,
+                   progress: &mut dyn ProgressSink)
+                   -> Result<image::RgbaImage>
+    where D: FnMut(usize) -> FrameInfo,
+          F: FnMut(&FrameInfo) -> Result<image::DynamicImage>
+{
+    let mut compositor = Compositor::new(count, options)?;
+    while let Some(index) = compositor.next_index() {
+        let info = describe(index);
+        load(&info)
+            .and_then(|frame| compositor.push(frame, &info, progress))
+            .chain_err(|| ErrorKind::Frame(info))?;
+    }
+    compositor.finish(progress)
+}
+
+/// Builds the composite of a render to memory one frame at a time, as they are pushed in, so that
+/// frames can be loaded however the caller likes, including asynchronously.
+pub(crate) struct Compositor<'a> {
+    pub(crate) options: &'a RenderOptions,
+    /// How many frames there are.
+    pub(crate) count: usize,
+    preblend: usize,
+    /// The group of frames each band is taken from.
+    bands: Vec<usize>,
+    stages: FrameStages<'a>,
+    /// The composite, once the first frame has given its dimensions.
+    buf: Option<image::RgbaImage>,
+    num_frames: u32,
+    lines_covered: u32,
+    /// The band being built.
+    band: usize,
+    /// The blend of the frames of the band's group pushed so far, how many there are, and the path
+    /// of the first.
+    blend: Option<(Preblend, usize, PathBuf)>,
+    finished: bool,
+}
+
+impl<'a> Compositor<'a> {
+    /// Starts a render of `count` frames.
+    ///
+    /// # Errors
+    /// This fails if there are no frames, or if the options ask for something a custom geometry
+    /// doesn't support.
+    pub(crate) fn new(count: usize, options: &'a RenderOptions) -> Result<Compositor<'a>> {
+        check_options(options, false)?;
+        if count == 0 {
+            bail!(ErrorKind::NoFrames);
         }
+        let preblend = ::std::cmp::max(options.preblend, 1);
+        let bands = band_groups(count.div_ceil(preblend), options);
+        Ok(Compositor {
+            options,
+            count,
+            preblend,
+            bands,
+            stages: FrameStages::new(options),
+            buf: None,
+            num_frames: 0,
+            lines_covered: 0,
+            band: 0,
+            blend: None,
+            finished: false,
+        })
     }
 
-    if !suppress_output {
-        frame_pb.finish();
+    /// The index of the frame to push next, or `None` once the composite is finished. Frames that
+    /// no band is taken from are skipped over, as are those after the last band once the first
+    /// frame has said how many bands there are.
+    pub(crate) fn next_index(&self) -> Option<usize> {
+        if self.finished || self.band >= self.bands.len() {
+            return None;
+        }
+        if self.buf.is_some() && self.band >= self.num_frames as usize {
+            return None;
+        }
+        let pushed = self.blend.as_ref().map_or(0, |&(_, pushed, _)| pushed);
+        Some(self.bands[self.band] * self.preblend + pushed)
     }
 
-    let output = output.as_ref();
+    /// Adds the frame `next_index` asked for, after its transforms, copying the band once the last
+    /// frame of its group is in. `info` says where the frame came from.
+    ///
+    /// # Errors
+    /// This fails if the frame has the wrong dimensions for the resize policy, or if the band
+    /// cannot be copied.
+    pub(crate) fn push(&mut self,
+                       frame: image::DynamicImage,
+                       info: &FrameInfo,
+                       progress: &mut dyn ProgressSink)
+                       -> Result<()> {
+        self.next_index().expect("a frame was pushed after the composite finished");
+        let first = self.bands[self.band] * self.preblend;
+        let last = ::std::cmp::min(first + self.preblend, self.count);
+        let frame = match self.buf.as_ref().map(|buf| buf.dimensions()) {
+            Some(dimensions) => {
+                let policy = self.options.resize_policy;
+                frame_processing::conform_frame(frame, dimensions, policy, &info.path)?
+            }
+            None => {
+                let (width, height) = frame.dimensions();
+                let num_lines = self.options.shutter_geometry().band_count((width, height));
+                self.num_frames = ::std::cmp::min(self.bands.len() as u32, num_lines);
+                self.lines_covered =
+                    if self.options.auto_stretch { num_lines } else { self.num_frames };
+                self.buf = Some(ImageBuffer::new(width, height));
+                frame
+            }
+        };
+
+        let i = self.band as u32;
+        let (mut blend, pushed, first_path) = match self.blend.take() {
+            Some(blend) => blend,
+            None => {
+                if i < self.num_frames {
+                    progress.start(PROCESSING, i as u64, self.num_frames as u64, info);
+                }
+                (Preblend::new(self.options.linear_blend), 0, info.path.clone())
+            }
+        };
+        self.stages.add(&mut blend, frame);
+        if first + pushed + 1 < last {
+            self.blend = Some((blend, pushed + 1, first_path));
+            return Ok(());
+        }
 
-    buf.save(output).chain_err(|| ErrorKind::CouldNotSaveOutput(output.to_path_buf().clone()))?;
-    if !suppress_output {
-        println!("\nDone.");
+        let mut cur_img = self.stages.finish(blend)?;
+        let lines = band_lines(i, self.num_frames, self.lines_covered);
+        let buf = self.buf.as_mut().unwrap();
+        let copied = copy_band(buf, &mut cur_img, i, self.num_frames, lines, self.options)
+            .chain_err(|| ErrorKind::CouldNotProcessImage(first_path))?;
+        if copied {
+            progress.done(PROCESSING, i as u64 + 1, self.num_frames as u64);
+            self.band += 1;
+        } else {
+            self.finished = true;
+        }
+        Ok(())
     }
 
-    Ok(())
+    /// Applies the output stages to the finished composite and returns it.
+    ///
+    /// # Errors
+    /// This fails if the stabilized frames have no region in common to crop to.
+    pub(crate) fn finish(self, progress: &mut dyn ProgressSink) -> Result<image::RgbaImage> {
+        progress.finish(PROCESSING);
+        let buf = self.buf.expect("the composite was finished before any frames were pushed");
+        let autocrop = self.stages.autocrop(buf.dimensions())?;
+        Ok(output_stages(buf, self.options, autocrop))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use ::Direction;
+    use ::output_sink::MemoryOutput;
+    use ::progress::NoProgress;
+    use ::testing::frame_dir;
+
+    fn options(direction: Direction) -> RenderOptions {
+        RenderOptions {
+            direction,
+            ..RenderOptions::default()
+        }
+    }
+
+    fn solid(width: u32, height: u32, value: u8) -> image::DynamicImage {
+        image::DynamicImage::ImageRgba8(ImageBuffer::from_pixel(width,
+                                                                height,
+                                                                Rgba([value, value, value, 255])))
+    }
+
+    #[test]
+    fn test_parse_dither_mode() {
+        assert_eq!("ordered".parse::<DitherMode>().unwrap(), DitherMode::Ordered);
+        assert_eq!("blue-noise".parse::<DitherMode>().unwrap(), DitherMode::BlueNoise);
+        match "bayer".parse::<DitherMode>() {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "bayer"),
+            result => assert!(false, "expected a parse error, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn test_composite_frames() {
+        let frames = vec![solid(2, 3, 10), solid(2, 3, 20), solid(2, 3, 30)];
+        let composite = composite_frames(frames.clone(), &options(Direction::N), &mut NoProgress)
+            .unwrap();
+        assert_eq!(composite.dimensions(), (2, 3));
+        assert_eq!(composite.get_pixel(1, 0), &Rgba([10, 10, 10, 255]));
+        assert_eq!(composite.get_pixel(1, 2), &Rgba([30, 30, 30, 255]));
+
+        // There are more frames than columns, so the last one is left out.
+        let composite = composite_frames(frames.clone(), &options(Direction::E), &mut NoProgress)
+            .unwrap();
+        assert_eq!(composite.get_pixel(1, 0), &Rgba([10, 10, 10, 255]));
+        assert_eq!(composite.get_pixel(0, 0), &Rgba([20, 20, 20, 255]));
+        let mut stretched = options(Direction::S);
+        stretched.preblend = 2;
+        stretched.auto_stretch = true;
+        let mut calls = vec![];
+        let composite = {
+            let mut sink = |_: &'static str, done: u64, total: u64| calls.push((done, total));
+            composite_frames(frames, &stretched, &mut sink).unwrap()
+        };
+        assert_eq!(calls, vec![(1, 2), (2, 2)]);
+        assert_eq!(composite.get_pixel(0, 2), &Rgba([15, 15, 15, 255]));
+        assert_eq!(composite.get_pixel(0, 0), &Rgba([30, 30, 30, 255]));
+
+        let mismatched = vec![solid(2, 3, 0), solid(3, 2, 0)];
+        match composite_frames(mismatched, &options(Direction::N), &mut NoProgress) {
+            Err(ref e) if e.frame() == Some(&FrameInfo::unnamed(1)) => {
+                match e.iter().nth(1).and_then(|cause| cause.downcast_ref::<Error>()) {
+                    Some(&Error(ErrorKind::FrameSizeMismatch(..), _)) => (),
+                    _ => assert!(false, "expected frame size mismatch, got {}.", e),
+                }
+            }
+            result => assert!(false, "expected frame 1 to fail, got {:?}.", result.is_ok()),
+        }
+        match composite_frames(vec![], &options(Direction::N), &mut NoProgress) {
+            Err(Error(ErrorKind::NoFrames, _)) => (),
+            result => assert!(false, "expected no frames error, got {:?}.", result.is_ok()),
+        }
+    }
+
+    /// Sweeps diagonally from the top left corner, one anti-diagonal per band.
+    #[derive(Debug)]
+    struct Diagonal;
+
+    impl ShutterGeometry for Diagonal {
+        fn band_count(&self, (width, height): (u32, u32)) -> u32 {
+            width + height - 1
+        }
+
+        fn region_for_frame(&self, index: u32, (width, height): (u32, u32)) -> Option<BandRegion> {
+            if index >= self.band_count((width, height)) {
+                return None;
+            }
+            let pixels = (0..width)
+                .flat_map(|x| (0..height).map(move |y| (x, y)))
+                .filter(|&(x, y)| x + y == index)
+                .collect();
+            Some(BandRegion::Pixels(pixels))
+        }
+    }
+
+    #[test]
+    fn test_custom_geometry() {
+        let mut diagonal = options(Direction::N);
+        diagonal.geometry = Some(Arc::new(Diagonal));
+        let frames = vec![solid(2, 3, 10), solid(2, 3, 20), solid(2, 3, 30), solid(2, 3, 40)];
+        let composite = composite_frames(frames.clone(), &diagonal, &mut NoProgress).unwrap();
+        assert_eq!(composite.get_pixel(0, 0), &Rgba([10, 10, 10, 255]));
+        assert_eq!(composite.get_pixel(1, 0), &Rgba([20, 20, 20, 255]));
+        assert_eq!(composite.get_pixel(0, 2), &Rgba([30, 30, 30, 255]));
+        assert_eq!(composite.get_pixel(1, 2), &Rgba([40, 40, 40, 255]));
+
+        diagonal.dither = Some(Dither {
+            mode: DitherMode::Ordered,
+            radius: 1,
+        });
+        match composite_frames(frames, &diagonal, &mut NoProgress) {
+            Err(Error(ErrorKind::UnsupportedByGeometry(_), _)) => (),
+            result => assert!(false, "expected unsupported dithering, got {:?}.", result.is_ok()),
+        }
+    }
+
+    #[test]
+    fn test_dither_with_stretch() {
+        let mut stretched = options(Direction::N);
+        stretched.auto_stretch = true;
+        stretched.dither = Some(Dither {
+            mode: DitherMode::Ordered,
+            radius: 1,
+        });
+        let frames = vec![solid(2, 4, 10), solid(2, 4, 20)];
+        match composite_frames(frames, &stretched, &mut NoProgress) {
+            Err(Error(ErrorKind::IncompatibleOptions(..), _)) => (),
+            result => assert!(false, "expected incompatible options, got {:?}.", result.is_ok()),
+        }
+    }
+
+    #[test]
+    fn test_band_map() {
+        let frames: Vec<_> = (0..5).map(|i| solid(8, 6, 10 * (i + 1))).collect();
+        let mut stretched = options(Direction::S);
+        stretched.auto_stretch = true;
+        let mut dithered = options(Direction::E);
+        dithered.dither = Some(Dither {
+            mode: DitherMode::BlueNoise,
+            radius: 2,
+        });
+        let mut diagonal = options(Direction::N);
+        diagonal.geometry = Some(Arc::new(Diagonal));
+        // The map gives every pixel the band that compositing on the CPU takes it from.
+        for options in &[options(Direction::W), stretched, dithered, diagonal] {
+            let composite = composite_frames(frames.clone(), options, &mut NoProgress).unwrap();
+            let num_lines = options.shutter_geometry().band_count((8, 6));
+            let num_frames = ::std::cmp::min(5, num_lines);
+            let lines_covered = if options.auto_stretch { num_lines } else { num_frames };
+            let map = band_map((8, 6), options, num_frames, lines_covered);
+            for (&band, pixel) in map.iter().zip(composite.pixels()) {
+                match band {
+                    gpu::NO_BAND => assert_eq!(pixel[3], 0),
+                    band => {
+                        let value = 10 * (band as u8 + 1);
+                        assert_eq!(pixel, &Rgba([value, value, value, 255]));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let dir = frame_dir("render", (1..4).map(|i| solid(2, 3, 10 * i).to_rgba()));
+        let paths = dir.frames.clone();
+        let north = options(Direction::N);
+        let from_paths = render(&north, FrameSource::Paths(paths.clone()), &mut NoProgress)
+            .unwrap();
+        let frames = vec![solid(2, 3, 10), solid(2, 3, 20), solid(2, 3, 30)];
+        let from_frames = render(&north, FrameSource::Frames(frames), &mut NoProgress).unwrap();
+        assert_eq!(from_paths.get_pixel(0, 1), &Rgba([20, 20, 20, 255]));
+        assert_eq!(from_paths.into_raw(), from_frames.clone().into_raw());
+
+        let encoded = paths.iter().map(|path| fs::read(path).unwrap()).collect();
+        let from_bytes = render(&north, FrameSource::Encoded(encoded), &mut NoProgress).unwrap();
+        assert_eq!(from_bytes.into_raw(), from_frames.into_raw());
+
+        let missing = FrameSource::Paths(vec![dir.join("missing.png")]);
+        match render(&north, missing, &mut NoProgress) {
+            Err(ref e) if e.frame().map(|frame| &frame.path) == Some(&dir.join("missing.png")) => {
+                assert_eq!(::errors::exit_code(e), ::errors::EXIT_DECODE_FAILED);
+            }
+            result => assert!(false, "expected an unreadable frame, got {:?}.", result.is_ok()),
+        }
+    }
+
+    #[test]
+    fn test_composite_loads_only_used_frames() {
+        // Three lines take bands from the first three frames, so the other three are never loaded.
+        let mut loaded = vec![];
+        let buf = composite(6,
+                            FrameInfo::unnamed,
+                            |frame| {
+                                loaded.push(frame.index);
+                                Ok(solid(2, 3, 10 * frame.index as u8))
+                            },
+                            &options(Direction::N),
+                            &mut NoProgress)
+            .unwrap();
+        assert_eq!(loaded, vec![0, 1, 2]);
+        assert_eq!(buf.get_pixel(0, 2), &Rgba([20, 20, 20, 255]));
+    }
+
+    #[test]
+    fn test_process_images_tiled() {
+        let frames = (0..5).map(|i| {
+            RgbaImage::from_fn(4, 6, |x, y| Rgba([i as u8 * 40, x as u8 * 10, y as u8 * 10, 255]))
+        });
+        let dir = frame_dir("tiled", frames);
+        let render = |options: &RenderOptions, output: &Path| {
+            process_images(dir.frames.clone().into_iter(),
+                           &[output],
+                           options,
+                           &mut [],
+                           &mut [],
+                           &mut NoProgress)
+                .unwrap();
+            image::open(output).unwrap().to_rgba().into_raw()
+        };
+        for &direction in &[Direction::N, Direction::S, Direction::E, Direction::W] {
+            let mut options = options(direction);
+            options.suppress_output = true;
+            let whole = render(&options, &dir.join("whole.png"));
+            // Strips that don't divide the image evenly, and bands that cross them.
+            options.tile_rows = Some(4);
+            assert!(can_tile(&options, &[dir.join("tiled.png")], 0));
+            let tiled = render(&options, &dir.join("tiled.png"));
+            assert_eq!(tiled, whole, "{:?}", direction);
+        }
+    }
+
+    #[test]
+    fn test_stream_keeps_output_on_failure() {
+        let dir = frame_dir("stream-failure", (1..5).map(|i| solid(2, 4, 10 * i).to_rgba()));
+        fs::write(&dir.frames[2], b"not an image").unwrap();
+        let output = dir.join("out.png");
+        fs::write(&output, b"an earlier render").unwrap();
+        let mut options = options(Direction::N);
+        options.suppress_output = true;
+        assert!(can_stream(&options, &[&output], 0));
+        let result = process_images(dir.frames.clone().into_iter(),
+                                    &[&output],
+                                    &options,
+                                    &mut [],
+                                    &mut [],
+                                    &mut NoProgress);
+        assert_eq!(result.unwrap_err().frame().map(|frame| frame.index), Some(2));
+        // The rows streamed before the failure went into a temporary file, which is gone.
+        assert_eq!(fs::read(&output).unwrap(), b"an earlier render");
+        assert!(!dir.join("out.png.tmp").exists());
+    }
+
+    #[test]
+    fn test_process_images_sinks() {
+        let dir = frame_dir("sinks", (1..4).map(|i| solid(2, 3, 10 * i).to_rgba()));
+        let paths = &dir.frames;
+        let mut options = options(Direction::N);
+        options.suppress_output = true;
+        let mut memory = MemoryOutput::new();
+        let report = process_images(paths.clone().into_iter(),
+                                    &[dir.join("out.png")],
+                                    &options,
+                                    &mut [&mut memory],
+                                    &mut [],
+                                    &mut NoProgress)
+            .unwrap();
+        assert_eq!(report.outputs,
+                   vec![ReportOutput {
+                            path: dir.join("out.png"),
+                            dimensions: (2, 3),
+                        }]);
+        let sources: Vec<_> = report.frames.iter().map(|frame| &frame.path).collect();
+        assert_eq!(sources, paths.iter().collect::<Vec<_>>());
+        assert!(report.frames.iter().all(|frame| frame.modified.is_some()));
+        let saved = image::open(dir.join("out.png")).unwrap().to_rgba().into_raw();
+        assert_eq!(memory.into_image().unwrap().into_raw(), saved);
+    }
+
+    #[test]
+    fn test_process_images_nothing_to_do() {
+        let render = |paths: Vec<PathBuf>, outputs: &[PathBuf]| {
+            process_images(paths.into_iter(),
+                           outputs,
+                           &options(Direction::N),
+                           &mut [],
+                           &mut [],
+                           &mut NoProgress)
+        };
+        match render(vec![], &["out.png".into()]) {
+            Err(Error(ErrorKind::NoFrames, _)) => (),
+            result => assert!(false, "expected no frames error, got {:?}.", result.is_ok()),
+        }
+        // Nothing is read before the outputs are checked, so the frame needn't exist.
+        match render(vec!["0.png".into()], &[]) {
+            Err(Error(ErrorKind::NoOutputs, _)) => (),
+            result => assert!(false, "expected no outputs error, got {:?}.", result.is_ok()),
+        }
+    }
+
+    #[test]
+    fn test_single_decode_pass() {
+        let dir = frame_dir("single-pass", (1..4).map(|i| solid(2, 3, 10 * i).to_rgba()));
+        let mut options = options(Direction::N);
+        options.suppress_output = true;
+        options.time_map = Some(dir.join("time.png"));
+        options.export_bands = Some(dir.join("bands"));
+        options.compare_with = Some(CompareWith::First);
+        let mut recorder = Recorder {
+            events: vec![],
+            stop_after: usize::MAX,
+        };
+        let outputs = [dir.join("a.png"), dir.join("b.png")];
+        process_images(dir.frames.clone().into_iter(),
+                       &outputs,
+                       &options,
+                       &mut [],
+                       &mut [&mut recorder],
+                       &mut NoProgress)
+            .unwrap();
+        // Every output came from the one pass, which decoded each frame once.
+        let decoded: Vec<_> =
+            recorder.events.iter().filter(|event| event.starts_with("decoded")).collect();
+        assert_eq!(decoded, vec!["decoded 0/3 0", "decoded 1/3 1", "decoded 2/3 2"]);
+        assert_eq!(fs::read(&outputs[0]).unwrap(), fs::read(&outputs[1]).unwrap());
+        assert!(dir.join("time.png").exists() && dir.join("a.compare.png").exists());
+        assert!(dir.join("bands").join("00002.png").exists());
+    }
+
+    /// Records the events of a render, and stops it after `stop_after` bands.
+    struct Recorder {
+        events: Vec<String>,
+        stop_after: usize,
+    }
+
+    impl RenderObserver for Recorder {
+        fn frame_decoded(&mut self, index: u64, total: u64, frame: &FrameInfo) -> Result<()> {
+            self.events.push(format!("decoded {}/{} {}", index, total, frame.index));
+            Ok(())
+        }
+
+        fn band_written(&mut self, index: u64, total: u64, frame: &FrameInfo) -> Result<()> {
+            self.events.push(format!("written {}/{} {}", index, total, frame.index));
+            Ok(())
+        }
+
+        fn snapshot_ready(&mut self, _composite: &image::RgbaImage) -> Result<Flow> {
+            self.events.push("snapshot".into());
+            let bands = self.events.iter().filter(|event| event.starts_with("written")).count();
+            Ok(if bands >= self.stop_after { Flow::Stop } else { Flow::Continue })
+        }
+
+        fn render_finished(&mut self, report: &Report) -> Result<()> {
+            self.events.push(format!("finished {}", report.frames_used));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_process_images_observers() {
+        let dir = frame_dir("observers", (1..7).map(|i| solid(2, 3, 10 * i).to_rgba()));
+        let mut options = options(Direction::N);
+        options.preblend = 2;
+        options.suppress_output = true;
+        let mut recorder = Recorder {
+            events: vec![],
+            stop_after: 2,
+        };
+        let report = process_images(dir.frames.clone().into_iter(),
+                                    &[dir.join("out.png")],
+                                    &options,
+                                    &mut [],
+                                    &mut [&mut recorder],
+                                    &mut NoProgress)
+            .unwrap();
+        assert_eq!(report.frames_used, 4);
+        assert_eq!(recorder.events,
+                   vec!["decoded 0/3 0",
+                        "decoded 0/3 1",
+                        "written 0/3 0",
+                        "snapshot",
+                        "decoded 1/3 2",
+                        "decoded 1/3 3",
+                        "written 1/3 2",
+                        "snapshot",
+                        "finished 4"]);
+    }
+
+    #[test]
+    fn test_parallel_decode() {
+        let frames = (0..9).map(|i| {
+            ImageBuffer::from_fn(2, 4, |x, y| {
+                Rgba([20 * (i as u8 + 1), 40 * y as u8, 100 * x as u8, 255])
+            })
+        });
+        let dir = frame_dir("parallel", frames);
+        let paths = dir.frames.clone();
+        let mut options = options(Direction::S);
+        options.preblend = 2;
+        options.suppress_output = true;
+        // However many frames are decoded at once, they are composited in the same order.
+        let mut renders = vec![];
+        for &threads in &[1, 3] {
+            options.threads = threads;
+            let output = dir.join(format!("out-{}.png", threads));
+            let mut recorder = Recorder {
+                events: vec![],
+                stop_after: usize::MAX,
+            };
+            process_images(paths.clone().into_iter(),
+                           &[&output],
+                           &options,
+                           &mut [],
+                           &mut [&mut recorder],
+                           &mut NoProgress)
+                .unwrap();
+            renders.push((recorder.events, fs::read(&output).unwrap()));
+        }
+        // Four bands of two frames, each written and shown, and then the end of the render.
+        assert_eq!(renders[0].0.len(), 4 * (2 + 1 + 1) + 1);
+        assert_eq!(renders[0], renders[1]);
+        // Only the rows of each band were decoded, which gives the same composite as decoding the
+        // frames in full.
+        let frames: Vec<_> = paths.iter().map(|path| image::open(path).unwrap()).collect();
+        let full = composite_frames(frames, &options, &mut NoProgress).unwrap();
+        let rendered = image::load_from_memory(&renders[0].1).unwrap().to_rgba();
+        assert_eq!(rendered.into_raw(), full.into_raw());
+
+        // A frame that can't be decoded still fails the render, even when decoded ahead.
+        fs::write(&paths[5], b"not an image").unwrap();
+        let result = process_images(paths.into_iter(),
+                                    &[dir.join("broken.png")],
+                                    &options,
+                                    &mut [],
+                                    &mut [],
+                                    &mut NoProgress);
+        assert_eq!(result.unwrap_err().frame().map(|frame| frame.index), Some(5));
+    }
+
+    #[test]
+    fn test_copy_lines() {
+        let frame = ImageBuffer::from_fn(5, 4, |x, y| Rgba([x as u8, y as u8, 7, 255]));
+        for &direction in &[Direction::N, Direction::S, Direction::E, Direction::W] {
+            for lines in &[0..1, 1..3, 2..6] {
+                // Copying a row at a time gives the same composite as copying pixel by pixel.
+                let mut expected = RgbaImage::new(5, 4);
+                let mut generic = DynamicImage::ImageRgba8(frame.clone());
+                let copied = lines.clone()
+                    .map(|line| {
+                        process_image(&mut expected, &mut generic, line as usize, &direction)
+                    })
+                    .collect::<Result<Vec<_>>>()
+                    .unwrap();
+                let mut buf = RgbaImage::new(5, 4);
+                assert_eq!(copy_lines(&mut buf, &frame, lines.clone(), direction),
+                           copied.into_iter().all(|copied| copied));
+                assert_eq!(buf.into_raw(), expected.into_raw());
+            }
+        }
+    }
+
+    #[test]
+    fn test_copy_columns() {
+        let frame = ImageBuffer::from_fn(5, 4, |x, y| Rgba([x as u8, y as u8, 7, 255]));
+        for &direction in &[Direction::E, Direction::W] {
+            for lines in &[0..1, 1..3, 3..7] {
+                let mut expected = RgbaImage::new(5, 4);
+                let copied = copy_lines(&mut expected, &frame, lines.clone(), direction);
+                let mut buf = RgbaImage::new(4, 5);
+                assert_eq!(copy_columns(&mut buf, &frame, lines.clone(), direction), copied);
+                assert_eq!(transpose(&buf).into_raw(), expected.into_raw());
+            }
+        }
+    }
+
+    #[test]
+    fn test_transpose() {
+        let buf = ImageBuffer::from_fn(70, 3, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        let transposed = transpose(&buf);
+        assert_eq!(transposed.dimensions(), (3, 70));
+        assert_eq!(transposed.get_pixel(2, 65), &Rgba([65, 2, 0, 255]));
+        assert_eq!(transpose(&transposed).into_raw(), buf.into_raw());
+    }
+
+    #[test]
+    fn test_composite_buffer() {
+        let transposed = |options: &RenderOptions, followers| {
+            match composite_buffer(RgbaImage::new(4, 2), options, 4, 4, followers).unwrap() {
+                Composite::Transposed(buf) => Some(buf.dimensions()),
+                _ => None,
+            }
+        };
+        assert_eq!(transposed(&options(Direction::N), 0), None);
+        let mut columns = options(Direction::W);
+        assert_eq!(transposed(&columns, 0), Some((2, 4)));
+        // Anything looking at the composite as it is built up needs it the right way round.
+        assert_eq!(transposed(&columns, 1), None);
+        columns.snapshots = Some(SnapshotSettings {
+            dir: "snapshots".into(),
+            every: 1,
+        });
+        assert_eq!(transposed(&columns, 0), None);
+    }
+
+    #[test]
+    fn test_deterministic_render() {
+        let frames: Vec<_> = (0..8).map(|i| solid(8, 8, 30 * i)).collect();
+        let dir = frame_dir("deterministic", frames.iter().map(|frame| frame.to_rgba()));
+        let mut options = options(Direction::N);
+        options.dither = Some(Dither {
+            mode: DitherMode::BlueNoise,
+            radius: 2,
+        });
+        options.seed = Seed(3);
+        options.metadata = Some(Metadata::default());
+        options.suppress_output = true;
+
+        // The dither pattern is pinned, so that it can't drift between platforms or versions.
+        let composite = composite_frames(frames.clone(), &options, &mut NoProgress).unwrap();
+        let row: Vec<u8> = (0..8).map(|x| composite.get_pixel(x, 0).data[0]).collect();
+        assert_eq!(row, vec![0, 0, 30, 0, 30, 0, 60, 0]);
+
+        let outputs = [dir.join("first.png"), dir.join("second.png")];
+        for output in &outputs {
+            process_images(dir.frames.clone().into_iter(),
+                           &[output],
+                           &options,
+                           &mut [],
+                           &mut [],
+                           &mut NoProgress)
+                .unwrap();
+        }
+        assert_eq!(fs::read(&outputs[0]).unwrap(), fs::read(&outputs[1]).unwrap());
+
+        options.seed = Seed(4);
+        let reseeded = composite_frames(frames, &options, &mut NoProgress).unwrap();
+        assert!(reseeded.into_raw() != composite.into_raw());
+    }
+
+    #[test]
+    fn test_decode_batch() {
+        assert_eq!(decode_batch(4, None, 100, 200), (4, true));
+        assert_eq!(decode_batch(4, Some(1200), 100, 200), (4, true));
+        // Two batches of three fit, but not of four.
+        assert_eq!(decode_batch(4, Some(900), 100, 200), (3, true));
+        assert_eq!(decode_batch(4, Some(400), 100, 200), (1, true));
+        assert_eq!(decode_batch(4, Some(250), 100, 200), (1, false));
+        assert_eq!(decode_batch(4, Some(100), 100, 200), (1, false));
+    }
+
+    #[test]
+    fn test_usable_frames() {
+        let mut options = options(Direction::N);
+        assert_eq!(options.usable_frames(10, (4, 6)), 6);
+        assert_eq!(options.usable_frames(5, (4, 6)), 5);
+        options.direction = Direction::E;
+        assert_eq!(options.usable_frames(10, (4, 6)), 4);
+        options.preblend = 2;
+        assert_eq!(options.usable_frames(10, (4, 6)), 8);
+        assert_eq!(options.usable_frames(7, (4, 6)), 7);
+        // A half-size render takes every other frame for its two lines.
+        options.preblend = 1;
+        options.transforms.scale = Some(0.5);
+        assert_eq!(options.usable_frames(10, (4, 6)), 3);
+    }
+
+    #[test]
+    fn test_scaled_bands() {
+        assert_eq!(scaled_bands(10, 0.25), vec![0, 4, 8]);
+        assert_eq!(scaled_bands(4, 0.5), vec![0, 2]);
+        assert_eq!(scaled_bands(3, 1.0), vec![0, 1, 2]);
+    }
 
     #[test]
     fn test_subimage_coords() {
@@ -175,4 +2954,95 @@ mod tests {
         assert_eq!(generage_subimage_coords(bounds, width + 5, Direction::E),
                    None);
     }
+
+    #[test]
+    fn test_time_map() {
+        // Only three frames for four lines, so the last line is never reached.
+        let map = time_map((2, 4), Direction::S, 3, 3, None, Seed::default());
+        assert_eq!(map.get_pixel(1, 3).data, [0, 0, 0, 65535]);
+        assert_eq!(map.get_pixel(0, 2).data, [32767, 32767, 32767, 65535]);
+        assert_eq!(map.get_pixel(1, 1).data, [65535, 65535, 65535, 65535]);
+        assert_eq!(map.get_pixel(0, 0).data, [0, 0, 0, 0]);
+
+        // Stretched, two frames cover two lines each.
+        let map = time_map((2, 4), Direction::S, 2, 4, None, Seed::default());
+        assert_eq!(map.get_pixel(0, 3).data, [0, 0, 0, 65535]);
+        assert_eq!(map.get_pixel(0, 2).data, [0, 0, 0, 65535]);
+        assert_eq!(map.get_pixel(0, 1).data, [65535, 65535, 65535, 65535]);
+        assert_eq!(map.get_pixel(0, 0).data, [65535, 65535, 65535, 65535]);
+
+        let dither = Dither {
+            mode: DitherMode::Ordered,
+            radius: 2,
+        };
+        let map = time_map((16, 16), Direction::W, 16, 16, Some(dither), Seed(7));
+        for (x, y, pixel) in map.enumerate_pixels() {
+            let index = dither.frame_index(x, y, 16, Seed(7));
+            assert_eq!(pixel.data[0], (index * 65535 / 15) as u16);
+        }
+    }
+
+    #[test]
+    fn test_band_lines() {
+        assert_eq!(band_lines(5, 10, 10), 5..6);
+        assert_eq!(band_lines(0, 3, 10), 0..3);
+        assert_eq!(band_lines(1, 3, 10), 3..6);
+        assert_eq!(band_lines(2, 3, 10), 6..10);
+        for line in 0..10 {
+            assert!(band_lines(line_band(line, 3, 10), 3, 10).contains(&line));
+            assert_eq!(line_band(line, 10, 10), line);
+        }
+    }
+
+    #[test]
+    fn test_band_coords() {
+        let bounds = (0, 0, 640, 480);
+        assert_eq!(band_coords(bounds, 10..11, Direction::N), Some((0, 10, 640, 1)));
+        assert_eq!(band_coords(bounds, 10..14, Direction::S), Some((0, 466, 640, 4)));
+        assert_eq!(band_coords(bounds, 10..14, Direction::E), Some((626, 0, 4, 480)));
+        assert_eq!(band_coords(bounds, 478..481, Direction::N), None);
+    }
+
+    #[test]
+    fn test_band_path() {
+        assert_eq!(band_path(Path::new("bands"), 42), PathBuf::from("bands/00042.png"));
+    }
+
+    #[test]
+    fn test_line_to_coords() {
+        let bounds = (0, 0, 640, 480);
+
+        assert_eq!(line_to_coords(bounds, 10, 20, Direction::N), (20, 10));
+        assert_eq!(line_to_coords(bounds, 10, 20, Direction::S), (20, 480 - 10 - 1));
+        assert_eq!(line_to_coords(bounds, 10, 20, Direction::W), (10, 20));
+        assert_eq!(line_to_coords(bounds, 10, 20, Direction::E), (640 - 10 - 1, 20));
+    }
+
+    #[test]
+    fn test_dither_frame_index() {
+        for &mode in &[DitherMode::Ordered, DitherMode::BlueNoise] {
+            let dither = Dither {
+                mode: mode,
+                radius: 3,
+            };
+            for line in 0..100 {
+                for cross in 0..16 {
+                    let index = dither.frame_index(line, cross, 100, Seed(42));
+                    assert!(index < 100);
+                    assert!((index as i64 - line as i64).abs() <= 3,
+                            "line {} was moved to frame {}",
+                            line,
+                            index);
+                }
+            }
+        }
+
+        let none = Dither {
+            mode: DitherMode::Ordered,
+            radius: 0,
+        };
+        for line in 0..10 {
+            assert_eq!(none.frame_index(line, 5, 10, Seed::default()), line);
+        }
+    }
 }
@@ -0,0 +1,108 @@
+use std::fmt::Write;
+
+use rolling_shutter::Direction;
+
+/// The band sizes, in lines, that frame counts are suggested for.
+const BAND_SIZES: [u32; 6] = [1, 2, 4, 8, 16, 32];
+
+/// How many lines the shutter crosses for an image of the given size.
+fn num_lines((width, height): (u32, u32), direction: Direction) -> u32 {
+    match direction {
+        Direction::N | Direction::S => height,
+        Direction::E | Direction::W => width,
+    }
+}
+
+/// How many frames it takes to cover `lines` lines with bands of `band_size` lines.
+fn frames_needed(lines: u32, band_size: u32) -> u32 {
+    lines.div_ceil(band_size)
+}
+
+/// Describes what it takes to capture a render of the given size: how many frames are needed for
+/// each of a few band sizes, and how long that is at `fps`, if given. With the length of a clip,
+/// in frames, it also says what band size that clip gives.
+pub fn describe(size: (u32, u32),
+                direction: Direction,
+                clip: Option<u32>,
+                fps: Option<f64>)
+                -> String {
+    let lines = num_lines(size, direction);
+    let mut out = String::new();
+    writeln!(out,
+             "A {}x{} image with the shutter starting from {:?} has {} lines.",
+             size.0,
+             size.1,
+             direction,
+             lines)
+        .unwrap();
+    writeln!(out).unwrap();
+
+    write!(out, "{:>10}  {:>13}", "Band size", "Frames needed").unwrap();
+    if let Some(fps) = fps {
+        write!(out, "  {:>10}", format!("At {} fps", fps)).unwrap();
+    }
+    writeln!(out).unwrap();
+    for &band_size in BAND_SIZES.iter().filter(|&&band_size| band_size <= lines) {
+        let frames = frames_needed(lines, band_size);
+        let unit = if band_size == 1 { "line" } else { "lines" };
+        write!(out, "{:>10}  {:>13}", format!("{} {}", band_size, unit), frames).unwrap();
+        if let Some(fps) = fps {
+            write!(out, "  {:>10}", format!("{:.1} s", frames as f64 / fps)).unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+
+    if let Some(clip) = clip {
+        writeln!(out).unwrap();
+        if clip >= lines {
+            writeln!(out,
+                     "A clip of {} frames gives a band of one line per frame; only the first {} \
+                      frames are used.",
+                     clip,
+                     lines)
+                .unwrap();
+        } else {
+            writeln!(out,
+                     "A clip of {} frames gives bands of {:.2} lines; render it with \
+                      --auto-stretch to cover the whole image.",
+                     clip,
+                     lines as f64 / clip as f64)
+                .unwrap();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_needed() {
+        assert_eq!(frames_needed(1080, 1), 1080);
+        assert_eq!(frames_needed(1080, 4), 270);
+        assert_eq!(frames_needed(1080, 16), 68);
+    }
+
+    #[test]
+    fn test_describe() {
+        let info = describe((40, 10), Direction::W, Some(16), Some(10.0));
+        let lines: Vec<&str> = info.lines().collect();
+        assert_eq!(lines[0], "A 40x10 image with the shutter starting from W has 40 lines.");
+        assert_eq!(lines[2], " Band size  Frames needed   At 10 fps");
+        assert_eq!(lines[3], "    1 line             40       4.0 s");
+        assert_eq!(lines[5], "   4 lines             10       1.0 s");
+        assert_eq!(lines[8], "  32 lines              2       0.2 s");
+        assert_eq!(lines[10],
+                   "A clip of 16 frames gives bands of 2.50 lines; render it with --auto-stretch \
+                    to cover the whole image.");
+
+        let info = describe((40, 10), Direction::N, Some(16), None);
+        let lines: Vec<&str> = info.lines().collect();
+        assert_eq!(lines[2], " Band size  Frames needed");
+        assert_eq!(lines[6], "   8 lines              2");
+        assert_eq!(lines[8],
+                   "A clip of 16 frames gives a band of one line per frame; only the first 10 \
+                    frames are used.");
+    }
+}
@@ -0,0 +1,43 @@
+#[cfg(feature = "cli")]
+use ctrlc;
+
+#[cfg(feature = "cli")]
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "cli")]
+use ::errors::{self, ErrorKind, ResultExt};
+use ::errors::Result;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes Ctrl-C ask the render to stop once the current band is done, so the work so far can be
+/// saved, instead of killing it outright. A second Ctrl-C quits straight away.
+///
+/// # Errors
+/// This fails if the handler cannot be installed.
+#[cfg(feature = "cli")]
+pub fn install() -> Result<()> {
+    ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                process::exit(errors::EXIT_INTERRUPTED);
+            }
+            info!("Interrupted; stopping after the current band. Press Ctrl-C again to quit \
+                   without saving.");
+        })
+        .chain_err(|| ErrorKind::CouldNotHandleInterrupts)
+}
+
+/// Without the `cli` feature there is no Ctrl-C to handle, so renders are never interrupted.
+///
+/// # Errors
+/// This never fails.
+#[cfg(not(feature = "cli"))]
+pub fn install() -> Result<()> {
+    Ok(())
+}
+
+/// Whether Ctrl-C has been pressed since the handler was installed.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
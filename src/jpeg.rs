@@ -0,0 +1,139 @@
+use image::DynamicImage;
+
+/// The bytes every JPEG starts with: the start of image marker and the start of the next segment.
+const JPEG_SIGNATURE: &[u8] = &[0xFF, 0xD8, 0xFF];
+
+/// Decodes JPEG data with the faster decoder, if it is built in and can decode it. Returns `None`
+/// for anything else, so that the data is decoded with `image` instead.
+#[cfg(feature = "fast-jpeg")]
+pub fn decode(data: &[u8]) -> Option<DynamicImage> {
+    use image::ImageBuffer;
+    use zune_core::colorspace::ColorSpace;
+    use zune_core::options::DecoderOptions;
+    use zune_jpeg::JpegDecoder;
+
+    if !data.starts_with(JPEG_SIGNATURE) {
+        return None;
+    }
+    // Grayscale and CMYK JPEGs are converted too, so every frame comes out as RGB.
+    let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGB);
+    let mut decoder = JpegDecoder::new_with_options(data, options);
+    let pixels = decoder.decode().ok()?;
+    let (width, height) = decoder.dimensions()?;
+    ImageBuffer::from_raw(width as u32, height as u32, pixels).map(DynamicImage::ImageRgb8)
+}
+
+/// Stands in for the faster decoder in builds without the fast-jpeg feature.
+#[cfg(not(feature = "fast-jpeg"))]
+pub fn decode(_data: &[u8]) -> Option<DynamicImage> {
+    None
+}
+
+/// Decodes JPEG data of the given dimensions straight into `out` as RGBA, with the faster decoder,
+/// for sequences known to be all JPEGs of one size and color type. Returns `None` if the data
+/// isn't a JPEG of the given dimensions that it can decode.
+#[cfg(feature = "fast-jpeg")]
+pub fn decode_rgba(data: &[u8], (width, height): (u32, u32), out: &mut [u8]) -> Option<()> {
+    use zune_core::colorspace::ColorSpace;
+    use zune_core::options::DecoderOptions;
+    use zune_jpeg::JpegDecoder;
+
+    if !data.starts_with(JPEG_SIGNATURE) {
+        return None;
+    }
+    let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGBA);
+    let mut decoder = JpegDecoder::new_with_options(data, options);
+    decoder.decode_headers().ok()?;
+    if decoder.dimensions()? != (width as usize, height as usize) ||
+       decoder.output_buffer_size()? != out.len() {
+        return None;
+    }
+    decoder.decode_into(out).ok()
+}
+
+/// Decodes JPEG data of the given dimensions into `out` as RGBA with the decoder `image` uses, in
+/// builds without the fast-jpeg feature. Returns `None` if the data isn't an RGB or grayscale
+/// JPEG of the given dimensions.
+#[cfg(not(feature = "fast-jpeg"))]
+pub fn decode_rgba(data: &[u8], dimensions: (u32, u32), out: &mut [u8]) -> Option<()> {
+    use image::{ColorType, DecodingResult, ImageDecoder};
+    use image::jpeg::JPEGDecoder;
+
+    use ::frame_processing;
+
+    if !data.starts_with(JPEG_SIGNATURE) {
+        return None;
+    }
+    let mut decoder = JPEGDecoder::new(data);
+    if decoder.dimensions().ok()? != dimensions {
+        return None;
+    }
+    let channels = match decoder.colortype().ok()? {
+        ColorType::Gray(8) => 1,
+        ColorType::RGB(8) => 3,
+        _ => return None,
+    };
+    match decoder.read_image().ok()? {
+        DecodingResult::U8(ref pixels) if pixels.len() / channels == out.len() / 4 => {
+            frame_processing::expand_to_rgba(pixels, channels, out);
+            Some(())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_other_formats() {
+        assert!(decode(b"\x89PNG\r\n\x1a\n").is_none());
+        assert!(decode(b"").is_none());
+    }
+
+    #[test]
+    fn test_decode_rgba() {
+        use image::{ColorType, ImageBuffer, Rgb};
+        use image::jpeg::JPEGEncoder;
+
+        let frame = ImageBuffer::from_fn(16, 8, |x, y| Rgb([x as u8 * 16, y as u8 * 32, 128]));
+        let mut data = vec![];
+        JPEGEncoder::new_with_quality(&mut data, 100)
+            .encode(&frame, 16, 8, ColorType::RGB(8))
+            .unwrap();
+        let mut out = vec![0; 16 * 8 * 4];
+        decode_rgba(&data, (16, 8), &mut out).unwrap();
+        let reference = ::image::load_from_memory(&data).unwrap().to_rgba();
+        for (a, b) in out.chunks(4).zip(reference.pixels()) {
+            assert_eq!(a[3], 255);
+            for c in 0..3 {
+                assert!((a[c] as i32 - b[c] as i32).abs() <= 4);
+            }
+        }
+        assert!(decode_rgba(&data, (8, 16), &mut out).is_none());
+        assert!(decode_rgba(b"\x89PNG\r\n\x1a\n", (16, 8), &mut out).is_none());
+    }
+
+    #[cfg(feature = "fast-jpeg")]
+    #[test]
+    fn test_decode() {
+        use image::{ColorType, GenericImage, ImageBuffer, Rgb};
+        use image::jpeg::JPEGEncoder;
+
+        let frame = ImageBuffer::from_fn(16, 8, |x, y| Rgb([x as u8 * 16, y as u8 * 32, 128]));
+        let mut data = vec![];
+        JPEGEncoder::new_with_quality(&mut data, 100)
+            .encode(&frame, 16, 8, ColorType::RGB(8))
+            .unwrap();
+        let fast = decode(&data).unwrap();
+        assert_eq!(fast.dimensions(), (16, 8));
+        // The decoders round differently, but agree to within a few levels.
+        let reference = ::image::load_from_memory(&data).unwrap().to_rgb();
+        for (a, b) in fast.to_rgb().pixels().zip(reference.pixels()) {
+            for c in 0..3 {
+                assert!((a[c] as i32 - b[c] as i32).abs() <= 4);
+            }
+        }
+    }
+}
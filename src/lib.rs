@@ -0,0 +1,221 @@
+#![deny(missing_docs)]
+//! The engine behind the `rolling-shutter` tool, which creates rolling shutter images from a
+//! sequence of frames, emulating how a phone's rolling shutter sees.
+//!
+//! The items at the root are the supported way in: [`render`] renders a [`FrameSource`] with the
+//! [`RenderOptions`] a [`ShutterConfig`] builds, and returns the composite or an [`Error`].
+//!
+//! ```
+//! extern crate image;
+//! extern crate rolling_shutter;
+//!
+//! use image::{DynamicImage, ImageBuffer, Rgba};
+//! use rolling_shutter::{render, Direction, FrameSource, NoProgress, ShutterConfig};
+//!
+//! # fn run() -> rolling_shutter::Result<()> {
+//! let frames = (0..4u8)
+//!     .map(|i| ImageBuffer::from_pixel(4, 4, Rgba([60 * i, 0, 0, 255])))
+//!     .map(DynamicImage::ImageRgba8)
+//!     .collect();
+//! let options = ShutterConfig::new().direction(Direction::N).build();
+//! let composite = render(&options, FrameSource::Frames(frames), &mut NoProgress)?;
+//! // The shutter starts from the top, so the bottom line comes from the last frame.
+//! assert_eq!(composite.get_pixel(0, 3), &Rgba([180, 0, 0, 255]));
+//! # Ok(())
+//! # }
+//! # fn main() { run().unwrap(); }
+//! ```
+//!
+//! For more control, [`get_paths`] finds the frames a file mask names and [`process_images`]
+//! renders them to files and [`OutputSink`]s, telling any [`RenderObserver`] how it goes.
+//!
+//! Everything public is re-exported here: the types the options are made of, the checks run on
+//! frames before a render, and the tool's other subcommands, such as [`benchmark`] and
+//! [`generate`]. The modules behind them are private, so anything not listed here is internal.
+
+#[cfg(feature = "cli")]
+extern crate ctrlc;
+extern crate deflate;
+#[cfg(feature = "async")]
+extern crate futures_core;
+extern crate gif;
+extern crate image;
+extern crate inflate;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+extern crate io_uring;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+extern crate libc;
+#[macro_use]
+extern crate log;
+#[cfg(not(target_arch = "wasm32"))]
+extern crate memmap2;
+#[cfg(feature = "preview")]
+extern crate minifb;
+extern crate png;
+#[cfg(feature = "gpu")]
+extern crate pollster;
+extern crate rayon;
+extern crate regex;
+#[macro_use]
+extern crate serde;
+extern crate toml;
+#[cfg(feature = "gpu")]
+extern crate wgpu;
+#[cfg(feature = "fast-jpeg")]
+extern crate zune_core;
+#[cfg(feature = "fast-jpeg")]
+extern crate zune_jpeg;
+
+use std::str::FromStr;
+
+// Errors.
+pub use errors::{exit_code, Error, ErrorKind, Result, ResultExt};
+
+// Rendering.
+pub use geometry::{BandRegion, ShutterGeometry};
+#[cfg(feature = "async")]
+pub use frame_stream::{render as render_stream, StreamRender};
+pub use image_processing::{composite_frames, process_images, render, FrameSource, RenderOptions};
+pub use shutter_config::ShutterConfig;
+
+// The options a render takes.
+pub use animation::{AnimationFormat, AnimationSettings, SnapshotSettings, VideoCodec};
+pub use checkpoint::Checkpoint;
+pub use color_processing::{Adjustments, Lut, LutTarget};
+pub use comparison::CompareWith;
+pub use frame_processing::{open_file_limit, set_open_file_limit, Denoise, DenoiseFilter, Flip,
+                           FrameTransforms, Region, ResizePolicy, Rotation, WhiteBalanceMode};
+pub use image_processing::{Dither, DitherMode, Seed};
+pub use metadata::Metadata;
+pub use output_processing::{BitDepth, EncoderSettings, OutputFormat, OutputSize, Overlay,
+                            OverlayPosition, PngCompression};
+
+// Finding and checking frames.
+pub use file_processing::{find_gaps, get_paths, read_dimensions, validate_layout, validate_paths,
+                          FrameLayout, Gap, PathMode};
+pub use frame_info::FrameInfo;
+
+// Following renders and taking their results.
+pub use observer::{Flow, RenderObserver};
+pub use output_sink::{MemoryOutput, OutputSink};
+pub use progress::{NoProgress, ProgressSink};
+pub use report::{Report, ReportOutput};
+
+// The tool's other subcommands, and what it needs around renders.
+pub use bench::{run as benchmark, Benchmark};
+pub use contact_sheet::build as contact_sheet;
+pub use generate::{generate, Pattern};
+pub use interrupt::{install as handle_interrupts, interrupted};
+pub use output_processing::save as save_image;
+pub use unroll::rectify;
+
+// Declared first so the other modules can use its `bail!` macro.
+/// The errors everything here fails with, and the process exit codes for each class of them.
+#[macro_use]
+mod errors;
+/// Animations of a render as it is built up, and snapshots taken along the way.
+mod animation;
+/// Measuring how fast this machine copies bands and decodes frames.
+mod bench;
+/// Checkpoints of interrupted renders, to resume them from.
+mod checkpoint;
+/// Color adjustments, grading and LUTs applied to frames.
+mod color_processing;
+/// Side-by-side comparisons of a render with one of its frames.
+mod comparison;
+/// Contact sheets of numbered thumbnails, to check frames before rendering.
+mod contact_sheet;
+mod debug_overlay;
+/// A C interface to rendering frames held in memory, declared in `include/rolling_shutter.h`.
+#[cfg(feature = "ffi")]
+mod ffi;
+/// Finding the input frames and checking them before rendering.
+mod file_processing;
+/// Where each frame came from: its path, when its file was written and when it was taken.
+mod frame_info;
+/// Loading frames and the transforms applied to each before it is sampled.
+mod frame_processing;
+/// Rendering from a stream of frames, waiting for each without blocking the thread.
+#[cfg(feature = "async")]
+mod frame_stream;
+/// Synthetic frame sequences to try the effect on.
+mod generate;
+/// Scan patterns, and the bands they make: where each band of the composite is taken from.
+mod geometry;
+mod gpu;
+mod icc;
+/// Rendering frames into a rolling shutter image.
+mod image_processing;
+/// Stopping a render cleanly on Ctrl-C.
+mod interrupt;
+mod jpeg;
+/// Metadata written into outputs.
+mod metadata;
+/// Following renders as they go: frames decoded, bands written and renders finished.
+mod observer;
+/// Saving outputs in each supported format.
+mod output_processing;
+/// Where the composite goes: files, standard output, memory, animations and snapshots.
+mod output_sink;
+mod png_writer;
+mod preview;
+/// Where renders report their progress.
+mod progress;
+/// Machine-readable summaries of renders.
+mod report;
+/// A builder for render options.
+mod shutter_config;
+mod stabilization;
+mod tiff_writer;
+#[cfg(test)]
+mod testing;
+mod uring;
+/// Straightening images skewed by a real rolling shutter.
+mod unroll;
+
+/// The *starting* direction of the shutter. That is, what part of the image does the shutter start
+/// from, and then go to the other side.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Direction {
+    /// From the top down.
+    N,
+    /// From the right to the left.
+    E,
+    /// From the bottom up.
+    S,
+    /// From the left to the right.
+    W,
+}
+
+impl FromStr for Direction {
+    type Err = Error;
+
+    /// Parses a direction from its letter or its name, in any case: `N`, `n` or `north`.
+    fn from_str(s: &str) -> Result<Direction> {
+        match s.to_lowercase().as_str() {
+            "n" | "north" => Ok(Direction::N),
+            "e" | "east" => Ok(Direction::E),
+            "s" | "south" => Ok(Direction::S),
+            "w" | "west" => Ok(Direction::W),
+            _ => bail!(ErrorKind::CouldNotParseDirection(s.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_direction() {
+        assert_eq!("N".parse::<Direction>().unwrap(), Direction::N);
+        assert_eq!("e".parse::<Direction>().unwrap(), Direction::E);
+        assert_eq!("South".parse::<Direction>().unwrap(), Direction::S);
+        assert_eq!("WEST".parse::<Direction>().unwrap(), Direction::W);
+        match "up".parse::<Direction>() {
+            Err(Error(ErrorKind::CouldNotParseDirection(ref s), _)) => assert_eq!(s, "up"),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+}
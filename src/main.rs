@@ -2,64 +2,132 @@
 //! A tool for creating roller shutter images, which emulate how a phone's rolling shutter sees.
 
 extern crate clap;
-#[macro_use]
-extern crate error_chain;
+#[cfg(feature = "tui")]
+extern crate crossterm;
 extern crate image;
+extern crate log;
 extern crate pbr;
+#[cfg(feature = "tui")]
+extern crate ratatui;
 extern crate regex;
+#[macro_use]
+extern crate rolling_shutter;
+#[macro_use]
+extern crate serde;
+extern crate toml;
 
-use clap::{Arg, ArgMatches, App};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use log::{Level, LevelFilter, Log, Metadata as LogMetadata, Record};
 
-mod errors;
-mod file_processing;
-mod image_processing;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
 
-use self::errors::{ErrorKind, Result, ResultExt};
-use self::file_processing::*;
+mod args;
+mod config;
+mod info;
+mod output_paths;
+mod terminal;
+mod tui;
 
-/// The *starting* direction of the shutter. That is, what part of the image does the shutter start
-/// from, and then go to the other side.
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum Direction {
-    N,
-    E,
-    S,
-    W,
-}
+use rolling_shutter::{benchmark, contact_sheet, exit_code, find_gaps, generate, get_paths,
+                      handle_interrupts, interrupted, process_images, read_dimensions, rectify,
+                      save_image, set_open_file_limit, validate_layout, validate_paths};
+use rolling_shutter::{Adjustments, AnimationFormat, AnimationSettings, Checkpoint, Denoise,
+                      Direction, Dither, EncoderSettings, Error, ErrorKind, FrameTransforms, Lut,
+                      Metadata, NoProgress, OutputSize, Overlay, PathMode, Pattern, ProgressSink,
+                      ResizePolicy, Result, ResultExt, Seed, ShutterConfig, SnapshotSettings};
+
+use output_paths::{OutputPaths, OverwritePolicy};
+use terminal::{SharedProgress, TerminalProgress};
 
-impl<'a> From<&'a str> for Direction {
-    fn from(s: &'a str) -> Direction {
-        match s {
-            "N" => Direction::N,
-            "E" => Direction::E,
-            "S" => Direction::S,
-            "W" => Direction::W,
-            _ => unreachable!(),
+/// Prints what the engine logs to standard error, the way the tool has always shown it.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= Level::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Error => eprintln!("Error: {}", record.args()),
+            Level::Warn => eprintln!("Warning: {}", record.args()),
+            // Status messages come while a progress bar may be on the current line.
+            _ => eprintln!("\n{}", record.args()),
         }
     }
+
+    fn flush(&self) {}
 }
 
-quick_main!(run);
+static LOGGER: StderrLogger = StderrLogger;
+
+fn main() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Info);
+    }
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        for cause in e.iter().skip(1) {
+            eprintln!("Caused by: {}", cause);
+        }
+        process::exit(exit_code(&e));
+    }
+}
 
-fn parse_args<'a>() -> ArgMatches<'a> {
+/// Builds the command line parser. Without `require`, required options may be left out, so that a
+/// first pass can find which options were given before a config file fills in the rest.
+fn app<'a, 'b>(require: bool) -> App<'a, 'b> {
+    let output = Arg::with_name("output")
+        .short("o")
+        .long("output")
+        .help("Output filename, or - to write the image to standard output. May be repeated to \
+               save the same render in several formats.")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .allow_hyphen_values(true);
+    let folder = Arg::with_name("folder")
+        .short("f")
+        .long("folder")
+        .help("A folder to use for frames.{n}Frames will be taken in platform-sorted order.")
+        .takes_value(true);
     App::new("Rolling Shutter")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about("Creates a rolling shutter simulation of a set of frames.")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("direction")
             .short("d")
             .long("direction")
             .help("Changes direction of shutter movement; specifically, it determines the \
-                   cardinal direction where the shutter *starts* from.")
+                   cardinal direction where the shutter *starts* from, as N, E, S or W, or its \
+                   name.")
             .takes_value(true)
-            .possible_values(&["N", "E", "S", "W"])
+            .validator(|s| s.parse::<Direction>().map(|_| ()).map_err(|e| e.to_string()))
             .default_value("N"))
-        .arg(Arg::with_name("output")
-            .short("o")
-            .long("output")
-            .help("Output filename.")
+        .arg(if require {
+            output.required_unless_one(&["list-presets", "save-preset"])
+        } else {
+            output
+        })
+        .arg(force_arg())
+        .arg(output_suffix_arg())
+        .arg(Arg::with_name("output-format")
+            .long("output-format")
+            .help("The format to encode outputs in when their extension doesn't determine one, \
+                   such as standard output, which is written as PNG by default.")
             .takes_value(true)
-            .required(true))
+            .possible_values(&["png", "jpeg", "ppm", "tiff"]))
         .arg(Arg::with_name("input")
             .short("i")
             .long("input")
@@ -68,40 +136,1460 @@ fn parse_args<'a>() -> ArgMatches<'a> {
             .takes_value(true)
             .conflicts_with("folder")
             .index(1))
-        .arg(Arg::with_name("folder")
-            .short("f")
-            .long("folder")
-            .help("A folder to use for frames.{n}Frames will be taken in platform-sorted order.")
+        .arg(if require {
+            folder.required_unless_one(&["input", "list-presets", "save-preset"])
+        } else {
+            folder
+        })
+        .arg(Arg::with_name("config")
+            .long("config")
+            .help("A TOML file of options to use, such as `direction = \"W\"`, with the same \
+                   names as the long flags. Options given on the command line take precedence.")
+            .takes_value(true))
+        .arg(Arg::with_name("preset")
+            .long("preset")
+            .help("Uses the options saved in a preset. Options given on the command line or in a \
+                   config file take precedence.")
+            .takes_value(true)
+            .validator(validate_preset_name))
+        .arg(Arg::with_name("save-preset")
+            .long("save-preset")
+            .help("Saves the options given on the command line, other than the input and \
+                   outputs, as a preset with the given name in the user configuration directory, \
+                   instead of rendering.")
+            .takes_value(true)
+            .validator(validate_preset_name)
+            .conflicts_with("list-presets"))
+        .arg(Arg::with_name("list-presets")
+            .long("list-presets")
+            .help("Lists the saved presets, instead of rendering."))
+        .arg(Arg::with_name("preblend")
+            .long("preblend")
+            .help("Averages every group of the given number of consecutive frames into one frame \
+                   before the shutter pass, for captures with far more frames than needed.")
+            .takes_value(true)
+            .validator(validate_positive_u32))
+        .arg(Arg::with_name("threads")
+            .long("threads")
+            .help("How many frames to decode at once, each on its own thread, ahead of the bands \
+                   they are for. Defaults to one per CPU core; 1 decodes them one at a time.")
+            .takes_value(true)
+            .validator(validate_positive_u32))
+        .arg(Arg::with_name("max-open-files")
+            .long("max-open-files")
+            .help("How many frame files are open at once, across every render running, for systems \
+                   with a low limit on open files and for network mounts.")
+            .takes_value(true)
+            .validator(validate_positive_u32)
+            .default_value("32"))
+        .arg(Arg::with_name("max-memory")
+            .long("max-memory")
+            .help("Keeps the decoded frames the render holds within roughly the given size, such \
+                   as 512M or 2G, by decoding fewer of them ahead. The composite is held too, \
+                   unless it can be written straight to a PNG as it is built.")
+            .takes_value(true)
+            .validator(validate_size))
+        .arg(Arg::with_name("gpu")
+            .long("gpu")
+            .help("Composites on the GPU, which pays off for custom geometries and dithered \
+                   bands on large frames. Needs a build with --features gpu."))
+        .arg(Arg::with_name("dither-bands")
+            .long("dither-bands")
+            .help("Dithers the boundaries between bands to break up straight seams.")
+            .takes_value(true)
+            .possible_values(&["ordered", "blue-noise"]))
+        .arg(Arg::with_name("auto-stretch")
+            .long("auto-stretch")
+            .help("When there are fewer frames than lines, widens the bands so that they cover \
+                   the whole image instead of leaving the end of it empty.")
+            .conflicts_with("dither-bands"))
+        .arg(Arg::with_name("dither-radius")
+            .long("dither-radius")
+            .help("How many lines a dithered pixel may be moved away from its own band.")
+            .takes_value(true)
+            .validator(validate_u32)
+            .default_value("2"))
+        .arg(Arg::with_name("seed")
+            .long("seed")
+            .help("Seeds the randomized options, such as dithering. A render with the same frames, \
+                   options and seed gives byte-identical outputs on any platform.")
+            .takes_value(true)
+            .validator(validate_u64)
+            .default_value("0"))
+        .arg(Arg::with_name("resize-policy")
+            .long("resize-policy")
+            .help("What to do with frames whose dimensions differ from the first frame's.{n}fit \
+                   letterboxes them, fill crops them, stretch ignores their aspect ratio, and \
+                   error refuses to process them.")
+            .takes_value(true)
+            .possible_values(&["fit", "fill", "stretch", "error"])
+            .default_value("error"))
+        .arg(Arg::with_name("rotate")
+            .long("rotate")
+            .help("Rotates every frame clockwise by the given number of degrees.")
+            .takes_value(true)
+            .possible_values(&["90", "180", "270"]))
+        .arg(Arg::with_name("flip")
+            .long("flip")
+            .help("Mirrors every frame horizontally (h) or vertically (v), after any rotation.")
+            .takes_value(true)
+            .possible_values(&["h", "v"]))
+        .arg(Arg::with_name("crop")
+            .long("crop")
+            .help("Only uses the given region of every frame, of the form x,y,width,height, after \
+                   any rotation or flip. The output has the dimensions of the region.")
+            .takes_value(true)
+            .validator(|s| {
+                args::parse_region(&s).map(|_| ()).map_err(|e| e.to_string())
+            }))
+        .arg(Arg::with_name("preview-scale")
+            .long("preview-scale")
+            .help("Shrinks every frame by this factor, between 0 and 1, as it is loaded, and only \
+                   uses the frames the matching bands of the full-size render would, for a small \
+                   proof of the render that is quick to make.")
+            .takes_value(true)
+            .validator(validate_scale))
+        .arg(Arg::with_name("output-size")
+            .long("output-size")
+            .help("Rescales the output to the given size, of the form WIDTHxHEIGHT.")
+            .takes_value(true)
+            .conflicts_with("scale")
+            .validator(|s| {
+                args::parse_size(&s).map(|_| ()).map_err(|e| e.to_string())
+            }))
+        .arg(Arg::with_name("scale")
+            .long("scale")
+            .help("Rescales the output by the given factor, e.g. 0.5 for half size.")
+            .takes_value(true)
+            .validator(validate_positive_f64))
+        .arg(Arg::with_name("filter")
+            .long("filter")
+            .help("The filter used when rescaling the output.")
+            .takes_value(true)
+            .possible_values(&["nearest", "bilinear", "bicubic", "lanczos"])
+            .default_value("lanczos"))
+        .arg(Arg::with_name("stabilize")
+            .long("stabilize")
+            .help("Aligns every frame to the first frame to compensate for camera shake."))
+        .arg(Arg::with_name("autocrop")
+            .long("autocrop")
+            .help("Crops the output to the region that every stabilized frame covers.")
+            .requires("stabilize"))
+        .arg(Arg::with_name("deflicker")
+            .long("deflicker")
+            .help("Normalizes each frame's exposure towards a rolling average of the frames \
+                   before it, to remove striped banding from flickering exposure."))
+        .arg(Arg::with_name("deflicker-window")
+            .long("deflicker-window")
+            .help("How many frames the deflicker pass averages over.")
+            .takes_value(true)
+            .validator(validate_u32)
+            .default_value("15"))
+        .arg(Arg::with_name("normalize-wb")
+            .long("normalize-wb")
+            .help("Aligns white balance across frames, either by assuming each frame averages to \
+                   gray or by matching the first frame.")
             .takes_value(true)
-            .required_unless("input"))
+            .possible_values(&["gray-world", "reference"]))
+        .arg(Arg::with_name("denoise")
+            .long("denoise")
+            .help("Denoises every frame with the given filter before its band is copied.")
+            .takes_value(true)
+            .possible_values(&["box", "median", "bilateral"]))
+        .arg(Arg::with_name("denoise-radius")
+            .long("denoise-radius")
+            .help("The radius of the neighbourhood the denoise filter considers.")
+            .takes_value(true)
+            .validator(validate_u32)
+            .default_value("1"))
+        .arg(Arg::with_name("brightness")
+            .long("brightness")
+            .help("Adds the given offset, from -255 to 255, to every channel of every frame.")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .validator(validate_f64))
+        .arg(Arg::with_name("contrast")
+            .long("contrast")
+            .help("Changes the contrast of every frame by the given percentage; negative values \
+                   reduce contrast, down to -100, which leaves every frame flat grey.")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .validator(validate_contrast))
+        .arg(Arg::with_name("gamma")
+            .long("gamma")
+            .help("Applies gamma correction to every frame; values above 1 brighten midtones.")
+            .takes_value(true)
+            .validator(validate_positive_f64))
+        .arg(Arg::with_name("lut")
+            .long("lut")
+            .help("Grades colors with a 3D LUT read from a .cube file.")
+            .takes_value(true))
+        .arg(Arg::with_name("lut-target")
+            .long("lut-target")
+            .help("Whether the LUT is applied to every frame or to the final output.")
+            .takes_value(true)
+            .possible_values(&["frames", "output"])
+            .default_value("frames"))
+        .arg(Arg::with_name("sharpen")
+            .long("sharpen")
+            .help("Sharpens the output with an unsharp mask of the given amount, e.g. 0.5.")
+            .takes_value(true)
+            .validator(validate_positive_f64))
+        .arg(Arg::with_name("sharpen-radius")
+            .long("sharpen-radius")
+            .help("The blur radius of the unsharp mask used by --sharpen.")
+            .takes_value(true)
+            .validator(validate_positive_f64)
+            .default_value("1.0"))
+        .arg(Arg::with_name("overlay")
+            .long("overlay")
+            .help("Composites the given image, such as a logo, on top of the output.")
+            .takes_value(true))
+        .arg(Arg::with_name("overlay-pos")
+            .long("overlay-pos")
+            .help("Where the overlay is placed on the output.")
+            .takes_value(true)
+            .possible_values(&["top-left", "top-right", "bottom-left", "bottom-right", "center"])
+            .default_value("bottom-right"))
+        .arg(Arg::with_name("overlay-margin")
+            .long("overlay-margin")
+            .help("The distance in pixels between the overlay and the edges of the output.")
+            .takes_value(true)
+            .validator(validate_u32)
+            .default_value("16"))
+        .arg(Arg::with_name("jpeg-quality")
+            .long("jpeg-quality")
+            .help("The quality of JPEG outputs, from 1 to 100.")
+            .takes_value(true)
+            .validator(|s| validate_quality(s, 1))
+            .default_value("90"))
+        .arg(Arg::with_name("png-compression")
+            .long("png-compression")
+            .help("How hard to compress PNG outputs; best is smallest but slowest.")
+            .takes_value(true)
+            .possible_values(&["fast", "default", "best"])
+            .default_value("default"))
+        .arg(Arg::with_name("webp-quality")
+            .long("webp-quality")
+            .help("The quality of animated WebPs saved by --animate, from 0 to 100.")
+            .takes_value(true)
+            .validator(|s| validate_quality(s, 0))
+            .default_value("75"))
+        .arg(Arg::with_name("bit-depth")
+            .long("bit-depth")
+            .help("The number of bits per channel of the output. 16-bit output keeps the \
+                   precision of frames blended by --preblend, unless --normalize-wb, --deflicker, \
+                   --denoise or color adjustments follow the blend, as well as that of the output \
+                   LUT, rescaling and sharpening, and requires a .png or .tiff output.")
+            .takes_value(true)
+            .possible_values(&["8", "16"])
+            .default_value("8"))
+        .arg(Arg::with_name("linear-blend")
+            .long("linear-blend")
+            .help("Blends and averages colors in linear light instead of on sRGB-encoded values, \
+                   which avoids darkening when denoising, rescaling, sharpening, or compositing an \
+                   overlay. Band edges, dithered or not, take each pixel whole from one frame, so \
+                   they aren't blended."))
+        .arg(Arg::with_name("no-icc")
+            .long("no-icc")
+            .help("Doesn't copy the first frame's embedded ICC color profile into the output."))
+        .arg(Arg::with_name("no-metadata")
+            .long("no-metadata")
+            .help("Doesn't record the render's parameters, such as the direction, frame count, \
+                   input and command line, in the output's PNG text chunks, JPEG comment or TIFF \
+                   description."))
+        .arg(Arg::with_name("animate")
+            .long("animate")
+            .help("Also saves an animation to the given path, showing the shutter sweeping \
+                   across the image as the output is built up. The format is picked by \
+                   extension: .gif, .png for an animated PNG, or .webp, which requires ffmpeg.")
+            .takes_value(true))
+        .arg(Arg::with_name("animate-video")
+            .long("animate-video")
+            .help("Like --animate, but saves a video such as out.mp4 by piping frames to ffmpeg, \
+                   which must be installed. Better suited to long, high resolution renders.")
+            .takes_value(true)
+            .conflicts_with("animate"))
+        .arg(Arg::with_name("video-codec")
+            .long("video-codec")
+            .help("The codec used by --animate-video.")
+            .takes_value(true)
+            .possible_values(&["h264", "h265"])
+            .default_value("h264"))
+        .arg(Arg::with_name("animate-every")
+            .long("animate-every")
+            .help("How many bands are copied between the frames of the animation.")
+            .takes_value(true)
+            .validator(validate_positive_u32)
+            .default_value("10"))
+        .arg(Arg::with_name("snapshot-every")
+            .long("snapshot-every")
+            .help("Saves a numbered PNG of the output so far every time the given number of bands \
+                   have been copied.")
+            .takes_value(true)
+            .validator(validate_positive_u32))
+        .arg(Arg::with_name("snapshot-dir")
+            .long("snapshot-dir")
+            .help("The directory --snapshot-every saves snapshots in.")
+            .takes_value(true)
+            .default_value("snapshots"))
+        .arg(Arg::with_name("tile-rows")
+            .long("tile-rows")
+            .help("Renders the output the given number of rows at a time, writing each strip to \
+                   the output before starting the next, so that images too large to hold in \
+                   memory can be rendered. Only works for a single PNG output, and goes over the \
+                   frames again for every strip they reach.")
+            .takes_value(true)
+            .validator(validate_positive_u32))
+        .arg(Arg::with_name("autosave-every")
+            .long("autosave-every")
+            .help("Saves the output so far as out.partial.png, with a checkpoint to resume it \
+                   from in out.checkpoint.toml, every time the given number of bands have been \
+                   copied, so that a render that crashes can be recovered. They are removed once \
+                   the render finishes.")
+            .takes_value(true)
+            .validator(validate_positive_u32))
+        .arg(Arg::with_name("debug-overlay")
+            .long("debug-overlay")
+            .help("Also saves a copy of the output, named like out.debug.png, marked with band \
+                   ticks, frame indices, and the direction the shutter travels."))
+        .arg(Arg::with_name("compare-with")
+            .long("compare-with")
+            .help("Also saves the output side by side with a reference, named like \
+                   out.compare.png. The reference is the first or middle frame, or any other \
+                   image, which is scaled to fit.")
+            .takes_value(true)
+            .value_name("first|middle|PATH"))
+        .arg(Arg::with_name("export-bands")
+            .long("export-bands")
+            .help("Also saves the band taken from each frame as its own image in the given \
+                   directory, numbered in order, e.g. bands/00000.png. Bands are exported before \
+                   any dithering.")
+            .takes_value(true)
+            .value_name("DIR"))
+        .arg(Arg::with_name("dump-time-map")
+            .long("dump-time-map")
+            .help("Also saves a map of which frame each pixel of the output was taken from, \
+                   including any dithering, as a 16-bit grayscale PNG where black is the first \
+                   frame and white is the last.")
+            .takes_value(true)
+            .value_name("PATH"))
+        .arg(Arg::with_name("resume")
+            .long("resume")
+            .help("Continues a render that was interrupted with Ctrl-C, or that crashed while \
+                   autosaving, from the checkpoint it saved, such as out.checkpoint.toml. The \
+                   other options should match the interrupted render's.")
+            .takes_value(true)
+            .value_name("CHECKPOINT"))
+        .arg(Arg::with_name("validate")
+            .long("validate")
+            .help("Checks every frame's header before rendering and reports all problems at \
+                   once. This is the default.")
+            .overrides_with("no-validate"))
+        .arg(Arg::with_name("no-validate")
+            .long("no-validate")
+            .help("Skips the validation pass.")
+            .overrides_with("validate"))
+        .arg(Arg::with_name("report")
+            .long("report")
+            .help("Writes a JSON summary of the render to the given path: how many frames were \
+                   found and used, which were skipped, where each band's frame came from, the \
+                   frame and output dimensions, and how long each phase took.")
+            .takes_value(true))
         .arg(Arg::with_name("quiet")
             .short("q")
             .long("quiet")
             .help("Suppress output."))
-        .get_matches()
+        .arg(Arg::with_name("no-progress")
+            .long("no-progress")
+            .help("Hides the progress bar but keeps other messages, for logs and other places \
+                   that aren't a terminal."))
+        .subcommand(SubCommand::with_name("contact-sheet")
+            .about("Saves a grid of thumbnails of the input frames, labelled with their indices, \
+                    to check their order and content before rendering.")
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .help("Output filename.")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .help("File mask for input, as for rendering.")
+                .takes_value(true)
+                .conflicts_with("folder")
+                .index(1))
+            .arg(Arg::with_name("folder")
+                .short("f")
+                .long("folder")
+                .help("A folder to use for frames, as for rendering.")
+                .takes_value(true)
+                .required_unless("input"))
+            .arg(Arg::with_name("columns")
+                .long("columns")
+                .help("How many thumbnails to put in each row.")
+                .takes_value(true)
+                .validator(validate_positive_u32)
+                .default_value("8"))
+            .arg(Arg::with_name("thumb-width")
+                .long("thumb-width")
+                .help("The width of each thumbnail. Their height follows the first frame's \
+                       aspect ratio.")
+                .takes_value(true)
+                .validator(validate_positive_u32)
+                .default_value("160"))
+            .arg(force_arg())
+            .arg(output_suffix_arg())
+            .arg(Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .help("Suppress output.")))
+        .subcommand(SubCommand::with_name("check")
+            .about("Checks that the input frames would render cleanly, reading only their headers: \
+                    reports frames that are empty, in an unsupported format or a different size \
+                    from the first, and gaps in the sequence, exiting with an error if there are \
+                    any.")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .help("File mask for input, as for rendering.")
+                .takes_value(true)
+                .conflicts_with("folder")
+                .index(1))
+            .arg(Arg::with_name("folder")
+                .short("f")
+                .long("folder")
+                .help("A folder to use for frames, as for rendering.")
+                .takes_value(true)
+                .required_unless("input"))
+            .arg(Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .help("Suppress output.")))
+        .subcommand(SubCommand::with_name("batch")
+            .about("Renders every job in a TOML batch file in turn. Options at the top of the file \
+                    are shared by every job, and each [[job]] table gives one job's own options, \
+                    with the same names as the long flags, and optionally a name.")
+            .arg(Arg::with_name("file")
+                .help("The batch file.")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .help("How many jobs to render at once. Their progress is printed as lines \
+                       labelled with each job's name rather than drawn as bars.")
+                .takes_value(true)
+                .validator(validate_positive_u32)
+                .default_value("1")))
+        .subcommand(SubCommand::with_name("generate")
+            .about("Generates a sequence of frames of a synthetic animation, to try the effect out \
+                    on without having to find footage.")
+            .arg(Arg::with_name("pattern")
+                .long("pattern")
+                .help("What the frames show: a turning propeller, bars sliding across, a clock \
+                       hand sweeping around, or a timing grid for measuring a real camera's \
+                       rolling shutter off a screen playing the frames.")
+                .takes_value(true)
+                .possible_values(&["spinner", "bars", "clock", "calibrate"])
+                .default_value("spinner"))
+            .arg(Arg::with_name("frames")
+                .long("frames")
+                .help("How many frames to generate.")
+                .takes_value(true)
+                .validator(validate_positive_u32)
+                .default_value("120"))
+            .arg(Arg::with_name("size")
+                .long("size")
+                .help("The size of each frame, of the form WIDTHxHEIGHT.")
+                .takes_value(true)
+                .validator(|s| {
+                    args::parse_size(&s).map(|_| ()).map_err(|e| e.to_string())
+                })
+                .default_value("640x480"))
+            .arg(Arg::with_name("out")
+                .short("o")
+                .long("out")
+                .help("The directory to save the frames to, as numbered PNGs.")
+                .takes_value(true)
+                .required(true))
+            .arg(force_arg())
+            .arg(output_suffix_arg())
+            .arg(Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .help("Suppress output.")))
+        .subcommand(SubCommand::with_name("info")
+            .about("Prints how many frames a render of a given size needs at various band sizes, \
+                    and what band size a clip of a given length gives, for planning a capture.")
+            .arg(Arg::with_name("size")
+                .long("size")
+                .help("The size of the image, of the form WIDTHxHEIGHT.")
+                .takes_value(true)
+                .validator(|s| {
+                    args::parse_size(&s).map(|_| ()).map_err(|e| e.to_string())
+                })
+                .required(true))
+            .arg(Arg::with_name("direction")
+                .short("d")
+                .long("direction")
+                .help("The cardinal direction the shutter starts from.")
+                .takes_value(true)
+                .validator(|s| s.parse::<Direction>().map(|_| ()).map_err(|e| e.to_string()))
+                .default_value("N"))
+            .arg(Arg::with_name("frames")
+                .long("frames")
+                .help("The length of a clip, in frames, to work out the band size it gives.")
+                .takes_value(true)
+                .validator(validate_positive_u32))
+            .arg(Arg::with_name("fps")
+                .long("fps")
+                .help("The frame rate to capture at, to show how long each capture takes.")
+                .takes_value(true)
+                .validator(validate_positive_f64)))
+        .subcommand(SubCommand::with_name("bench")
+            .about("Measures how many bands a second this machine copies in each direction along \
+                    each copy path, and how many frames a second each decoder decodes, on \
+                    synthetic frames generated in memory, to compare machines and check that the \
+                    GPU and fast-jpeg backends help.")
+            .arg(Arg::with_name("size")
+                .long("size")
+                .help("The size of the frames and the composite, of the form WIDTHxHEIGHT.")
+                .takes_value(true)
+                .validator(|s| {
+                    args::parse_size(&s).map(|_| ()).map_err(|e| e.to_string())
+                })
+                .default_value("1920x1080"))
+            .arg(Arg::with_name("rounds")
+                .long("rounds")
+                .help("How many times to build each composite and decode each frame.")
+                .takes_value(true)
+                .validator(validate_positive_u32)
+                .default_value("3")))
+        .subcommand(SubCommand::with_name("preview")
+            .about("Renders as usual, taking the same options, while showing the composite in a \
+                    window as it is built up. Press S to stop early and save the bands done so \
+                    far, or Escape or Q to abort without saving.")
+            .setting(AppSettings::TrailingVarArg)
+            .setting(AppSettings::AllowLeadingHyphen)
+            .arg(Arg::with_name("args")
+                .help("The options and input to render with.")
+                .multiple(true)
+                .required(true)))
+        .subcommand(SubCommand::with_name("tui")
+            .about("Opens a terminal UI for setting up renders and queueing them, showing the \
+                    frames found, the progress of the current render and any warnings.")
+            .arg(Arg::with_name("input")
+                .help("The input file mask or folder to start with.")
+                .index(1))
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .help("The output file to start with.")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("unroll")
+            .about("Straightens a subject skewed by a rolling shutter, such as one in a real \
+                    photo, by shifting each line back by how far the subject moved before that \
+                    line was read out.")
+            .arg(Arg::with_name("image")
+                .help("The rolling shutter image to correct.")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .help("Output filename.")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("direction")
+                .short("d")
+                .long("direction")
+                .help("The cardinal direction the shutter started reading out from.")
+                .takes_value(true)
+                .validator(|s| s.parse::<Direction>().map(|_| ()).map_err(|e| e.to_string()))
+                .default_value("N"))
+            .arg(Arg::with_name("skew")
+                .long("skew")
+                .help("How many pixels the subject moved per line read out, to the right for N \
+                       and S, or downwards for E and W. Negative values move it the other way.")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .validator(validate_f64)
+                .required(true))
+            .arg(force_arg())
+            .arg(output_suffix_arg()))
 }
 
-fn run() -> Result<()> {
-    let matches = parse_args();
+/// The `--force` flag, for every command that saves files.
+fn force_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("force")
+        .long("force")
+        .help("Overwrites files that already exist, instead of refusing to save over them. \
+               This covers every file saved, such as animations, reports and exported bands, \
+               not only the output.")
+        .conflicts_with("output-suffix")
+}
+
+/// The `--output-suffix` flag, for every command that saves files.
+fn output_suffix_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("output-suffix")
+        .long("output-suffix")
+        .help("Saves files that already exist to a new name instead, such as out-1.png for \
+               out.png. Directories count as existing once anything is in them.")
+}
+
+/// How the files a command saves are kept from replacing existing ones, following `--force` and
+/// `--output-suffix`.
+fn output_paths(matches: &ArgMatches) -> OutputPaths {
+    OutputPaths::new(if matches.is_present("force") {
+        OverwritePolicy::Force
+    } else if matches.is_present("output-suffix") {
+        OverwritePolicy::Suffix
+    } else {
+        OverwritePolicy::Refuse
+    })
+}
 
-    let direction = matches.value_of("direction").unwrap().into();
+/// Decides where a file the command saves goes, along with any `siblings` saved next to it and
+/// named after it, saying so unless quiet if it isn't where it was asked for.
+fn resolve_path<F>(paths: &mut OutputPaths,
+                   path: &str,
+                   siblings: F,
+                   matches: &ArgMatches)
+                   -> Result<PathBuf>
+    where F: Fn(&Path) -> Vec<PathBuf>
+{
+    let resolved = paths.resolve(path.as_ref(), siblings)?;
+    if resolved != Path::new(path) && !matches.is_present("quiet") {
+        eprintln!("{} already exists; saving to {} instead.", path, resolved.display());
+    }
+    Ok(resolved)
+}
+
+/// Options that aren't saved in presets, since they name what to render rather than how, or manage
+/// the presets themselves.
+const UNSAVED_OPTIONS: &[&str] = &["input", "folder", "output", "config", "preset", "save-preset",
+                                   "list-presets"];
+
+/// Parses the command line, filling in any options it leaves out from `ROLLING_SHUTTER_*`
+/// environment variables, then from the `--config` file, and then from the `--preset`.
+fn parse_args<'a>() -> Result<ArgMatches<'a>> {
+    parse_render_args(env::args_os().collect())
+}
 
-    let path_mode = if let Some(path) = matches.value_of("folder") {
-        PathMode::Folder(path)
+/// Parses arguments for rendering, filling in any options they leave out from the environment, and
+/// then from the config file and preset they or the environment name.
+fn parse_render_args<'a>(mut args: Vec<OsString>) -> Result<ArgMatches<'a>> {
+    let given = app(false).get_matches_from(&args);
+    if given.subcommand_name().is_none() {
+        let mut environment = config::from_env(env::vars_os(), is_long_option)?;
+        let env_config = take_entry(&mut environment, "config");
+        let env_preset = take_entry(&mut environment, "preset");
+        let mut sources = vec![environment];
+        if let Some(path) = given.value_of("config").map(String::from).or(env_config) {
+            sources.push(config::load(path.as_ref())?);
+        }
+        if let Some(name) = given.value_of("preset").map(String::from).or(env_preset) {
+            sources.push(config::load_preset(&name)?);
+        }
+
+        layer_args(&mut args, &given, sources)?;
+    }
+    Ok(app(true).get_matches_from(args))
+}
+
+/// Whether `name` is the long name of one of the options rendering takes.
+fn is_long_option(name: &str) -> bool {
+    // The input is taken as a positional argument, and clap prints the help or version as soon as
+    // it parses those flags, so they aren't tried.
+    match name {
+        "input" => return true,
+        "help" | "version" => return false,
+        _ => (),
+    }
+    let arg = format!("--{}", name);
+    match app(false).get_matches_from_safe(&["rolling-shutter", arg.as_str()]) {
+        Ok(_) => true,
+        // Any other error, such as a missing value, is about an option that exists.
+        Err(e) => e.kind != clap::ErrorKind::UnknownArgument,
+    }
+}
+
+/// Takes an option that names where other options come from out of `entries`, returning its value.
+fn take_entry(entries: &mut Vec<(String, config::Value)>, key: &str) -> Option<String> {
+    let index = entries.iter().position(|entry| entry.0 == key)?;
+    match entries.remove(index).1 {
+        config::Value::String(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// Adds the options from each source that `given` leaves out to the arguments it was parsed from,
+/// with earlier sources taking precedence over later ones.
+fn layer_args(args: &mut Vec<OsString>,
+              given: &ArgMatches,
+              sources: Vec<Vec<(String, config::Value)>>)
+              -> Result<()> {
+    let mut inputs_given = given.is_present("input") || given.is_present("folder");
+    let mut keys_given = vec![];
+    let mut extra_args = vec![];
+    for entries in sources {
+        let mut inputs_found = false;
+        for (key, value) in entries {
+            let is_input = key == "input" || key == "folder";
+            if given.occurrences_of(&key) > 0 || keys_given.contains(&key) ||
+               (is_input && inputs_given) {
+                continue;
+            }
+            inputs_found |= is_input;
+            extra_args.extend(option_args(&key, &value)?);
+            keys_given.push(key);
+        }
+        inputs_given |= inputs_found;
+    }
+    args.splice(1..1, extra_args);
+    Ok(())
+}
+
+/// Turns a config file or preset entry into command line arguments, passing the input as a
+/// positional argument, since it can't be named like the other options.
+fn option_args(key: &str, value: &config::Value) -> Result<Vec<OsString>> {
+    if key == "config" || key == "preset" {
+        bail!(ErrorKind::InvalidConfig(format!("'{}' can't be set in a file", key)));
+    }
+    let args = config::to_args(key, value)?;
+    Ok(args.into_iter()
+        .map(|arg| if key == "input" { arg["--input=".len()..].into() } else { arg.into() })
+        .collect())
+}
+
+/// Finds the options given on the command line that belong in a preset, with their values.
+fn preset_entries(matches: &ArgMatches) -> Vec<(String, config::Value)> {
+    let mut entries: Vec<(String, config::Value)> = vec![];
+    for arg in env::args_os().skip(1) {
+        let arg = arg.to_string_lossy();
+        let name = match arg.strip_prefix("--") {
+            Some(long) => long.split('=').next().unwrap(),
+            None if arg.starts_with("-d") => "direction",
+            None if arg == "-q" => "quiet",
+            // The other short options name inputs and outputs, which aren't saved.
+            None => continue,
+        };
+        if matches.occurrences_of(name) == 0 || UNSAVED_OPTIONS.contains(&name) ||
+           entries.iter().any(|entry| entry.0 == name) {
+            continue;
+        }
+        let mut values: Vec<_> = matches.values_of(name)
+            .into_iter()
+            .flat_map(|values| values.map(|v| config::Value::String(v.into())))
+            .collect();
+        let value = match values.len() {
+            0 => config::Value::Boolean(true),
+            1 => values.remove(0),
+            _ => config::Value::Array(values),
+        };
+        entries.push((name.to_string(), value));
+    }
+    entries
+}
+
+fn validate_preset_name(s: String) -> ::std::result::Result<(), String> {
+    if !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not a valid preset name; use only letters, digits, - and _", s))
+    }
+}
+
+fn validate_u32(s: String) -> ::std::result::Result<(), String> {
+    s.parse::<u32>().map(|_| ()).map_err(|e| format!("'{}' is not a valid number: {}", s, e))
+}
+
+fn validate_u64(s: String) -> ::std::result::Result<(), String> {
+    s.parse::<u64>().map(|_| ()).map_err(|e| format!("'{}' is not a valid number: {}", s, e))
+}
+
+fn validate_positive_u32(s: String) -> ::std::result::Result<(), String> {
+    match s.parse::<u32>() {
+        Ok(0) => Err(format!("'{}' must be a positive number", s)),
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("'{}' is not a valid number: {}", s, e)),
+    }
+}
+
+fn validate_quality(s: String, min: u8) -> ::std::result::Result<(), String> {
+    match s.parse::<u8>() {
+        Ok(value) if value >= min && value <= 100 => Ok(()),
+        Ok(_) => Err(format!("'{}' must be between {} and 100", s, min)),
+        Err(e) => Err(format!("'{}' is not a valid number: {}", s, e)),
+    }
+}
+
+fn validate_f64(s: String) -> ::std::result::Result<(), String> {
+    match s.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(()),
+        Ok(_) => Err(format!("'{}' must be a finite number", s)),
+        Err(e) => Err(format!("'{}' is not a valid number: {}", s, e)),
+    }
+}
+
+fn validate_contrast(s: String) -> ::std::result::Result<(), String> {
+    match s.parse::<f64>() {
+        Ok(value) if value >= -100.0 && value.is_finite() => Ok(()),
+        Ok(_) => Err(format!("'{}' must be a finite number of at least -100", s)),
+        Err(e) => Err(format!("'{}' is not a valid number: {}", s, e)),
+    }
+}
+
+fn validate_positive_f64(s: String) -> ::std::result::Result<(), String> {
+    match s.parse::<f64>() {
+        Ok(value) if value > 0.0 && value.is_finite() => Ok(()),
+        Ok(_) => Err(format!("'{}' must be a positive number", s)),
+        Err(e) => Err(format!("'{}' is not a valid number: {}", s, e)),
+    }
+}
+
+/// Parses a size in bytes, optionally followed by K, M, G or T, in either case, for that many
+/// KiB, MiB, GiB or TiB.
+fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let shift = match s.chars().last()?.to_ascii_uppercase() {
+        'K' => 10,
+        'M' => 20,
+        'G' => 30,
+        'T' => 40,
+        _ => 0,
+    };
+    let digits = if shift == 0 { s } else { &s[..s.len() - 1] };
+    digits.trim().parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+fn validate_size(s: String) -> ::std::result::Result<(), String> {
+    match parse_size(&s) {
+        Some(0) => Err(format!("'{}' must be more than nothing", s)),
+        Some(_) => Ok(()),
+        None => Err(format!("'{}' is not a valid size, such as 512M or 2G", s)),
+    }
+}
+
+fn validate_scale(s: String) -> ::std::result::Result<(), String> {
+    match s.parse::<f64>() {
+        Ok(value) if value > 0.0 && value <= 1.0 => Ok(()),
+        Ok(_) => Err(format!("'{}' must be greater than 0 and at most 1", s)),
+        Err(e) => Err(format!("'{}' is not a valid number: {}", s, e)),
+    }
+}
+
+/// Where a subcommand reports its progress: a bar on the terminal, or nowhere if it is quiet.
+fn progress(matches: &ArgMatches) -> Box<dyn ProgressSink> {
+    if matches.is_present("quiet") {
+        Box::new(NoProgress)
+    } else {
+        Box::new(TerminalProgress::new(None))
+    }
+}
+
+/// Reads which frames to use from either `--folder` or `--input`, one of which clap ensures is
+/// present.
+fn path_mode(matches: &ArgMatches) -> PathMode {
+    if let Some(path) = matches.value_of("folder") {
+        PathMode::Folder(path.into())
     } else if let Some(path) = matches.value_of("input") {
-        PathMode::FileMask(path)
+        PathMode::FileMask(path.into())
     } else {
         unreachable!();
-    };
+    }
+}
 
+fn run_contact_sheet(matches: &ArgMatches) -> Result<()> {
     let output = matches.value_of("output").unwrap();
+    let output = resolve_path(&mut output_paths(matches), output, |_| vec![], matches)?;
+    let paths = get_paths(&path_mode(matches)).chain_err(|| ErrorKind::CouldNotGetPaths)?;
+    let sheet = contact_sheet(&paths,
+                              &FrameTransforms::default(),
+                              matches.value_of("columns").unwrap().parse().unwrap(),
+                              matches.value_of("thumb-width").unwrap().parse().unwrap(),
+                              &mut *progress(matches))?;
+    save_image(&sheet, &output, None, None, None, &EncoderSettings::default())
+        .chain_err(|| ErrorKind::CouldNotSaveOutput(output.clone()))
+}
 
-    let paths = file_processing::get_paths(&path_mode).chain_err(|| ErrorKind::CouldNotGetPaths)?;
+/// Checks the input frames for anything that would stop them rendering cleanly, reporting every
+/// problem found at once.
+fn run_check(matches: &ArgMatches) -> Result<()> {
+    let path_mode = path_mode(matches);
+    let paths = get_paths(&path_mode).chain_err(|| ErrorKind::CouldNotGetPaths)?;
+
+    let mut problems = vec![];
+    if let PathMode::FileMask(ref filemask) = path_mode {
+        for gap in find_gaps(filemask)? {
+            problems.push(format!("Missing {} frame(s) from {} on; the {} frame(s) after them \
+                                   won't be rendered.",
+                                  gap.missing,
+                                  gap.first.display(),
+                                  gap.following));
+        }
+    }
+    match validate_paths(&paths, true) {
+        Ok(()) => (),
+        Err(Error(ErrorKind::InvalidFrames(invalid), _)) => problems.extend(invalid),
+        Err(e) => return Err(e),
+    }
+    if !problems.is_empty() {
+        bail!(ErrorKind::CheckFailed(problems));
+    }
+
+    if !matches.is_present("quiet") {
+        let (width, height) = read_dimensions(&paths[0])?;
+        println!("{} frames, all {}x{}, from {} to {}.",
+                 paths.len(),
+                 width,
+                 height,
+                 paths[0].display(),
+                 paths[paths.len() - 1].display());
+    }
+    Ok(())
+}
+
+fn run_generate(matches: &ArgMatches) -> Result<()> {
+    let pattern: Pattern = matches.value_of("pattern").unwrap().parse()?;
+    let frames: u32 = matches.value_of("frames").unwrap().parse().unwrap();
+    let out = matches.value_of("out").unwrap();
+    let dir = resolve_path(&mut output_paths(matches), out, |_| vec![], matches)?;
+    let mask = generate(pattern,
+                        frames,
+                        args::parse_size(matches.value_of("size").unwrap())?,
+                        &dir,
+                        &mut *progress(matches))?;
+    if !matches.is_present("quiet") {
+        eprintln!("Render them with: rolling-shutter {} -o out.png", mask.display());
+        if pattern == Pattern::Calibrate {
+            eprintln!("The lit stripe moves along by one every frame, and the line turns {:.3} \
+                       degrees per frame.",
+                      360.0 / frames as f64);
+        }
+    }
+    Ok(())
+}
+
+fn run_info(matches: &ArgMatches) -> Result<()> {
+    let size = args::parse_size(matches.value_of("size").unwrap())?;
+    print!("{}",
+           info::describe(size,
+                          matches.value_of("direction").unwrap().parse()?,
+                          matches.value_of("frames").map(|frames| frames.parse().unwrap()),
+                          matches.value_of("fps").map(|fps| fps.parse().unwrap())));
+    Ok(())
+}
+
+fn run_bench(matches: &ArgMatches) -> Result<()> {
+    let size = args::parse_size(matches.value_of("size").unwrap())?;
+    let rounds = matches.value_of("rounds").unwrap().parse().unwrap();
+    print!("{}", benchmark(size, rounds)?);
+    Ok(())
+}
+
+fn run_unroll(matches: &ArgMatches) -> Result<()> {
+    let output = matches.value_of("output").unwrap();
+    let output = resolve_path(&mut output_paths(matches), output, |_| vec![], matches)?;
+    let path = Path::new(matches.value_of("image").unwrap());
+    let image = image::open(path).chain_err(|| ErrorKind::CouldNotOpenImage(path.into()))?;
+    let rectified = rectify(&image.to_rgba(),
+                            matches.value_of("direction").unwrap().parse()?,
+                            matches.value_of("skew").unwrap().parse().unwrap());
+    save_image(&rectified, &output, None, None, None, &EncoderSettings::default())
+        .chain_err(|| ErrorKind::CouldNotSaveOutput(output.clone()))
+}
+
+/// Describes why a job's options couldn't be parsed. Only the first line of clap's error is kept,
+/// since its usage doesn't apply to a job.
+fn invalid_job(e: clap::Error) -> ErrorKind {
+    let reason = e.message.lines().next().unwrap().trim_start_matches("error: ");
+    ErrorKind::InvalidJob(reason.into())
+}
+
+/// Parses a batch job's options, filling in any it leaves out from the options shared by every
+/// job, and renders it.
+fn render_job(program: &OsStr,
+              job: Vec<(String, config::Value)>,
+              shared: Vec<(String, config::Value)>,
+              job_label: Option<&str>)
+              -> Result<()> {
+    let mut args = vec![program.to_os_string()];
+    for (key, value) in job {
+        args.extend(option_args(&key, &value)?);
+    }
+    let given = app(false).get_matches_from_safe(&args).map_err(invalid_job)?;
+    layer_args(&mut args, &given, vec![shared])?;
+    let matches = app(true).get_matches_from_safe(args).map_err(invalid_job)?;
+    render(&matches,
+           &RenderContext {
+               job_label,
+               ..RenderContext::default()
+           })
+}
+
+/// Renders a batch job, reporting when it starts and how it went. Returns whether it succeeded.
+fn run_job(program: &OsStr,
+           index: usize,
+           total: usize,
+           name: &str,
+           job: Vec<(String, config::Value)>,
+           shared: Vec<(String, config::Value)>,
+           labelled: bool)
+           -> bool {
+    eprintln!("[{}/{}] Rendering {}...", index + 1, total, name);
+    let start = Instant::now();
+    let job_label = if labelled { Some(name) } else { None };
+    match render_job(program, job, shared, job_label) {
+        Ok(()) => {
+            let elapsed = start.elapsed();
+            eprintln!("[{}/{}] Finished {} in {}.{:01}s.",
+                      index + 1,
+                      total,
+                      name,
+                      elapsed.as_secs(),
+                      elapsed.subsec_nanos() / 100_000_000);
+            true
+        }
+        Err(e) => {
+            eprintln!("[{}/{}] {} failed: {}", index + 1, total, name, e);
+            for cause in e.iter().skip(1) {
+                eprintln!("  caused by: {}", cause);
+            }
+            false
+        }
+    }
+}
+
+/// Renders every job in a batch file, reporting how each one went, with up to `--jobs` of them
+/// running at once. A job failing doesn't stop the rest, but an interrupt stops any more from
+/// starting.
+fn run_batch(matches: &ArgMatches) -> Result<()> {
+    let (shared, jobs) = config::load_jobs(matches.value_of("file").unwrap().as_ref())?;
+    let workers: usize = matches.value_of("jobs").unwrap().parse().unwrap();
+    let program = env::args_os().next().unwrap_or_default();
+
+    let total = jobs.len();
+    let mut named_jobs = vec![];
+    for (i, mut job) in jobs.into_iter().enumerate() {
+        let name = match job.iter().position(|entry| entry.0 == "name") {
+            Some(index) => {
+                match job.remove(index).1 {
+                    config::Value::String(name) => name,
+                    _ => {
+                        let reason = format!("the name of job {} isn't a string", i + 1);
+                        bail!(ErrorKind::InvalidConfig(reason));
+                    }
+                }
+            }
+            None => format!("job {}", i + 1),
+        };
+        named_jobs.push((name, job));
+    }
+
+    handle_interrupts()?;
+    // Each worker takes the next job that hasn't been started until there are none left.
+    let queue = Arc::new(Mutex::new(named_jobs.into_iter().enumerate()));
+    let labelled = workers > 1;
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            let (queue, program, shared) = (queue.clone(), program.clone(), shared.clone());
+            thread::spawn(move || {
+                let mut failed = 0;
+                while !interrupted() {
+                    let next = queue.lock().unwrap().next();
+                    let (i, (name, job)) = match next {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    if !run_job(&program, i, total, &name, job, shared.clone(), labelled) {
+                        failed += 1;
+                    }
+                }
+                failed
+            })
+        })
+        .collect();
+    let failed: usize = handles.into_iter().map(|handle| handle.join().unwrap()).sum();
+
+    let not_started = queue.lock().unwrap().len();
+    if not_started > 0 {
+        eprintln!("Stopped the batch with {} of {} jobs not started.", not_started, total);
+    }
+    if failed > 0 {
+        bail!(ErrorKind::BatchFailed(failed, total));
+    }
+    Ok(())
+}
+
+fn run() -> Result<()> {
+    let matches = parse_args()?;
+
+    match matches.subcommand() {
+        ("contact-sheet", Some(matches)) => return run_contact_sheet(matches),
+        ("unroll", Some(matches)) => return run_unroll(matches),
+        ("batch", Some(matches)) => return run_batch(matches),
+        ("check", Some(matches)) => return run_check(matches),
+        ("preview", Some(matches)) => return run_preview(matches),
+        ("generate", Some(matches)) => return run_generate(matches),
+        ("info", Some(matches)) => return run_info(matches),
+        ("bench", Some(matches)) => return run_bench(matches),
+        ("tui", Some(matches)) => return run_tui(matches),
+        _ => (),
+    }
+
+    if matches.is_present("list-presets") {
+        for name in config::list_presets()? {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+    if let Some(name) = matches.value_of("save-preset") {
+        let path = config::save_preset(name, &preset_entries(&matches))?;
+        if !matches.is_present("quiet") {
+            eprintln!("Saved preset {} to {}.", name, path.display());
+        }
+        return Ok(());
+    }
+
+    handle_interrupts()?;
+    render(&matches, &RenderContext::default())
+}
+
+/// Renders with the options given to the `preview` subcommand, showing the composite as it is
+/// built up.
+fn run_preview(matches: &ArgMatches) -> Result<()> {
+    let mut args = vec![env::args_os().next().unwrap()];
+    args.extend(matches.values_of_os("args").unwrap().map(OsStr::to_os_string));
+    let matches = parse_render_args(args)?;
+    if let Some(name) = matches.subcommand_name() {
+        bail!(ErrorKind::InvalidPreview(format!("'{}' can't be previewed", name)));
+    }
+    if matches.is_present("list-presets") || matches.is_present("save-preset") {
+        bail!(ErrorKind::InvalidPreview("presets can't be listed or saved from a preview".into()));
+    }
+
+    handle_interrupts()?;
+    render(&matches,
+           &RenderContext {
+               preview: true,
+               ..RenderContext::default()
+           })
+}
+
+/// Opens the terminal UI, rendering each job queued from it with the same options as the command
+/// line takes. The renders are quiet, since their progress is shown by the UI instead.
+fn run_tui(matches: &ArgMatches) -> Result<()> {
+    let program = env::args_os().next().unwrap_or_default();
+    tui::run(matches.value_of("input"),
+             matches.value_of("output"),
+             move |job_args, progress| {
+        let mut args = vec![program.clone()];
+        args.extend(job_args);
+        args.push("--quiet".into());
+        let matches = app(true).get_matches_from_safe(args).map_err(invalid_job)?;
+        render(&matches,
+               &RenderContext {
+                   progress: Some(progress),
+                   ..RenderContext::default()
+               })
+    })
+}
 
-    image_processing::process_images(paths.into_iter(),
-                                     &output,
-                                     direction,
-                                     matches.is_present("quiet"))?;
+/// How a render is run, beyond the options it was given.
+#[derive(Default)]
+struct RenderContext<'a> {
+    /// The label to print progress lines with instead of drawing a bar, when several renders run
+    /// at once.
+    job_label: Option<&'a str>,
+    /// Whether to show the composite in a window as it is built up.
+    preview: bool,
+    /// Where to report progress for another thread to show, if anywhere. Progress is reported even
+    /// if the render is quiet, since it is only shown by that thread.
+    progress: Option<SharedProgress>,
+}
+
+/// Renders the composite described by the parsed options, in the given context.
+fn render(matches: &ArgMatches, context: &RenderContext) -> Result<()> {
+    let direction: Direction = matches.value_of("direction").unwrap().parse()?;
+
+    let path_mode = path_mode(matches);
+
+    // Every file the render saves is checked before anything is rendered, so that none of them is
+    // refused once the work is done.
+    let mut output_paths = output_paths(matches);
+
+    let dither = match matches.value_of("dither-bands") {
+        Some(mode) => {
+            Some(Dither {
+                mode: mode.parse()?,
+                radius: matches.value_of("dither-radius").unwrap().parse().unwrap(),
+            })
+        }
+        None => None,
+    };
+
+    let deflicker = if matches.is_present("deflicker") {
+        Some(matches.value_of("deflicker-window").unwrap().parse().unwrap())
+    } else {
+        None
+    };
+    let white_balance = match matches.value_of("normalize-wb") {
+        Some(mode) => Some(mode.parse()?),
+        None => None,
+    };
+
+    let resize_policy: ResizePolicy = matches.value_of("resize-policy").unwrap().parse()?;
+
+    let rotate = match matches.value_of("rotate") {
+        Some(rotate) => Some(rotate.parse()?),
+        None => None,
+    };
+    let flip = match matches.value_of("flip") {
+        Some(flip) => Some(flip.parse()?),
+        None => None,
+    };
+
+    let transforms = FrameTransforms {
+        rotate,
+        flip,
+        crop: matches.value_of("crop").map(|s| args::parse_region(s).unwrap()),
+        scale: matches.value_of("preview-scale").map(|scale| scale.parse().unwrap()),
+    };
+
+    let output_size = if let Some(size) = matches.value_of("output-size") {
+        let (width, height) = args::parse_size(size).unwrap();
+        Some(OutputSize::Exact(width, height))
+    } else {
+        matches.value_of("scale").map(|s| OutputSize::Scale(s.parse().unwrap()))
+    };
+
+    let denoise = match matches.value_of("denoise") {
+        Some(filter) => {
+            Some(Denoise {
+                filter: filter.parse()?,
+                radius: matches.value_of("denoise-radius").unwrap().parse().unwrap(),
+            })
+        }
+        None => None,
+    };
+
+    let mut adjustments = Adjustments::default();
+    if let Some(brightness) = matches.value_of("brightness") {
+        adjustments.brightness = brightness.parse().unwrap();
+    }
+    if let Some(contrast) = matches.value_of("contrast") {
+        adjustments.contrast = contrast.parse().unwrap();
+    }
+    if let Some(gamma) = matches.value_of("gamma") {
+        adjustments.gamma = gamma.parse().unwrap();
+    }
+
+    let lut = match matches.value_of("lut") {
+        Some(path) => Some(Lut::open(path.as_ref())?),
+        None => None,
+    };
+
+    let overlay = match matches.value_of("overlay") {
+        Some(path) => {
+            let image = image::open(path)
+                .chain_err(|| ErrorKind::CouldNotOpenImage(path.into()))?
+                .to_rgba();
+            Some(Overlay {
+                image,
+                position: matches.value_of("overlay-pos").unwrap().parse()?,
+                margin: matches.value_of("overlay-margin").unwrap().parse().unwrap(),
+            })
+        }
+        None => None,
+    };
+
+    let animation_every = matches.value_of("animate-every").unwrap().parse().unwrap();
+    let webp_quality = matches.value_of("webp-quality").unwrap().parse().unwrap();
+    let animation = if let Some(path) = matches.value_of("animate") {
+        Some(AnimationSettings {
+            path: resolve_path(&mut output_paths, path, |_| vec![], matches)?,
+            format: AnimationFormat::from_path(path.as_ref())?,
+            webp_quality,
+            every: animation_every,
+        })
+    } else if let Some(path) = matches.value_of("animate-video") {
+        let codec = matches.value_of("video-codec").unwrap().parse()?;
+        Some(AnimationSettings {
+            path: resolve_path(&mut output_paths, path, |_| vec![], matches)?,
+            format: AnimationFormat::Video(codec),
+            webp_quality,
+            every: animation_every,
+        })
+    } else {
+        None
+    };
+
+    let metadata = if matches.is_present("no-metadata") {
+        None
+    } else {
+        let mut metadata = Metadata::default();
+        metadata.push("Software",
+                      format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")));
+        match path_mode {
+            PathMode::FileMask(ref mask) => metadata.push("Input", mask),
+            PathMode::Folder(ref folder) => metadata.push("Input", folder.display()),
+        }
+        let args: Vec<String> =
+            env::args_os().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        metadata.push("Command", args.join(" "));
+        Some(metadata)
+    };
+
+    let snapshots = match matches.value_of("snapshot-every") {
+        Some(every) => {
+            let dir = matches.value_of("snapshot-dir").unwrap();
+            Some(SnapshotSettings {
+                dir: resolve_path(&mut output_paths, dir, |_| vec![], matches)?,
+                every: every.parse().unwrap(),
+            })
+        }
+        None => None,
+    };
+    let export_bands = match matches.value_of("export-bands") {
+        Some(dir) => Some(resolve_path(&mut output_paths, dir, |_| vec![], matches)?),
+        None => None,
+    };
+    let time_map = match matches.value_of("dump-time-map") {
+        Some(path) => Some(resolve_path(&mut output_paths, path, |_| vec![], matches)?),
+        None => None,
+    };
+    let report_path = match matches.value_of("report") {
+        Some(path) => Some(resolve_path(&mut output_paths, path, |_| vec![], matches)?),
+        None => None,
+    };
+
+    let output_format = match matches.value_of("output-format") {
+        Some(format) => Some(format.parse()?),
+        None => None,
+    };
+
+    let resume = match matches.value_of("resume") {
+        Some(path) => Some(Checkpoint::load(path.as_ref())?),
+        None => None,
+    };
+
+    let mut options = ShutterConfig::new()
+        .direction(direction)
+        .dither(dither)
+        .seed(Seed(matches.value_of("seed").unwrap().parse().unwrap()))
+        .preblend(matches.value_of("preblend").map_or(1, |k| k.parse().unwrap()))
+        .threads(matches.value_of("threads").map_or(0, |n| n.parse().unwrap()))
+        .max_memory(matches.value_of("max-memory").map(|size| parse_size(size).unwrap()))
+        .gpu(matches.is_present("gpu"))
+        .stabilize(matches.is_present("stabilize"))
+        .autocrop(matches.is_present("autocrop"))
+        .deflicker(deflicker)
+        .white_balance(white_balance)
+        .denoise(denoise)
+        .adjustments(adjustments)
+        .lut(lut)
+        .lut_target(matches.value_of("lut-target").unwrap().parse()?)
+        .resize_policy(resize_policy)
+        .transforms(transforms)
+        .output_size(output_size)
+        .output_filter(args::parse_filter(matches.value_of("filter").unwrap())?)
+        .sharpen(matches.value_of("sharpen").map(|amount| {
+            (amount.parse().unwrap(), matches.value_of("sharpen-radius").unwrap().parse().unwrap())
+        }))
+        .overlay(overlay)
+        .output_format(output_format)
+        .encoder(EncoderSettings {
+            jpeg_quality: matches.value_of("jpeg-quality").unwrap().parse().unwrap(),
+            png_compression: matches.value_of("png-compression").unwrap().parse()?,
+        })
+        .bit_depth(matches.value_of("bit-depth").unwrap().parse()?)
+        .linear_blend(matches.is_present("linear-blend"))
+        .preserve_icc(!matches.is_present("no-icc"))
+        .metadata(metadata)
+        .animation(animation)
+        .snapshots(snapshots)
+        .tile_rows(matches.value_of("tile-rows").map(|rows| rows.parse().unwrap()))
+        .autosave_every(matches.value_of("autosave-every").map(|every| every.parse().unwrap()))
+        .debug_overlay(matches.is_present("debug-overlay"))
+        .compare_with(matches.value_of("compare-with").map(Into::into))
+        .export_bands(export_bands)
+        .time_map(time_map)
+        .resume(resume)
+        .suppress_output(matches.is_present("quiet"))
+        .auto_stretch(matches.is_present("auto-stretch"))
+        .job_label(context.job_label.map(Into::into))
+        .preview(context.preview)
+        .build();
+
+    let mut outputs = vec![];
+    for (i, output) in matches.values_of("output").unwrap().enumerate() {
+        // The debug overlay and the comparison are saved next to the first output, named after it.
+        let siblings = |output: &Path| if i == 0 {
+            options.sibling_outputs(output)
+        } else {
+            vec![]
+        };
+        outputs.push(resolve_path(&mut output_paths, output, siblings, matches)?);
+    }
+
+    set_open_file_limit(matches.value_of("max-open-files").unwrap().parse().unwrap());
+
+    let discovery_start = Instant::now();
+    let mut paths = get_paths(&path_mode).chain_err(|| ErrorKind::CouldNotGetPaths)?;
+    // Frames after the last one a band can be taken from are never read, so they aren't checked
+    // either. A first frame that can't be read is left for the checks to report.
+    let found = paths.len();
+    let usable = match read_dimensions(&paths[0]) {
+        Ok(dimensions) => options.usable_frames(found, dimensions),
+        Err(_) => found,
+    };
+    let excess = paths.split_off(usable);
+    if !excess.is_empty() && !matches.is_present("quiet") {
+        eprintln!("Only the first {} of the {} frames found are needed to fill the image; skipping \
+                   the other {}.",
+                  usable,
+                  found,
+                  excess.len());
+    }
+    let mut timings = vec![("discovery", discovery_start.elapsed())];
+
+    if !matches.is_present("no-validate") {
+        let validation_start = Instant::now();
+        options.frame_layout = validate_layout(&paths, resize_policy == ResizePolicy::Error)?;
+        timings.push(("validation", validation_start.elapsed()));
+    }
+
+    let mut shared = context.progress.clone();
+    let mut terminal = TerminalProgress::new(context.job_label);
+    let mut hidden = NoProgress;
+    let progress: &mut dyn ProgressSink = match shared {
+        Some(ref mut shared) => shared,
+        None if matches.is_present("quiet") || matches.is_present("no-progress") => &mut hidden,
+        None => &mut terminal,
+    };
+    let mut report = process_images(paths.into_iter(),
+                                    &outputs,
+                                    &options,
+                                    &mut [],
+                                    &mut [],
+                                    progress)?;
+
+    // The render is complete, so the checkpoint it was resumed from is no longer needed.
+    if let (Some(path), Some(checkpoint)) = (matches.value_of("resume"), options.resume.as_ref()) {
+        let _ = fs::remove_file(&checkpoint.partial);
+        let _ = fs::remove_file(path);
+    }
+
+    if let Some(path) = report_path {
+        report.frames_discovered = found;
+        report.skipped.extend(excess);
+        timings.extend(report.timings);
+        report.timings = timings;
+        report.save(&path)?;
+    }
 
     Ok(())
 }
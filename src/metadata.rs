@@ -0,0 +1,135 @@
+use ::png_writer::{self, PNG_SIGNATURE};
+
+/// The most text a single JPEG COM segment can hold, after its length.
+const JPEG_COMMENT_SIZE: usize = 65535 - 2;
+
+/// Describes how an output was rendered, as a list of keys and values, so that a result can be
+/// reproduced from the image alone.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Metadata {
+    entries: Vec<(String, String)>,
+}
+
+impl Metadata {
+    /// Adds an entry. Keys are written as PNG keywords, so they must be short, printable ASCII.
+    pub fn push<V: ToString>(&mut self, key: &str, value: V) {
+        debug_assert!(!key.is_empty() && key.len() < 80 &&
+                      key.bytes().all(|b| (0x20..0x7f).contains(&b)));
+        self.entries.push((key.to_string(), value.to_string()));
+    }
+
+    /// Formats every entry as a `key: value` line, for formats that only hold a single comment.
+    pub fn to_text(&self) -> String {
+        self.entries
+            .iter()
+            .map(|(key, value)| format!("{}: {}\n", key, value))
+            .collect()
+    }
+
+    /// Builds a PNG chunk for every entry. Values are stored in `tEXt` chunks when they are ASCII,
+    /// and in uncompressed `iTXt` chunks otherwise, since `tEXt` is limited to Latin-1.
+    pub fn png_chunks(&self) -> Vec<u8> {
+        let mut out = vec![];
+        for (key, value) in &self.entries {
+            let mut body = key.as_bytes().to_vec();
+            body.push(0);
+            if value.is_ascii() {
+                body.extend_from_slice(value.as_bytes());
+                out.extend(png_writer::chunk(b"tEXt", &body));
+            } else {
+                // No compression, and an empty language tag and translated keyword.
+                body.extend_from_slice(&[0, 0, 0, 0]);
+                body.extend_from_slice(value.as_bytes());
+                out.extend(png_writer::chunk(b"iTXt", &body));
+            }
+        }
+        out
+    }
+}
+
+/// Inserts metadata into encoded PNG data, right after the `IHDR` chunk.
+pub fn embed_png(data: &[u8], metadata: &Metadata) -> Vec<u8> {
+    // The signature is followed by the IHDR chunk: length, type, 13 bytes of data, and a CRC.
+    let ihdr_end = PNG_SIGNATURE.len() + 8 + 13 + 4;
+    let chunks = metadata.png_chunks();
+    let mut out = Vec::with_capacity(data.len() + chunks.len());
+    out.extend_from_slice(&data[..ihdr_end]);
+    out.extend_from_slice(&chunks);
+    out.extend_from_slice(&data[ihdr_end..]);
+    out
+}
+
+/// Inserts metadata into encoded JPEG data as a COM segment, after the start of image marker and
+/// any APP0 segment. Text that doesn't fit in a single segment is cut off.
+pub fn embed_jpeg(data: &[u8], metadata: &Metadata) -> Vec<u8> {
+    let mut insert_at = 2;
+    if data.len() >= 6 && data[2] == 0xff && data[3] == 0xe0 {
+        insert_at += 2 + (((data[4] as usize) << 8) | data[5] as usize);
+    }
+
+    let text = metadata.to_text();
+    let text = &text.as_bytes()[..::std::cmp::min(text.len(), JPEG_COMMENT_SIZE)];
+    let length = 2 + text.len();
+
+    let mut out = Vec::with_capacity(data.len() + 2 + length);
+    out.extend_from_slice(&data[..insert_at]);
+    out.extend_from_slice(&[0xff, 0xfe, (length >> 8) as u8, length as u8]);
+    out.extend_from_slice(text);
+    out.extend_from_slice(&data[insert_at..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ::png_writer::read_u32;
+
+    fn metadata() -> Metadata {
+        let mut metadata = Metadata::default();
+        metadata.push("Direction", "N");
+        metadata.push("Frames", 480);
+        metadata.push("Input", "café/*.jpg");
+        metadata
+    }
+
+    #[test]
+    fn test_to_text() {
+        assert_eq!(metadata().to_text(), "Direction: N\nFrames: 480\nInput: café/*.jpg\n");
+    }
+
+    #[test]
+    fn test_embed_png() {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(png_writer::ihdr_chunk(1, 1, 8));
+        png.extend(png_writer::chunk(b"IEND", &[]));
+
+        let embedded = embed_png(&png, &metadata());
+        assert!(embedded.ends_with(&png[PNG_SIGNATURE.len() + 25..]));
+        let mut offset = PNG_SIGNATURE.len() + 25;
+        let mut chunks = vec![];
+        while offset < embedded.len() {
+            let length = read_u32(&embedded[offset..]) as usize;
+            chunks.push((embedded[offset + 4..offset + 8].to_vec(),
+                         embedded[offset + 8..offset + 8 + length].to_vec()));
+            offset += length + 12;
+        }
+        assert_eq!(chunks[0], (b"tEXt".to_vec(), b"Direction\0N".to_vec()));
+        assert_eq!(chunks[1], (b"tEXt".to_vec(), b"Frames\x00480".to_vec()));
+        assert_eq!(chunks[2].0, b"iTXt".to_vec());
+        assert!(chunks[2].1.ends_with("Input\0\0\0\0\0café/*.jpg".as_bytes()));
+    }
+
+    #[test]
+    fn test_embed_jpeg() {
+        // SOI, an empty APP0 segment, and then start of scan.
+        let jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x02, 0xff, 0xda, 0x00, 0x02];
+        let embedded = embed_jpeg(&jpeg, &metadata());
+        assert_eq!(&embedded[..6], &jpeg[..6]);
+        assert_eq!(&embedded[6..8], &[0xff, 0xfe]);
+        let length = ((embedded[8] as usize) << 8) | embedded[9] as usize;
+        assert_eq!(&embedded[10..8 + length], metadata().to_text().as_bytes());
+        assert_eq!(&embedded[8 + length..], &jpeg[6..]);
+    }
+}
@@ -0,0 +1,112 @@
+use image::RgbaImage;
+
+use ::errors::Result;
+use ::frame_info::FrameInfo;
+use ::progress::ProgressSink;
+use ::report::Report;
+
+/// What an observer asks the render to do once it has seen the composite.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Flow {
+    /// Carry on rendering.
+    Continue,
+    /// Stop after this band, leaving the rest of the composite empty, and finish it as it is.
+    Stop,
+}
+
+/// Something that follows a render as it goes: the frames being decoded, the bands being copied
+/// into the composite, and the render finishing. The animation recorder, the preview window and
+/// the progress bar all follow renders this way, and so can anything else a caller provides.
+///
+/// Bands are counted from 0 among the bands the render copies, out of `total`; a resumed render
+/// doesn't count the bands copied before it was interrupted. Every callback does nothing by
+/// default, so observers only implement the ones they need.
+pub trait RenderObserver {
+    /// Called once a frame has been decoded, with the band it is for. Bands blended from several
+    /// frames see this once for each of them.
+    ///
+    /// # Errors
+    /// An error stops the render.
+    fn frame_decoded(&mut self, _index: u64, _total: u64, _frame: &FrameInfo) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once a band has been copied into the composite, with where its first frame came
+    /// from.
+    ///
+    /// # Errors
+    /// An error stops the render.
+    fn band_written(&mut self, _index: u64, _total: u64, _frame: &FrameInfo) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called with the composite as it stands after each band is copied. Renders written straight
+    /// to their output band by band never hold the composite, and renders composited on the GPU
+    /// only read it back at the end, so neither calls this.
+    ///
+    /// # Errors
+    /// An error stops the render, such as when the composite couldn't be recorded.
+    fn snapshot_ready(&mut self, _composite: &RgbaImage) -> Result<Flow> {
+        Ok(Flow::Continue)
+    }
+
+    /// Called once the render has finished and its outputs have been saved, with its report.
+    ///
+    /// # Errors
+    /// An error fails the render, though its outputs have already been saved.
+    fn render_finished(&mut self, _report: &Report) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Follows a render for a `ProgressSink`, reporting each band under `phase` as its frames are
+/// decoded and once it is copied, and ending the phase once the render has finished.
+pub struct ProgressObserver<'a> {
+    phase: &'static str,
+    sink: &'a mut dyn ProgressSink,
+}
+
+impl<'a> ProgressObserver<'a> {
+    /// Creates an observer reporting to `sink` under `phase`.
+    pub fn new(phase: &'static str, sink: &'a mut dyn ProgressSink) -> ProgressObserver<'a> {
+        ProgressObserver { phase, sink }
+    }
+}
+
+impl<'a> RenderObserver for ProgressObserver<'a> {
+    fn frame_decoded(&mut self, index: u64, total: u64, frame: &FrameInfo) -> Result<()> {
+        self.sink.start(self.phase, index, total, frame);
+        Ok(())
+    }
+
+    fn band_written(&mut self, index: u64, total: u64, _frame: &FrameInfo) -> Result<()> {
+        self.sink.done(self.phase, index + 1, total);
+        Ok(())
+    }
+
+    fn render_finished(&mut self, _report: &Report) -> Result<()> {
+        self.sink.finish(self.phase);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_observer() {
+        let mut calls = vec![];
+        {
+            let mut sink =
+                |phase: &'static str, done: u64, total: u64| calls.push((phase, done, total));
+            let mut observer = ProgressObserver::new("Testing:", &mut sink);
+            let frame = FrameInfo::unnamed(4);
+            observer.frame_decoded(0, 2, &frame).unwrap();
+            observer.band_written(0, 2, &frame).unwrap();
+            observer.band_written(1, 2, &frame).unwrap();
+            assert_eq!(observer.snapshot_ready(&RgbaImage::new(1, 1)).unwrap(), Flow::Continue);
+        }
+        assert_eq!(calls, vec![("Testing:", 1, 2), ("Testing:", 2, 2)]);
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rolling_shutter::{ErrorKind, Result};
+
+/// What to do when an output file already exists.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OverwritePolicy {
+    /// Stop before rendering anything.
+    Refuse,
+    /// Replace the existing file.
+    Force,
+    /// Save to a new name instead, made by adding a number to the original.
+    Suffix,
+}
+
+/// Adds a number to a path's file stem, e.g. `out-2.png` for `out.png`.
+fn numbered_path(path: &Path, number: u32) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, number),
+    };
+    path.with_file_name(name)
+}
+
+/// Whether saving to `path` would replace something: a file, or a directory with anything in it.
+/// An empty directory has nothing in it to lose.
+fn occupied(path: &Path) -> bool {
+    match fs::read_dir(path) {
+        Ok(mut entries) => entries.next().is_some(),
+        Err(_) => path.exists(),
+    }
+}
+
+/// Decides where each file a run saves goes, so that an existing file is only replaced when that
+/// was asked for. Every file the tool writes goes through the same one, including directories of
+/// numbered files, which count as existing unless they are empty. It remembers the new names it
+/// picked, so that two files asked for under the same name aren't both given the same new one.
+#[derive(Clone, Debug)]
+pub struct OutputPaths {
+    policy: OverwritePolicy,
+    renamed: Vec<PathBuf>,
+}
+
+impl OutputPaths {
+    /// Resolves paths with the given policy.
+    pub fn new(policy: OverwritePolicy) -> OutputPaths {
+        OutputPaths {
+            policy,
+            renamed: vec![],
+        }
+    }
+
+    /// Decides where the file asked for at `path` is saved, along with any others saved next to it
+    /// and named after it, such as `out.compare.png` next to `out.png`: `siblings` gives their
+    /// paths from the file's, and the file and its siblings are only saved under a name none of
+    /// them are taken by. Standard output is always used as is.
+    ///
+    /// # Errors
+    /// This fails if the file or any of its siblings exists and the policy is
+    /// `OverwritePolicy::Refuse`.
+    pub fn resolve<F>(&mut self, path: &Path, siblings: F) -> Result<PathBuf>
+        where F: Fn(&Path) -> Vec<PathBuf>
+    {
+        if path == Path::new("-") {
+            return Ok(path.to_path_buf());
+        }
+        let taken = |paths: &OutputPaths, path: &Path| {
+            Some(path.to_path_buf()).into_iter().chain(siblings(path)).find(|path| {
+                occupied(path) || paths.renamed.contains(path)
+            })
+        };
+        let existing = match taken(self, path) {
+            Some(existing) => existing,
+            None => return Ok(path.to_path_buf()),
+        };
+        match self.policy {
+            OverwritePolicy::Refuse => bail!(ErrorKind::OutputExists(existing)),
+            OverwritePolicy::Force => Ok(path.to_path_buf()),
+            OverwritePolicy::Suffix => {
+                let renamed = (1..)
+                    .map(|number| numbered_path(path, number))
+                    .find(|path| taken(self, path).is_none())
+                    .unwrap();
+                self.renamed.push(renamed.clone());
+                self.renamed.extend(siblings(&renamed));
+                Ok(renamed)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbered_path() {
+        assert_eq!(numbered_path(Path::new("renders/out.png"), 2),
+                   PathBuf::from("renders/out-2.png"));
+        assert_eq!(numbered_path(Path::new("out"), 1), PathBuf::from("out-1"));
+    }
+
+    #[test]
+    fn test_output_paths() {
+        let existing = Path::new(file!());
+        let none = |_: &Path| vec![];
+        assert!(OutputPaths::new(OverwritePolicy::Refuse).resolve(existing, none).is_err());
+        assert_eq!(OutputPaths::new(OverwritePolicy::Force).resolve(existing, none).unwrap(),
+                   existing);
+        // The same name asked for twice gets a new one each time.
+        let mut paths = OutputPaths::new(OverwritePolicy::Suffix);
+        assert_eq!(paths.resolve(existing, none).unwrap(), numbered_path(existing, 1));
+        assert_eq!(paths.resolve(existing, none).unwrap(), numbered_path(existing, 2));
+
+        // A file is refused, or renamed, for a sibling that exists too.
+        let missing = Path::new("src/missing.rs");
+        let sibling = |_: &Path| vec![existing.to_path_buf()];
+        assert!(OutputPaths::new(OverwritePolicy::Refuse).resolve(missing, sibling).is_err());
+        let sibling = |path: &Path| vec![path.with_extension("rs")];
+        let mut paths = OutputPaths::new(OverwritePolicy::Suffix);
+        assert_eq!(paths.resolve(Path::new("src/lib"), sibling).unwrap(),
+                   Path::new("src/lib-1"));
+        // Directories only count once something is in them.
+        let src = Path::new("src");
+        assert!(OutputPaths::new(OverwritePolicy::Refuse).resolve(src, none).is_err());
+
+        for &policy in &[OverwritePolicy::Refuse, OverwritePolicy::Suffix] {
+            assert_eq!(OutputPaths::new(policy).resolve(Path::new("-"), none).unwrap(),
+                       Path::new("-"));
+        }
+    }
+}
@@ -0,0 +1,651 @@
+use deflate::Compression;
+use image::{self, ColorType, FilterType, GenericImage, ImageBuffer, RgbaImage};
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use ::color_processing::{self, Channel, RgbaBuffer};
+use ::errors::{Error, ErrorKind, Result};
+use ::frame_processing::Region;
+use ::icc;
+use ::metadata::{self, Metadata};
+use ::png_writer;
+use ::tiff_writer;
+
+/// The size the final composite should be rescaled to before it is saved.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OutputSize {
+    /// Rescale to exactly this width and height.
+    Exact(u32, u32),
+    /// Rescale both dimensions by this factor.
+    Scale(f64),
+}
+
+impl OutputSize {
+    /// Computes the final dimensions of an image of the given size.
+    fn dimensions(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        match *self {
+            OutputSize::Exact(width, height) => (width, height),
+            OutputSize::Scale(factor) => {
+                (::std::cmp::max((width as f64 * factor).round() as u32, 1),
+                 ::std::cmp::max((height as f64 * factor).round() as u32, 1))
+            }
+        }
+    }
+}
+
+/// (De)serializes an `image` filter by the name it is given on the command line.
+#[cfg(feature = "serde")]
+pub(crate) mod serde_filter {
+    use image::FilterType;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use std::result::Result;
+
+    pub fn serialize<S: Serializer>(filter: &FilterType, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match *filter {
+            FilterType::Nearest => "nearest",
+            FilterType::Triangle => "bilinear",
+            FilterType::CatmullRom => "bicubic",
+            FilterType::Lanczos3 => "lanczos",
+            FilterType::Gaussian => "gaussian",
+        })
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FilterType, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        match name.as_str() {
+            "nearest" => Ok(FilterType::Nearest),
+            "bilinear" => Ok(FilterType::Triangle),
+            "bicubic" => Ok(FilterType::CatmullRom),
+            "lanczos" => Ok(FilterType::Lanczos3),
+            "gaussian" => Ok(FilterType::Gaussian),
+            _ => {
+                Err(de::Error::unknown_variant(&name,
+                                               &["nearest", "bilinear", "bicubic", "lanczos",
+                                                 "gaussian"]))
+            }
+        }
+    }
+}
+
+/// The number of bits per channel of the output image, and of the output stages run on the
+/// composite. At 16 bits, blended frames are composited at that depth too, unless a correction that
+/// only works at 8 bits follows the blend.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BitDepth {
+    /// 8 bits per channel.
+    Eight,
+    /// 16 bits per channel.
+    Sixteen,
+}
+
+impl FromStr for BitDepth {
+    type Err = Error;
+
+    /// Parses a bit depth from its number of bits per channel: `8` or `16`.
+    fn from_str(s: &str) -> Result<BitDepth> {
+        match s {
+            "8" => Ok(BitDepth::Eight),
+            "16" => Ok(BitDepth::Sixteen),
+            _ => bail!(ErrorKind::CouldNotParseOption("bit depth", s.into(), "8 or 16")),
+        }
+    }
+}
+
+/// How hard the PNG encoder tries to compress the output.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PngCompression {
+    /// Compresses quickly, but produces larger files.
+    Fast,
+    /// A balance between speed and size.
+    Default,
+    /// Produces the smallest files, but compresses slowly.
+    Best,
+}
+
+impl FromStr for PngCompression {
+    type Err = Error;
+
+    /// Parses a compression setting from its name: `fast`, `default` or `best`.
+    fn from_str(s: &str) -> Result<PngCompression> {
+        match s {
+            "fast" => Ok(PngCompression::Fast),
+            "default" => Ok(PngCompression::Default),
+            "best" => Ok(PngCompression::Best),
+            _ => {
+                let expected = "fast, default or best";
+                bail!(ErrorKind::CouldNotParseOption("PNG compression", s.into(), expected))
+            }
+        }
+    }
+}
+
+impl PngCompression {
+    /// The deflate level the `png` encoder uses for this setting.
+    pub(crate) fn deflate(&self) -> Compression {
+        match *self {
+            PngCompression::Fast => Compression::Fast,
+            PngCompression::Default => Compression::Default,
+            PngCompression::Best => Compression::Best,
+        }
+    }
+}
+
+/// Settings passed on to the image encoders.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EncoderSettings {
+    /// The quality of JPEG outputs, from 1 to 100.
+    pub jpeg_quality: u8,
+    /// How hard to compress PNG outputs.
+    pub png_compression: PngCompression,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> EncoderSettings {
+        EncoderSettings {
+            jpeg_quality: 90,
+            png_compression: PngCompression::Default,
+        }
+    }
+}
+
+/// Crops the final composite to the given region.
+pub fn crop<C: Channel>(mut buf: RgbaBuffer<C>, region: Region) -> RgbaBuffer<C> {
+    let mut cropped: RgbaBuffer<C> = ImageBuffer::new(region.width, region.height);
+    cropped.copy_from(&buf.sub_image(region.x, region.y, region.width, region.height),
+                      0,
+                      0);
+    cropped
+}
+
+/// Blurs the color channels of a `width` by `height` image with a Gaussian of standard deviation
+/// `sigma`, repeating the edge pixels past the borders.
+///
+/// `imageops::blur` doesn't normalize its kernel, so it shifts the brightness of even a flat
+/// image, which an unsharp mask would then take for detail.
+fn gaussian_blur(pixels: &[[f32; 3]], width: u32, height: u32, sigma: f32) -> Vec<[f32; 3]> {
+    if sigma <= 0.0 {
+        return pixels.to_vec();
+    }
+    let radius = (3.0 * sigma).ceil() as i64;
+    let kernel: Vec<f32> = (-radius..radius + 1)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = kernel.iter().sum();
+
+    let (width, height) = (width as i64, height as i64);
+    let pass = |source: &[[f32; 3]], (step_x, step_y): (i64, i64)| {
+        let mut blurred = Vec::with_capacity(source.len());
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0f32; 3];
+                for (i, weight) in kernel.iter().enumerate() {
+                    let offset = i as i64 - radius;
+                    let sx = (x + offset * step_x).max(0).min(width - 1);
+                    let sy = (y + offset * step_y).max(0).min(height - 1);
+                    let pixel = source[(sy * width + sx) as usize];
+                    for c in 0..3 {
+                        sum[c] += weight * pixel[c];
+                    }
+                }
+                blurred.push([sum[0] / total, sum[1] / total, sum[2] / total]);
+            }
+        }
+        blurred
+    };
+    pass(&pass(pixels, (1, 0)), (0, 1))
+}
+
+/// Sharpens the final composite with an unsharp mask.
+///
+/// # Arguments
+/// * `buf` - The composite.
+/// * `amount` - How strongly to sharpen; 1.0 doubles the difference from the blurred image.
+/// * `sigma` - The standard deviation of the blur used to find edges.
+/// * `linear` - Whether to blur and sharpen in linear light rather than on sRGB-encoded values.
+pub fn sharpen<C: Channel>(buf: &mut RgbaBuffer<C>,
+                           amount: f32,
+                           sigma: f32,
+                           linear: bool) {
+    let (width, height) = buf.dimensions();
+    let original: Vec<[f32; 3]> = if linear {
+        let decoded = color_processing::to_linear(buf);
+        decoded.pixels().map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect()
+    } else {
+        buf.pixels()
+            .map(|pixel| [pixel[0].to_unit(), pixel[1].to_unit(), pixel[2].to_unit()])
+            .collect()
+    };
+    let blurred = gaussian_blur(&original, width, height, sigma);
+    for ((pixel, original), blurred) in buf.pixels_mut().zip(&original).zip(&blurred) {
+        for c in 0..3 {
+            let value = original[c] + amount * (original[c] - blurred[c]);
+            pixel.data[c] = if linear {
+                color_processing::linear_to_srgb(value)
+            } else {
+                C::from_unit(value)
+            };
+        }
+    }
+}
+
+/// Rescales the final composite.
+///
+/// # Arguments
+/// * `buf` - The composite.
+/// * `size` - The size to rescale to.
+/// * `filter` - The filter to use when resampling.
+/// * `linear` - Whether to resample in linear light rather than on sRGB-encoded values.
+pub fn rescale<C: Channel>(buf: RgbaBuffer<C>,
+                           size: OutputSize,
+                           filter: FilterType,
+                           linear: bool)
+                           -> RgbaBuffer<C> {
+    let (width, height) = size.dimensions(buf.dimensions());
+    if (width, height) == buf.dimensions() {
+        return buf;
+    }
+    if linear {
+        let resized = image::imageops::resize(&color_processing::to_linear(&buf),
+                                              width,
+                                              height,
+                                              filter);
+        return color_processing::from_linear(&resized);
+    }
+    image::imageops::resize(&buf, width, height, filter)
+}
+
+/// Where an overlay is placed on the output.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OverlayPosition {
+    /// The top left corner.
+    TopLeft,
+    /// The top right corner.
+    TopRight,
+    /// The bottom left corner.
+    BottomLeft,
+    /// The bottom right corner.
+    BottomRight,
+    /// The middle of the image.
+    Center,
+}
+
+impl FromStr for OverlayPosition {
+    type Err = Error;
+
+    /// Parses an overlay position from its name, such as `top-left` or `center`.
+    fn from_str(s: &str) -> Result<OverlayPosition> {
+        match s {
+            "top-left" => Ok(OverlayPosition::TopLeft),
+            "top-right" => Ok(OverlayPosition::TopRight),
+            "bottom-left" => Ok(OverlayPosition::BottomLeft),
+            "bottom-right" => Ok(OverlayPosition::BottomRight),
+            "center" => Ok(OverlayPosition::Center),
+            _ => {
+                let expected = "top-left, top-right, bottom-left, bottom-right or center";
+                bail!(ErrorKind::CouldNotParseOption("overlay position", s.into(), expected))
+            }
+        }
+    }
+}
+
+/// An image composited on top of the output, such as a logo.
+#[derive(Clone, Debug)]
+pub struct Overlay {
+    /// The overlay image, whose alpha channel is respected.
+    pub image: RgbaImage,
+    /// Where the overlay is placed.
+    pub position: OverlayPosition,
+    /// The distance between the overlay and the edges of the output, for corner positions.
+    pub margin: u32,
+}
+
+impl Overlay {
+    /// Computes where the top left corner of the overlay goes on an image of the given size. The
+    /// result may be negative if the overlay is larger than the image.
+    fn origin(&self, (width, height): (u32, u32)) -> (i64, i64) {
+        let (ow, oh) = self.image.dimensions();
+        let margin = self.margin as i64;
+        let left = margin;
+        let top = margin;
+        let right = width as i64 - ow as i64 - margin;
+        let bottom = height as i64 - oh as i64 - margin;
+        match self.position {
+            OverlayPosition::TopLeft => (left, top),
+            OverlayPosition::TopRight => (right, top),
+            OverlayPosition::BottomLeft => (left, bottom),
+            OverlayPosition::BottomRight => (right, bottom),
+            OverlayPosition::Center => {
+                ((width as i64 - ow as i64) / 2, (height as i64 - oh as i64) / 2)
+            }
+        }
+    }
+
+    /// Composites the overlay onto an image with the "over" operator, mixing colors in linear
+    /// light if `linear` is set.
+    pub fn apply<C: Channel>(&self, buf: &mut RgbaBuffer<C>, linear: bool) {
+        let decode = |value: f32| if linear {
+            color_processing::decode_srgb(value)
+        } else {
+            value
+        };
+        let encode = |value: f32| if linear {
+            color_processing::encode_srgb(value)
+        } else {
+            value
+        };
+        let (width, height) = buf.dimensions();
+        let (x0, y0) = self.origin((width, height));
+        for (ox, oy, pixel) in self.image.enumerate_pixels() {
+            let x = x0 + ox as i64;
+            let y = y0 + oy as i64;
+            if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                continue;
+            }
+            let alpha = pixel[3].to_unit();
+            if alpha == 0.0 {
+                continue;
+            }
+            let base = buf.get_pixel_mut(x as u32, y as u32);
+            for c in 0..3 {
+                let value = decode(pixel[c].to_unit()) * alpha +
+                            decode(base[c].to_unit()) * (1.0 - alpha);
+                base.data[c] = C::from_unit(encode(value));
+            }
+            base.data[3] = C::from_unit(alpha + base[3].to_unit() * (1.0 - alpha));
+        }
+    }
+}
+
+/// The formats the output can be encoded in without going through a file.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OutputFormat {
+    /// PNG, which supports 8 and 16 bits per channel.
+    Png,
+    /// JPEG, which only supports 8 bits per channel.
+    Jpeg,
+    /// Binary PPM, which is easy for other tools to read from a pipe.
+    Ppm,
+    /// Uncompressed TIFF, which supports 8 and 16 bits per channel and is read by most photo
+    /// editors.
+    Tiff,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    /// Parses a format from its name or extension, such as `png`, `jpg` or `jpeg`.
+    fn from_str(s: &str) -> Result<OutputFormat> {
+        match s {
+            "png" => Ok(OutputFormat::Png),
+            "jpg" | "jpeg" => Ok(OutputFormat::Jpeg),
+            "ppm" => Ok(OutputFormat::Ppm),
+            "tif" | "tiff" => Ok(OutputFormat::Tiff),
+            _ => {
+                let expected = "png, jpg, ppm or tiff";
+                bail!(ErrorKind::CouldNotParseOption("output format", s.into(), expected))
+            }
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Picks the format from a path's extension, if it is one of these formats.
+    pub fn from_path(path: &Path) -> Option<OutputFormat> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| extension.to_lowercase().parse().ok())
+    }
+}
+
+/// Encodes the final composite in the given format, embedding an ICC profile and render metadata
+/// if they are given and the format can hold them. `path` is where the data will be written, for
+/// error messages.
+///
+/// # Errors
+/// This fails if the image cannot be encoded, or if the format does not support the image's bit
+/// depth.
+pub(crate) fn encode<C: Channel>(buf: &RgbaBuffer<C>,
+                                 path: &Path,
+                                 format: OutputFormat,
+                                 profile: Option<&[u8]>,
+                                 metadata: Option<&Metadata>,
+                                 settings: &EncoderSettings)
+                                 -> Result<Vec<u8>> {
+    let (width, height) = buf.dimensions();
+    let bytes = C::to_bytes(buf);
+    let rgb = || -> Vec<u8> { bytes.chunks(4).flat_map(|p| p[..3].iter().cloned()).collect() };
+
+    let mut data = vec![];
+    match format {
+        OutputFormat::Png => {
+            data = png_writer::encode(&bytes,
+                                      width,
+                                      height,
+                                      C::BIT_DEPTH,
+                                      settings.png_compression.deflate());
+        }
+        OutputFormat::Tiff => {
+            // TIFF has its own tags for the profile and metadata, so they're written by the
+            // encoder.
+            let description = metadata.map(Metadata::to_text);
+            return Ok(tiff_writer::encode(&bytes,
+                                          width,
+                                          height,
+                                          C::BIT_DEPTH,
+                                          profile,
+                                          description.as_deref()));
+        }
+        _ if C::BIT_DEPTH != 8 => {
+            bail!(ErrorKind::UnsupportedBitDepth(path.to_path_buf(), C::BIT_DEPTH))
+        }
+        OutputFormat::Jpeg => {
+            image::jpeg::JPEGEncoder::new_with_quality(&mut data, settings.jpeg_quality)
+                .encode(&rgb(), width, height, ColorType::RGB(8))?;
+        }
+        OutputFormat::Ppm => {
+            image::ppm::PPMEncoder::new(&mut data)
+                .encode(&rgb(), width, height, ColorType::RGB(8))?;
+        }
+    }
+
+    if let Some(profile) = profile {
+        if format != OutputFormat::Ppm {
+            data = icc::embed_profile(&data, profile)?;
+        }
+    }
+    if let Some(metadata) = metadata {
+        match format {
+            OutputFormat::Png => data = metadata::embed_png(&data, metadata),
+            OutputFormat::Jpeg => data = metadata::embed_jpeg(&data, metadata),
+            _ => (),
+        }
+    }
+    Ok(data)
+}
+
+/// Encodes the final composite in the given format and writes it to standard output.
+///
+/// # Errors
+/// This fails if the image cannot be encoded or written.
+pub fn write_stdout<C: Channel>(buf: &RgbaBuffer<C>,
+                                format: OutputFormat,
+                                profile: Option<&[u8]>,
+                                metadata: Option<&Metadata>,
+                                settings: &EncoderSettings)
+                                -> Result<()> {
+    let data = encode(buf, Path::new("-"), format, profile, metadata, settings)?;
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    stdout.write_all(&data)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Saves the final composite, embedding an ICC profile and render metadata if they are given and
+/// the output format can hold them. The format is taken from the path's extension, falling back to
+/// `format` if the extension isn't that of an `OutputFormat`.
+///
+/// 8-bit images can be saved in any format `image` supports, but 16-bit images can only be saved
+/// as PNG.
+///
+/// # Errors
+/// This fails if the image cannot be encoded or written, or if the output format does not support
+/// the image's bit depth.
+pub fn save<C: Channel>(buf: &RgbaBuffer<C>,
+                        path: &Path,
+                        format: Option<OutputFormat>,
+                        profile: Option<&[u8]>,
+                        metadata: Option<&Metadata>,
+                        settings: &EncoderSettings)
+                        -> Result<()> {
+    let data = match OutputFormat::from_path(path).or(format) {
+        Some(format) => encode(buf, path, format, profile, metadata, settings)?,
+        None if C::BIT_DEPTH == 8 => {
+            let (width, height) = buf.dimensions();
+            image::save_buffer(path, &C::to_bytes(buf), width, height, ColorType::RGBA(8))?;
+            return Ok(());
+        }
+        None => bail!(ErrorKind::UnsupportedBitDepth(path.to_path_buf(), C::BIT_DEPTH)),
+    };
+    File::create(path)?.write_all(&data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bit_depth() {
+        assert_eq!("16".parse::<BitDepth>().unwrap(), BitDepth::Sixteen);
+        match "32".parse::<BitDepth>() {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "32"),
+            result => assert!(false, "expected a parse error, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn test_parse_png_compression() {
+        assert_eq!("best".parse::<PngCompression>().unwrap(), PngCompression::Best);
+        match "9".parse::<PngCompression>() {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "9"),
+            result => assert!(false, "expected a parse error, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn test_sharpen() {
+        let mut flat: RgbaImage = ImageBuffer::from_pixel(8, 8, image::Rgba([100, 100, 100, 255]));
+        sharpen(&mut flat, 1.0, 1.0, false);
+        assert_eq!(flat.get_pixel(4, 4).data, [100, 100, 100, 255]);
+
+        let mut edge: RgbaImage = ImageBuffer::from_fn(8, 8, |x, _| {
+            let value = if x < 4 { 50 } else { 200 };
+            image::Rgba([value, value, value, 255])
+        });
+        sharpen(&mut edge, 1.0, 1.0, false);
+        assert!(edge.get_pixel(3, 4)[0] < 50);
+        assert!(edge.get_pixel(4, 4)[0] > 200);
+        assert_eq!(edge.get_pixel(3, 4)[3], 255);
+
+        let mut wide = color_processing::to_16_bit(&flat);
+        sharpen(&mut wide, 1.0, 1.0, false);
+        assert_eq!(wide.get_pixel(4, 4).data, [100 * 257, 100 * 257, 100 * 257, 65535]);
+    }
+
+    #[test]
+    fn test_overlay() {
+        let mut logo: RgbaImage = ImageBuffer::from_pixel(2, 2, image::Rgba([255, 255, 255, 255]));
+        logo.put_pixel(0, 0, image::Rgba([255, 255, 255, 0]));
+        logo.put_pixel(1, 0, image::Rgba([255, 255, 255, 128]));
+        let overlay = Overlay {
+            image: logo,
+            position: OverlayPosition::BottomRight,
+            margin: 1,
+        };
+        assert_eq!(overlay.origin((10, 8)), (7, 5));
+
+        let mut buf: RgbaImage = ImageBuffer::from_pixel(10, 8, image::Rgba([0, 0, 0, 255]));
+        let mut linear = buf.clone();
+        overlay.apply(&mut buf, false);
+        assert_eq!(buf.get_pixel(7, 5).data, [0, 0, 0, 255]);
+        assert_eq!(buf.get_pixel(8, 5).data, [128, 128, 128, 255]);
+        assert_eq!(buf.get_pixel(8, 6).data, [255, 255, 255, 255]);
+        assert_eq!(buf.get_pixel(9, 7).data, [0, 0, 0, 255]);
+
+        // Half-transparent white over black mixes to half intensity, which is brighter in sRGB.
+        overlay.apply(&mut linear, true);
+        assert_eq!(linear.get_pixel(8, 5).data, [188, 188, 188, 255]);
+        assert_eq!(linear.get_pixel(8, 6).data, [255, 255, 255, 255]);
+
+        let centered = Overlay {
+            position: OverlayPosition::Center,
+            ..overlay
+        };
+        assert_eq!(centered.origin((10, 8)), (4, 3));
+    }
+
+    #[test]
+    fn test_parse_overlay_position() {
+        assert_eq!("bottom-left".parse::<OverlayPosition>().unwrap(), OverlayPosition::BottomLeft);
+        match "middle".parse::<OverlayPosition>() {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "middle"),
+            result => assert!(false, "expected a parse error, got {:?}.", result),
+        }
+    }
+
+    #[test]
+    fn test_output_format() {
+        assert_eq!(OutputFormat::from_path(Path::new("out.PNG")), Some(OutputFormat::Png));
+        assert_eq!(OutputFormat::from_path(Path::new("out.jpeg")), Some(OutputFormat::Jpeg));
+        assert_eq!(OutputFormat::from_path(Path::new("out.ppm")), Some(OutputFormat::Ppm));
+        assert_eq!(OutputFormat::from_path(Path::new("out.tif")), Some(OutputFormat::Tiff));
+        assert_eq!(OutputFormat::from_path(Path::new("out.bmp")), None);
+        assert_eq!(OutputFormat::from_path(Path::new("-")), None);
+        match "bmp".parse::<OutputFormat>() {
+            Err(Error(ErrorKind::CouldNotParseOption(_, ref s, _), _)) => assert_eq!(s, "bmp"),
+            result => assert!(false, "expected a parse error, got {:?}.", result),
+        }
+
+        let buf: RgbaImage = ImageBuffer::from_pixel(2, 1, image::Rgba([1, 2, 3, 255]));
+        let path = Path::new("out");
+        let settings = EncoderSettings::default();
+        let ppm = encode(&buf, path, OutputFormat::Ppm, None, None, &settings).unwrap();
+        assert!(ppm.starts_with(b"P6"));
+        assert!(ppm.ends_with(&[1, 2, 3, 1, 2, 3]));
+        let png = encode(&buf, path, OutputFormat::Png, None, None, &settings).unwrap();
+        assert!(png.starts_with(&png_writer::PNG_SIGNATURE));
+        let decoded = image::load_from_memory(&png).unwrap().to_rgba();
+        assert_eq!(decoded.into_raw(), buf.clone().into_raw());
+
+        // Metadata doesn't get in the way of decoding.
+        let mut metadata = Metadata::default();
+        metadata.push("Direction", "N");
+        for &format in &[OutputFormat::Png, OutputFormat::Jpeg] {
+            let data = encode(&buf, path, format, None, Some(&metadata), &settings).unwrap();
+            assert!(image::load_from_memory(&data).is_ok());
+        }
+
+        let wide = color_processing::to_16_bit(&buf);
+        assert!(encode(&wide, path, OutputFormat::Png, None, None, &settings).is_ok());
+        assert!(encode(&wide, path, OutputFormat::Jpeg, None, None, &settings).is_err());
+        let tiff = encode(&wide, path, OutputFormat::Tiff, None, None, &settings).unwrap();
+        assert!(tiff.starts_with(b"MM\x00\x2a"));
+    }
+
+    #[test]
+    fn test_output_dimensions() {
+        assert_eq!(OutputSize::Exact(100, 50).dimensions((640, 480)), (100, 50));
+        assert_eq!(OutputSize::Scale(0.5).dimensions((640, 480)), (320, 240));
+        assert_eq!(OutputSize::Scale(0.0001).dimensions((640, 480)), (1, 1));
+    }
+}
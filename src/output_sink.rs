@@ -0,0 +1,108 @@
+use image::RgbaImage;
+
+use std::path::{Path, PathBuf};
+
+use ::errors::{ErrorKind, Result, ResultExt};
+use ::image_processing::FinishedComposite;
+use ::observer::RenderObserver;
+use ::report::ReportOutput;
+
+/// Somewhere the finished composite goes. Sinks follow the render as it goes too, so those that
+/// record the composite as it is built up, such as animations and snapshots, do so as
+/// `RenderObserver`s.
+///
+/// Outputs, animations and snapshots are all sinks, so a render can send its composite to any
+/// number of them, including ones the caller provides.
+pub trait OutputSink: RenderObserver {
+    /// Called once the composite is finished. Returns the output that was saved, if any, for the
+    /// report of the render.
+    ///
+    /// # Errors
+    /// This fails if the composite could not be saved.
+    fn finish(&mut self, composite: &FinishedComposite) -> Result<Option<ReportOutput>>;
+}
+
+/// Saves the finished composite to a file, in the format its extension or the options name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileOutput {
+    path: PathBuf,
+}
+
+impl FileOutput {
+    /// Creates a sink that saves to `path`.
+    pub fn new<P: Into<PathBuf>>(path: P) -> FileOutput {
+        FileOutput { path: path.into() }
+    }
+}
+
+impl RenderObserver for FileOutput {}
+
+impl OutputSink for FileOutput {
+    fn finish(&mut self, composite: &FinishedComposite) -> Result<Option<ReportOutput>> {
+        let dimensions = composite.save(&self.path)
+            .chain_err(|| ErrorKind::CouldNotSaveOutput(self.path.clone()))?;
+        Ok(Some(ReportOutput {
+            path: self.path.clone(),
+            dimensions,
+        }))
+    }
+}
+
+/// Writes the finished composite to standard output, in the format the options name, or as a PNG.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StdoutOutput;
+
+impl RenderObserver for StdoutOutput {}
+
+impl OutputSink for StdoutOutput {
+    fn finish(&mut self, composite: &FinishedComposite) -> Result<Option<ReportOutput>> {
+        let path = Path::new("-");
+        let dimensions = composite.save(path)
+            .chain_err(|| ErrorKind::CouldNotSaveOutput(path.to_path_buf()))?;
+        Ok(Some(ReportOutput {
+            path: path.to_path_buf(),
+            dimensions,
+        }))
+    }
+}
+
+/// Keeps the finished composite in memory, after the output stages, at 8 bits per channel.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryOutput {
+    image: Option<RgbaImage>,
+}
+
+impl MemoryOutput {
+    /// Creates a sink with nothing captured yet.
+    pub fn new() -> MemoryOutput {
+        MemoryOutput::default()
+    }
+
+    /// The captured composite, once the render has finished.
+    pub fn image(&self) -> Option<&RgbaImage> {
+        self.image.as_ref()
+    }
+
+    /// Takes the captured composite, once the render has finished.
+    pub fn into_image(self) -> Option<RgbaImage> {
+        self.image
+    }
+}
+
+impl RenderObserver for MemoryOutput {}
+
+impl OutputSink for MemoryOutput {
+    fn finish(&mut self, composite: &FinishedComposite) -> Result<Option<ReportOutput>> {
+        self.image = Some(composite.output());
+        Ok(None)
+    }
+}
+
+/// The sink an output path names: standard output for `-`, and a file otherwise.
+pub fn for_path(path: &Path) -> Box<dyn OutputSink> {
+    if path == Path::new("-") {
+        Box::new(StdoutOutput)
+    } else {
+        Box::new(FileOutput::new(path))
+    }
+}
@@ -0,0 +1,380 @@
+use deflate::{self, Compression};
+use deflate::write::ZlibEncoder;
+
+use std::io::{self, Write};
+
+/// How much compressed data goes into each `IDAT` chunk when streaming.
+const IDAT_SIZE: usize = 1 << 16;
+
+/// The signature every PNG file starts with.
+pub const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+/// Computes the CRC-32 of a byte sequence, as used by PNG chunks.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+pub fn read_u32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+pub fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8,
+                            value as u8]);
+}
+
+/// Builds a PNG chunk with its length and CRC.
+pub fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    write_u32(&mut chunk, data.len() as u32);
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    let crc = crc32(&chunk[4..]);
+    write_u32(&mut chunk, crc);
+    chunk
+}
+
+/// Builds the `IHDR` chunk for an RGBA image.
+pub fn ihdr_chunk(width: u32, height: u32, bit_depth: u8) -> Vec<u8> {
+    let mut data = vec![];
+    write_u32(&mut data, width);
+    write_u32(&mut data, height);
+    // RGBA, with the standard compression, filtering and no interlacing.
+    data.extend_from_slice(&[bit_depth, 6, 0, 0, 0]);
+    chunk(b"IHDR", &data)
+}
+
+/// The Paeth predictor, which picks whichever of the left, up, and upper left bytes is closest to
+/// `left + up - upper_left`.
+fn paeth(left: u8, up: u8, upper_left: u8) -> u8 {
+    let p = left as i16 + up as i16 - upper_left as i16;
+    let pa = (p - left as i16).abs();
+    let pb = (p - up as i16).abs();
+    let pc = (p - upper_left as i16).abs();
+    if pa <= pb && pa <= pc {
+        left
+    } else if pb <= pc {
+        up
+    } else {
+        upper_left
+    }
+}
+
+/// Applies one of the five PNG filter types to a scanline, given the scanline above it.
+fn filter_row(filter: u8, row: &[u8], previous: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    out.clear();
+    for i in 0..row.len() {
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let up = previous[i];
+        let upper_left = if i >= bpp { previous[i - bpp] } else { 0 };
+        let prediction = match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            4 => paeth(left, up, upper_left),
+            _ => unreachable!(),
+        };
+        out.push(row[i].wrapping_sub(prediction));
+    }
+}
+
+/// Filters a scanline with whichever filter gives the output with the smallest sum of absolute
+/// values, which usually compresses best. The filtered scanline is left in `best`, and `candidate`
+/// is scratch space; returns the filter type.
+fn filter_scanline(row: &[u8],
+                   previous: &[u8],
+                   bpp: usize,
+                   candidate: &mut Vec<u8>,
+                   best: &mut Vec<u8>)
+                   -> u8 {
+    let mut best_filter = 0;
+    let mut best_cost = u64::MAX;
+    for filter in 0..5 {
+        filter_row(filter, row, previous, bpp, candidate);
+        let cost = candidate.iter().map(|&b| (b as i8 as i64).unsigned_abs()).sum();
+        if cost < best_cost {
+            best_cost = cost;
+            best_filter = filter;
+            ::std::mem::swap(best, candidate);
+        }
+    }
+    best_filter
+}
+
+/// Filters every scanline of an image, each with the filter that suits it best.
+pub fn filter_scanlines(data: &[u8], row_len: usize, bpp: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / row_len);
+    let zeros = vec![0u8; row_len];
+    let mut candidate = Vec::with_capacity(row_len);
+    let mut best = Vec::with_capacity(row_len);
+    let mut previous: &[u8] = &zeros;
+    for row in data.chunks(row_len) {
+        out.push(filter_scanline(row, previous, bpp, &mut candidate, &mut best));
+        out.extend_from_slice(&best);
+        previous = row;
+    }
+    out
+}
+
+/// Encodes RGBA data, with big-endian samples if they are 16 bits, as a PNG.
+pub fn encode(data: &[u8],
+              width: u32,
+              height: u32,
+              bit_depth: u8,
+              compression: Compression)
+              -> Vec<u8> {
+    let bpp = 4 * bit_depth as usize / 8;
+    let filtered = filter_scanlines(data, width as usize * bpp, bpp);
+    let compressed = deflate::deflate_bytes_zlib_conf(&filtered, compression);
+
+    let mut out = PNG_SIGNATURE.to_vec();
+    out.extend(ihdr_chunk(width, height, bit_depth));
+    out.extend(chunk(b"IDAT", &compressed));
+    out.extend(chunk(b"IEND", &[]));
+    out
+}
+
+/// Collects compressed image data and writes it out as a series of `IDAT` chunks.
+struct IdatWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> IdatWriter<W> {
+    /// Writes out whatever data is left, followed by the `IEND` chunk.
+    fn finish(mut self) -> io::Result<W> {
+        if !self.buffer.is_empty() {
+            self.inner.write_all(&chunk(b"IDAT", &self.buffer))?;
+        }
+        self.inner.write_all(&chunk(b"IEND", &[]))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for IdatWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= IDAT_SIZE {
+            self.inner.write_all(&chunk(b"IDAT", &self.buffer))?;
+            self.buffer.clear();
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Encodes an RGBA PNG one scanline at a time, from the top down, so that the whole image never
+/// has to be held in memory.
+pub struct StreamingEncoder<W: Write> {
+    encoder: ZlibEncoder<IdatWriter<W>>,
+    height: u32,
+    rows_written: u32,
+    bpp: usize,
+    previous: Vec<u8>,
+    candidate: Vec<u8>,
+    best: Vec<u8>,
+}
+
+impl<W: Write> StreamingEncoder<W> {
+    /// Writes the signature and `IHDR` chunk, followed by `extra_chunks`, which must be complete
+    /// chunks that belong before the image data, such as a color profile.
+    ///
+    /// # Errors
+    /// This fails if the header cannot be written.
+    pub fn new(mut inner: W,
+               width: u32,
+               height: u32,
+               bit_depth: u8,
+               compression: Compression,
+               extra_chunks: &[u8])
+               -> io::Result<StreamingEncoder<W>> {
+        inner.write_all(&PNG_SIGNATURE)?;
+        inner.write_all(&ihdr_chunk(width, height, bit_depth))?;
+        inner.write_all(extra_chunks)?;
+
+        let bpp = 4 * bit_depth as usize / 8;
+        let row_len = width as usize * bpp;
+        let idat = IdatWriter {
+            inner,
+            buffer: Vec::with_capacity(IDAT_SIZE),
+        };
+        Ok(StreamingEncoder {
+            encoder: ZlibEncoder::new(idat, compression),
+            height,
+            rows_written: 0,
+            bpp,
+            previous: vec![0; row_len],
+            candidate: Vec::with_capacity(row_len),
+            best: Vec::with_capacity(row_len),
+        })
+    }
+
+    /// Filters and compresses the next scanline. Returns `false` without writing anything if the
+    /// image already has all of its rows.
+    ///
+    /// # Errors
+    /// This fails if the compressed data cannot be written.
+    pub fn write_row(&mut self, row: &[u8]) -> io::Result<bool> {
+        debug_assert_eq!(row.len(), self.previous.len());
+        if self.rows_written >= self.height {
+            return Ok(false);
+        }
+        let filter =
+            filter_scanline(row, &self.previous, self.bpp, &mut self.candidate, &mut self.best);
+        self.encoder.write_all(&[filter])?;
+        self.encoder.write_all(&self.best)?;
+        self.previous.clear();
+        self.previous.extend_from_slice(row);
+        self.rows_written += 1;
+        Ok(true)
+    }
+
+    /// Fills any rows that were never written with transparent black, and finishes the image.
+    ///
+    /// # Errors
+    /// This fails if the remaining data cannot be written.
+    pub fn finish(mut self) -> io::Result<W> {
+        let blank = vec![0; self.previous.len()];
+        while self.write_row(&blank)? {}
+        self.encoder.finish()?.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use inflate;
+
+    /// Reverses `filter_scanlines`.
+    fn unfilter(filtered: &[u8], row_len: usize, bpp: usize) -> Vec<u8> {
+        let mut out: Vec<u8> = vec![];
+        for (y, row) in filtered.chunks(row_len + 1).enumerate() {
+            let start = out.len();
+            for i in 0..row_len {
+                let left = if i >= bpp { out[start + i - bpp] } else { 0 };
+                let up = if y > 0 { out[start + i - row_len] } else { 0 };
+                let upper_left = if y > 0 && i >= bpp {
+                    out[start + i - row_len - bpp]
+                } else {
+                    0
+                };
+                let prediction = match row[0] {
+                    0 => 0,
+                    1 => left,
+                    2 => up,
+                    3 => ((left as u16 + up as u16) / 2) as u8,
+                    4 => paeth(left, up, upper_left),
+                    _ => panic!("invalid filter type {}", row[0]),
+                };
+                out.push(row[i + 1].wrapping_add(prediction));
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let (width, height) = (7, 5);
+        let data: Vec<u8> = (0..width * height * 4).map(|i| ((i * 37) % 251) as u8).collect();
+        let filtered = filter_scanlines(&data, width * 4, 4);
+        assert_eq!(filtered.len(), data.len() + height);
+        assert_eq!(unfilter(&filtered, width * 4, 4), data);
+
+        // A smooth gradient is predicted perfectly from the left.
+        let gradient: Vec<u8> = (0..64).map(|i| i as u8).collect();
+        let filtered = filter_scanlines(&gradient, 64, 1);
+        assert_eq!(filtered[0], 1);
+        assert!(filtered[2..].iter().all(|&b| b == 1));
+    }
+
+    #[test]
+    fn test_encode() {
+        let data: Vec<u8> = (0..3 * 2 * 8).map(|i| i as u8).collect();
+        let png = encode(&data, 3, 2, 16, Compression::Best);
+        assert!(png.starts_with(&PNG_SIGNATURE));
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(read_u32(&png[16..]), 3);
+        assert_eq!(read_u32(&png[20..]), 2);
+        assert_eq!(png[24], 16);
+
+        let idat_len = read_u32(&png[33..]) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let filtered = inflate::inflate_bytes_zlib(&png[41..41 + idat_len]).unwrap();
+        assert_eq!(unfilter(&filtered, 3 * 8, 8), data);
+        assert!(png.ends_with(&chunk(b"IEND", &[])));
+    }
+
+    #[test]
+    fn test_streaming_encoder() {
+        let (width, height) = (300, 200);
+        // Noise from a linear congruential generator, which doesn't compress well.
+        let mut state = 1u32;
+        let data: Vec<u8> = (0..width * height * 4)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let row_len = width * 4;
+        let extra = chunk(b"tEXt", b"Key\0value");
+
+        // Only write half of the rows, so that the rest are filled in.
+        let mut encoder =
+            StreamingEncoder::new(vec![], width as u32, height as u32, 8, Compression::Fast, &extra)
+                .unwrap();
+        for row in data.chunks(row_len).take(height / 2) {
+            assert!(encoder.write_row(row).unwrap());
+        }
+        let png = encoder.finish().unwrap();
+
+        assert!(png.starts_with(&PNG_SIGNATURE));
+        assert_eq!(&png[33..33 + extra.len()], &extra[..]);
+        let mut offset = 33 + extra.len();
+        let mut compressed = vec![];
+        let mut idat_chunks = 0;
+        while offset < png.len() {
+            let length = read_u32(&png[offset..]) as usize;
+            if &png[offset + 4..offset + 8] == b"IDAT" {
+                compressed.extend_from_slice(&png[offset + 8..offset + 8 + length]);
+                idat_chunks += 1;
+            }
+            offset += length + 12;
+        }
+        // The data is split across several chunks.
+        assert!(idat_chunks > 1);
+        assert!(png.ends_with(&chunk(b"IEND", &[])));
+
+        let filtered = inflate::inflate_bytes_zlib(&compressed).unwrap();
+        let mut expected = data[..row_len * height / 2].to_vec();
+        expected.resize(data.len(), 0);
+        assert_eq!(unfilter(&filtered, row_len, 4), expected);
+
+        // Once every row is written, more are refused.
+        let mut encoder = StreamingEncoder::new(vec![], 1, 1, 8, Compression::Fast, &[]).unwrap();
+        assert!(encoder.write_row(&[1, 2, 3, 4]).unwrap());
+        assert!(!encoder.write_row(&[1, 2, 3, 4]).unwrap());
+    }
+}
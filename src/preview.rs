@@ -0,0 +1,187 @@
+// Without the preview feature only the stand-in window below is built, so the helpers go unused.
+#![cfg_attr(not(feature = "preview"), allow(dead_code))]
+
+use image::{Rgba, RgbaImage};
+
+use std::time::Duration;
+
+use ::errors::{ErrorKind, Result};
+use ::observer::{Flow, RenderObserver};
+
+/// The largest window the preview opens. Composites bigger than this are shown scaled down.
+const MAX_SIZE: (u32, u32) = (1280, 800);
+
+/// How often the window is redrawn. Redrawing after every band would slow down the render far
+/// more than the preview is worth.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The color the bands that haven't been rendered yet are shown on.
+const BACKGROUND: [u8; 3] = [32, 32, 32];
+
+/// What the preview window asks the render to do next.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PreviewAction {
+    /// Carry on rendering.
+    Continue,
+    /// Stop and save the bands done so far as the output.
+    Save,
+    /// Stop without saving anything.
+    Abort,
+}
+
+/// The whole number a composite's dimensions are divided by so that it fits in the window.
+fn preview_scale((width, height): (u32, u32)) -> u32 {
+    let fit = |size: u32, max: u32| size.div_ceil(max);
+    ::std::cmp::max(1, ::std::cmp::max(fit(width, MAX_SIZE.0), fit(height, MAX_SIZE.1)))
+}
+
+/// Converts a composite pixel to the window's `0RGB` format, blending it over the background.
+fn preview_pixel(pixel: &Rgba<u8>) -> u32 {
+    let alpha = pixel[3] as u32;
+    let mut rgb = 0;
+    for (c, &background) in BACKGROUND.iter().enumerate() {
+        let blended = (pixel[c] as u32 * alpha + background as u32 * (255 - alpha)) / 255;
+        rgb = (rgb << 8) | blended;
+    }
+    rgb
+}
+
+/// Fills `buffer` with the composite, scaled down by `scale` by taking every `scale`th pixel.
+fn fill_buffer(composite: &RgbaImage, scale: u32, buffer: &mut Vec<u32>) {
+    let (width, height) = composite.dimensions();
+    buffer.clear();
+    for y in 0..height / scale {
+        for x in 0..width / scale {
+            buffer.push(preview_pixel(composite.get_pixel(x * scale, y * scale)));
+        }
+    }
+}
+
+#[cfg(feature = "preview")]
+mod window {
+    use image::RgbaImage;
+    use minifb::{Key, KeyRepeat, Window, WindowOptions};
+
+    use std::time::Instant;
+
+    use ::errors::{ErrorKind, Result, ResultExt};
+    use super::{PreviewAction, REDRAW_INTERVAL, fill_buffer, preview_scale};
+
+    /// A window showing the composite as it is built up. Escape or Q aborts the render, and S
+    /// stops it early and saves what has been done so far.
+    pub struct PreviewWindow {
+        window: Window,
+        scale: u32,
+        size: (usize, usize),
+        buffer: Vec<u32>,
+        last_redraw: Option<Instant>,
+    }
+
+    impl PreviewWindow {
+        /// Opens a window for a composite of the given dimensions.
+        pub fn open(dimensions: (u32, u32)) -> Result<PreviewWindow> {
+            let scale = preview_scale(dimensions);
+            let size = ((dimensions.0 / scale) as usize, (dimensions.1 / scale) as usize);
+            let title = "rolling-shutter preview (S: save now, Esc: abort)";
+            let window = Window::new(title, size.0, size.1, WindowOptions::default())
+                .chain_err(|| ErrorKind::CouldNotOpenPreview)?;
+            Ok(PreviewWindow {
+                window,
+                scale,
+                size,
+                buffer: Vec::with_capacity(size.0 * size.1),
+                last_redraw: None,
+            })
+        }
+
+        /// Shows the composite, if it is time to redraw, and checks what the user asked for.
+        pub fn show(&mut self, composite: &RgbaImage) -> Result<PreviewAction> {
+            let redraw = match self.last_redraw {
+                Some(last) => last.elapsed() >= REDRAW_INTERVAL,
+                None => true,
+            };
+            if redraw {
+                fill_buffer(composite, self.scale, &mut self.buffer);
+                self.window
+                    .update_with_buffer(&self.buffer, self.size.0, self.size.1)
+                    .chain_err(|| ErrorKind::CouldNotOpenPreview)?;
+                self.last_redraw = Some(Instant::now());
+            } else {
+                self.window.update();
+            }
+
+            if !self.window.is_open() || self.window.is_key_down(Key::Escape) ||
+               self.window.is_key_down(Key::Q) {
+                Ok(PreviewAction::Abort)
+            } else if self.window.is_key_pressed(Key::S, KeyRepeat::No) {
+                Ok(PreviewAction::Save)
+            } else {
+                Ok(PreviewAction::Continue)
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "preview"))]
+mod window {
+    use image::RgbaImage;
+
+    use ::errors::{ErrorKind, Result};
+    use super::PreviewAction;
+
+    /// Stands in for the preview window in builds without the preview feature.
+    pub struct PreviewWindow;
+
+    impl PreviewWindow {
+        pub fn open(_dimensions: (u32, u32)) -> Result<PreviewWindow> {
+            bail!(ErrorKind::PreviewUnavailable)
+        }
+
+        pub fn show(&mut self, _composite: &RgbaImage) -> Result<PreviewAction> {
+            Ok(PreviewAction::Continue)
+        }
+    }
+}
+
+pub use self::window::PreviewWindow;
+
+impl RenderObserver for PreviewWindow {
+    /// Shows the composite, stopping the render if the user asked to save it now and failing it
+    /// if they asked to abort.
+    fn snapshot_ready(&mut self, composite: &RgbaImage) -> Result<Flow> {
+        match self.show(composite)? {
+            PreviewAction::Continue => Ok(Flow::Continue),
+            // The rest of the output is left empty.
+            PreviewAction::Save => Ok(Flow::Stop),
+            PreviewAction::Abort => bail!(ErrorKind::PreviewAborted),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_scale() {
+        assert_eq!(preview_scale((640, 480)), 1);
+        assert_eq!(preview_scale((1280, 800)), 1);
+        assert_eq!(preview_scale((1920, 1080)), 2);
+        assert_eq!(preview_scale((3000, 4000)), 5);
+    }
+
+    #[test]
+    fn test_preview_pixel() {
+        assert_eq!(preview_pixel(&Rgba([255, 128, 0, 255])), 0xff8000);
+        assert_eq!(preview_pixel(&Rgba([255, 255, 255, 0])), 0x202020);
+    }
+
+    #[test]
+    fn test_fill_buffer() {
+        let mut composite = RgbaImage::new(4, 2);
+        composite.put_pixel(2, 0, Rgba([0, 0, 255, 255]));
+        let mut buffer = vec![];
+        fill_buffer(&composite, 2, &mut buffer);
+        assert_eq!(buffer, vec![0x202020, 0x0000ff]);
+    }
+}
@@ -0,0 +1,53 @@
+use ::frame_info::FrameInfo;
+
+/// Where a render reports its progress through the frames. `phase` names what is being done to
+/// them, fit to be shown, such as `"Processing frames:"`; `total` is how many frames that is.
+///
+/// Any `FnMut(phase, done, total)` closure is a sink, called as each frame is done.
+pub trait ProgressSink {
+    /// Called as work on a frame starts, with its index among the frames of the phase and where
+    /// it came from.
+    fn start(&mut self, _phase: &'static str, _index: u64, _total: u64, _frame: &FrameInfo) {}
+
+    /// Called once a frame is done, with how many of the frames of the phase are done so far.
+    fn done(&mut self, phase: &'static str, done: u64, total: u64);
+
+    /// Called once the phase is over, whether or not every frame was done.
+    fn finish(&mut self, _phase: &'static str) {}
+}
+
+impl<F> ProgressSink for F
+    where F: FnMut(&'static str, u64, u64)
+{
+    fn done(&mut self, phase: &'static str, done: u64, total: u64) {
+        self(phase, done, total)
+    }
+}
+
+/// A sink that ignores progress, for renders that shouldn't report any.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn done(&mut self, _phase: &'static str, _done: u64, _total: u64) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sinks() {
+        let frame = FrameInfo { path: "f001.png".into(), ..FrameInfo::unnamed(0) };
+        let mut calls = vec![];
+        {
+            let mut sink = |phase: &'static str, done: u64, total: u64| {
+                calls.push((phase, done, total))
+            };
+            sink.start("Processing frames:", 0, 2, &frame);
+            sink.done("Processing frames:", 1, 2);
+            sink.finish("Processing frames:");
+        }
+        assert_eq!(calls, vec![("Processing frames:", 1, 2)]);
+    }
+}
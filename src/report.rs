@@ -0,0 +1,170 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use ::errors::{ErrorKind, Result, ResultExt};
+use ::frame_info::FrameInfo;
+
+/// A saved output and its final dimensions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReportOutput {
+    /// Where the output was saved.
+    pub path: PathBuf,
+    /// Its width and height.
+    pub dimensions: (u32, u32),
+}
+
+/// A machine-readable summary of a render, for pipelines that need to check results without
+/// parsing the progress output.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    /// How many frames were found for the input.
+    pub frames_discovered: usize,
+    /// How many frames made it into the composite.
+    pub frames_used: usize,
+    /// Frames that were found but not used, because the composite had no lines left for them.
+    pub skipped: Vec<PathBuf>,
+    /// Where the frame each band rendered was taken from came from, in the order of the bands.
+    /// When preblending, that is the first frame of the band's group.
+    pub frames: Vec<FrameInfo>,
+    /// The dimensions of the input frames.
+    pub frame_dimensions: (u32, u32),
+    /// Every output that was saved, in the order they were given.
+    pub outputs: Vec<ReportOutput>,
+    /// How long each phase of the render took, in the order they ran.
+    pub timings: Vec<(&'static str, Duration)>,
+}
+
+/// Quotes and escapes a string for JSON.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_path(path: &Path) -> String {
+    json_string(&path.to_string_lossy())
+}
+
+fn json_dimensions(dimensions: (u32, u32)) -> String {
+    format!("{{\"width\": {}, \"height\": {}}}", dimensions.0, dimensions.1)
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+/// Serializes where a frame came from, with its modification time in seconds since the Unix epoch.
+fn json_frame(frame: &FrameInfo) -> String {
+    let modified = frame.modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or("null".to_string(), |since| format!("{:.3}", seconds(since)));
+    let taken = frame.taken.as_ref().map_or("null".to_string(), |taken| json_string(taken));
+    format!("{{\"index\": {}, \"path\": {}, \"modified\": {}, \"taken\": {}}}",
+            frame.index,
+            json_path(&frame.path),
+            modified,
+            taken)
+}
+
+impl Report {
+    /// Serializes the report as a JSON object.
+    pub fn to_json(&self) -> String {
+        let skipped: Vec<String> = self.skipped.iter().map(|path| json_path(path)).collect();
+        let frames: Vec<String> = self.frames.iter().map(json_frame).collect();
+        let outputs: Vec<String> = self.outputs
+            .iter()
+            .map(|output| {
+                format!("{{\"path\": {}, \"width\": {}, \"height\": {}}}",
+                        json_path(&output.path),
+                        output.dimensions.0,
+                        output.dimensions.1)
+            })
+            .collect();
+        let timings: Vec<String> = self.timings
+            .iter()
+            .map(|&(phase, duration)| format!("{}: {:.3}", json_string(phase), seconds(duration)))
+            .collect();
+
+        let fields = [format!("\"version\": {}", json_string(env!("CARGO_PKG_VERSION"))),
+                      format!("\"frames_discovered\": {}", self.frames_discovered),
+                      format!("\"frames_used\": {}", self.frames_used),
+                      format!("\"skipped_frames\": [{}]", skipped.join(", ")),
+                      format!("\"frames\": [{}]", frames.join(", ")),
+                      format!("\"frame_dimensions\": {}", json_dimensions(self.frame_dimensions)),
+                      format!("\"outputs\": [{}]", outputs.join(", ")),
+                      format!("\"timings\": {{{}}}", timings.join(", "))];
+        format!("{{\n  {}\n}}\n", fields.join(",\n  "))
+    }
+
+    /// Writes the report as JSON to the given path.
+    ///
+    /// # Errors
+    /// This fails if the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        File::create(path)
+            .and_then(|mut file| file.write_all(self.to_json().as_bytes()))
+            .chain_err(|| ErrorKind::CouldNotWriteReport(path.to_path_buf()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a \"b\"\\c\nd\u{1}"), "\"a \\\"b\\\"\\\\c\\nd\\u0001\"");
+    }
+
+    #[test]
+    fn test_to_json() {
+        let report = Report {
+            frames_discovered: 3,
+            frames_used: 2,
+            skipped: vec!["frames/003.png".into()],
+            frames: vec![FrameInfo {
+                             index: 0,
+                             path: "frames/000.png".into(),
+                             modified: Some(UNIX_EPOCH + Duration::from_millis(2500)),
+                             taken: Some("2024:05:06 07:08:09".into()),
+                         },
+                         FrameInfo::unnamed(1)],
+            frame_dimensions: (4, 2),
+            outputs: vec![ReportOutput {
+                              path: "o.png".into(),
+                              dimensions: (8, 4),
+                          }],
+            timings: vec![("render", Duration::from_millis(1500))],
+        };
+        let version = format!("  \"version\": \"{}\",", env!("CARGO_PKG_VERSION"));
+        let expected = vec!["{",
+                            version.as_str(),
+                            "  \"frames_discovered\": 3,",
+                            "  \"frames_used\": 2,",
+                            "  \"skipped_frames\": [\"frames/003.png\"],",
+                            "  \"frames\": [{\"index\": 0, \"path\": \"frames/000.png\", \
+                             \"modified\": 2.500, \"taken\": \"2024:05:06 07:08:09\"}, \
+                             {\"index\": 1, \"path\": \"frame 1\", \"modified\": null, \
+                             \"taken\": null}],",
+                            "  \"frame_dimensions\": {\"width\": 4, \"height\": 2},",
+                            "  \"outputs\": [{\"path\": \"o.png\", \"width\": 8, \"height\": 4}],",
+                            "  \"timings\": {\"render\": 1.500}",
+                            "}"];
+        assert_eq!(report.to_json().lines().collect::<Vec<_>>(), expected);
+    }
+}
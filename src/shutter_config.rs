@@ -0,0 +1,326 @@
+use image::FilterType;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ::Direction;
+use ::animation::{AnimationSettings, SnapshotSettings};
+use ::checkpoint::Checkpoint;
+use ::color_processing::{Adjustments, Lut, LutTarget};
+use ::comparison::CompareWith;
+use ::file_processing::FrameLayout;
+use ::frame_processing::{Denoise, FrameTransforms, ResizePolicy, WhiteBalanceMode};
+use ::geometry::ShutterGeometry;
+use ::image_processing::{Dither, RenderOptions, Seed};
+use ::metadata::Metadata;
+use ::output_processing::{BitDepth, EncoderSettings, OutputFormat, OutputSize, Overlay};
+
+/// Builds the options for a render one setting at a time, starting from the defaults, so that
+/// callers only mention the settings they care about:
+///
+/// ```
+/// # use rolling_shutter::{Direction, ShutterConfig};
+/// let options = ShutterConfig::new().direction(Direction::W).preblend(2).stabilize(true).build();
+/// assert_eq!(options.preblend, 2);
+/// ```
+///
+/// Optional settings take an `Option`, so `None` turns them back off.
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct ShutterConfig {
+    options: RenderOptions,
+}
+
+impl ShutterConfig {
+    /// Starts from the default options.
+    pub fn new() -> ShutterConfig {
+        ShutterConfig::default()
+    }
+
+    /// Sets the direction from which the shutter *starts* moving.
+    pub fn direction(mut self, direction: Direction) -> ShutterConfig {
+        self.options.direction = direction;
+        self
+    }
+
+    /// Sets a custom scan pattern to place the bands by instead of the direction.
+    pub fn geometry(mut self, geometry: Option<Arc<dyn ShutterGeometry>>) -> ShutterConfig {
+        self.options.geometry = geometry;
+        self
+    }
+
+    /// Sets how to dither band boundaries.
+    pub fn dither(mut self, dither: Option<Dither>) -> ShutterConfig {
+        self.options.dither = dither;
+        self
+    }
+
+    /// Sets the seed for the randomized parts of the render, such as dithering.
+    pub fn seed(mut self, seed: Seed) -> ShutterConfig {
+        self.options.seed = seed;
+        self
+    }
+
+    /// Sets how many frames are decoded at once, ahead of the bands they are for. 0 decodes one
+    /// per CPU core.
+    pub fn threads(mut self, threads: usize) -> ShutterConfig {
+        self.options.threads = threads;
+        self
+    }
+
+    /// Sets roughly how many bytes of decoded frames the render may hold at once.
+    pub fn max_memory(mut self, bytes: Option<u64>) -> ShutterConfig {
+        self.options.max_memory = bytes;
+        self
+    }
+
+    /// Sets whether to composite on the GPU.
+    pub fn gpu(mut self, gpu: bool) -> ShutterConfig {
+        self.options.gpu = gpu;
+        self
+    }
+
+    /// Sets whether to align every frame to the first to compensate for camera shake.
+    pub fn stabilize(mut self, stabilize: bool) -> ShutterConfig {
+        self.options.stabilize = stabilize;
+        self
+    }
+
+    /// Sets how many consecutive frames are averaged into each frame the shutter sees.
+    pub fn preblend(mut self, preblend: usize) -> ShutterConfig {
+        self.options.preblend = preblend;
+        self
+    }
+
+    /// Sets whether to crop the output to the region every stabilized frame covers.
+    pub fn autocrop(mut self, autocrop: bool) -> ShutterConfig {
+        self.options.autocrop = autocrop;
+        self
+    }
+
+    /// Sets the number of frames to average exposure over when deflickering.
+    pub fn deflicker(mut self, window: Option<usize>) -> ShutterConfig {
+        self.options.deflicker = window;
+        self
+    }
+
+    /// Sets how to normalize white balance across frames.
+    pub fn white_balance(mut self, mode: Option<WhiteBalanceMode>) -> ShutterConfig {
+        self.options.white_balance = mode;
+        self
+    }
+
+    /// Sets how to denoise every frame.
+    pub fn denoise(mut self, denoise: Option<Denoise>) -> ShutterConfig {
+        self.options.denoise = denoise;
+        self
+    }
+
+    /// Sets the brightness, contrast and gamma adjustments applied to every frame.
+    pub fn adjustments(mut self, adjustments: Adjustments) -> ShutterConfig {
+        self.options.adjustments = adjustments;
+        self
+    }
+
+    /// Sets the color grading LUT to apply.
+    pub fn lut(mut self, lut: Option<Lut>) -> ShutterConfig {
+        self.options.lut = lut;
+        self
+    }
+
+    /// Sets whether the LUT is applied to every frame or to the output.
+    pub fn lut_target(mut self, target: LutTarget) -> ShutterConfig {
+        self.options.lut_target = target;
+        self
+    }
+
+    /// Sets what to do with frames whose dimensions differ from the first frame's.
+    pub fn resize_policy(mut self, policy: ResizePolicy) -> ShutterConfig {
+        self.options.resize_policy = policy;
+        self
+    }
+
+    /// Sets the transformations applied to every frame as it is loaded.
+    pub fn transforms(mut self, transforms: FrameTransforms) -> ShutterConfig {
+        self.options.transforms = transforms;
+        self
+    }
+
+    /// Sets the size to rescale the output to.
+    pub fn output_size(mut self, size: Option<OutputSize>) -> ShutterConfig {
+        self.options.output_size = size;
+        self
+    }
+
+    /// Sets the filter used when rescaling the output.
+    pub fn output_filter(mut self, filter: FilterType) -> ShutterConfig {
+        self.options.output_filter = filter;
+        self
+    }
+
+    /// Sets the amount and blur radius of the unsharp mask applied to the output.
+    pub fn sharpen(mut self, sharpen: Option<(f32, f32)>) -> ShutterConfig {
+        self.options.sharpen = sharpen;
+        self
+    }
+
+    /// Sets an image to composite on top of the output.
+    pub fn overlay(mut self, overlay: Option<Overlay>) -> ShutterConfig {
+        self.options.overlay = overlay;
+        self
+    }
+
+    /// Sets the format to encode outputs in when their extension doesn't determine one.
+    pub fn output_format(mut self, format: Option<OutputFormat>) -> ShutterConfig {
+        self.options.output_format = format;
+        self
+    }
+
+    /// Sets the settings passed on to the image encoders.
+    pub fn encoder(mut self, encoder: EncoderSettings) -> ShutterConfig {
+        self.options.encoder = encoder;
+        self
+    }
+
+    /// Sets the number of bits per channel of the output.
+    pub fn bit_depth(mut self, bit_depth: BitDepth) -> ShutterConfig {
+        self.options.bit_depth = bit_depth;
+        self
+    }
+
+    /// Sets whether blending and averaging is done in linear light.
+    pub fn linear_blend(mut self, linear_blend: bool) -> ShutterConfig {
+        self.options.linear_blend = linear_blend;
+        self
+    }
+
+    /// Sets whether to copy the first frame's ICC profile into the output.
+    pub fn preserve_icc(mut self, preserve_icc: bool) -> ShutterConfig {
+        self.options.preserve_icc = preserve_icc;
+        self
+    }
+
+    /// Sets what to record about the render in the output.
+    pub fn metadata(mut self, metadata: Option<Metadata>) -> ShutterConfig {
+        self.options.metadata = metadata;
+        self
+    }
+
+    /// Sets where and how often to record the composite as it is built up.
+    pub fn animation(mut self, animation: Option<AnimationSettings>) -> ShutterConfig {
+        self.options.animation = animation;
+        self
+    }
+
+    /// Sets where and how often to save snapshots of the composite as it is built up.
+    pub fn snapshots(mut self, snapshots: Option<SnapshotSettings>) -> ShutterConfig {
+        self.options.snapshots = snapshots;
+        self
+    }
+
+    /// Sets how many rows of the composite to render at a time, for images too large to hold
+    /// whole.
+    pub fn tile_rows(mut self, rows: Option<u32>) -> ShutterConfig {
+        self.options.tile_rows = rows;
+        self
+    }
+
+    /// Sets how many bands are copied between autosaves of the composite and a checkpoint to
+    /// resume it from.
+    pub fn autosave_every(mut self, bands: Option<u32>) -> ShutterConfig {
+        self.options.autosave_every = bands;
+        self
+    }
+
+    /// Sets whether to also save a copy of the output annotated with band ticks, frame indices
+    /// and the shutter direction.
+    pub fn debug_overlay(mut self, debug_overlay: bool) -> ShutterConfig {
+        self.options.debug_overlay = debug_overlay;
+        self
+    }
+
+    /// Sets which frame to save a side-by-side comparison of the output with.
+    pub fn compare_with(mut self, compare_with: Option<CompareWith>) -> ShutterConfig {
+        self.options.compare_with = compare_with;
+        self
+    }
+
+    /// Sets the directory to save the band taken from each frame to.
+    pub fn export_bands(mut self, dir: Option<PathBuf>) -> ShutterConfig {
+        self.options.export_bands = dir;
+        self
+    }
+
+    /// Sets where to save the map of which frame each pixel was taken from.
+    pub fn time_map(mut self, path: Option<PathBuf>) -> ShutterConfig {
+        self.options.time_map = path;
+        self
+    }
+
+    /// Sets the layout every frame shares, as `file_processing::validate_layout` found it, so that
+    /// they are all decoded the same way.
+    pub fn frame_layout(mut self, layout: Option<FrameLayout>) -> ShutterConfig {
+        self.options.frame_layout = layout;
+        self
+    }
+
+    /// Sets the checkpoint of an interrupted render to continue.
+    pub fn resume(mut self, checkpoint: Option<Checkpoint>) -> ShutterConfig {
+        self.options.resume = checkpoint;
+        self
+    }
+
+    /// Sets whether to suppress status messages.
+    pub fn suppress_output(mut self, suppress_output: bool) -> ShutterConfig {
+        self.options.suppress_output = suppress_output;
+        self
+    }
+
+    /// Sets whether to stretch the bands over the whole image when there are too few frames for a
+    /// line each. Rendering fails if dithering is set too.
+    pub fn auto_stretch(mut self, auto_stretch: bool) -> ShutterConfig {
+        self.options.auto_stretch = auto_stretch;
+        self
+    }
+
+    /// Sets the label of the render when several run at once.
+    pub fn job_label(mut self, label: Option<String>) -> ShutterConfig {
+        self.options.job_label = label;
+        self
+    }
+
+    /// Sets whether to show the composite in a window as it is built up.
+    pub fn preview(mut self, preview: bool) -> ShutterConfig {
+        self.options.preview = preview;
+        self
+    }
+
+    /// Finishes the options, ready to render with.
+    pub fn build(self) -> RenderOptions {
+        self.options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let options = ShutterConfig::new()
+            .direction(Direction::W)
+            .preblend(3)
+            .deflicker(Some(5))
+            .time_map(Some("map.png".into()))
+            .build();
+        assert_eq!(options.direction, Direction::W);
+        assert_eq!(options.preblend, 3);
+        assert_eq!(options.deflicker, Some(5));
+        assert_eq!(options.time_map, Some("map.png".into()));
+        assert!(!options.stabilize);
+
+        let options = ShutterConfig::new().deflicker(Some(5)).deflicker(None).build();
+        assert_eq!(options.deflicker, None);
+    }
+}
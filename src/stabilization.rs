@@ -0,0 +1,427 @@
+use image::{DynamicImage, ImageBuffer, RgbaImage};
+
+use ::frame_processing::Region;
+
+/// The largest dimension of the coarsest level of the alignment pyramid, at which frames are
+/// phase correlated.
+const COARSEST_SIZE: u32 = 128;
+
+/// A complex number, for the Fourier transforms behind phase correlation.
+#[derive(Clone, Copy, Debug, Default)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn mul(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        }
+    }
+
+    fn conj(self) -> Complex {
+        Complex {
+            re: self.re,
+            im: -self.im,
+        }
+    }
+
+    fn norm(self) -> f32 {
+        (self.re * self.re + self.im * self.im).sqrt()
+    }
+}
+
+/// Transforms `data` in place with a radix-2 fast Fourier transform, or its inverse (without the
+/// `1 / n` scaling). The length must be a power of two.
+fn fft(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * ::std::f32::consts::PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let twiddle = Complex {
+                    re: (angle * k as f32).cos(),
+                    im: (angle * k as f32).sin(),
+                };
+                let even = data[start + k];
+                let odd = data[start + k + len / 2].mul(twiddle);
+                data[start + k] = Complex {
+                    re: even.re + odd.re,
+                    im: even.im + odd.im,
+                };
+                data[start + k + len / 2] = Complex {
+                    re: even.re - odd.re,
+                    im: even.im - odd.im,
+                };
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Transforms a `width` by `height` row-major grid in place, rows then columns.
+fn fft_2d(data: &mut [Complex], width: usize, height: usize, inverse: bool) {
+    for row in data.chunks_mut(width) {
+        fft(row, inverse);
+    }
+    let mut column = vec![Complex::default(); height];
+    for x in 0..width {
+        for y in 0..height {
+            column[y] = data[y * width + x];
+        }
+        fft(&mut column, inverse);
+        for y in 0..height {
+            data[y * width + x] = column[y];
+        }
+    }
+}
+
+/// A single-channel luminance image used for alignment.
+#[derive(Clone, Debug)]
+struct LumaPlane {
+    width: u32,
+    height: u32,
+    data: Vec<f32>,
+}
+
+impl LumaPlane {
+    fn from_image(image: &RgbaImage) -> LumaPlane {
+        let (width, height) = image.dimensions();
+        let data = image.pixels()
+            .map(|p| 0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32)
+            .collect();
+        LumaPlane {
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn get(&self, x: u32, y: u32) -> f32 {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    /// Halves both dimensions by averaging 2x2 blocks.
+    fn downsample(&self) -> LumaPlane {
+        let width = ::std::cmp::max(self.width / 2, 1);
+        let height = ::std::cmp::max(self.height / 2, 1);
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let x0 = ::std::cmp::min(2 * x, self.width - 1);
+                let y0 = ::std::cmp::min(2 * y, self.height - 1);
+                let x1 = ::std::cmp::min(2 * x + 1, self.width - 1);
+                let y1 = ::std::cmp::min(2 * y + 1, self.height - 1);
+                data.push((self.get(x0, y0) + self.get(x1, y0) + self.get(x0, y1) +
+                           self.get(x1, y1)) / 4.0);
+            }
+        }
+        LumaPlane {
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Computes the mean absolute difference between this plane and `other` translated by
+    /// `(dx, dy)`, over the region where they overlap. Returns `None` if they overlap by less than
+    /// half of this plane.
+    fn difference(&self, other: &LumaPlane, dx: i32, dy: i32) -> Option<f32> {
+        let x_start = ::std::cmp::max(dx, 0);
+        let y_start = ::std::cmp::max(dy, 0);
+        let x_end = ::std::cmp::min(self.width as i32, other.width as i32 + dx);
+        let y_end = ::std::cmp::min(self.height as i32, other.height as i32 + dy);
+        if x_end <= x_start || y_end <= y_start {
+            return None;
+        }
+        let overlap = ((x_end - x_start) * (y_end - y_start)) as u64;
+        if overlap * 2 < self.width as u64 * self.height as u64 {
+            return None;
+        }
+
+        let mut total = 0f32;
+        for y in y_start..y_end {
+            for x in x_start..x_end {
+                total += (self.get(x as u32, y as u32) -
+                          other.get((x - dx) as u32, (y - dy) as u32))
+                    .abs();
+            }
+        }
+        Some(total / overlap as f32)
+    }
+}
+
+/// The spectrum of a plane, zero padded to `width` by `height`. The mean is taken out and a Hann
+/// window applied first, so that neither the overall brightness nor the plane's edges, which don't
+/// move with its content, dominate the correlation.
+fn spectrum(plane: &LumaPlane, width: usize, height: usize) -> Vec<Complex> {
+    let mean = plane.data.iter().sum::<f32>() / plane.data.len() as f32;
+    let hann = |i: u32, n: u32| {
+        if n < 2 {
+            1.0
+        } else {
+            0.5 - 0.5 * (2.0 * ::std::f32::consts::PI * i as f32 / (n - 1) as f32).cos()
+        }
+    };
+    let mut data = vec![Complex::default(); width * height];
+    for y in 0..plane.height {
+        for x in 0..plane.width {
+            data[y as usize * width + x as usize].re = (plane.get(x, y) - mean) *
+                                                       hann(x, plane.width) *
+                                                       hann(y, plane.height);
+        }
+    }
+    fft_2d(&mut data, width, height, false);
+    data
+}
+
+/// Estimates the translation `(dx, dy)` such that `frame(x - dx, y - dy)` matches
+/// `reference(x, y)` by phase correlation: the normalized cross-power spectrum of the two planes
+/// transforms back to a peak at their offset.
+fn phase_correlate(reference: &LumaPlane, frame: &LumaPlane) -> (i32, i32) {
+    let width = ::std::cmp::max(reference.width, frame.width).next_power_of_two() as usize;
+    let height = ::std::cmp::max(reference.height, frame.height).next_power_of_two() as usize;
+    let mut cross = spectrum(reference, width, height);
+    for (r, f) in cross.iter_mut().zip(spectrum(frame, width, height)) {
+        let product = r.mul(f.conj());
+        let norm = product.norm();
+        *r = if norm > f32::EPSILON {
+            Complex {
+                re: product.re / norm,
+                im: product.im / norm,
+            }
+        } else {
+            Complex::default()
+        };
+    }
+    fft_2d(&mut cross, width, height, true);
+
+    let peak = (0..cross.len())
+        .max_by(|&a, &b| cross[a].re.partial_cmp(&cross[b].re).unwrap())
+        .unwrap_or(0);
+    // Offsets past half the transform wrap around to negative ones.
+    let unwrap = |offset: usize, size: usize| if offset > size / 2 {
+        offset as i32 - size as i32
+    } else {
+        offset as i32
+    };
+    (unwrap(peak % width, width), unwrap(peak / width, height))
+}
+
+/// Builds an image pyramid, finest level first.
+fn pyramid(image: &RgbaImage) -> Vec<LumaPlane> {
+    let mut levels = vec![LumaPlane::from_image(image)];
+    loop {
+        let next = {
+            let last = levels.last().unwrap();
+            if ::std::cmp::max(last.width, last.height) <= COARSEST_SIZE {
+                break;
+            }
+            last.downsample()
+        };
+        levels.push(next);
+    }
+    levels
+}
+
+/// Finds the translation `(dx, dy)` that best aligns `frame` with `reference`, i.e. such that
+/// `frame(x - dx, y - dy)` best matches `reference(x, y)`.
+///
+/// The coarsest pyramid levels are phase correlated, and the offset found is then refined to the
+/// best match within a pixel of it at each finer level.
+fn find_offset(reference: &[LumaPlane], frame: &[LumaPlane]) -> (i32, i32) {
+    let levels = ::std::cmp::min(reference.len(), frame.len());
+    let best_near = |level: usize, center: (i32, i32), radius: i32| -> (i32, i32) {
+        let mut best = center;
+        let mut best_difference = f32::INFINITY;
+        for dy in (center.1 - radius)..(center.1 + radius + 1) {
+            for dx in (center.0 - radius)..(center.0 + radius + 1) {
+                if let Some(difference) = reference[level].difference(&frame[level], dx, dy) {
+                    if difference < best_difference {
+                        best = (dx, dy);
+                        best_difference = difference;
+                    }
+                }
+            }
+        }
+        best
+    };
+
+    let coarsest = levels - 1;
+    let estimate = phase_correlate(&reference[coarsest], &frame[coarsest]);
+    let mut offset = best_near(coarsest, estimate, 1);
+    for level in (0..coarsest).rev() {
+        offset = best_near(level, (offset.0 * 2, offset.1 * 2), 1);
+    }
+    offset
+}
+
+/// Translates an image by `(dx, dy)`, leaving uncovered pixels transparent.
+fn translate(image: &RgbaImage, dx: i32, dy: i32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let mut buf: RgbaImage = ImageBuffer::new(width, height);
+    for y in 0..height {
+        let sy = y as i32 - dy;
+        if sy < 0 || sy >= height as i32 {
+            continue;
+        }
+        for x in 0..width {
+            let sx = x as i32 - dx;
+            if sx < 0 || sx >= width as i32 {
+                continue;
+            }
+            buf.put_pixel(x, y, *image.get_pixel(sx as u32, sy as u32));
+        }
+    }
+    buf
+}
+
+/// Aligns every frame to the first frame, compensating for camera shake.
+///
+/// Only translation is compensated for; the offsets applied to each frame are kept so that the
+/// region every frame covers can be determined afterwards.
+#[derive(Clone, Debug, Default)]
+pub struct Stabilizer {
+    reference: Option<Vec<LumaPlane>>,
+    offsets: Vec<(i32, i32)>,
+}
+
+impl Stabilizer {
+    /// Creates a stabilizer that aligns frames to the first frame it is given.
+    pub fn new() -> Stabilizer {
+        Stabilizer::default()
+    }
+
+    /// Aligns a frame to the reference frame.
+    pub fn apply(&mut self, frame: DynamicImage) -> DynamicImage {
+        let image = frame.to_rgba();
+        let levels = pyramid(&image);
+        let (dx, dy) = match self.reference {
+            Some(ref reference) => find_offset(reference, &levels),
+            None => (0, 0),
+        };
+        if self.reference.is_none() {
+            self.reference = Some(levels);
+        }
+        self.offsets.push((dx, dy));
+
+        if (dx, dy) == (0, 0) {
+            DynamicImage::ImageRgba8(image)
+        } else {
+            DynamicImage::ImageRgba8(translate(&image, dx, dy))
+        }
+    }
+
+    /// Computes the region of a canvas of the given dimensions which every aligned frame so far
+    /// covers, or `None` if there is no such region.
+    pub fn common_region(&self, (width, height): (u32, u32)) -> Option<Region> {
+        common_region(&self.offsets, (width, height))
+    }
+}
+
+/// Intersects the regions of a canvas covered by frames translated by each of `offsets`.
+fn common_region(offsets: &[(i32, i32)], (width, height): (u32, u32)) -> Option<Region> {
+    let (mut x0, mut y0) = (0i64, 0i64);
+    let (mut x1, mut y1) = (width as i64, height as i64);
+    for &(dx, dy) in offsets {
+        x0 = ::std::cmp::max(x0, dx as i64);
+        y0 = ::std::cmp::max(y0, dy as i64);
+        x1 = ::std::cmp::min(x1, width as i64 + dx as i64);
+        y1 = ::std::cmp::min(y1, height as i64 + dy as i64);
+    }
+    if x1 <= x0 || y1 <= y0 {
+        return None;
+    }
+    Some(Region {
+        x: x0 as u32,
+        y: y0 as u32,
+        width: (x1 - x0) as u32,
+        height: (y1 - y0) as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::Rgba;
+
+    fn pattern(dx: i32, dy: i32) -> RgbaImage {
+        ImageBuffer::from_fn(160, 120, |x, y| {
+            let (x, y) = (x as i32 - dx, y as i32 - dy);
+            let blob = (x - 50) * (x - 50) + (y - 40) * (y - 40) < 20 * 20;
+            let bar = x > 100 && x < 120 && y > 20 && y < 100;
+            let value = if blob {
+                255
+            } else if bar {
+                160
+            } else {
+                ((x + y).abs() % 64) as u8
+            };
+            Rgba([value, value, value, 255])
+        })
+    }
+
+    #[test]
+    fn test_find_offset() {
+        let reference = pyramid(&pattern(0, 0));
+        assert_eq!(find_offset(&reference, &pyramid(&pattern(0, 0))), (0, 0));
+        assert_eq!(find_offset(&reference, &pyramid(&pattern(6, -4))), (-6, 4));
+        assert_eq!(find_offset(&reference, &pyramid(&pattern(-9, 3))), (9, -3));
+        assert_eq!(find_offset(&reference, &pyramid(&pattern(25, -18))), (-25, 18));
+    }
+
+    #[test]
+    fn test_phase_correlate() {
+        let reference = LumaPlane::from_image(&pattern(0, 0));
+        let frame = |dx, dy| LumaPlane::from_image(&pattern(dx, dy));
+        assert_eq!(phase_correlate(&reference, &frame(0, 0)), (0, 0));
+        assert_eq!(phase_correlate(&reference, &frame(5, 7)), (-5, -7));
+        assert_eq!(phase_correlate(&reference, &frame(-20, 12)), (20, -12));
+    }
+
+    #[test]
+    fn test_common_region() {
+        assert_eq!(common_region(&[], (100, 50)),
+                   Some(Region {
+                       x: 0,
+                       y: 0,
+                       width: 100,
+                       height: 50,
+                   }));
+        assert_eq!(common_region(&[(0, 0), (3, -2), (-5, 4)], (100, 50)),
+                   Some(Region {
+                       x: 3,
+                       y: 4,
+                       width: 92,
+                       height: 44,
+                   }));
+        assert_eq!(common_region(&[(60, 0), (-60, 0)], (100, 50)), None);
+    }
+
+    #[test]
+    fn test_translate() {
+        let image = pattern(0, 0);
+        let moved = translate(&image, 3, -2);
+        assert_eq!(moved.get_pixel(10, 10), image.get_pixel(7, 12));
+        assert_eq!(moved.get_pixel(0, 0)[3], 0);
+        assert_eq!(moved.get_pixel(159, 119)[3], 0);
+    }
+}
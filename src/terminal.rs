@@ -0,0 +1,341 @@
+use pbr::ProgressBar;
+
+use std::io::{self, Stderr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rolling_shutter::{FrameInfo, ProgressSink};
+
+/// How often a labelled progress line is printed.
+const LINE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the thread drawing a `TerminalProgress` brings it up to date.
+const DRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How many characters of the current file name are shown. Names are padded or shortened to this,
+/// so the bar doesn't jump around as they change length.
+const NAME_WIDTH: usize = 24;
+
+/// Formats a duration as `m:ss`, or as `h:mm:ss` once it reaches an hour.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Pads or shortens a file name to `NAME_WIDTH` characters. Long names keep their end, which is
+/// usually where the frame number is.
+fn fit_name(name: &str) -> String {
+    let len = name.chars().count();
+    if len > NAME_WIDTH {
+        let tail: String = name.chars().skip(len - (NAME_WIDTH - 3)).collect();
+        format!("...{}", tail)
+    } else {
+        format!("{:<width$}", name, width = NAME_WIDTH)
+    }
+}
+
+/// Builds the status shown before the bar: the file being worked on, how many frames have been
+/// done per second so far, and how long the rest should take at that rate.
+fn status(label: &str, name: &str, done: u64, total: u64, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    if done == 0 || secs <= 0.0 {
+        return format!("{} {}   --.- frames/s, --:-- left ", label, fit_name(name));
+    }
+    let speed = done as f64 / secs;
+    let left = Duration::from_secs((total.saturating_sub(done) as f64 / speed).round() as u64);
+    format!("{} {} {:>6.1} frames/s, {} left ",
+            label,
+            fit_name(name),
+            speed,
+            format_duration(left))
+}
+
+/// Progress reported to another thread, which shows it in its own way: how many frames are done,
+/// out of how many.
+#[derive(Clone, Debug, Default)]
+pub struct SharedProgress(Arc<Mutex<(u64, u64)>>);
+
+impl SharedProgress {
+    /// How many frames are done, and out of how many.
+    // Only the terminal UI shows it, so builds without the tui feature never ask.
+    #[cfg_attr(not(feature = "tui"), allow(dead_code))]
+    pub fn get(&self) -> (u64, u64) {
+        *self.0.lock().unwrap()
+    }
+
+    fn set(&self, done: u64, total: u64) {
+        *self.0.lock().unwrap() = (done, total);
+    }
+}
+
+impl ProgressSink for SharedProgress {
+    fn start(&mut self, _phase: &'static str, index: u64, total: u64, _frame: &FrameInfo) {
+        self.set(index, total);
+    }
+
+    fn done(&mut self, _phase: &'static str, done: u64, total: u64) {
+        self.set(done, total);
+    }
+}
+
+/// Progress a render counts for the thread that draws it: how many frames are done, and the file
+/// being worked on.
+#[derive(Default)]
+struct Counter {
+    done: AtomicU64,
+    current: Mutex<PathBuf>,
+}
+
+/// A `FrameProgress` drawn by a thread of its own from a `Counter`, so that the render only bumps
+/// the counter and never waits on the terminal. Dropping it draws the bar one last time and
+/// finishes it.
+struct Drawer {
+    counter: Arc<Counter>,
+    stop: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drawer {
+    fn new(mut bar: FrameProgress) -> Drawer {
+        let counter = Arc::new(Counter::default());
+        let (stop, stopped) = mpsc::channel();
+        let thread = {
+            let counter = counter.clone();
+            thread::spawn(move || {
+                let draw = |bar: &mut FrameProgress| {
+                    let current = counter.current.lock().unwrap().clone();
+                    bar.update(&current, counter.done.load(Ordering::Relaxed));
+                };
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(DRAW_INTERVAL) {
+                    draw(&mut bar);
+                }
+                draw(&mut bar);
+                bar.finish();
+            })
+        };
+        Drawer {
+            counter,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    fn start(&self, path: &Path) {
+        let mut current = self.counter.current.lock().unwrap();
+        current.clear();
+        current.push(path);
+    }
+
+    fn inc(&self) {
+        self.counter.done.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Drawer {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Progress drawn on the terminal as a `FrameProgress` bar, or printed as lines labelled with the
+/// render it belongs to when several run at once. The bar is only started once the first frame
+/// is, since that is when its total is known, and is drawn on a thread of its own.
+pub struct TerminalProgress {
+    /// The label for the render, if progress is printed as lines.
+    job: Option<String>,
+    bar: Option<Drawer>,
+}
+
+impl TerminalProgress {
+    /// Creates a sink that draws a bar, or prints lines labelled with `job` if given.
+    pub fn new(job: Option<&str>) -> TerminalProgress {
+        TerminalProgress {
+            job: job.map(Into::into),
+            bar: None,
+        }
+    }
+}
+
+impl ProgressSink for TerminalProgress {
+    fn start(&mut self, phase: &'static str, _index: u64, total: u64, frame: &FrameInfo) {
+        let job = &self.job;
+        let bar = self.bar.get_or_insert_with(|| {
+            Drawer::new(match *job {
+                Some(ref job) => FrameProgress::labelled(phase, job, total),
+                None => FrameProgress::new(phase, total),
+            })
+        });
+        bar.start(&frame.path);
+    }
+
+    fn done(&mut self, _phase: &'static str, _done: u64, _total: u64) {
+        if let Some(ref bar) = self.bar {
+            bar.inc();
+        }
+    }
+
+    fn finish(&mut self, _phase: &'static str) {
+        self.bar.take();
+    }
+}
+
+/// A progress bar over frames that shows the file being worked on, the throughput, and the time
+/// remaining, which matters far more than the bare counter on renders that take hours.
+///
+/// When several renders run at once, their bars would draw over each other, so each prints a line
+/// every few seconds instead, labelled with the render it belongs to.
+struct FrameProgress {
+    pb: Option<ProgressBar<Stderr>>,
+    label: &'static str,
+    /// The label for the render, if progress is printed as lines.
+    job: Option<String>,
+    total: u64,
+    done: u64,
+    start: Instant,
+    last_line: Option<Instant>,
+}
+
+impl FrameProgress {
+    /// Creates a progress bar over `total` frames, labelled with what is being done to them. It is
+    /// drawn on standard error, so that it stays out of anything written to standard output.
+    fn new(label: &'static str, total: u64) -> FrameProgress {
+        let mut pb = ProgressBar::on(io::stderr(), total);
+        pb.show_speed = false;
+        pb.show_time_left = false;
+        pb.set_max_refresh_rate(Some(Duration::from_millis(50)));
+        FrameProgress {
+            pb: Some(pb),
+            label,
+            job: None,
+            total,
+            done: 0,
+            start: Instant::now(),
+            last_line: None,
+        }
+    }
+
+    /// Creates a progress report over `total` frames that prints a line every few seconds,
+    /// labelled with the render it belongs to, instead of drawing a bar.
+    fn labelled(label: &'static str, job: &str, total: u64) -> FrameProgress {
+        FrameProgress {
+            pb: None,
+            label,
+            job: Some(job.into()),
+            total,
+            done: 0,
+            start: Instant::now(),
+            last_line: None,
+        }
+    }
+
+    /// Shows the file that is about to be worked on.
+    fn start(&mut self, path: &Path) {
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+        let status = status(self.label,
+                            &name.unwrap_or_default(),
+                            self.done,
+                            self.total,
+                            self.start.elapsed());
+        match (self.pb.as_mut(), self.job.as_ref()) {
+            (Some(pb), _) => {
+                pb.message(&status);
+                pb.tick();
+            }
+            (None, Some(job)) => {
+                match self.last_line {
+                    Some(last) if last.elapsed() < LINE_INTERVAL => (),
+                    _ => {
+                        eprintln!("[{}] {}{} / {}", job, status, self.done, self.total);
+                        self.last_line = Some(Instant::now());
+                    }
+                }
+            }
+            (None, None) => (),
+        }
+    }
+
+    /// Shows how many frames are done and the file being worked on, for progress counted
+    /// somewhere else.
+    fn update(&mut self, path: &Path, done: u64) {
+        if done != self.done {
+            self.done = done;
+            if let Some(ref mut pb) = self.pb {
+                pb.set(done);
+            }
+        }
+        self.start(path);
+    }
+
+    /// Fills the bar and moves past it.
+    fn finish(&mut self) {
+        match (self.pb.as_mut(), self.job.as_ref()) {
+            (Some(pb), _) => {
+                pb.message(&format!("{} ", self.label));
+                pb.finish();
+            }
+            (None, Some(job)) => {
+                eprintln!("[{}] {} {} / {}", job, self.label, self.done, self.total)
+            }
+            (None, None) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0:00");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2:05");
+        assert_eq!(format_duration(Duration::from_secs(3 * 3600 + 62)), "3:01:02");
+    }
+
+    #[test]
+    fn test_fit_name() {
+        assert_eq!(fit_name("f001.png"), format!("{:<24}", "f001.png"));
+        assert_eq!(fit_name("a_very_long_capture_name_frame_00001.png"),
+                   "..._name_frame_00001.png");
+    }
+
+    #[test]
+    fn test_drawer() {
+        let drawer = Drawer::new(FrameProgress::labelled("Processing frames:", "test", 3));
+        for name in &["f001.png", "f002.png", "f003.png"] {
+            drawer.start(Path::new(name));
+            drawer.inc();
+        }
+        assert_eq!(drawer.counter.done.load(Ordering::Relaxed), 3);
+        assert_eq!(*drawer.counter.current.lock().unwrap(), PathBuf::from("f003.png"));
+    }
+
+    #[test]
+    fn test_shared() {
+        let frame = FrameInfo { path: "f001.png".into(), ..FrameInfo::unnamed(0) };
+        let mut shared = SharedProgress::default();
+        shared.start("Processing frames:", 0, 4, &frame);
+        assert_eq!(shared.get(), (0, 4));
+        shared.done("Processing frames:", 1, 4);
+        assert_eq!(shared.get(), (1, 4));
+    }
+
+    #[test]
+    fn test_status() {
+        assert_eq!(status("Processing frames:", "f001.png", 0, 100, Duration::from_secs(0)),
+                   format!("Processing frames: {:<24}   --.- frames/s, --:-- left ", "f001.png"));
+        assert_eq!(status("Processing frames:", "f051.png", 50, 100, Duration::from_secs(25)),
+                   format!("Processing frames: {:<24}    2.0 frames/s, 0:25 left ", "f051.png"));
+    }
+}
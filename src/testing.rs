@@ -0,0 +1,51 @@
+//! Fixtures shared by the tests of several modules.
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use image::RgbaImage;
+
+/// A directory of frames saved for a test, removed with everything in it once dropped. It derefs
+/// to its path, so other files can be put in it too.
+pub struct FrameDir {
+    path: PathBuf,
+    /// The paths of the frames, saved as `0.png`, `1.png` and so on.
+    pub frames: Vec<PathBuf>,
+}
+
+/// Saves `frames` in a directory of their own, named after the test and this process, so that
+/// tests running at once, in this run or another, never share one.
+pub fn frame_dir<I>(name: &str, frames: I) -> FrameDir
+    where I: IntoIterator<Item = RgbaImage>
+{
+    let path = ::std::env::temp_dir()
+        .join(format!("rolling-shutter-test-{}-{}", name, process::id()));
+    // Left over from an earlier run that had the same process id and didn't finish.
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    let frames = frames.into_iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let frame_path = path.join(format!("{}.png", i));
+            frame.save(&frame_path).unwrap();
+            frame_path
+        })
+        .collect();
+    FrameDir { path, frames }
+}
+
+impl Deref for FrameDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FrameDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
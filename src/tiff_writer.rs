@@ -0,0 +1,206 @@
+use ::png_writer::write_u32;
+
+/// TIFF field types.
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const UNDEFINED: u16 = 7;
+
+/// The TIFF tag an ICC profile is stored under.
+const ICC_PROFILE_TAG: u16 = 34675;
+
+/// A single IFD entry. Values that fit in four bytes are stored inline; anything larger is stored
+/// elsewhere and pointed to.
+struct Entry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    /// The value itself, or the offset of the data.
+    value: u32,
+}
+
+impl Entry {
+    fn short(tag: u16, value: u16) -> Entry {
+        // Big-endian values smaller than four bytes are left-justified.
+        Entry {
+            tag,
+            field_type: SHORT,
+            count: 1,
+            value: (value as u32) << 16,
+        }
+    }
+
+    fn long(tag: u16, value: u32) -> Entry {
+        Entry {
+            tag,
+            field_type: LONG,
+            count: 1,
+            value,
+        }
+    }
+}
+
+fn write_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&[(value >> 8) as u8, value as u8]);
+}
+
+/// Pads data to an even length, since TIFF offsets should be word-aligned.
+fn pad(out: &mut Vec<u8>) {
+    if !out.len().is_multiple_of(2) {
+        out.push(0);
+    }
+}
+
+/// Encodes uncompressed RGBA data, with big-endian samples if they are 16 bits, as a big-endian
+/// TIFF with a single strip, embedding an ICC profile and a description if they are given.
+pub fn encode(data: &[u8],
+              width: u32,
+              height: u32,
+              bit_depth: u8,
+              profile: Option<&[u8]>,
+              description: Option<&str>)
+              -> Vec<u8> {
+    // The header is the byte order, the magic number, and the offset of the IFD, which is
+    // written after everything it points to.
+    let mut out = b"MM\x00\x2a".to_vec();
+    write_u32(&mut out, 0);
+
+    let strip_offset = out.len() as u32;
+    out.extend_from_slice(data);
+    pad(&mut out);
+
+    let bits_offset = out.len() as u32;
+    for _ in 0..4 {
+        write_u16(&mut out, bit_depth as u16);
+    }
+
+    let mut entries = vec![Entry::long(256, width),
+                           Entry::long(257, height),
+                           Entry {
+                               tag: 258,
+                               field_type: SHORT,
+                               count: 4,
+                               value: bits_offset,
+                           },
+                           // No compression.
+                           Entry::short(259, 1),
+                           // RGB.
+                           Entry::short(262, 2),
+                           Entry::long(273, strip_offset),
+                           Entry::short(277, 4),
+                           Entry::long(278, height),
+                           Entry::long(279, data.len() as u32),
+                           // Samples are interleaved.
+                           Entry::short(284, 1),
+                           // The fourth sample is unassociated alpha.
+                           Entry::short(338, 2)];
+
+    if let Some(description) = description {
+        // ASCII values are null-terminated. Values of four bytes or fewer would have to be stored
+        // inline, so short descriptions are padded with extra nulls to keep them out of line.
+        let count = ::std::cmp::max(description.len() + 1, 5);
+        // Entries must be sorted by tag, and this one goes right before the strip offsets.
+        entries.insert(5,
+                       Entry {
+                           tag: 270,
+                           field_type: ASCII,
+                           count: count as u32,
+                           value: out.len() as u32,
+                       });
+        out.extend_from_slice(description.as_bytes());
+        out.resize(out.len() + count - description.len(), 0);
+        pad(&mut out);
+    }
+
+    if let Some(profile) = profile {
+        entries.push(Entry {
+            tag: ICC_PROFILE_TAG,
+            field_type: UNDEFINED,
+            count: profile.len() as u32,
+            value: out.len() as u32,
+        });
+        out.extend_from_slice(profile);
+        pad(&mut out);
+    }
+
+    let ifd_offset = out.len() as u32;
+    out[4..8].copy_from_slice(&[(ifd_offset >> 24) as u8,
+                                (ifd_offset >> 16) as u8,
+                                (ifd_offset >> 8) as u8,
+                                ifd_offset as u8]);
+    write_u16(&mut out, entries.len() as u16);
+    for entry in &entries {
+        write_u16(&mut out, entry.tag);
+        write_u16(&mut out, entry.field_type);
+        write_u32(&mut out, entry.count);
+        write_u32(&mut out, entry.value);
+    }
+    // There is no next IFD.
+    write_u32(&mut out, 0);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ::png_writer::read_u32;
+
+    fn read_u16(bytes: &[u8]) -> u16 {
+        (bytes[0] as u16) << 8 | bytes[1] as u16
+    }
+
+    /// Reads the tag, type, count and value of every IFD entry.
+    fn read_entries(tiff: &[u8]) -> Vec<(u16, u16, u32, u32)> {
+        let ifd = read_u32(&tiff[4..]) as usize;
+        let count = read_u16(&tiff[ifd..]) as usize;
+        (0..count)
+            .map(|i| {
+                let entry = &tiff[ifd + 2 + i * 12..];
+                (read_u16(entry),
+                 read_u16(&entry[2..]),
+                 read_u32(&entry[4..]),
+                 read_u32(&entry[8..]))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_encode() {
+        let data: Vec<u8> = (0..3 * 2 * 8).map(|i| i as u8).collect();
+        let profile = b"not really a profile";
+        let tiff = encode(&data, 3, 2, 16, Some(profile), Some("Frames: 2"));
+        assert!(tiff.starts_with(b"MM\x00\x2a"));
+
+        let entries = read_entries(&tiff);
+        let tags: Vec<u16> = entries.iter().map(|entry| entry.0).collect();
+        let mut sorted = tags.clone();
+        sorted.sort();
+        assert_eq!(tags, sorted);
+
+        let value = |tag: u16| entries.iter().find(|entry| entry.0 == tag).unwrap().3;
+        assert_eq!(value(256), 3);
+        assert_eq!(value(257), 2);
+        assert_eq!(value(277), 4 << 16);
+        let strip = value(273) as usize;
+        assert_eq!(&tiff[strip..strip + value(279) as usize], &data[..]);
+        let bits = value(258) as usize;
+        assert_eq!(read_u16(&tiff[bits..]), 16);
+        let icc = value(ICC_PROFILE_TAG) as usize;
+        assert_eq!(&tiff[icc..icc + profile.len()], &profile[..]);
+        let description = value(270) as usize;
+        assert_eq!(&tiff[description..description + 10], b"Frames: 2\0");
+
+        // Short descriptions are still stored out of line.
+        let tiff = encode(&data, 3, 2, 16, None, Some("a"));
+        let entry = read_entries(&tiff).into_iter().find(|entry| entry.0 == 270).unwrap();
+        assert_eq!(entry.2, 5);
+        assert_eq!(&tiff[entry.3 as usize..entry.3 as usize + 5], b"a\0\0\0\0");
+
+        // Without a profile or description, their tags are left out entirely.
+        let tiff = encode(&data[..24], 3, 2, 8, None, None);
+        assert!(read_entries(&tiff)
+            .iter()
+            .all(|entry| entry.0 != ICC_PROFILE_TAG && entry.0 != 270));
+    }
+}
@@ -0,0 +1,483 @@
+// Without the tui feature only the stand-in below is built, so the parameters go unused.
+#![cfg_attr(not(feature = "tui"), allow(dead_code))]
+
+use std::path::Path;
+
+/// How a parameter is edited.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum FieldKind {
+    /// Typed in.
+    Text,
+    /// Cycled through a fixed set of values. The first is the default.
+    Choice(&'static [&'static str]),
+    /// Toggled on and off.
+    Flag,
+}
+
+/// A parameter shown in the parameter panel, named after the option it sets.
+#[derive(Clone, Debug, PartialEq)]
+struct Field {
+    name: &'static str,
+    kind: FieldKind,
+    value: String,
+}
+
+/// The parameters of the next render to queue. The most common options have their own fields, and
+/// any others can be typed into `extra` as they would be on the command line.
+#[derive(Clone, Debug, PartialEq)]
+struct Params {
+    fields: Vec<Field>,
+}
+
+impl Params {
+    fn new(input: Option<&str>, output: Option<&str>) -> Params {
+        let field = |name, kind, value: &str| {
+            Field {
+                name,
+                kind,
+                value: value.into(),
+            }
+        };
+        Params {
+            fields: vec![field("input", FieldKind::Text, input.unwrap_or("")),
+                         field("output", FieldKind::Text, output.unwrap_or("out.png")),
+                         field("direction", FieldKind::Choice(&["N", "E", "S", "W"]), "N"),
+                         field("preblend", FieldKind::Text, "1"),
+                         field("dither-bands",
+                               FieldKind::Choice(&["off", "ordered", "blue-noise"]),
+                               "off"),
+                         field("stabilize", FieldKind::Flag, "off"),
+                         field("auto-stretch", FieldKind::Flag, "off"),
+                         field("extra", FieldKind::Text, "")],
+        }
+    }
+
+    fn value(&self, name: &str) -> &str {
+        &self.fields.iter().find(|field| field.name == name).unwrap().value
+    }
+
+    /// Cycles a choice or toggles a flag. Returns whether the field is text, which is edited by
+    /// typing instead.
+    fn activate(&mut self, index: usize) -> bool {
+        let field = &mut self.fields[index];
+        match field.kind {
+            FieldKind::Text => return true,
+            FieldKind::Choice(values) => {
+                let current = values.iter().position(|&value| value == field.value).unwrap_or(0);
+                field.value = values[(current + 1) % values.len()].into();
+            }
+            FieldKind::Flag => {
+                field.value = if field.value == "on" { "off" } else { "on" }.into();
+            }
+        }
+        false
+    }
+
+    /// The command line arguments that render with these parameters.
+    fn to_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        for field in &self.fields {
+            match (field.name, field.kind) {
+                (_, _) if field.value.is_empty() => (),
+                ("input", _) if Path::new(&field.value).is_dir() => {
+                    args.push(format!("--folder={}", field.value))
+                }
+                ("input", _) => args.push(field.value.clone()),
+                ("extra", _) => args.extend(field.value.split_whitespace().map(String::from)),
+                (_, FieldKind::Flag) => {
+                    if field.value == "on" {
+                        args.push(format!("--{}", field.name));
+                    }
+                }
+                (_, FieldKind::Choice(_)) if field.value == "off" => (),
+                _ => args.push(format!("--{}={}", field.name, field.value)),
+            }
+        }
+        args
+    }
+}
+
+#[cfg(feature = "tui")]
+mod ui {
+    use crossterm::ExecutableCommand;
+    use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+    use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+    use ratatui::{Frame, Terminal};
+    use ratatui::backend::CrosstermBackend;
+    use ratatui::layout::{Constraint, Direction, Layout};
+    use ratatui::style::{Color, Modifier, Style};
+    use ratatui::widgets::{Block, Borders, Gauge, List, ListItem, Paragraph};
+
+    use std::collections::VecDeque;
+    use std::ffi::OsString;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc::{self, Sender};
+    use std::thread;
+    use std::time::Duration;
+
+    use rolling_shutter::{get_paths, Error, ErrorKind, PathMode, Result, ResultExt};
+
+    use ::terminal::SharedProgress;
+    use super::Params;
+
+    /// How many warnings are kept.
+    const MAX_WARNINGS: usize = 100;
+
+    /// How many frames the frame list scrolls by.
+    const PAGE: usize = 10;
+
+    const HELP: &str = "Up/Down: select  Enter: edit  r: queue render  f: reload frames  \
+                        PgUp/PgDn: scroll frames  q: quit";
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    enum JobStatus {
+        Queued,
+        Running,
+        Done,
+        Failed,
+    }
+
+    struct Job {
+        label: String,
+        status: JobStatus,
+    }
+
+    /// What the render thread reports about the jobs it is given.
+    enum JobEvent {
+        Started(usize),
+        Finished(usize, ::std::result::Result<(), String>),
+    }
+
+    /// Describes an error along with its causes, on one line.
+    fn describe(error: &Error) -> String {
+        error.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(" ")
+    }
+
+    struct App {
+        params: Params,
+        selected: usize,
+        /// The text being typed into the selected field, if it is being edited.
+        editing: Option<String>,
+        frames: Vec<PathBuf>,
+        frames_offset: usize,
+        jobs: Vec<Job>,
+        queue: Sender<(usize, Vec<OsString>)>,
+        progress: SharedProgress,
+        warnings: VecDeque<String>,
+        /// Whether quitting was asked for while a render is running, which needs confirming.
+        quit_pending: bool,
+    }
+
+    impl App {
+        fn warn(&mut self, warning: String) {
+            self.warnings.push_back(warning);
+            if self.warnings.len() > MAX_WARNINGS {
+                self.warnings.pop_front();
+            }
+        }
+
+        fn running(&self) -> bool {
+            self.jobs.iter().any(|job| job.status == JobStatus::Running)
+        }
+
+        /// Finds the frames the input names again.
+        fn reload_frames(&mut self) {
+            let input = self.params.value("input").to_string();
+            self.frames_offset = 0;
+            if input.is_empty() {
+                self.frames.clear();
+                return;
+            }
+            match get_paths(&PathMode::from(Path::new(&input))) {
+                Ok(frames) => self.frames = frames,
+                Err(e) => {
+                    self.frames.clear();
+                    self.warn(format!("{}: {}", input, describe(&e)));
+                }
+            }
+        }
+
+        /// Adds a render with the current parameters to the queue.
+        fn queue_render(&mut self) {
+            let id = self.jobs.len();
+            let output = self.params.value("output");
+            let label = format!("#{} {}", id + 1, output);
+            let args = self.params.to_args().into_iter().map(Into::into).collect();
+            if self.queue.send((id, args)).is_err() {
+                self.warn("The render thread has stopped; restart to render again.".into());
+                return;
+            }
+            self.jobs.push(Job {
+                label,
+                status: JobStatus::Queued,
+            });
+        }
+
+        fn handle_event(&mut self, event: JobEvent) {
+            match event {
+                JobEvent::Started(id) => self.jobs[id].status = JobStatus::Running,
+                JobEvent::Finished(id, Ok(())) => self.jobs[id].status = JobStatus::Done,
+                JobEvent::Finished(id, Err(e)) => {
+                    self.jobs[id].status = JobStatus::Failed;
+                    let warning = format!("{}: {}", self.jobs[id].label, e);
+                    self.warn(warning);
+                }
+            }
+        }
+
+        /// Handles a key press. Returns whether to quit.
+        fn handle_key(&mut self, code: KeyCode) -> bool {
+            if let Some(mut text) = self.editing.take() {
+                match code {
+                    KeyCode::Char(c) => text.push(c),
+                    KeyCode::Backspace => {
+                        text.pop();
+                    }
+                    KeyCode::Enter => {
+                        let name = self.params.fields[self.selected].name;
+                        self.params.fields[self.selected].value = text;
+                        if name == "input" {
+                            self.reload_frames();
+                        }
+                        return false;
+                    }
+                    KeyCode::Esc => return false,
+                    _ => (),
+                }
+                self.editing = Some(text);
+                return false;
+            }
+
+            if code != KeyCode::Char('q') && code != KeyCode::Esc {
+                self.quit_pending = false;
+            }
+            match code {
+                KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+                KeyCode::Down => {
+                    self.selected = ::std::cmp::min(self.selected + 1, self.params.fields.len() - 1)
+                }
+                KeyCode::Enter | KeyCode::Char(' ') => {
+                    if self.params.activate(self.selected) {
+                        self.editing = Some(self.params.fields[self.selected].value.clone());
+                    }
+                }
+                KeyCode::PageUp => self.frames_offset = self.frames_offset.saturating_sub(PAGE),
+                KeyCode::PageDown => {
+                    self.frames_offset = ::std::cmp::min(self.frames_offset + PAGE,
+                                                         self.frames.len().saturating_sub(1))
+                }
+                KeyCode::Char('f') => self.reload_frames(),
+                KeyCode::Char('r') => self.queue_render(),
+                KeyCode::Char('q') | KeyCode::Esc => {
+                    if !self.running() || self.quit_pending {
+                        return true;
+                    }
+                    self.warn("A render is still running; press q again to stop it and quit."
+                        .into());
+                    self.quit_pending = true;
+                }
+                _ => (),
+            }
+            false
+        }
+    }
+
+    fn draw(frame: &mut Frame, app: &App) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(8),
+                          Constraint::Length(3),
+                          Constraint::Length(8),
+                          Constraint::Length(1)])
+            .split(frame.size());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(35),
+                          Constraint::Percentage(35),
+                          Constraint::Percentage(30)])
+            .split(rows[0]);
+        let block = |title: String| Block::default().borders(Borders::ALL).title(title);
+
+        let frames: Vec<ListItem> = app.frames
+            .iter()
+            .enumerate()
+            .skip(app.frames_offset)
+            .map(|(i, path)| ListItem::new(format!("{:>5} {}", i, path.display())))
+            .collect();
+        let title = format!("Frames ({})", app.frames.len());
+        frame.render_widget(List::new(frames).block(block(title)), columns[0]);
+
+        let params: Vec<ListItem> = app.params
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let value = match app.editing {
+                    Some(ref text) if i == app.selected => format!("{}_", text),
+                    _ => field.value.clone(),
+                };
+                let style = if i == app.selected {
+                    Style::default().add_modifier(Modifier::REVERSED)
+                } else {
+                    Style::default()
+                };
+                ListItem::new(format!("{:<13} {}", field.name, value)).style(style)
+            })
+            .collect();
+        frame.render_widget(List::new(params).block(block("Parameters".into())), columns[1]);
+
+        let jobs: Vec<ListItem> = app.jobs
+            .iter()
+            .map(|job| ListItem::new(format!("{:<8} {}", format!("{:?}", job.status), job.label)))
+            .collect();
+        frame.render_widget(List::new(jobs).block(block("Queue".into())), columns[2]);
+
+        let (done, total) = app.progress.get();
+        let ratio = if app.running() && total > 0 {
+            ::std::cmp::min(done, total) as f64 / total as f64
+        } else {
+            0.0
+        };
+        let gauge = Gauge::default()
+            .block(block("Progress".into()))
+            .gauge_style(Style::default().fg(Color::Cyan))
+            .ratio(ratio)
+            .label(format!("{} / {} frames", done, total));
+        frame.render_widget(gauge, rows[1]);
+
+        let height = rows[2].height.saturating_sub(2) as usize;
+        let skip = app.warnings.len().saturating_sub(height);
+        let warnings = app.warnings.iter().skip(skip).cloned().collect::<Vec<_>>().join("\n");
+        frame.render_widget(Paragraph::new(warnings).block(block("Warnings".into())), rows[2]);
+
+        frame.render_widget(Paragraph::new(HELP), rows[3]);
+    }
+
+    /// Runs the terminal UI until it is quit, rendering each queued job in turn with `render`,
+    /// which is given the job's arguments and where to report its progress.
+    ///
+    /// # Errors
+    /// This fails if the terminal can't be set up or drawn to.
+    pub(crate) fn run<F>(input: Option<&str>, output: Option<&str>, render: F) -> Result<()>
+        where F: Fn(Vec<OsString>, SharedProgress) -> Result<()> + Send + 'static
+    {
+        let (queue, jobs) = mpsc::channel::<(usize, Vec<OsString>)>();
+        let (events, received) = mpsc::channel();
+        let progress = SharedProgress::default();
+        let worker_progress = progress.clone();
+        thread::spawn(move || {
+            for (id, args) in jobs {
+                let _ = events.send(JobEvent::Started(id));
+                let result = render(args, worker_progress.clone()).map_err(|e| describe(&e));
+                let _ = events.send(JobEvent::Finished(id, result));
+            }
+        });
+
+        let mut app = App {
+            params: Params::new(input, output),
+            selected: 0,
+            editing: None,
+            frames: vec![],
+            frames_offset: 0,
+            jobs: vec![],
+            queue,
+            progress,
+            warnings: VecDeque::new(),
+            quit_pending: false,
+        };
+        app.reload_frames();
+
+        terminal::enable_raw_mode().chain_err(|| ErrorKind::CouldNotRunTui)?;
+        let result = io::stdout()
+            .execute(EnterAlternateScreen)
+            .and_then(|_| Terminal::new(CrosstermBackend::new(io::stdout())))
+            .chain_err(|| ErrorKind::CouldNotRunTui)
+            .and_then(|mut terminal| {
+                loop {
+                    while let Ok(event) = received.try_recv() {
+                        app.handle_event(event);
+                    }
+                    terminal.draw(|frame| draw(frame, &app))
+                        .chain_err(|| ErrorKind::CouldNotRunTui)?;
+                    if !event::poll(Duration::from_millis(100))
+                        .chain_err(|| ErrorKind::CouldNotRunTui)? {
+                        continue;
+                    }
+                    match event::read().chain_err(|| ErrorKind::CouldNotRunTui)? {
+                        Event::Key(key) if key.kind == KeyEventKind::Press => {
+                            if app.handle_key(key.code) {
+                                return Ok(());
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            });
+        // The terminal is put back however the UI ended.
+        let _ = io::stdout().execute(LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+        result
+    }
+}
+
+#[cfg(not(feature = "tui"))]
+mod ui {
+    use std::ffi::OsString;
+
+    use rolling_shutter::{ErrorKind, Result};
+
+    use ::terminal::SharedProgress;
+
+    /// Stands in for the terminal UI in builds without the tui feature.
+    pub(crate) fn run<F>(_input: Option<&str>, _output: Option<&str>, _render: F) -> Result<()>
+        where F: Fn(Vec<OsString>, SharedProgress) -> Result<()> + Send + 'static
+    {
+        bail!(ErrorKind::TuiUnavailable)
+    }
+}
+
+pub(crate) use self::ui::run;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_activate() {
+        let mut params = Params::new(None, None);
+        assert!(params.activate(0));
+        assert!(!params.activate(2));
+        assert_eq!(params.value("direction"), "E");
+        for _ in 0..3 {
+            params.activate(2);
+        }
+        assert_eq!(params.value("direction"), "N");
+        params.activate(5);
+        assert_eq!(params.value("stabilize"), "on");
+        params.activate(5);
+        assert_eq!(params.value("stabilize"), "off");
+    }
+
+    #[test]
+    fn test_to_args() {
+        let mut params = Params::new(Some("frames/%03d.png"), Some("fan.png"));
+        assert_eq!(params.to_args(),
+                   vec!["frames/%03d.png", "--output=fan.png", "--direction=N", "--preblend=1"]);
+
+        params.activate(2);
+        params.activate(4);
+        params.activate(6);
+        params.fields[7].value = "--deflicker 5  --force".into();
+        assert_eq!(params.to_args(),
+                   vec!["frames/%03d.png",
+                        "--output=fan.png",
+                        "--direction=E",
+                        "--preblend=1",
+                        "--dither-bands=ordered",
+                        "--auto-stretch",
+                        "--deflicker",
+                        "5",
+                        "--force"]);
+    }
+}
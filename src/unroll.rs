@@ -0,0 +1,105 @@
+use image::{ImageBuffer, RgbaImage};
+
+use ::Direction;
+use ::image_processing::line_to_coords;
+
+/// Samples a line of an image at a fractional position along it, interpolating linearly between
+/// the two nearest pixels. Positions outside the image are transparent.
+fn sample(image: &RgbaImage, line: u32, cross: f64, direction: Direction) -> [u8; 4] {
+    let (width, height) = image.dimensions();
+    let bounds = (0, 0, width, height);
+    let cross_len = match direction {
+        Direction::N | Direction::S => width,
+        Direction::E | Direction::W => height,
+    };
+    let pixel = |cross: i64| -> [f64; 4] {
+        if cross < 0 || cross >= cross_len as i64 {
+            return [0.0; 4];
+        }
+        let (x, y) = line_to_coords(bounds, line, cross as u32, direction);
+        let data = image.get_pixel(x, y).data;
+        [data[0] as f64, data[1] as f64, data[2] as f64, data[3] as f64]
+    };
+
+    let before = cross.floor();
+    let t = cross - before;
+    let (a, b) = (pixel(before as i64), pixel(before as i64 + 1));
+    let mut out = [0; 4];
+    for c in 0..4 {
+        out[c] = (a[c] * (1.0 - t) + b[c] * t).round() as u8;
+    }
+    out
+}
+
+/// Undoes the shear a rolling shutter gives a subject moving across the frame.
+///
+/// Each line is read out a little later than the one before, so a subject moving sideways is
+/// displaced further along every line the shutter reaches. `skew` is that displacement, in pixels
+/// per line, along the direction of increasing `x` for N and S, or increasing `y` for E and W.
+/// Every line is shifted back by its displacement, so the first line read out stays in place.
+pub fn rectify(image: &RgbaImage, direction: Direction, skew: f64) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let bounds = (0, 0, width, height);
+    let (num_lines, cross_len) = match direction {
+        Direction::N | Direction::S => (height, width),
+        Direction::E | Direction::W => (width, height),
+    };
+
+    let mut out: RgbaImage = ImageBuffer::new(width, height);
+    for line in 0..num_lines {
+        let shift = skew * line as f64;
+        for cross in 0..cross_len {
+            let (x, y) = line_to_coords(bounds, line, cross, direction);
+            out.get_pixel_mut(x, y).data = sample(image, line, cross as f64 + shift, direction);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::Rgba;
+
+    /// A vertical bar, sheared as a rolling shutter reading from the top would see it if it moved
+    /// one pixel to the right per line.
+    fn sheared_bar() -> RgbaImage {
+        ImageBuffer::from_fn(8, 4, |x, y| {
+            if x == y + 2 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn test_rectify() {
+        let rectified = rectify(&sheared_bar(), Direction::N, 1.0);
+        for y in 0..4 {
+            assert_eq!(rectified.get_pixel(2, y).data, [255, 255, 255, 255]);
+            assert_eq!(rectified.get_pixel(3, y).data, [0, 0, 0, 255]);
+        }
+        // Pixels shifted in from past the edge are transparent.
+        assert_eq!(rectified.get_pixel(7, 3).data, [0, 0, 0, 0]);
+
+        // Read from the bottom up, the same image is sheared the other way.
+        let rectified = rectify(&sheared_bar(), Direction::S, -1.0);
+        for y in 0..4 {
+            assert_eq!(rectified.get_pixel(5, y).data, [255, 255, 255, 255]);
+        }
+    }
+
+    #[test]
+    fn test_rectify_fractional() {
+        let rectified = rectify(&sheared_bar(), Direction::N, 0.5);
+        // The second line is shifted by half a pixel, which blends the bar with its neighbour.
+        assert_eq!(rectified.get_pixel(2, 1).data, [128, 128, 128, 255]);
+        assert_eq!(rectified.get_pixel(1, 2).data, [0, 0, 0, 255]);
+
+        let unchanged = rectify(&sheared_bar(), Direction::W, 0.0);
+        assert_eq!(unchanged.dimensions(), sheared_bar().dimensions());
+        assert_eq!(unchanged.into_raw(), sheared_bar().into_raw());
+    }
+}
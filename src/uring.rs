@@ -0,0 +1,379 @@
+// Without the uring feature nothing is ever queued, so the depth goes unused.
+#![cfg_attr(not(all(feature = "uring", target_os = "linux")), allow(dead_code))]
+
+use std::path::Path;
+
+/// How many frame files are opened and read through the ring at once. Files queued beyond that
+/// wait for earlier ones to finish.
+const DEPTH: u32 = 64;
+
+/// Reads of frame files queued ahead of when they are decoded, through io_uring, so that on network
+/// file systems and other slow storage the opens and reads of upcoming frames are in flight with
+/// the kernel while earlier ones decode, instead of each stalling the thread decoding it in turn.
+///
+/// Clones of a decoder share one, behind a lock, since a ring is driven from one thread at a time.
+#[cfg(all(feature = "uring", target_os = "linux"))]
+pub struct ReadAhead(::std::sync::Mutex<imp::Ring>);
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+impl ReadAhead {
+    /// Sets up a ring, or returns `None` if the kernel can't, such as one too old for io_uring or
+    /// one that forbids it, so that frames are read the usual way.
+    pub fn new() -> Option<ReadAhead> {
+        imp::Ring::new(DEPTH).ok().map(|ring| ReadAhead(::std::sync::Mutex::new(ring)))
+    }
+
+    /// Queues reads of the given frame files, in the order they will be decoded.
+    pub fn queue<'a, I>(&self, paths: I)
+        where I: IntoIterator<Item = &'a Path>
+    {
+        let mut ring = self.0.lock().unwrap();
+        for path in paths {
+            ring.queue(path);
+        }
+    }
+
+    /// The contents of a queued frame file, waiting for its read to finish. Returns `None` if it
+    /// wasn't queued or couldn't be read through the ring, so that it is read the usual way, which
+    /// reports the error if there is one.
+    pub fn take(&self, path: &Path) -> Option<Vec<u8>> {
+        self.0.lock().unwrap().take(path)
+    }
+}
+
+/// Stands in for the ring in builds without the uring feature, or on other platforms, where frames
+/// are always read the usual way.
+#[cfg(not(all(feature = "uring", target_os = "linux")))]
+pub struct ReadAhead(());
+
+#[cfg(not(all(feature = "uring", target_os = "linux")))]
+impl ReadAhead {
+    /// There is no ring to set up.
+    pub fn new() -> Option<ReadAhead> {
+        None
+    }
+
+    /// Nothing is queued.
+    pub fn queue<'a, I>(&self, _paths: I)
+        where I: IntoIterator<Item = &'a Path>
+    {
+    }
+
+    /// Nothing was queued, so nothing is read.
+    pub fn take(&self, _path: &Path) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod imp {
+    use io_uring::{opcode, squeue, types, IoUring};
+    use libc;
+
+    use std::collections::{HashMap, VecDeque};
+    use std::ffi::CString;
+    use std::fs::File;
+    use std::io;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::path::{Path, PathBuf};
+
+    use ::frame_processing::OpenFile;
+
+    /// How far a queued read has got.
+    enum Step {
+        /// Waiting for room in the ring.
+        Waiting,
+        /// The file is being opened.
+        Opening,
+        /// The file is open and being read into `data`, of which `read` bytes are done. Reads can
+        /// come back short, so the rest is asked for again until the file is read.
+        Reading {
+            file: File,
+            data: Vec<u8>,
+            read: usize,
+        },
+        /// The contents of the file, or `None` if it couldn't be read.
+        Done(Option<Vec<u8>>),
+    }
+
+    /// A queued read. The name and the buffer being read into are on the heap, so they stay put
+    /// for the kernel however the request moves.
+    struct Request {
+        name: CString,
+        step: Step,
+        /// Its place in the window of open frame files, from when it is opened until it is read.
+        open: Option<OpenFile>,
+    }
+
+    /// A ring and the reads queued on it, by the id each operation is tagged with.
+    pub struct Ring {
+        ring: IoUring,
+        depth: usize,
+        requests: HashMap<u64, Request>,
+        ids: HashMap<PathBuf, u64>,
+        waiting: VecDeque<u64>,
+        /// How many requests have an operation with the kernel, which is at most `depth`, so the
+        /// submission queue never fills.
+        in_flight: usize,
+        next_id: u64,
+    }
+
+    impl Ring {
+        pub fn new(depth: u32) -> io::Result<Ring> {
+            Ok(Ring {
+                ring: IoUring::new(depth)?,
+                depth: depth as usize,
+                requests: HashMap::new(),
+                ids: HashMap::new(),
+                waiting: VecDeque::new(),
+                in_flight: 0,
+                next_id: 0,
+            })
+        }
+
+        pub fn queue(&mut self, path: &Path) {
+            if self.ids.contains_key(path) {
+                return;
+            }
+            let name = match CString::new(path.as_os_str().as_bytes()) {
+                Ok(name) => name,
+                Err(_) => return,
+            };
+            let id = self.next_id;
+            self.next_id += 1;
+            self.requests.insert(id,
+                                 Request {
+                                     name,
+                                     step: Step::Waiting,
+                                     open: None,
+                                 });
+            self.ids.insert(path.to_path_buf(), id);
+            self.waiting.push_back(id);
+            self.reap();
+            self.start_waiting();
+            // Errors submitting are seen again when the read is waited for.
+            let _ = self.ring.submit();
+        }
+
+        pub fn take(&mut self, path: &Path) -> Option<Vec<u8>> {
+            let id = self.ids.remove(path)?;
+            loop {
+                self.reap();
+                if let Step::Done(_) = self.requests[&id].step {
+                    break;
+                }
+                self.start_waiting();
+                if self.in_flight == 0 {
+                    // Every file the window allows is open elsewhere, so there is nothing to wait
+                    // for here, and the file is read the usual way, waiting for room there.
+                    self.waiting.retain(|&waiting| waiting != id);
+                    self.requests.remove(&id);
+                    return None;
+                }
+                match self.ring.submit_and_wait(1) {
+                    Ok(_) => (),
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    // The read is left to finish, or not, and the file is read the usual way.
+                    Err(_) => return None,
+                }
+            }
+            match self.requests.remove(&id).map(|request| request.step) {
+                Some(Step::Done(data)) => data,
+                _ => None,
+            }
+        }
+
+        /// Opens the files waiting for room in the ring, as far as there is room in it and in the
+        /// window of open frame files.
+        fn start_waiting(&mut self) {
+            while self.in_flight < self.depth && !self.waiting.is_empty() {
+                let place = match OpenFile::try_take() {
+                    Some(place) => place,
+                    None => break,
+                };
+                let id = self.waiting.pop_front().unwrap();
+                let open = {
+                    let request = self.requests.get_mut(&id).unwrap();
+                    request.step = Step::Opening;
+                    request.open = Some(place);
+                    opcode::OpenAt::new(types::Fd(libc::AT_FDCWD), request.name.as_ptr())
+                        .flags(libc::O_RDONLY | libc::O_CLOEXEC)
+                        .build()
+                        .user_data(id)
+                };
+                self.push(&open);
+            }
+        }
+
+        /// Hands an operation to the kernel. There is always room, since no more than `depth`
+        /// are ever in flight.
+        fn push(&mut self, entry: &squeue::Entry) {
+            // Safe since the name and buffer the entry points to are kept by its request until
+            // it completes, even when the request is no longer wanted.
+            unsafe {
+                self.ring.submission().push(entry).expect("the ring has room");
+            }
+            self.in_flight += 1;
+        }
+
+        /// Moves every request whose operation completed on to its next step, without waiting for
+        /// any. Requests give their places in the window of open frame files back as soon as they
+        /// are done, so this is called whenever the ring is used, not only while waiting on it.
+        fn reap(&mut self) {
+            let completed: Vec<(u64, i32)> =
+                self.ring.completion().map(|cqe| (cqe.user_data(), cqe.result())).collect();
+            for (id, result) in completed {
+                self.in_flight -= 1;
+                let next = self.advance(id, result);
+                if let Some(entry) = next {
+                    self.push(&entry);
+                }
+            }
+        }
+
+        /// Moves a request on once its operation completed with `result`, returning the next
+        /// operation to hand the kernel, if it needs one.
+        fn advance(&mut self, id: u64, result: i32) -> Option<squeue::Entry> {
+            let request = self.requests.get_mut(&id)?;
+            let (step, next) = match mem::replace(&mut request.step, Step::Waiting) {
+                Step::Opening if result < 0 => (Step::Done(None), None),
+                Step::Opening => {
+                    // The ring opened the file, so it is ours to close.
+                    let file = unsafe { File::from_raw_fd(result) };
+                    match file.metadata() {
+                        Ok(ref metadata) if metadata.len() == 0 => (Step::Done(Some(vec![])), None),
+                        Ok(metadata) => read(id, file, vec![0; metadata.len() as usize], 0),
+                        Err(_) => (Step::Done(None), None),
+                    }
+                }
+                Step::Reading { .. } if result < 0 => (Step::Done(None), None),
+                Step::Reading { file, mut data, read: done } => {
+                    let done = done + result as usize;
+                    // A read of nothing means the file was cut short while it was read.
+                    if result == 0 || done == data.len() {
+                        data.truncate(done);
+                        (Step::Done(Some(data)), None)
+                    } else {
+                        read(id, file, data, done)
+                    }
+                }
+                step => (step, None),
+            };
+            if let Step::Done(_) = step {
+                request.open = None;
+            }
+            request.step = step;
+            next
+        }
+    }
+
+    /// The step of a request reading the rest of `file` into `data` from `done` on, and the read
+    /// that does it.
+    fn read(id: u64,
+            file: File,
+            mut data: Vec<u8>,
+            done: usize)
+            -> (Step, Option<squeue::Entry>) {
+        let rest = &mut data[done..];
+        let fd = types::Fd(file.as_raw_fd());
+        let entry = opcode::Read::new(fd, rest.as_mut_ptr(), rest.len() as u32)
+            .offset(done as u64)
+            .build()
+            .user_data(id);
+        (Step::Reading {
+             file,
+             data,
+             read: done,
+         },
+         Some(entry))
+    }
+
+    impl Drop for Ring {
+        /// Waits for every operation in flight, since the kernel may still write into the
+        /// buffers of reads nobody took.
+        fn drop(&mut self) {
+            while self.in_flight > 0 {
+                match self.ring.submit_and_wait(1) {
+                    Ok(_) => self.reap(),
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    Err(_) => {
+                        // The buffers can't be known to be safe to free, so they are leaked.
+                        mem::forget(mem::replace(&mut self.requests, HashMap::new()));
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{self, Rgba, RgbaImage};
+
+    use std::fs;
+    use std::sync::Mutex;
+
+    use ::frame_processing::{self, DEFAULT_OPEN_FILES};
+    use ::image_processing::{process_images, RenderOptions};
+    use ::progress::NoProgress;
+    use ::testing::frame_dir;
+
+    /// Held by the tests here, since one changes the limit on open files the others need room in.
+    static WINDOW: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_read_ahead() {
+        let _window = WINDOW.lock().unwrap_or_else(|e| e.into_inner());
+        let read_ahead = match ReadAhead::new() {
+            Some(read_ahead) => read_ahead,
+            // Without a ring, frames are always read the usual way.
+            None => return,
+        };
+        let dir = frame_dir("uring", vec![]);
+        let paths: Vec<_> = (0..100).map(|i| dir.join(format!("{}.bin", i))).collect();
+        for (i, path) in paths.iter().enumerate() {
+            fs::write(path, vec![i as u8; i * 1000]).unwrap();
+        }
+        let missing = dir.join("missing.bin");
+        read_ahead.queue(paths.iter().map(|path| path.as_path()));
+        read_ahead.queue(Some(missing.as_path()));
+        // Reads are taken out of order, and some are never taken.
+        for (i, path) in paths.iter().enumerate().rev().step_by(2) {
+            assert_eq!(read_ahead.take(path), Some(vec![i as u8; i * 1000]));
+        }
+        assert_eq!(read_ahead.take(&missing), None);
+        assert_eq!(read_ahead.take(&dir.join("unqueued.bin")), None);
+    }
+
+    #[test]
+    fn test_open_file_limit() {
+        let _window = WINDOW.lock().unwrap_or_else(|e| e.into_inner());
+        let frames = (1..9).map(|i| RgbaImage::from_pixel(2, 8, Rgba([10 * i, 0, 0, 255])));
+        let dir = frame_dir("open-file-limit", frames);
+        let options = RenderOptions {
+            threads: 4,
+            suppress_output: true,
+            ..RenderOptions::default()
+        };
+        // More frames are decoded at once than files may be open, so the threads decoding them
+        // wait on the reads queued ahead of them to give their places back.
+        for &limit in &[1, 2] {
+            frame_processing::set_open_file_limit(limit);
+            let result = process_images(dir.frames.clone().into_iter(),
+                                        &[dir.join("out.png")],
+                                        &options,
+                                        &mut [],
+                                        &mut [],
+                                        &mut NoProgress);
+            frame_processing::set_open_file_limit(DEFAULT_OPEN_FILES);
+            result.unwrap();
+            let rendered = image::open(dir.join("out.png")).unwrap().to_rgba();
+            assert_eq!(rendered.get_pixel(0, 7), &Rgba([80, 0, 0, 255]));
+        }
+    }
+}